@@ -0,0 +1,58 @@
+/*
+ * C declarations for the keyboard_fsm `ffi` module.
+ *
+ * Events in both directions are three bytes, a tag followed by two
+ * arguments. Inputs are 0 press, 1 release, 2 pointer move, 3 pointer
+ * button, 4 wheel, 5 travel, 6 rotate, 7 application, 8 window title,
+ * 9 host layout, 10 battery, 11 external power, 12 host LEDs, 13 output
+ * lock and 16 onwards custom events; outputs are 0 press, 1 release,
+ * 2 layer activated, 3 layer deactivated, 4 enter bootloader and 5 system
+ * reset.
+ * Timestamps are milliseconds and must never go backwards.
+ */
+
+#ifndef KEYBOARD_FSM_H
+#define KEYBOARD_FSM_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* A machine's initial state, exported by the Rust crate defining it. */
+typedef struct fsm_state fsm_state;
+
+/* A running machine. */
+typedef struct fsm_machine fsm_machine;
+
+/* Called by fsm_drain for each emitted event. */
+typedef void (*fsm_output_fn)(void *user_data, uint8_t tag, uint8_t x, uint8_t y);
+
+/* A machine starting at `initial`, or NULL if `initial` is NULL. */
+fsm_machine *fsm_new(const fsm_state *initial, uint64_t now_ms, fsm_output_fn output,
+                     void *user_data);
+
+/* Free a machine. Events not yet drained are dropped. NULL is ignored. */
+void fsm_free(fsm_machine *machine);
+
+/* Push an event, returning false if it isn't a valid encoding. */
+bool fsm_push(fsm_machine *machine, uint64_t now_ms, uint8_t tag, uint8_t x, uint8_t y);
+
+/* Take any timed transition due at `now_ms`. */
+void fsm_tick(fsm_machine *machine, uint64_t now_ms);
+
+/* Write the next time fsm_tick is needed, returning false if there's none. */
+bool fsm_next_deadline(const fsm_machine *machine, uint64_t now_ms, uint64_t *deadline_ms);
+
+/* Call the output callback with each event emitted since the last drain,
+ * returning how many there were. The callback must not use the machine. */
+size_t fsm_drain(fsm_machine *machine);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,209 @@
+//! Slow Keys and Bounce Keys input filters.
+//!
+//! These are timing filters that sit in front of the machine and decide
+//! which input events reach it at all.
+//!
+//! - Slow Keys: a key only registers once it has been held for a minimum
+//!   time, brief accidental presses are dropped.
+//! - Bounce Keys: presses of a key that arrive too soon after its previous
+//!   release are ignored.
+
+use crate::time::{self, Duration, Instant};
+use crate::{InputEvent, KeySet};
+
+struct Pending<Clock: time::Clock> {
+    key: u8,
+    since: Clock::Instant,
+}
+
+/// Delays presses until they have been held for `hold`.
+///
+/// Presses are reported from [`SlowKeys::tick`] once accepted, and their
+/// release is only forwarded if the press was.
+struct SlowKeys<Clock: time::Clock, const N: usize> {
+    hold: Duration,
+    pending: [Option<Pending<Clock>>; N],
+    accepted: KeySet,
+}
+
+impl<Clock: time::Clock, const N: usize> SlowKeys<Clock, N> {
+    const fn new(hold: Duration) -> Self {
+        Self {
+            hold,
+            pending: [const { None }; N],
+            accepted: KeySet::empty(),
+        }
+    }
+
+    fn push(&mut self, current_time: Clock::Instant, event: InputEvent) -> Option<InputEvent> {
+        match event {
+            InputEvent::Press(key) => {
+                // with no free slot the press is dropped, as if released too early
+                if let Some(slot) = self.pending.iter_mut().find(|p| p.is_none()) {
+                    *slot = Some(Pending {
+                        key,
+                        since: current_time,
+                    });
+                }
+                None
+            }
+            InputEvent::Depress(key) => {
+                for slot in &mut self.pending {
+                    if matches!(slot, Some(p) if p.key == key) {
+                        *slot = None;
+                    }
+                }
+
+                if self.accepted.remove(key) {
+                    Some(event)
+                } else {
+                    None
+                }
+            }
+            event => Some(event),
+        }
+    }
+
+    /// Returns the next press that has now been held for long enough.
+    fn tick(&mut self, current_time: Clock::Instant) -> Option<InputEvent> {
+        let hold = self.hold;
+        let slot = self.pending.iter_mut().find(|p| match p {
+            Some(p) => {
+                let elapsed = current_time.duration_since(&p.since);
+                elapsed >= hold
+            }
+            None => false,
+        })?;
+
+        let key = slot.take()?.key;
+        self.accepted.insert(key);
+
+        Some(InputEvent::Press(key))
+    }
+}
+
+/// Ignores presses of a key within `window` of its last release.
+struct BounceKeys<Clock: time::Clock, const N: usize> {
+    window: Duration,
+    released: [Option<Pending<Clock>>; N],
+    /// Ignored presses, so that their release is ignored too.
+    ignored: KeySet,
+}
+
+impl<Clock: time::Clock, const N: usize> BounceKeys<Clock, N> {
+    const fn new(window: Duration) -> Self {
+        Self {
+            window,
+            released: [const { None }; N],
+            ignored: KeySet::empty(),
+        }
+    }
+
+    fn elapsed(current_time: Clock::Instant, since: &Clock::Instant) -> Duration {
+        current_time.duration_since(since)
+    }
+
+    fn push(&mut self, current_time: Clock::Instant, event: InputEvent) -> Option<InputEvent> {
+        let window = self.window;
+
+        // forget releases that are outside of the window
+        for slot in &mut self.released {
+            if matches!(slot, Some(p) if Self::elapsed(current_time, &p.since) >= window) {
+                *slot = None;
+            }
+        }
+
+        match event {
+            InputEvent::Press(key) => {
+                if self.released.iter().flatten().any(|p| p.key == key) {
+                    self.ignored.insert(key);
+                    None
+                } else {
+                    Some(event)
+                }
+            }
+            InputEvent::Depress(key) => {
+                if self.ignored.remove(key) {
+                    return None;
+                }
+
+                let index = self
+                    .released
+                    .iter()
+                    .position(|p| matches!(p, Some(p) if p.key == key))
+                    .or_else(|| self.released.iter().position(|p| p.is_none()));
+
+                if let Some(index) = index {
+                    self.released[index] = Some(Pending {
+                        key,
+                        since: current_time,
+                    });
+                }
+
+                Some(event)
+            }
+            event => Some(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BounceKeys, SlowKeys};
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::InputEvent;
+
+    #[test]
+    fn slow_keys_drops_short_presses() {
+        let mut clock = TickerClock(0);
+        let mut slow = SlowKeys::<TickerClock, 4>::new(Duration::from_millis(10));
+
+        assert_eq!(slow.push(clock.now(), InputEvent::Press(1)), None);
+        clock.tick_n(5);
+        assert_eq!(slow.tick(clock.now()), None);
+        assert_eq!(slow.push(clock.now(), InputEvent::Depress(1)), None);
+        clock.tick_n(10);
+        assert_eq!(slow.tick(clock.now()), None);
+
+        assert_eq!(slow.push(clock.now(), InputEvent::Press(1)), None);
+        clock.tick_n(10);
+        assert_eq!(slow.tick(clock.now()), Some(InputEvent::Press(1)));
+        assert_eq!(slow.tick(clock.now()), None);
+        assert_eq!(
+            slow.push(clock.now(), InputEvent::Depress(1)),
+            Some(InputEvent::Depress(1))
+        );
+    }
+
+    #[test]
+    fn bounce_keys_ignores_quick_repeats() {
+        let mut clock = TickerClock(0);
+        let mut bounce = BounceKeys::<TickerClock, 4>::new(Duration::from_millis(10));
+
+        assert_eq!(
+            bounce.push(clock.now(), InputEvent::Press(1)),
+            Some(InputEvent::Press(1))
+        );
+        clock.tick();
+        assert_eq!(
+            bounce.push(clock.now(), InputEvent::Depress(1)),
+            Some(InputEvent::Depress(1))
+        );
+
+        clock.tick_n(3);
+        assert_eq!(bounce.push(clock.now(), InputEvent::Press(1)), None);
+        assert_eq!(
+            bounce.push(clock.now(), InputEvent::Press(2)),
+            Some(InputEvent::Press(2))
+        );
+        clock.tick();
+        assert_eq!(bounce.push(clock.now(), InputEvent::Depress(1)), None);
+
+        clock.tick_n(10);
+        assert_eq!(
+            bounce.push(clock.now(), InputEvent::Press(1)),
+            Some(InputEvent::Press(1))
+        );
+    }
+}
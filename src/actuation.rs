@@ -0,0 +1,122 @@
+//! Fixed actuation points for analog keys.
+//!
+//! [`Actuation`] turns [`InputEvent::Travel`] streams into presses and
+//! releases. A key presses once its travel reaches the press point and
+//! releases once it falls back below the release point, so a release point
+//! under the press point keeps a finger hovering at the threshold from
+//! chattering.
+//!
+//! Machines can also match on travel directly with
+//! [`TransitionCondition::TravelAbove`](crate::TransitionCondition::TravelAbove)
+//! and [`TransitionCondition::TravelBelow`](crate::TransitionCondition::TravelBelow).
+
+use crate::{InputEvent, KeyCode, KeySet};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct ActuationPoint {
+    press: u8,
+    /// Should be at most `press`, the gap between them is the hysteresis.
+    release: u8,
+}
+
+struct Actuation {
+    point: ActuationPoint,
+    /// Per key replacements for `point`.
+    overrides: &'static [(KeyCode, ActuationPoint)],
+    pressed: KeySet,
+}
+
+impl Actuation {
+    const fn new(point: ActuationPoint, overrides: &'static [(KeyCode, ActuationPoint)]) -> Self {
+        Self {
+            point,
+            overrides,
+            pressed: KeySet::empty(),
+        }
+    }
+
+    fn point_for(&self, key: KeyCode) -> ActuationPoint {
+        self.overrides
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map_or(self.point, |(_, point)| *point)
+    }
+
+    /// Turns travel events into presses and releases, other events are passed
+    /// through.
+    fn push(&mut self, event: InputEvent) -> Option<InputEvent> {
+        let InputEvent::Travel(key, travel) = event else {
+            return Some(event);
+        };
+        let point = self.point_for(key);
+
+        if self.pressed.contains(key) {
+            if travel < point.release {
+                self.pressed.remove(key);
+                return Some(InputEvent::Depress(key));
+            }
+        } else if travel >= point.press {
+            self.pressed.insert(key);
+            return Some(InputEvent::Press(key));
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Actuation, ActuationPoint};
+    use crate::{InputEvent, TransitionCondition};
+
+    fn travel(actuation: &mut Actuation, key: u8, travel: &[u8]) -> Vec<InputEvent> {
+        travel
+            .iter()
+            .filter_map(|t| actuation.push(InputEvent::Travel(key, *t)))
+            .collect()
+    }
+
+    #[test]
+    fn hysteresis() {
+        static OVERRIDES: [(u8, ActuationPoint); 1] = [(
+            2,
+            ActuationPoint {
+                press: 200,
+                release: 200,
+            },
+        )];
+        let mut actuation = Actuation::new(
+            ActuationPoint {
+                press: 100,
+                release: 80,
+            },
+            &OVERRIDES,
+        );
+
+        assert_eq!(
+            travel(&mut actuation, 1, &[50, 99, 100, 90, 81, 120, 79, 99]),
+            [InputEvent::Press(1), InputEvent::Depress(1)]
+        );
+        assert_eq!(
+            travel(&mut actuation, 2, &[150, 200, 199]),
+            [InputEvent::Press(2), InputEvent::Depress(2)]
+        );
+        assert_eq!(
+            actuation.push(InputEvent::Press(3)),
+            Some(InputEvent::Press(3))
+        );
+    }
+
+    #[test]
+    fn match_travel() {
+        let deep = TransitionCondition::TravelAbove(1, 200);
+        let shallow = TransitionCondition::TravelBelow(1, 50);
+        let context = crate::tests::context();
+
+        assert!(deep.evaluate(&context, Some(InputEvent::Travel(1, 200))));
+        assert!(!deep.evaluate(&context, Some(InputEvent::Travel(1, 199))));
+        assert!(!deep.evaluate(&context, Some(InputEvent::Travel(2, 255))));
+        assert!(shallow.evaluate(&context, Some(InputEvent::Travel(1, 49))));
+        assert!(!shallow.evaluate(&context, Some(InputEvent::Travel(1, 50))));
+    }
+}
@@ -0,0 +1,358 @@
+//! Machines packed into contiguous arrays.
+//!
+//! A [`Table`](crate::table::Table) still points at the condition and
+//! emission arrays of each transition static, which the linker may scatter
+//! across flash. [`Arena::pack`] copies all of them into one array per kind,
+//! with each transition holding offsets into those arrays, so evaluating a
+//! state reads a few runs of neighbouring memory.
+//!
+//! Packing is a const fn over the machine's statics, so the arena is itself
+//! a static, laid out at compile time and kept in flash:
+//!
+//! ```ignore
+//! static ARENA: Arena<4, 15, 21, 25, 0> = match Arena::pack(&home_a::IDLE) {
+//!     Ok(arena) => arena,
+//!     Err(_) => panic!("the arena is too small"),
+//! };
+//! let machine = TableMachine::new(&ARENA, clock.now());
+//! ```
+
+use core::ops::Range;
+
+use crate::table::{Layout, StateIndex};
+use crate::validate::{reachable, ValidationError};
+use crate::{InternalEvent, KeyEvent, State, TransitionCondition};
+
+/// Which of the arena's arrays ran out of room, or why the machine can't be
+/// packed at all.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum PackError {
+    States,
+    Transitions,
+    Conditions,
+    KeyEvents,
+    InternalEvents,
+    /// The named states have the same id, so can't be told apart.
+    DuplicateId(&'static str, &'static str),
+}
+
+/// A run of one of the arena's arrays.
+#[derive(Debug, Default, Clone, Copy)]
+struct Span {
+    start: u16,
+    len: u16,
+}
+
+impl Span {
+    const EMPTY: Self = Self { start: 0, len: 0 };
+
+    fn range(self) -> Range<usize> {
+        self.start as usize..(self.start + self.len) as usize
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ArenaState {
+    name: &'static str,
+    transitions: Span,
+}
+
+#[derive(Clone, Copy)]
+struct ArenaTransition {
+    conditions: Span,
+    key_event_emissions: Span,
+    internal_event_emissions: Span,
+    target: StateIndex,
+}
+
+struct Arena<
+    const STATES: usize,
+    const TRANSITIONS: usize,
+    const CONDITIONS: usize,
+    const KEY_EVENTS: usize,
+    const INTERNAL_EVENTS: usize,
+> {
+    states: [ArenaState; STATES],
+    transitions: [ArenaTransition; TRANSITIONS],
+    conditions: [TransitionCondition; CONDITIONS],
+    key_events: [KeyEvent; KEY_EVENTS],
+    internal_events: [InternalEvent; INTERNAL_EVENTS],
+    state_count: u16,
+}
+
+/// Where `len` more entries go after the first `*len` of an array of
+/// `capacity`, taking them.
+const fn reserve(
+    capacity: usize,
+    used: &mut u16,
+    len: usize,
+    error: PackError,
+) -> Result<Span, PackError> {
+    let start = *used as usize;
+    let end = start + len;
+    if end > capacity || end > u16::MAX as usize {
+        return Err(error);
+    }
+    *used = end as u16;
+    Ok(Span {
+        start: start as u16,
+        len: len as u16,
+    })
+}
+
+impl<
+        const STATES: usize,
+        const TRANSITIONS: usize,
+        const CONDITIONS: usize,
+        const KEY_EVENTS: usize,
+        const INTERNAL_EVENTS: usize,
+    > Arena<STATES, TRANSITIONS, CONDITIONS, KEY_EVENTS, INTERNAL_EVENTS>
+{
+    /// Copy every state, transition, condition and emission of the machine
+    /// starting at `initial` into the arena. States are numbered in the order
+    /// they're reached, like a [`Table`](crate::table::Table)'s, and
+    /// transitions keep their order, so a state's transitions are still tried
+    /// in the order they were written.
+    const fn pack(initial: &'static State) -> Result<Self, PackError> {
+        const EMPTY_STATE: ArenaState = ArenaState {
+            name: "",
+            transitions: Span::EMPTY,
+        };
+        const EMPTY_TRANSITION: ArenaTransition = ArenaTransition {
+            conditions: Span::EMPTY,
+            key_event_emissions: Span::EMPTY,
+            internal_event_emissions: Span::EMPTY,
+            target: StateIndex(0),
+        };
+
+        let (states, state_count) = match reachable(initial) {
+            Ok(reachable) => reachable,
+            Err(ValidationError::DuplicateId(a, b)) => return Err(PackError::DuplicateId(a, b)),
+            Err(_) => return Err(PackError::States),
+        };
+        if state_count > STATES {
+            return Err(PackError::States);
+        }
+
+        let mut arena = Self {
+            states: [EMPTY_STATE; STATES],
+            transitions: [EMPTY_TRANSITION; TRANSITIONS],
+            conditions: [const { TransitionCondition::WheelScrolled }; CONDITIONS],
+            key_events: [KeyEvent::Press(0); KEY_EVENTS],
+            internal_events: [InternalEvent::RecordActivity; INTERNAL_EVENTS],
+            state_count: state_count as u16,
+        };
+        let (mut transitions, mut conditions, mut key_events, mut internal_events) = (0, 0, 0, 0);
+
+        let mut index = 0;
+        while index < state_count {
+            let Some(state) = states[index] else {
+                unreachable!()
+            };
+            let start = transitions;
+            let mut t = 0;
+            while t < state.transitions.len() {
+                let transition = state.transitions[t];
+                if transitions as usize >= TRANSITIONS {
+                    return Err(PackError::Transitions);
+                }
+
+                let span = match reserve(
+                    CONDITIONS,
+                    &mut conditions,
+                    transition.conditions.len(),
+                    PackError::Conditions,
+                ) {
+                    Ok(span) => span,
+                    Err(error) => return Err(error),
+                };
+                let mut i = 0;
+                while i < transition.conditions.len() {
+                    arena.conditions[span.start as usize + i] = transition.conditions[i].copy();
+                    i += 1;
+                }
+                arena.transitions[transitions as usize].conditions = span;
+
+                let span = match reserve(
+                    KEY_EVENTS,
+                    &mut key_events,
+                    transition.key_event_emissions.len(),
+                    PackError::KeyEvents,
+                ) {
+                    Ok(span) => span,
+                    Err(error) => return Err(error),
+                };
+                let mut i = 0;
+                while i < transition.key_event_emissions.len() {
+                    arena.key_events[span.start as usize + i] = transition.key_event_emissions[i];
+                    i += 1;
+                }
+                arena.transitions[transitions as usize].key_event_emissions = span;
+
+                let span = match reserve(
+                    INTERNAL_EVENTS,
+                    &mut internal_events,
+                    transition.internal_event_emissions.len(),
+                    PackError::InternalEvents,
+                ) {
+                    Ok(span) => span,
+                    Err(error) => return Err(error),
+                };
+                let mut i = 0;
+                while i < transition.internal_event_emissions.len() {
+                    arena.internal_events[span.start as usize + i] =
+                        transition.internal_event_emissions[i];
+                    i += 1;
+                }
+                arena.transitions[transitions as usize].internal_event_emissions = span;
+
+                // every target was reached, so it's one of the states
+                let mut target = 0;
+                while let Some(s) = states[target] {
+                    if s.id.0 == transition.target.id.0 {
+                        break;
+                    }
+                    target += 1;
+                }
+                arena.transitions[transitions as usize].target = StateIndex(target as u16);
+
+                transitions += 1;
+                t += 1;
+            }
+            arena.states[index] = ArenaState {
+                name: state.name,
+                transitions: Span {
+                    start,
+                    len: transitions - start,
+                },
+            };
+            index += 1;
+        }
+
+        Ok(arena)
+    }
+}
+
+impl<
+        const STATES: usize,
+        const TRANSITIONS: usize,
+        const CONDITIONS: usize,
+        const KEY_EVENTS: usize,
+        const INTERNAL_EVENTS: usize,
+    > Layout for Arena<STATES, TRANSITIONS, CONDITIONS, KEY_EVENTS, INTERNAL_EVENTS>
+{
+    fn state_count(&self) -> usize {
+        self.state_count as usize
+    }
+
+    fn transition_range(&self, state: StateIndex) -> Range<usize> {
+        self.states[state.0 as usize].transitions.range()
+    }
+
+    fn conditions(&self, transition: usize) -> &[TransitionCondition] {
+        &self.conditions[self.transitions[transition].conditions.range()]
+    }
+
+    fn key_event_emissions(&self, transition: usize) -> &[KeyEvent] {
+        &self.key_events[self.transitions[transition].key_event_emissions.range()]
+    }
+
+    fn internal_event_emissions(&self, transition: usize) -> &[InternalEvent] {
+        &self.internal_events[self.transitions[transition]
+            .internal_event_emissions
+            .range()]
+    }
+
+    fn target(&self, transition: usize) -> StateIndex {
+        self.transitions[transition].target
+    }
+
+    fn name(&self, state: StateIndex) -> &'static str {
+        self.states[state.0 as usize].name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Arena, PackError};
+    use crate::behaviors::hold_tap;
+    use crate::table::{Layout, StateIndex, Table, TableMachine};
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::InputEvent;
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    static ARENA: Arena<4, 15, 32, 32, 0> = match Arena::pack(&home_a::IDLE) {
+        Ok(arena) => arena,
+        Err(_) => panic!("the arena is too small"),
+    };
+
+    #[test]
+    fn pack() {
+        let table = Table::<4, 15>::compile(home_a::IDLE.as_dyn()).unwrap();
+        let arena = &ARENA;
+
+        for state in 0..table.state_count() {
+            let state = StateIndex(state as u16);
+            assert_eq!(arena.name(state), table.name(state));
+            assert_eq!(arena.transition_range(state), table.transition_range(state));
+            for transition in table.transition_range(state) {
+                assert_eq!(
+                    arena.key_event_emissions(transition),
+                    table.key_event_emissions(transition)
+                );
+                assert_eq!(
+                    arena.conditions(transition).len(),
+                    table.conditions(transition).len()
+                );
+                assert_eq!(arena.target(transition), table.target(transition));
+            }
+        }
+
+        assert_eq!(
+            Arena::<4, 15, 4, 32, 0>::pack(&home_a::IDLE).err(),
+            Some(PackError::Conditions)
+        );
+        assert_eq!(
+            Arena::<3, 15, 32, 32, 0>::pack(&home_a::IDLE).err(),
+            Some(PackError::States)
+        );
+        assert_eq!(
+            Arena::<4, 15, 32, 32, 0>::pack(&home_a::HOLD)
+                .map(|arena| arena.state_count())
+                .ok(),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn runs_like_table() {
+        let table = Table::<4, 15>::compile(home_a::IDLE.as_dyn()).unwrap();
+        let mut clock = TickerClock(0);
+        let mut packed = TableMachine::<TickerClock, _>::new(&ARENA, clock.now());
+        let mut unpacked = TableMachine::<TickerClock, _>::new(&table, clock.now());
+
+        for event in [
+            InputEvent::Press(1),
+            InputEvent::Depress(1),
+            InputEvent::Press(1),
+            InputEvent::Press(2),
+        ] {
+            clock.tick_n(3);
+            assert_eq!(packed.tick(clock.now()), unpacked.tick(clock.now()));
+            assert_eq!(
+                packed.push(clock.now(), event),
+                unpacked.push(clock.now(), event)
+            );
+            assert_eq!(packed.current_state(), unpacked.current_state());
+        }
+    }
+}
@@ -0,0 +1,244 @@
+//! Autocorrect.
+//!
+//! [`Autocorrect`] sits on the output side, following what's typed with an
+//! [`OutputHistory`], and fixes common typos as they're finished: typing
+//! `teh` sends backspaces over it and then `the`. The typos are looked up
+//! in a trie, generated as a const table from a list of typos and their
+//! corrections by [`builder::generate`] in a build script:
+//!
+//! ```ignore
+//! let source = autocorrect::builder::generate(
+//!     "AUTOCORRECT",
+//!     &[("teh", "the"), (":wich", "which"), ("recieve", "receive")],
+//! )?;
+//! std::fs::write(out_dir.join("autocorrect.rs"), source)?;
+//! ```
+//!
+//! Typos are lowercase letters, with a `:` at either end for a word
+//! boundary, so `:wich` isn't corrected in `sandwich` and `thier:` only at
+//! the end of a word. A typo ending in a boundary is corrected once the key
+//! after it is typed, which is typed again after the correction. Where
+//! typos overlap the longest one typed is corrected. The correction is
+//! typed in lowercase whatever the case of the typo.
+//!
+//! The table is a trie of the typos read backwards, so it's walked from the
+//! key just typed. Each node is an optional match, as `1`, the number of
+//! backspaces, the length of the correction and its key codes, then the
+//! branches, each as a key code and the little endian `u16` offset of the
+//! node it leads to, then `0`. Boundaries are the key code `2`.
+
+#[cfg(feature = "codegen")]
+pub(crate) mod builder;
+
+use crate::history::OutputHistory;
+use crate::{KeyCode, KeyEvent};
+
+const BACKSPACE: KeyCode = 0x2a;
+const MATCH: u8 = 1;
+const BOUNDARY: u8 = 2;
+const END: u8 = 0;
+
+const fn is_letter(key: KeyCode) -> bool {
+    matches!(key, 0x04..=0x1d)
+}
+
+/// A typo found in the table.
+struct Correction {
+    backspaces: u8,
+    correction: &'static [KeyCode],
+    /// The typo ends in a boundary, which is the key just typed.
+    retype: bool,
+}
+
+pub(crate) struct Autocorrect<const N: usize> {
+    table: &'static [u8],
+    history: OutputHistory<N>,
+    /// A key released early to type a correction, whose release is not to
+    /// be sent again.
+    released: Option<KeyCode>,
+}
+
+impl<const N: usize> Autocorrect<N> {
+    /// Correct the typos in `table`, as made by [`builder::build`]. Typos
+    /// longer than `N` are never noticed.
+    pub(crate) const fn new(table: &'static [u8]) -> Self {
+        Self {
+            table,
+            history: OutputHistory::new(),
+            released: None,
+        }
+    }
+
+    /// The `i`th key counting back from the last typed, with anything that
+    /// isn't a letter read as a boundary, or `None` if it's been forgotten.
+    fn key_back(&self, i: usize) -> Option<u8> {
+        match self.history.back(i) {
+            Some(key) if is_letter(key) => Some(key),
+            Some(_) => Some(BOUNDARY),
+            None => self.history.starts_word(i, &[]).then_some(BOUNDARY),
+        }
+    }
+
+    /// The child of the node at `node` for `key`.
+    fn branch(&self, node: usize, key: u8) -> Option<usize> {
+        let mut at = node;
+        if *self.table.get(at)? == MATCH {
+            at += 3 + *self.table.get(at + 2)? as usize;
+        }
+        loop {
+            match *self.table.get(at..at + 3)? {
+                [END, ..] => return None,
+                [k, lo, hi] if k == key => return Some(u16::from_le_bytes([lo, hi]) as usize),
+                _ => at += 3,
+            }
+        }
+    }
+
+    fn correction_at(&self, node: usize) -> Option<(u8, &'static [KeyCode])> {
+        let table = self.table;
+        match *table.get(node..node + 3)? {
+            [MATCH, backspaces, len] => {
+                Some((backspaces, table.get(node + 3..node + 3 + len as usize)?))
+            }
+            _ => None,
+        }
+    }
+
+    /// The longest typo the keys typed last are.
+    fn find(&self) -> Option<Correction> {
+        let retype = self.key_back(0)? == BOUNDARY;
+        let mut node = 0;
+        let mut found = None;
+        // the history holds at most N keys, and a boundary before them
+        for i in 0..=N {
+            let Some(key) = self.key_back(i) else {
+                break;
+            };
+            let Some(child) = self.branch(node, key) else {
+                break;
+            };
+            node = child;
+            if let Some((backspaces, correction)) = self.correction_at(node) {
+                found = Some(Correction {
+                    backspaces,
+                    correction,
+                    retype,
+                });
+            }
+        }
+        found
+    }
+
+    /// Emit `event` as it should be sent to the host, followed by the fix
+    /// for the typo it finishes, if it does.
+    pub(crate) fn output(&mut self, event: KeyEvent, mut emit: impl FnMut(KeyEvent)) {
+        match event {
+            KeyEvent::Depress(key) if self.released == Some(key) => {
+                self.released = None;
+                return;
+            }
+            KeyEvent::Press(key) if self.released == Some(key) => self.released = None,
+            _ => {}
+        }
+        self.send(event, &mut emit);
+        let KeyEvent::Press(key) = event else {
+            return;
+        };
+        if key == BACKSPACE {
+            return;
+        }
+        let Some(fix) = self.find() else {
+            return;
+        };
+
+        // the key is still held, so it's released for the correction to be
+        // typed, and pressed again after it if it's the boundary
+        self.send(KeyEvent::Depress(key), &mut emit);
+        if !fix.retype {
+            self.released = Some(key);
+        }
+        for _ in 0..fix.backspaces {
+            self.send(KeyEvent::Press(BACKSPACE), &mut emit);
+            self.send(KeyEvent::Depress(BACKSPACE), &mut emit);
+        }
+        for &key in fix.correction {
+            self.send(KeyEvent::Press(key), &mut emit);
+            self.send(KeyEvent::Depress(key), &mut emit);
+        }
+        if fix.retype {
+            self.send(KeyEvent::Press(key), &mut emit);
+        }
+    }
+
+    fn send(&mut self, event: KeyEvent, emit: &mut impl FnMut(KeyEvent)) {
+        self.history.observe(event);
+        emit(event);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::Autocorrect;
+    use crate::KeyEvent;
+
+    /// `teh` to `the` and `:wich:` to `which`, as generated by the builder.
+    pub(crate) static TABLE: [u8; 51] = [
+        2, 7, 0, 11, 36, 0, 0, 11, 11, 0, 0, 6, 15, 0, 0, 12, 19, 0, 0, 26, 23, 0, 0, 2, 27, 0, 0,
+        1, 5, 5, 26, 11, 12, 6, 11, 0, 8, 40, 0, 0, 23, 44, 0, 0, 1, 3, 3, 23, 11, 8, 0,
+    ];
+
+    fn typed(autocorrect: &mut Autocorrect<8>, keys: &[u8]) -> Vec<KeyEvent> {
+        let mut out = Vec::new();
+        for &key in keys {
+            autocorrect.output(KeyEvent::Press(key), |e| out.push(e));
+            autocorrect.output(KeyEvent::Depress(key), |e| out.push(e));
+        }
+        out
+    }
+
+    fn taps(keys: &[u8]) -> Vec<KeyEvent> {
+        keys.iter()
+            .flat_map(|&key| [KeyEvent::Press(key), KeyEvent::Depress(key)])
+            .collect()
+    }
+
+    #[test]
+    fn corrects() {
+        let mut autocorrect = Autocorrect::<8>::new(&TABLE);
+
+        // t e h
+        assert_eq!(typed(&mut autocorrect, &[0x17, 0x08]), taps(&[0x17, 0x08]));
+        assert_eq!(
+            typed(&mut autocorrect, &[0x0b]),
+            taps(&[0x0b, 0x2a, 0x2a, 0x2a, 0x17, 0x0b, 0x08])
+        );
+
+        // w i c h, then space
+        typed(&mut autocorrect, &[0x2c, 0x1a, 0x0c, 0x06, 0x0b]);
+        assert_eq!(
+            typed(&mut autocorrect, &[0x2c]),
+            [
+                KeyEvent::Press(0x2c),
+                KeyEvent::Depress(0x2c),
+                KeyEvent::Press(0x2a),
+                KeyEvent::Depress(0x2a),
+                KeyEvent::Press(0x2a),
+                KeyEvent::Depress(0x2a),
+                KeyEvent::Press(0x2a),
+                KeyEvent::Depress(0x2a),
+                KeyEvent::Press(0x2a),
+                KeyEvent::Depress(0x2a),
+                KeyEvent::Press(0x2a),
+                KeyEvent::Depress(0x2a),
+            ]
+            .into_iter()
+            .chain(taps(&[0x1a, 0x0b, 0x0c, 0x06, 0x0b]))
+            .chain([KeyEvent::Press(0x2c), KeyEvent::Depress(0x2c)])
+            .collect::<Vec<_>>()
+        );
+
+        // not within a word
+        typed(&mut autocorrect, &[0x16, 0x1a, 0x0c, 0x06, 0x0b]);
+        assert_eq!(typed(&mut autocorrect, &[0x2c]).len(), 2);
+    }
+}
@@ -0,0 +1,147 @@
+//! Building the autocorrect table, for build scripts.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::{BOUNDARY, END, MATCH};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum TrieError {
+    /// A typo or correction has a character that isn't a lowercase letter,
+    /// or a boundary anywhere but at the ends of a typo.
+    InvalidCharacter(String, char),
+    /// A typo has no letters.
+    EmptyTypo,
+    /// Two entries have the same typo.
+    DuplicateTypo(String),
+    /// A correction is longer than a table can hold.
+    TooLong(String),
+    /// The table is too big for its offsets.
+    TooLarge,
+}
+
+#[derive(Default)]
+struct Node {
+    correction: Option<(u8, Vec<u8>)>,
+    children: BTreeMap<u8, Node>,
+}
+
+fn letter(text: &str, c: char) -> Result<u8, TrieError> {
+    match c {
+        'a'..='z' => Ok(0x04 + (c as u8 - b'a')),
+        c => Err(TrieError::InvalidCharacter(text.into(), c)),
+    }
+}
+
+/// The typo's keys, read backwards, and how many of them were typed.
+fn typo_keys(typo: &str) -> Result<(Vec<u8>, usize), TrieError> {
+    let starts = typo.starts_with(':');
+    let ends = typo.len() > 1 && typo.ends_with(':');
+    let letters = &typo[starts as usize..typo.len() - ends as usize];
+    if letters.is_empty() {
+        return Err(TrieError::EmptyTypo);
+    }
+
+    let mut keys = Vec::new();
+    if starts {
+        keys.push(BOUNDARY);
+    }
+    for c in letters.chars() {
+        keys.push(letter(typo, c)?);
+    }
+    if ends {
+        keys.push(BOUNDARY);
+    }
+    keys.reverse();
+    // a boundary before the typo was typed before it
+    let typed = letters.len() + ends as usize;
+    Ok((keys, typed))
+}
+
+fn write_node(node: &Node, out: &mut Vec<u8>) -> Result<(), TrieError> {
+    if let Some((backspaces, correction)) = &node.correction {
+        out.extend([MATCH, *backspaces, correction.len() as u8]);
+        out.extend(correction);
+    }
+    let branches = out.len();
+    for &key in node.children.keys() {
+        out.extend([key, 0, 0]);
+    }
+    out.push(END);
+
+    for (i, child) in node.children.values().enumerate() {
+        let offset = u16::try_from(out.len()).map_err(|_| TrieError::TooLarge)?;
+        let at = branches + i * 3 + 1;
+        out[at..at + 2].copy_from_slice(&offset.to_le_bytes());
+        write_node(child, out)?;
+    }
+    Ok(())
+}
+
+/// The table for `entries` of a typo and its correction, see
+/// [`Autocorrect`](super::Autocorrect).
+pub(crate) fn build(entries: &[(&str, &str)]) -> Result<Vec<u8>, TrieError> {
+    let mut root = Node::default();
+    for &(typo, correction) in entries {
+        let (keys, typed) = typo_keys(typo)?;
+        let correction = correction
+            .chars()
+            .map(|c| letter(correction, c))
+            .collect::<Result<Vec<_>, _>>()?;
+        if correction.len() > u8::MAX as usize || typed > u8::MAX as usize {
+            return Err(TrieError::TooLong(typo.into()));
+        }
+
+        let node = keys.iter().fold(&mut root, |node, key| {
+            node.children.entry(*key).or_default()
+        });
+        if node.correction.is_some() {
+            return Err(TrieError::DuplicateTypo(typo.into()));
+        }
+        node.correction = Some((typed as u8, correction));
+    }
+
+    let mut out = Vec::new();
+    write_node(&root, &mut out)?;
+    Ok(out)
+}
+
+/// Rust source declaring the table for `entries` as a static named `name`.
+pub(crate) fn generate(name: &str, entries: &[(&str, &str)]) -> Result<String, TrieError> {
+    let table = build(entries)?;
+    let mut out = String::new();
+    writeln!(out, "pub static {name}: [u8; {}] = [", table.len()).unwrap();
+    for row in table.chunks(16) {
+        let row: Vec<String> = row.iter().map(u8::to_string).collect();
+        writeln!(out, "    {},", row.join(", ")).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build, generate, TrieError};
+    use crate::autocorrect::tests::TABLE;
+
+    #[test]
+    fn builds() {
+        assert_eq!(
+            build(&[("teh", "the"), (":wich:", "which")]).unwrap(),
+            TABLE
+        );
+        assert!(generate("AUTOCORRECT", &[("teh", "the")])
+            .unwrap()
+            .starts_with("pub static AUTOCORRECT: [u8; "));
+
+        assert_eq!(
+            build(&[("t3h", "the")]),
+            Err(TrieError::InvalidCharacter("t3h".into(), '3'))
+        );
+        assert_eq!(build(&[("::", "")]), Err(TrieError::EmptyTypo));
+        assert_eq!(
+            build(&[("teh", "the"), ("teh", "then")]),
+            Err(TrieError::DuplicateTypo("teh".into()))
+        );
+    }
+}
@@ -0,0 +1,12 @@
+//! Generators for commonly used behaviors.
+//!
+//! Each generator expands to a module of `static` states and transitions that
+//! can be entered like any hand written part of a machine.
+
+mod auto_mouse;
+mod hold_tap;
+mod turbo;
+
+pub(crate) use auto_mouse::auto_mouse_layer;
+pub(crate) use hold_tap::hold_tap;
+pub(crate) use turbo::turbo_key;
@@ -0,0 +1,280 @@
+/// Generates an auto mouse layer.
+///
+/// Pointer movement in `IDLE` activates `layer` and enters `ACTIVE`. The layer
+/// is dismissed once `timeout` passes without pointer activity or use of the
+/// keys in `mouse_keys`, or when any other key is pressed. While a mouse key
+/// or a pointing device button is held (`DRAGGING`) the layer is never
+/// dismissed, until the last of them is released.
+///
+/// Inactivity is tracked with the activity timer rather than the time spent
+/// in a state, so moving between `ACTIVE` and `DRAGGING` doesn't restart it.
+/// Activating and dismissing the layer emits
+/// [`KeyEvent::LayerActivated`](crate::KeyEvent::LayerActivated) and
+/// [`KeyEvent::LayerDeactivated`](crate::KeyEvent::LayerDeactivated).
+///
+/// ```ignore
+/// auto_mouse_layer! {
+///     mod auto_mouse {
+///         layer: 1,
+///         mouse_keys: 0xf0..=0xf4,
+///         timeout: Duration::from_millis(650),
+///     }
+/// }
+/// ```
+macro_rules! auto_mouse_layer {
+    (
+        $vis:vis mod $name:ident {
+            layer: $layer:expr,
+            mouse_keys: $mouse_keys:expr,
+            timeout: $timeout:expr $(,)?
+        }
+    ) => {
+        $vis mod $name {
+            use super::*;
+            use $crate::{InternalEvent, KeyEvent, State, StateId, Transition, TransitionCondition};
+
+            pub static IDLE: State = State {
+                name: concat!(stringify!($name), "::IDLE"),
+                id: StateId(0),
+                transitions: &[&IDLE_MOVE],
+            };
+
+            static IDLE_MOVE: Transition = Transition {
+                conditions: &[TransitionCondition::PointerMoved],
+                key_event_emissions: &[KeyEvent::LayerActivated($layer)],
+                internal_event_emissions: &[
+                    InternalEvent::ActivateLayer($layer),
+                    InternalEvent::RecordActivity,
+                ],
+                target: &ACTIVE,
+            };
+
+            pub static ACTIVE: State = State {
+                name: concat!(stringify!($name), "::ACTIVE"),
+                id: StateId(1),
+                transitions: &[
+                    &ACTIVE_MOVE,
+                    &ACTIVE_WHEEL,
+                    &ACTIVE_MOUSE_PRESS,
+                    &ACTIVE_BUTTON_PRESS,
+                    &ACTIVE_OTHER_PRESS,
+                    &ACTIVE_TIMEOUT,
+                ],
+            };
+
+            static ACTIVE_MOVE: Transition = Transition {
+                conditions: &[TransitionCondition::PointerMoved],
+                key_event_emissions: &[],
+                internal_event_emissions: &[InternalEvent::RecordActivity],
+                target: &ACTIVE,
+            };
+
+            static ACTIVE_WHEEL: Transition = Transition {
+                conditions: &[TransitionCondition::WheelScrolled],
+                key_event_emissions: &[],
+                internal_event_emissions: &[InternalEvent::RecordActivity],
+                target: &ACTIVE,
+            };
+
+            static ACTIVE_MOUSE_PRESS: Transition = Transition {
+                conditions: &[TransitionCondition::Pressed($mouse_keys)],
+                key_event_emissions: &[],
+                internal_event_emissions: &[InternalEvent::RecordActivity],
+                target: &DRAGGING,
+            };
+
+            static ACTIVE_BUTTON_PRESS: Transition = Transition {
+                conditions: &[TransitionCondition::PointerButtonPressed(0..=u8::MAX)],
+                key_event_emissions: &[],
+                internal_event_emissions: &[InternalEvent::RecordActivity],
+                target: &DRAGGING,
+            };
+
+            static ACTIVE_OTHER_PRESS: Transition = Transition {
+                conditions: &[TransitionCondition::Pressed(0..=u8::MAX)],
+                key_event_emissions: &[KeyEvent::LayerDeactivated($layer)],
+                internal_event_emissions: &[InternalEvent::DeactivateLayer($layer)],
+                target: &IDLE,
+            };
+
+            static ACTIVE_TIMEOUT: Transition = Transition {
+                conditions: &[TransitionCondition::IdleGreater($timeout)],
+                key_event_emissions: &[KeyEvent::LayerDeactivated($layer)],
+                internal_event_emissions: &[InternalEvent::DeactivateLayer($layer)],
+                target: &IDLE,
+            };
+
+            pub static DRAGGING: State = State {
+                name: concat!(stringify!($name), "::DRAGGING"),
+                id: StateId(2),
+                transitions: &[
+                    &DRAGGING_MOVE,
+                    &DRAGGING_WHEEL,
+                    &DRAGGING_MOUSE_PRESS,
+                    &DRAGGING_MOUSE_DEPRESS,
+                    &DRAGGING_BUTTON_RELEASE,
+                    &DRAGGING_HELD_DEPRESS,
+                    &DRAGGING_HELD_RELEASE,
+                ],
+            };
+
+            static DRAGGING_MOVE: Transition = Transition {
+                conditions: &[TransitionCondition::PointerMoved],
+                key_event_emissions: &[],
+                internal_event_emissions: &[InternalEvent::RecordActivity],
+                target: &DRAGGING,
+            };
+
+            static DRAGGING_WHEEL: Transition = Transition {
+                conditions: &[TransitionCondition::WheelScrolled],
+                key_event_emissions: &[],
+                internal_event_emissions: &[InternalEvent::RecordActivity],
+                target: &DRAGGING,
+            };
+
+            static DRAGGING_MOUSE_PRESS: Transition = Transition {
+                conditions: &[TransitionCondition::Pressed($mouse_keys)],
+                key_event_emissions: &[],
+                internal_event_emissions: &[InternalEvent::RecordActivity],
+                target: &DRAGGING,
+            };
+
+            static DRAGGING_MOUSE_DEPRESS: Transition = Transition {
+                conditions: &[
+                    TransitionCondition::Depressed($mouse_keys),
+                    TransitionCondition::AllReleased($mouse_keys),
+                    TransitionCondition::AllButtonsReleased(0..=u8::MAX),
+                ],
+                key_event_emissions: &[],
+                internal_event_emissions: &[InternalEvent::RecordActivity],
+                target: &ACTIVE,
+            };
+
+            static DRAGGING_BUTTON_RELEASE: Transition = Transition {
+                conditions: &[
+                    TransitionCondition::PointerButtonReleased(0..=u8::MAX),
+                    TransitionCondition::AllReleased($mouse_keys),
+                    TransitionCondition::AllButtonsReleased(0..=u8::MAX),
+                ],
+                key_event_emissions: &[],
+                internal_event_emissions: &[InternalEvent::RecordActivity],
+                target: &ACTIVE,
+            };
+
+            static DRAGGING_HELD_DEPRESS: Transition = Transition {
+                conditions: &[TransitionCondition::Depressed($mouse_keys)],
+                key_event_emissions: &[],
+                internal_event_emissions: &[InternalEvent::RecordActivity],
+                target: &DRAGGING,
+            };
+
+            static DRAGGING_HELD_RELEASE: Transition = Transition {
+                conditions: &[TransitionCondition::PointerButtonReleased(0..=u8::MAX)],
+                key_event_emissions: &[],
+                internal_event_emissions: &[InternalEvent::RecordActivity],
+                target: &DRAGGING,
+            };
+        }
+    };
+}
+
+pub(crate) use auto_mouse_layer;
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{GlobalState, InputEvent, KeyEvent};
+
+    auto_mouse_layer! {
+        mod auto_mouse {
+            layer: 1,
+            mouse_keys: 0xf0..=0xf4,
+            timeout: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn activates_on_pointer_and_times_out() {
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(auto_mouse::IDLE.as_dyn(), clock.now());
+
+        let s = state.push(clock.now(), InputEvent::PointerMove(1, -1));
+        assert_matches!(s, [KeyEvent::LayerActivated(1)]);
+        assert_eq!(state.current_state, auto_mouse::ACTIVE.as_dyn());
+        assert!(state.layers.is_active(1));
+
+        clock.tick_n(8);
+        state.push(clock.now(), InputEvent::Press(0xf0));
+        assert_eq!(state.current_state, auto_mouse::DRAGGING.as_dyn());
+        clock.tick_n(30);
+        assert_matches!(state.tick(clock.now()), []);
+        state.push(clock.now(), InputEvent::Depress(0xf0));
+        clock.tick_n(8);
+        state.tick(clock.now());
+        assert!(state.layers.is_active(1));
+
+        clock.tick_n(2);
+        let s = state.tick(clock.now());
+        assert_matches!(s, [KeyEvent::LayerDeactivated(1)]);
+        assert_eq!(state.current_state, auto_mouse::IDLE.as_dyn());
+        assert!(!state.layers.is_active(1));
+    }
+
+    #[test]
+    fn pointer_buttons_and_wheel() {
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(auto_mouse::IDLE.as_dyn(), clock.now());
+
+        state.push(clock.now(), InputEvent::PointerMove(1, 0));
+        clock.tick_n(8);
+        state.push(clock.now(), InputEvent::Wheel(0, -1));
+        clock.tick_n(8);
+        state.tick(clock.now());
+        assert!(state.layers.is_active(1));
+
+        state.push(clock.now(), InputEvent::PointerButton(0, true));
+        assert_eq!(state.current_state, auto_mouse::DRAGGING.as_dyn());
+        clock.tick_n(30);
+        state.push(clock.now(), InputEvent::PointerButton(0, false));
+        assert_eq!(state.current_state, auto_mouse::ACTIVE.as_dyn());
+        assert!(state.layers.is_active(1));
+    }
+
+    #[test]
+    fn drags_until_last_release() {
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(auto_mouse::IDLE.as_dyn(), clock.now());
+
+        state.push(clock.now(), InputEvent::PointerMove(1, 0));
+        state.push(clock.now(), InputEvent::Press(0xf0));
+        state.push(clock.now(), InputEvent::Press(0xf1));
+        state.push(clock.now(), InputEvent::PointerButton(0, true));
+
+        state.push(clock.now(), InputEvent::Depress(0xf0));
+        assert_eq!(state.current_state, auto_mouse::DRAGGING.as_dyn());
+        state.push(clock.now(), InputEvent::PointerButton(0, false));
+        assert_eq!(state.current_state, auto_mouse::DRAGGING.as_dyn());
+        clock.tick_n(30);
+        assert_matches!(state.tick(clock.now()), []);
+        assert!(state.layers.is_active(1));
+
+        state.push(clock.now(), InputEvent::Depress(0xf1));
+        assert_eq!(state.current_state, auto_mouse::ACTIVE.as_dyn());
+        assert!(state.layers.is_active(1));
+    }
+
+    #[test]
+    fn deactivates_on_other_key() {
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(auto_mouse::IDLE.as_dyn(), clock.now());
+
+        state.push(clock.now(), InputEvent::PointerMove(3, 0));
+        assert!(state.layers.is_active(1));
+
+        clock.tick();
+        state.push(clock.now(), InputEvent::Press(4));
+        assert_eq!(state.current_state, auto_mouse::IDLE.as_dyn());
+        assert!(!state.layers.is_active(1));
+    }
+}
@@ -0,0 +1,379 @@
+/// Generates a hold-tap key.
+///
+/// Tapping `key` within `tapping_term` sends `tap`, holding it past the term
+/// or pressing another key while it is down sends `hold` instead. Other keys
+/// are passed through as `PressCurrent`/`DepressCurrent`. Releasing a key
+/// pressed before `key` passes the release through and leaves `key`
+/// undecided, with the term starting over.
+///
+/// While [`StateFlags::GAME_MODE`](crate::StateFlags::GAME_MODE) is set the
+/// key resolves immediately as `tap` and stays pressed for as long as `key`
+/// is held.
+///
+/// Resolving the key emits a [`KeyEvent::Lighting`](crate::KeyEvent::Lighting)
+/// for `key` after the tap or hold key.
+///
+/// ```ignore
+/// hold_tap! {
+///     mod home_a {
+///         key: 0,
+///         tap: 0x04,
+///         hold: 0xe1,
+///         tapping_term: Duration::from_millis(200),
+///     }
+/// }
+/// ```
+///
+/// The term can instead be a [`TunableTerm`](crate::TunableTerm) static,
+/// given as `tunable_term: TERM`, to change it at runtime.
+macro_rules! hold_tap {
+    (
+        @machine $vis:vis $name:ident,
+        $key:expr, $tap:expr, $hold:expr,
+        $within_term:expr, $past_term:expr
+    ) => {
+        $vis mod $name {
+            use super::*;
+            use $crate::{
+                KeyEvent, Lighting, State, StateFlags, StateId, Transition, TransitionCondition,
+            };
+
+            pub static IDLE: State = State {
+                name: concat!(stringify!($name), "::IDLE"),
+                id: StateId(0),
+                transitions: &[
+                    &IDLE_GAME_PRESS,
+                    &IDLE_PRESS,
+                    &IDLE_OTHER_PRESS,
+                    &IDLE_OTHER_DEPRESS,
+                ],
+            };
+
+            static IDLE_GAME_PRESS: Transition = Transition {
+                conditions: &[
+                    TransitionCondition::StateSet(StateFlags::GAME_MODE),
+                    TransitionCondition::pressed_single($key),
+                ],
+                key_event_emissions: &[
+                    KeyEvent::Press($tap),
+                    KeyEvent::Lighting($key, Lighting::Tapped),
+                ],
+                internal_event_emissions: &[],
+                target: &TAP_HELD,
+            };
+
+            static IDLE_PRESS: Transition = Transition {
+                conditions: &[TransitionCondition::pressed_single($key)],
+                key_event_emissions: &[],
+                internal_event_emissions: &[],
+                target: &UNDECIDED,
+            };
+
+            static IDLE_OTHER_PRESS: Transition = Transition {
+                conditions: &[TransitionCondition::Pressed(0..=u8::MAX)],
+                key_event_emissions: &[KeyEvent::PressCurrent],
+                internal_event_emissions: &[],
+                target: &IDLE,
+            };
+
+            static IDLE_OTHER_DEPRESS: Transition = Transition {
+                conditions: &[TransitionCondition::Depressed(0..=u8::MAX)],
+                key_event_emissions: &[KeyEvent::DepressCurrent],
+                internal_event_emissions: &[],
+                target: &IDLE,
+            };
+
+            pub static UNDECIDED: State = State {
+                name: concat!(stringify!($name), "::UNDECIDED"),
+                id: StateId(1),
+                transitions: &[
+                    &UNDECIDED_TAP,
+                    &UNDECIDED_LATE_RELEASE,
+                    &UNDECIDED_OTHER_PRESS,
+                    &UNDECIDED_OTHER_DEPRESS,
+                    &UNDECIDED_TIMEOUT,
+                ],
+            };
+
+            static UNDECIDED_TAP: Transition = Transition {
+                conditions: &[
+                    TransitionCondition::depressed_single($key),
+                    $within_term,
+                ],
+                key_event_emissions: &[
+                    KeyEvent::Press($tap),
+                    KeyEvent::Depress($tap),
+                    KeyEvent::Lighting($key, Lighting::Tapped),
+                ],
+                internal_event_emissions: &[],
+                target: &IDLE,
+            };
+
+            // released after the term without a tick in between
+            static UNDECIDED_LATE_RELEASE: Transition = Transition {
+                conditions: &[TransitionCondition::depressed_single($key)],
+                key_event_emissions: &[
+                    KeyEvent::Press($hold),
+                    KeyEvent::Depress($hold),
+                    KeyEvent::Lighting($key, Lighting::Held),
+                ],
+                internal_event_emissions: &[],
+                target: &IDLE,
+            };
+
+            static UNDECIDED_OTHER_PRESS: Transition = Transition {
+                conditions: &[TransitionCondition::Pressed(0..=u8::MAX)],
+                key_event_emissions: &[
+                    KeyEvent::Press($hold),
+                    KeyEvent::Lighting($key, Lighting::Held),
+                    KeyEvent::PressCurrent,
+                ],
+                internal_event_emissions: &[],
+                target: &HOLD,
+            };
+
+            // a key pressed before this one, which mustn't get stuck
+            static UNDECIDED_OTHER_DEPRESS: Transition = Transition {
+                conditions: &[TransitionCondition::Depressed(0..=u8::MAX)],
+                key_event_emissions: &[KeyEvent::DepressCurrent],
+                internal_event_emissions: &[],
+                target: &UNDECIDED,
+            };
+
+            static UNDECIDED_TIMEOUT: Transition = Transition {
+                conditions: &[$past_term],
+                key_event_emissions: &[
+                    KeyEvent::Press($hold),
+                    KeyEvent::Lighting($key, Lighting::Held),
+                ],
+                internal_event_emissions: &[],
+                target: &HOLD,
+            };
+
+            pub static HOLD: State = State {
+                name: concat!(stringify!($name), "::HOLD"),
+                id: StateId(2),
+                transitions: &[
+                    &HOLD_DEPRESS,
+                    &HOLD_OTHER_PRESS,
+                    &HOLD_OTHER_DEPRESS,
+                ],
+            };
+
+            static HOLD_DEPRESS: Transition = Transition {
+                conditions: &[TransitionCondition::depressed_single($key)],
+                key_event_emissions: &[KeyEvent::Depress($hold)],
+                internal_event_emissions: &[],
+                target: &IDLE,
+            };
+
+            static HOLD_OTHER_PRESS: Transition = Transition {
+                conditions: &[TransitionCondition::Pressed(0..=u8::MAX)],
+                key_event_emissions: &[KeyEvent::PressCurrent],
+                internal_event_emissions: &[],
+                target: &HOLD,
+            };
+
+            static HOLD_OTHER_DEPRESS: Transition = Transition {
+                conditions: &[TransitionCondition::Depressed(0..=u8::MAX)],
+                key_event_emissions: &[KeyEvent::DepressCurrent],
+                internal_event_emissions: &[],
+                target: &HOLD,
+            };
+
+            pub static TAP_HELD: State = State {
+                name: concat!(stringify!($name), "::TAP_HELD"),
+                id: StateId(3),
+                transitions: &[
+                    &TAP_HELD_DEPRESS,
+                    &TAP_HELD_OTHER_PRESS,
+                    &TAP_HELD_OTHER_DEPRESS,
+                ],
+            };
+
+            static TAP_HELD_DEPRESS: Transition = Transition {
+                conditions: &[TransitionCondition::depressed_single($key)],
+                key_event_emissions: &[KeyEvent::Depress($tap)],
+                internal_event_emissions: &[],
+                target: &IDLE,
+            };
+
+            static TAP_HELD_OTHER_PRESS: Transition = Transition {
+                conditions: &[TransitionCondition::Pressed(0..=u8::MAX)],
+                key_event_emissions: &[KeyEvent::PressCurrent],
+                internal_event_emissions: &[],
+                target: &TAP_HELD,
+            };
+
+            static TAP_HELD_OTHER_DEPRESS: Transition = Transition {
+                conditions: &[TransitionCondition::Depressed(0..=u8::MAX)],
+                key_event_emissions: &[KeyEvent::DepressCurrent],
+                internal_event_emissions: &[],
+                target: &TAP_HELD,
+            };
+        }
+    };
+    (
+        $vis:vis mod $name:ident {
+            key: $key:expr,
+            tap: $tap:expr,
+            hold: $hold:expr,
+            tapping_term: $term:expr $(,)?
+        }
+    ) => {
+        $crate::behaviors::hold_tap! {
+            @machine $vis $name,
+            $key, $tap, $hold,
+            $crate::TransitionCondition::ElapsedLess($term),
+            $crate::TransitionCondition::ElapsedGreater($term)
+        }
+    };
+    (
+        $vis:vis mod $name:ident {
+            key: $key:expr,
+            tap: $tap:expr,
+            hold: $hold:expr,
+            tunable_term: $term:path $(,)?
+        }
+    ) => {
+        $crate::behaviors::hold_tap! {
+            @machine $vis $name,
+            $key, $tap, $hold,
+            $crate::TransitionCondition::ElapsedLessTunable(&$term),
+            $crate::TransitionCondition::ElapsedGreaterTunable(&$term)
+        }
+    };
+}
+
+pub(crate) use hold_tap;
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{GlobalState, InputEvent, KeyEvent, Lighting, StateFlags, TunableTerm};
+
+    hold_tap! {
+        mod home_a {
+            key: 0,
+            tap: 4,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    static TERM: TunableTerm = TunableTerm::new(Duration::from_millis(10));
+
+    hold_tap! {
+        mod home_s {
+            key: 1,
+            tap: 5,
+            hold: 0xe0,
+            tunable_term: TERM,
+        }
+    }
+
+    #[test]
+    fn tap_and_hold() {
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(home_a::IDLE.as_dyn(), clock.now());
+
+        assert_matches!(state.push(clock.now(), InputEvent::Press(0)), []);
+        clock.tick_n(5);
+        let s = state.push(clock.now(), InputEvent::Depress(0));
+        assert_matches!(
+            s,
+            [
+                KeyEvent::Press(4),
+                KeyEvent::Depress(4),
+                KeyEvent::Lighting(0, Lighting::Tapped)
+            ]
+        );
+
+        clock.tick();
+        state.push(clock.now(), InputEvent::Press(0));
+        clock.tick_n(10);
+        assert_matches!(
+            state.tick(clock.now()),
+            [KeyEvent::Press(0xe1), KeyEvent::Lighting(0, Lighting::Held)]
+        );
+        let s = state.push(clock.now(), InputEvent::Press(7));
+        assert_matches!(s, [KeyEvent::PressCurrent]);
+        let s = state.push(clock.now(), InputEvent::Depress(0));
+        assert_matches!(s, [KeyEvent::Depress(0xe1)]);
+        assert_eq!(state.current_state, home_a::IDLE.as_dyn());
+
+        state.push(clock.now(), InputEvent::Press(0));
+        clock.tick();
+        let s = state.push(clock.now(), InputEvent::Press(7));
+        assert_matches!(
+            s,
+            [
+                KeyEvent::Press(0xe1),
+                KeyEvent::Lighting(0, Lighting::Held),
+                KeyEvent::PressCurrent
+            ]
+        );
+        assert_eq!(state.current_state, home_a::HOLD.as_dyn());
+    }
+
+    #[test]
+    fn rolled_release() {
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(home_a::IDLE.as_dyn(), clock.now());
+
+        state.push(clock.now(), InputEvent::Press(7));
+        state.push(clock.now(), InputEvent::Press(0));
+        clock.tick_n(2);
+        let s = state.push(clock.now(), InputEvent::Depress(7));
+        assert_matches!(s, [KeyEvent::DepressCurrent]);
+        assert_eq!(state.current_state, home_a::UNDECIDED.as_dyn());
+        clock.tick_n(2);
+        assert_matches!(
+            state.push(clock.now(), InputEvent::Depress(0)),
+            [KeyEvent::Press(4), KeyEvent::Depress(4), _]
+        );
+    }
+
+    #[test]
+    fn tunable_term() {
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(home_s::IDLE.as_dyn(), clock.now());
+
+        state.push(clock.now(), InputEvent::Press(1));
+        clock.tick_n(10);
+        assert_matches!(state.tick(clock.now()), [KeyEvent::Press(0xe0), ..]);
+        state.push(clock.now(), InputEvent::Depress(1));
+
+        TERM.set(Duration::from_millis(20));
+        state.push(clock.now(), InputEvent::Press(1));
+        clock.tick_n(15);
+        assert_matches!(state.tick(clock.now()), []);
+        assert_matches!(
+            state.push(clock.now(), InputEvent::Depress(1)),
+            [KeyEvent::Press(5), KeyEvent::Depress(5), _]
+        );
+    }
+
+    #[test]
+    fn game_mode_taps_immediately() {
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(home_a::IDLE.as_dyn(), clock.now());
+        state.flags.insert(StateFlags::GAME_MODE);
+
+        assert_matches!(
+            state.push(clock.now(), InputEvent::Press(0)),
+            [KeyEvent::Press(4), KeyEvent::Lighting(0, Lighting::Tapped)]
+        );
+        clock.tick_n(50);
+        assert_matches!(state.tick(clock.now()), []);
+        assert_matches!(
+            state.push(clock.now(), InputEvent::Press(7)),
+            [KeyEvent::PressCurrent]
+        );
+        assert_matches!(
+            state.push(clock.now(), InputEvent::Depress(0)),
+            [KeyEvent::Depress(4)]
+        );
+    }
+}
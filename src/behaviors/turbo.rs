@@ -0,0 +1,241 @@
+/// Generates a turbo (autofire) key.
+///
+/// Pressing `key` taps `output` immediately and then again every `rate` for
+/// as long as the key is held. Each repeat is a transition from `HELD` back
+/// into itself, which restarts the elapsed time of the state.
+///
+/// With `mode: toggle` the first press and release latches the autofire on
+/// and it continues until `key` is pressed a second time.
+///
+/// ```ignore
+/// turbo_key! {
+///     mod turbo {
+///         key: 3,
+///         output: 4,
+///         rate: Duration::from_millis(50),
+///         mode: toggle,
+///     }
+/// }
+/// ```
+macro_rules! turbo_key {
+    (
+        $vis:vis mod $name:ident {
+            key: $key:expr,
+            output: $output:expr,
+            rate: $rate:expr $(,)?
+        }
+    ) => {
+        $crate::behaviors::turbo_key! {
+            $vis mod $name {
+                key: $key,
+                output: $output,
+                rate: $rate,
+                mode: hold,
+            }
+        }
+    };
+    (
+        $vis:vis mod $name:ident {
+            key: $key:expr,
+            output: $output:expr,
+            rate: $rate:expr,
+            mode: hold $(,)?
+        }
+    ) => {
+        $vis mod $name {
+            use super::*;
+            use $crate::{KeyEvent, State, StateId, Transition, TransitionCondition};
+
+            pub static IDLE: State = State {
+                name: concat!(stringify!($name), "::IDLE"),
+                id: StateId(0),
+                transitions: &[&IDLE_PRESS],
+            };
+
+            static IDLE_PRESS: Transition = Transition {
+                conditions: &[TransitionCondition::pressed_single($key)],
+                key_event_emissions: &[KeyEvent::Press($output), KeyEvent::Depress($output)],
+                internal_event_emissions: &[],
+                target: &HELD,
+            };
+
+            pub static HELD: State = State {
+                name: concat!(stringify!($name), "::HELD"),
+                id: StateId(1),
+                transitions: &[&HELD_DEPRESS, &HELD_REPEAT],
+            };
+
+            static HELD_DEPRESS: Transition = Transition {
+                conditions: &[TransitionCondition::depressed_single($key)],
+                key_event_emissions: &[],
+                internal_event_emissions: &[],
+                target: &IDLE,
+            };
+
+            static HELD_REPEAT: Transition = Transition {
+                conditions: &[TransitionCondition::ElapsedGreater($rate)],
+                key_event_emissions: &[KeyEvent::Press($output), KeyEvent::Depress($output)],
+                internal_event_emissions: &[],
+                target: &HELD,
+            };
+        }
+    };
+    (
+        $vis:vis mod $name:ident {
+            key: $key:expr,
+            output: $output:expr,
+            rate: $rate:expr,
+            mode: toggle $(,)?
+        }
+    ) => {
+        $vis mod $name {
+            use super::*;
+            use $crate::{KeyEvent, State, StateId, Transition, TransitionCondition};
+
+            pub static IDLE: State = State {
+                name: concat!(stringify!($name), "::IDLE"),
+                id: StateId(0),
+                transitions: &[&IDLE_PRESS],
+            };
+
+            static IDLE_PRESS: Transition = Transition {
+                conditions: &[TransitionCondition::pressed_single($key)],
+                key_event_emissions: &[KeyEvent::Press($output), KeyEvent::Depress($output)],
+                internal_event_emissions: &[],
+                target: &LATCH,
+            };
+
+            // autofiring, waiting for the latching press to be released
+            pub static LATCH: State = State {
+                name: concat!(stringify!($name), "::LATCH"),
+                id: StateId(1),
+                transitions: &[&LATCH_DEPRESS, &LATCH_REPEAT],
+            };
+
+            static LATCH_DEPRESS: Transition = Transition {
+                conditions: &[TransitionCondition::depressed_single($key)],
+                key_event_emissions: &[],
+                internal_event_emissions: &[],
+                target: &ON,
+            };
+
+            static LATCH_REPEAT: Transition = Transition {
+                conditions: &[TransitionCondition::ElapsedGreater($rate)],
+                key_event_emissions: &[KeyEvent::Press($output), KeyEvent::Depress($output)],
+                internal_event_emissions: &[],
+                target: &LATCH,
+            };
+
+            pub static ON: State = State {
+                name: concat!(stringify!($name), "::ON"),
+                id: StateId(2),
+                transitions: &[&ON_PRESS, &ON_REPEAT],
+            };
+
+            static ON_PRESS: Transition = Transition {
+                conditions: &[TransitionCondition::pressed_single($key)],
+                key_event_emissions: &[],
+                internal_event_emissions: &[],
+                target: &UNLATCH,
+            };
+
+            static ON_REPEAT: Transition = Transition {
+                conditions: &[TransitionCondition::ElapsedGreater($rate)],
+                key_event_emissions: &[KeyEvent::Press($output), KeyEvent::Depress($output)],
+                internal_event_emissions: &[],
+                target: &ON,
+            };
+
+            // switched off, waiting for the unlatching press to be released
+            pub static UNLATCH: State = State {
+                name: concat!(stringify!($name), "::UNLATCH"),
+                id: StateId(3),
+                transitions: &[&UNLATCH_DEPRESS],
+            };
+
+            static UNLATCH_DEPRESS: Transition = Transition {
+                conditions: &[TransitionCondition::depressed_single($key)],
+                key_event_emissions: &[],
+                internal_event_emissions: &[],
+                target: &IDLE,
+            };
+        }
+    };
+}
+
+pub(crate) use turbo_key;
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{GlobalState, InputEvent, KeyEvent};
+
+    turbo_key! {
+        mod turbo {
+            key: 3,
+            output: 4,
+            rate: Duration::from_millis(5),
+        }
+    }
+
+    turbo_key! {
+        mod turbo_toggle {
+            key: 3,
+            output: 4,
+            rate: Duration::from_millis(5),
+            mode: toggle,
+        }
+    }
+
+    #[test]
+    fn repeats_while_held() {
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(turbo::IDLE.as_dyn(), clock.now());
+
+        let s = state.push(clock.now(), InputEvent::Press(3));
+        assert_matches!(s, [KeyEvent::Press(4), KeyEvent::Depress(4)]);
+
+        for _ in 0..3 {
+            clock.tick_n(4);
+            assert_matches!(state.tick(clock.now()), []);
+
+            clock.tick();
+            let s = state.tick(clock.now());
+            assert_matches!(s, [KeyEvent::Press(4), KeyEvent::Depress(4)]);
+        }
+
+        let s = state.push(clock.now(), InputEvent::Depress(3));
+        assert_matches!(s, []);
+        assert_eq!(state.current_state, turbo::IDLE.as_dyn());
+
+        clock.tick_n(10);
+        assert_matches!(state.tick(clock.now()), []);
+    }
+
+    #[test]
+    fn toggle_latches_until_pressed_again() {
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(turbo_toggle::IDLE.as_dyn(), clock.now());
+
+        let s = state.push(clock.now(), InputEvent::Press(3));
+        assert_matches!(s, [KeyEvent::Press(4), KeyEvent::Depress(4)]);
+
+        clock.tick();
+        state.push(clock.now(), InputEvent::Depress(3));
+        assert_eq!(state.current_state, turbo_toggle::ON.as_dyn());
+
+        clock.tick_n(5);
+        let s = state.tick(clock.now());
+        assert_matches!(s, [KeyEvent::Press(4), KeyEvent::Depress(4)]);
+
+        clock.tick();
+        state.push(clock.now(), InputEvent::Press(3));
+        clock.tick();
+        state.push(clock.now(), InputEvent::Depress(3));
+        assert_eq!(state.current_state, turbo_toggle::IDLE.as_dyn());
+
+        clock.tick_n(10);
+        assert_matches!(state.tick(clock.now()), []);
+    }
+}
@@ -0,0 +1,132 @@
+//! How much memory a machine takes.
+//!
+//! [`budget`] adds up the flash taken by every state reachable from a
+//! machine's initial state, with their transitions, conditions, emissions and
+//! names, and the RAM taken by running it. As a const fn it can cap a keymap
+//! at compile time:
+//!
+//! ```ignore
+//! const _: () = assert!(budget::<MyClock>(&home_a::IDLE).flash <= 4096);
+//! ```
+//!
+//! Each state's transitions are counted once for every state that lists
+//! them, so a transition shared between states is counted more than once.
+//! Padding the linker adds between statics isn't counted, nor are buffers
+//! kept alongside the machine, such as a
+//! [`TraceBuffer`](crate::trace::TraceBuffer); add `size_of` those to `ram`.
+
+use core::mem::{size_of, size_of_val};
+
+use crate::time;
+use crate::validate::reachable;
+use crate::{GlobalState, InternalEvent, KeyEvent, State, Transition, TransitionCondition};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Budget {
+    states: usize,
+    transitions: usize,
+    conditions: usize,
+    /// Bytes of flash taken by the machine's statics.
+    flash: usize,
+    /// Bytes of RAM taken by a [`GlobalState`] running the machine.
+    ram: usize,
+}
+
+/// The memory taken by the machine starting at `initial`. Panics if it has
+/// more states than [`validate`](crate::validate) can check, or states with
+/// the same id.
+const fn budget<Clock: time::Clock>(initial: &'static State) -> Budget {
+    let (states, len) = match reachable(initial) {
+        Ok(reachable) => reachable,
+        Err(error) => panic!("{}", error.message()),
+    };
+
+    let mut budget = Budget {
+        states: len,
+        transitions: 0,
+        conditions: 0,
+        flash: 0,
+        ram: size_of::<GlobalState<Clock>>(),
+    };
+
+    let mut i = 0;
+    while i < len {
+        let Some(state) = states[i] else {
+            unreachable!()
+        };
+        budget.flash += size_of::<State>() + state.name.len() + size_of_val(state.transitions);
+
+        let mut t = 0;
+        while t < state.transitions.len() {
+            let transition = state.transitions[t];
+            budget.transitions += 1;
+            budget.conditions += transition.conditions.len();
+            budget.flash += size_of::<Transition>()
+                + size_of_val(transition.conditions)
+                + size_of_val(transition.key_event_emissions)
+                + size_of_val(transition.internal_event_emissions);
+            t += 1;
+        }
+        i += 1;
+    }
+
+    budget
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::size_of;
+
+    use super::budget;
+    use crate::behaviors::hold_tap;
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{GlobalState, KeyEvent, State, StateId, Transition, TransitionCondition};
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    static A: State = State {
+        name: "A",
+        id: StateId(0),
+        transitions: &[&A_0],
+    };
+
+    static A_0: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(0)],
+        key_event_emissions: &[KeyEvent::Press(0), KeyEvent::Depress(0)],
+        internal_event_emissions: &[],
+        target: &A,
+    };
+
+    const _: () = assert!(budget::<TickerClock>(&home_a::IDLE).flash < 4096);
+
+    #[test]
+    fn budget_of_machine() {
+        let single = budget::<TickerClock>(&A);
+        assert_eq!(
+            (single.states, single.transitions, single.conditions),
+            (1, 1, 1)
+        );
+        assert_eq!(
+            single.flash,
+            size_of::<State>()
+                + 1
+                + size_of::<&Transition>()
+                + size_of::<Transition>()
+                + size_of::<TransitionCondition>()
+                + 2 * size_of::<KeyEvent>()
+        );
+        assert_eq!(single.ram, size_of::<GlobalState<TickerClock>>());
+
+        let hold_tap = budget::<TickerClock>(&home_a::IDLE);
+        assert_eq!((hold_tap.states, hold_tap.transitions), (4, 15));
+        assert!(hold_tap.flash > single.flash);
+    }
+}
@@ -0,0 +1,223 @@
+//! Feeding the output of one machine into another.
+//!
+//! A [`Chain`] runs machines as stages, with the key events each stage
+//! emits turned back into input events for the next, so that post
+//! processing like key overrides or output filters can be small machines of
+//! their own rather than merged into every state of one big one.
+//!
+//! Each stage's output goes through its successor's translation, a plain
+//! function from a [`KeyEvent`] to the [`InputEvent`] the next stage sees.
+//! The default, [`press_release`], turns presses and releases into presses
+//! and releases of the same key code. Events a translation returns `None`
+//! for skip the rest of the chain and are emitted as they are, as is
+//! everything the last stage emits. `PressCurrent` and `DepressCurrent` are
+//! resolved against the event the stage was given before being translated.
+//!
+//! The stages don't share flags or layers, each sees only its own.
+
+use crate::time;
+use crate::{DynState, GlobalState, InputEvent, KeyEvent};
+
+/// The default translation between stages, passing presses and releases on.
+fn press_release(event: KeyEvent) -> Option<InputEvent> {
+    match event {
+        KeyEvent::Press(key) => Some(InputEvent::Press(key)),
+        KeyEvent::Depress(key) => Some(InputEvent::Depress(key)),
+        _ => None,
+    }
+}
+
+struct Chain<Clock: time::Clock, const N: usize> {
+    stages: [GlobalState<Clock>; N],
+    /// How the output of the stage before each stage is translated into its
+    /// input. The first stage's is unused.
+    translations: [fn(KeyEvent) -> Option<InputEvent>; N],
+}
+
+impl<Clock: time::Clock, const N: usize> Chain<Clock, N> {
+    /// Run `stages` in order, events pushed going to the first.
+    fn new(stages: [&'static dyn DynState; N], current_time: Clock::Instant) -> Self {
+        Self {
+            stages: stages.map(|stage| GlobalState::new(stage, current_time)),
+            translations: [press_release; N],
+        }
+    }
+
+    /// Translate the output of the stage before `stage` with `translation`.
+    fn set_translation(&mut self, stage: usize, translation: fn(KeyEvent) -> Option<InputEvent>) {
+        self.translations[stage] = translation;
+    }
+
+    fn step(
+        &mut self,
+        stage: usize,
+        current_time: Clock::Instant,
+        event: Option<InputEvent>,
+        emit: &mut impl FnMut(KeyEvent),
+    ) {
+        let len = match event {
+            Some(event) => self.stages[stage].push(current_time, event).len(),
+            None => self.stages[stage].tick(current_time).len(),
+        };
+
+        // only later stages are stepped, so what this one emitted stays put
+        for i in 0..len {
+            let emitted = self.stages[stage].emitted()[i];
+            let emitted = emitted.resolve_current(event);
+            let next = stage + 1;
+            match self.translations.get(next).and_then(|t| t(emitted)) {
+                Some(input) => self.step(next, current_time, Some(input), emit),
+                None => emit(emitted),
+            }
+        }
+    }
+
+    fn push(
+        &mut self,
+        current_time: Clock::Instant,
+        event: InputEvent,
+        mut emit: impl FnMut(KeyEvent),
+    ) {
+        if N > 0 {
+            self.step(0, current_time, Some(event), &mut emit);
+        }
+    }
+
+    /// Tick every stage in order, so that what an earlier stage emits on
+    /// this tick reaches the later ones before they're ticked.
+    fn tick(&mut self, current_time: Clock::Instant, mut emit: impl FnMut(KeyEvent)) {
+        for stage in 0..N {
+            self.step(stage, current_time, None, &mut emit);
+        }
+    }
+
+    /// The earliest [`GlobalState::next_deadline`] of any stage.
+    fn next_deadline(&self, current_time: Clock::Instant) -> Option<Clock::Instant> {
+        self.stages
+            .iter()
+            .filter_map(|stage| stage.next_deadline(current_time))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Chain;
+    use crate::behaviors::hold_tap;
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{InputEvent, KeyEvent, Lighting, State, StateId, Transition, TransitionCondition};
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    // sends 9 in place of 6, and everything else as it is
+    static OVERRIDE: State = State {
+        name: "OVERRIDE",
+        id: StateId(0),
+        transitions: &[
+            &OVERRIDE_PRESS,
+            &OVERRIDE_DEPRESS,
+            &OTHER_PRESS,
+            &OTHER_DEPRESS,
+        ],
+    };
+
+    static OVERRIDE_PRESS: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(6)],
+        key_event_emissions: &[KeyEvent::Press(9)],
+        internal_event_emissions: &[],
+        target: &OVERRIDE,
+    };
+
+    static OVERRIDE_DEPRESS: Transition = Transition {
+        conditions: &[TransitionCondition::depressed_single(6)],
+        key_event_emissions: &[KeyEvent::Depress(9)],
+        internal_event_emissions: &[],
+        target: &OVERRIDE,
+    };
+
+    static OTHER_PRESS: Transition = Transition {
+        conditions: &[TransitionCondition::Pressed(0..=u8::MAX)],
+        key_event_emissions: &[KeyEvent::PressCurrent],
+        internal_event_emissions: &[],
+        target: &OVERRIDE,
+    };
+
+    static OTHER_DEPRESS: Transition = Transition {
+        conditions: &[TransitionCondition::Depressed(0..=u8::MAX)],
+        key_event_emissions: &[KeyEvent::DepressCurrent],
+        internal_event_emissions: &[],
+        target: &OVERRIDE,
+    };
+
+    fn push(
+        chain: &mut Chain<TickerClock, 2>,
+        clock: &TickerClock,
+        event: InputEvent,
+    ) -> Vec<KeyEvent> {
+        let mut out = Vec::new();
+        chain.push(clock.now(), event, |e| out.push(e));
+        out
+    }
+
+    #[test]
+    fn chain() {
+        let mut clock = TickerClock(0);
+        let mut chain = Chain::new([home_a::IDLE.as_dyn(), OVERRIDE.as_dyn()], clock.now());
+
+        assert_eq!(push(&mut chain, &clock, InputEvent::Press(1)), []);
+        clock.tick();
+        assert_eq!(
+            push(&mut chain, &clock, InputEvent::Depress(1)),
+            [
+                KeyEvent::Press(9),
+                KeyEvent::Depress(9),
+                KeyEvent::Lighting(1, Lighting::Tapped)
+            ]
+        );
+        assert_eq!(
+            push(&mut chain, &clock, InputEvent::Press(3)),
+            [KeyEvent::Press(3)]
+        );
+
+        push(&mut chain, &clock, InputEvent::Press(1));
+        assert_eq!(
+            chain.next_deadline(clock.now()),
+            Some(TickerClock(clock.0 + 10).now())
+        );
+        clock.tick_n(10);
+        let mut out = Vec::new();
+        chain.tick(clock.now(), |e| out.push(e));
+        assert_eq!(
+            out,
+            [KeyEvent::Press(0xe1), KeyEvent::Lighting(1, Lighting::Held)]
+        );
+    }
+
+    #[test]
+    fn translation() {
+        let clock = TickerClock(0);
+        let mut chain = Chain::new([OVERRIDE.as_dyn(), OVERRIDE.as_dyn()], clock.now());
+        // the second stage sees 6 where the first emits 9, and nothing else
+        chain.set_translation(1, |event| match event {
+            KeyEvent::Press(9) => Some(InputEvent::Press(6)),
+            _ => None,
+        });
+
+        assert_eq!(
+            push(&mut chain, &clock, InputEvent::Press(6)),
+            [KeyEvent::Press(9)]
+        );
+        assert_eq!(
+            push(&mut chain, &clock, InputEvent::Depress(6)),
+            [KeyEvent::Depress(9)]
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Switch chatter detection.
+//!
+//! A worn switch can chatter, making contact more than once as it's pressed,
+//! which gets past debouncing as a release and a press again a few
+//! milliseconds apart and types the key twice. [`ChatterDetector`] goes
+//! after the [debouncer](crate::debounce) and counts, for each key, the
+//! presses that come implausibly soon after the key was released, within
+//! its threshold. The counts can be read over [raw HID](crate::raw_hid), so
+//! a user can find which switch is failing.
+//!
+//! With a [`ChatterExtension`], a key that has chattered often enough has
+//! its debounce window extended: its releases are held back for the
+//! extension's window, and a release followed by a press within it is
+//! dropped, the key staying held. The release is sent once the window is
+//! over, so only taps of that key faster than the window are lost. It goes
+//! out at the time it's sent rather than when it happened, as what follows
+//! the detector may already have been given later events.
+
+use crate::time::{self, Duration, Instant};
+use crate::{InputEvent, TimedEvent};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct ChatterExtension {
+    /// How many times a key chatters before its window is extended.
+    pub(crate) limit: u16,
+    pub(crate) window: Duration,
+}
+
+struct KeyChatter<Clock: time::Clock> {
+    count: u16,
+    /// When the key was last released.
+    released: Option<Clock::Instant>,
+    /// The key's release is being held back.
+    holding: bool,
+}
+
+impl<Clock: time::Clock> KeyChatter<Clock> {
+    const NEW: Self = Self {
+        count: 0,
+        released: None,
+        holding: false,
+    };
+
+    /// Whether the key was released less than `time` before `at`.
+    fn released_within(&self, at: Clock::Instant, time: Duration) -> bool {
+        self.released.is_some_and(|released| {
+            let elapsed = at.duration_since(&released);
+            elapsed < time
+        })
+    }
+}
+
+pub(crate) struct ChatterDetector<Clock: time::Clock, const N: usize> {
+    threshold: Duration,
+    extension: Option<ChatterExtension>,
+    keys: [KeyChatter<Clock>; N],
+}
+
+impl<Clock: time::Clock, const N: usize> ChatterDetector<Clock, N> {
+    /// Count presses less than `threshold` after a release as chatter.
+    pub(crate) const fn new(threshold: Duration, extension: Option<ChatterExtension>) -> Self {
+        Self {
+            threshold,
+            extension,
+            keys: [KeyChatter::NEW; N],
+        }
+    }
+
+    /// How many times `key` has chattered.
+    pub(crate) fn count(&self, key: u8) -> u16 {
+        self.keys.get(key as usize).map_or(0, |k| k.count)
+    }
+
+    /// The window of `key`, if it's been extended.
+    fn window(&self, key: usize) -> Option<Duration> {
+        let extension = self.extension?;
+        (self.keys[key].count >= extension.limit).then_some(extension.window)
+    }
+
+    /// Pass on a debounced event, or hold it back.
+    pub(crate) fn push(
+        &mut self,
+        debounced: TimedEvent<Clock>,
+        mut emit: impl FnMut(TimedEvent<Clock>),
+    ) {
+        let (key, pressed) = match debounced.event {
+            InputEvent::Press(key) if (key as usize) < N => (key as usize, true),
+            InputEvent::Depress(key) if (key as usize) < N => (key as usize, false),
+            _ => return emit(debounced),
+        };
+        let window = self.window(key);
+        let state = &mut self.keys[key];
+
+        if !pressed {
+            state.released = Some(debounced.time);
+            state.holding = window.is_some();
+            if !state.holding {
+                emit(debounced);
+            }
+            return;
+        }
+
+        if state.released_within(debounced.time, self.threshold) {
+            state.count = state.count.saturating_add(1);
+        }
+        if core::mem::take(&mut state.holding) {
+            if window.is_some_and(|window| state.released_within(debounced.time, window)) {
+                // it never really let go
+                return;
+            }
+            emit(TimedEvent {
+                time: debounced.time,
+                event: InputEvent::Depress(key as u8),
+            });
+        }
+        emit(debounced);
+    }
+
+    /// Send the releases held back for longer than their window by now.
+    pub(crate) fn tick(
+        &mut self,
+        current_time: Clock::Instant,
+        mut emit: impl FnMut(TimedEvent<Clock>),
+    ) {
+        for key in 0..N {
+            let window = self.window(key).unwrap_or(Duration::from_millis(0));
+            let state = &mut self.keys[key];
+            if state.holding && !state.released_within(current_time, window) {
+                state.holding = false;
+                emit(TimedEvent {
+                    time: current_time,
+                    event: InputEvent::Depress(key as u8),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChatterDetector, ChatterExtension};
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{
+        GlobalState, InputEvent, KeyEvent, State, StateId, TimedEvent, Transition,
+        TransitionCondition,
+    };
+
+    static IDLE: State = State {
+        name: "IDLE",
+        id: StateId(0),
+        transitions: &[&ARM],
+    };
+
+    static ARMED: State = State {
+        name: "ARMED",
+        id: StateId(1),
+        transitions: &[&TIMEOUT],
+    };
+
+    static ARM: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(2)],
+        key_event_emissions: &[],
+        internal_event_emissions: &[],
+        target: &ARMED,
+    };
+
+    static TIMEOUT: Transition = Transition {
+        conditions: &[TransitionCondition::ElapsedGreater(Duration::from_millis(
+            50,
+        ))],
+        key_event_emissions: &[KeyEvent::Press(9)],
+        internal_event_emissions: &[],
+        target: &IDLE,
+    };
+
+    fn push(
+        detector: &mut ChatterDetector<TickerClock, 4>,
+        clock: &TickerClock,
+        event: InputEvent,
+    ) -> Vec<InputEvent> {
+        let mut out = Vec::new();
+        let event = TimedEvent {
+            time: clock.now(),
+            event,
+        };
+        detector.push(event, |e| out.push(e.event));
+        out
+    }
+
+    #[test]
+    fn counts() {
+        let mut clock = TickerClock(0);
+        let mut detector = ChatterDetector::<_, 4>::new(Duration::from_millis(10), None);
+
+        push(&mut detector, &clock, InputEvent::Press(1));
+        clock.tick_n(50);
+        push(&mut detector, &clock, InputEvent::Depress(1));
+        clock.tick_n(3);
+        assert_eq!(
+            push(&mut detector, &clock, InputEvent::Press(1)),
+            [InputEvent::Press(1)]
+        );
+        assert_eq!(detector.count(1), 1);
+
+        // a plausible double tap
+        push(&mut detector, &clock, InputEvent::Depress(1));
+        clock.tick_n(40);
+        push(&mut detector, &clock, InputEvent::Press(1));
+        assert_eq!(detector.count(1), 1);
+        assert_eq!(detector.count(2), 0);
+        assert_eq!(detector.count(9), 0);
+    }
+
+    #[test]
+    fn extends_window() {
+        let mut clock = TickerClock(0);
+        let mut detector = ChatterDetector::<_, 4>::new(
+            Duration::from_millis(10),
+            Some(ChatterExtension {
+                limit: 1,
+                window: Duration::from_millis(30),
+            }),
+        );
+
+        push(&mut detector, &clock, InputEvent::Press(0));
+        push(&mut detector, &clock, InputEvent::Depress(0));
+        clock.tick_n(2);
+        push(&mut detector, &clock, InputEvent::Press(0));
+
+        // held back, and dropped along with the press that follows it
+        clock.tick_n(50);
+        assert_eq!(push(&mut detector, &clock, InputEvent::Depress(0)), []);
+        clock.tick_n(5);
+        assert_eq!(push(&mut detector, &clock, InputEvent::Press(0)), []);
+        assert_eq!(detector.count(0), 2);
+
+        // sent once the window's over
+        clock.tick_n(50);
+        push(&mut detector, &clock, InputEvent::Depress(0));
+        clock.tick_n(29);
+        let mut out = Vec::new();
+        detector.tick(clock.now(), |e| out.push(e));
+        assert!(out.is_empty());
+        clock.tick();
+        detector.tick(clock.now(), |e| out.push(e));
+        assert_eq!(
+            out,
+            [TimedEvent {
+                time: clock.now(),
+                event: InputEvent::Depress(0)
+            }]
+        );
+
+        // or before a press after it, if that comes first
+        push(&mut detector, &clock, InputEvent::Press(0));
+        push(&mut detector, &clock, InputEvent::Depress(0));
+        clock.tick_n(40);
+        assert_eq!(
+            push(&mut detector, &clock, InputEvent::Press(0)),
+            [InputEvent::Depress(0), InputEvent::Press(0)]
+        );
+        // other keys aren't held back
+        assert_eq!(
+            push(&mut detector, &clock, InputEvent::Depress(1)),
+            [InputEvent::Depress(1)]
+        );
+    }
+
+    #[test]
+    fn held_release_keeps_time_moving() {
+        let mut clock = TickerClock(0);
+        let mut detector = ChatterDetector::<_, 4>::new(
+            Duration::from_millis(10),
+            Some(ChatterExtension {
+                limit: 1,
+                window: Duration::from_millis(30),
+            }),
+        );
+        let mut machine = GlobalState::<TickerClock>::new(IDLE.as_dyn(), clock.now());
+        let mut emitted = Vec::new();
+        let mut run = |detector: &mut ChatterDetector<TickerClock, 4>, at: &TickerClock, event| {
+            let mut forwarded = Vec::new();
+            match event {
+                Some(event) => detector.push(
+                    TimedEvent {
+                        time: at.now(),
+                        event,
+                    },
+                    |e| forwarded.push(e),
+                ),
+                None => detector.tick(at.now(), |e| forwarded.push(e)),
+            }
+            for e in forwarded {
+                emitted.extend_from_slice(machine.push(e.time, e.event));
+            }
+        };
+
+        run(&mut detector, &clock, Some(InputEvent::Press(1)));
+        run(&mut detector, &clock, Some(InputEvent::Depress(1)));
+        clock.tick_n(2);
+        run(&mut detector, &clock, Some(InputEvent::Press(1)));
+        clock.tick_n(4);
+        // held back, while the machine goes on to wait for its timeout
+        run(&mut detector, &clock, Some(InputEvent::Depress(1)));
+        clock.tick_n(4);
+        run(&mut detector, &clock, Some(InputEvent::Press(2)));
+
+        // the release isn't from before the machine was armed, so it doesn't
+        // look like it's been waiting forever
+        clock.tick_n(30);
+        run(&mut detector, &clock, None);
+        assert_eq!(emitted, []);
+    }
+}
@@ -0,0 +1,133 @@
+//! Ready made [`embedded_time::Clock`]s.
+//!
+//! [`MonotonicClock`] wraps a free running counter that never wraps in
+//! practice, such as a 64 bit microsecond timer. [`TickClock`] counts ticks
+//! from an interrupt instead, for a SysTick or timer that fires at a fixed
+//! rate, including an embedded-hal `CountDown` restarted with the same
+//! period each time it expires.
+//!
+//! `HZ` is the rate the counter advances at in both.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embedded_time::rate::Fraction;
+use embedded_time::{clock, Instant};
+
+/// A clock reading a `u64` counter through `now`.
+pub(crate) struct MonotonicClock<F, const HZ: u32> {
+    now: F,
+}
+
+impl<F: Fn() -> u64, const HZ: u32> MonotonicClock<F, HZ> {
+    const fn new(now: F) -> Self {
+        Self { now }
+    }
+}
+
+impl<F, const HZ: u32> fmt::Debug for MonotonicClock<F, HZ> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MonotonicClock").finish_non_exhaustive()
+    }
+}
+
+impl<F: Fn() -> u64, const HZ: u32> embedded_time::Clock for MonotonicClock<F, HZ> {
+    type T = u64;
+    const SCALING_FACTOR: Fraction = Fraction::new(1, HZ);
+
+    fn try_now(&self) -> Result<Instant<Self>, clock::Error> {
+        Ok(Instant::new((self.now)()))
+    }
+}
+
+/// A clock advanced by calling [`TickClock::tick`] from an interrupt.
+///
+/// The count is 32 bits, so at 1kHz it wraps after about 49 days.
+#[derive(Debug)]
+struct TickClock<const HZ: u32> {
+    ticks: AtomicU32,
+}
+
+impl<const HZ: u32> TickClock<HZ> {
+    const fn new() -> Self {
+        Self {
+            ticks: AtomicU32::new(0),
+        }
+    }
+
+    fn tick(&self) {
+        self.advance(1);
+    }
+
+    /// For timers that only interrupt every `ticks` counts.
+    fn advance(&self, ticks: u32) {
+        self.ticks.fetch_add(ticks, Ordering::Relaxed);
+    }
+}
+
+impl<const HZ: u32> embedded_time::Clock for TickClock<HZ> {
+    type T = u32;
+    const SCALING_FACTOR: Fraction = Fraction::new(1, HZ);
+
+    fn try_now(&self) -> Result<Instant<Self>, clock::Error> {
+        Ok(Instant::new(self.ticks.load(Ordering::Relaxed)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use embedded_time::duration::Milliseconds;
+    use embedded_time::Clock;
+
+    use super::{MonotonicClock, TickClock};
+    use crate::behaviors::hold_tap;
+    use crate::time::Duration;
+    use crate::{GlobalState, InputEvent, KeyEvent};
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn monotonic() {
+        let micros = Cell::new(0_u64);
+        let clock = MonotonicClock::<_, 1_000_000>::new(|| micros.get());
+        let mut machine = GlobalState::<MonotonicClock<_, 1_000_000>>::new(
+            home_a::IDLE.as_dyn(),
+            clock.try_now().unwrap(),
+        );
+
+        machine.push(clock.try_now().unwrap(), InputEvent::Press(1));
+        micros.set(9_999);
+        assert_eq!(machine.tick(clock.try_now().unwrap()), &[]);
+        micros.set(10_001);
+        assert_eq!(
+            machine.tick(clock.try_now().unwrap())[0],
+            KeyEvent::Press(0xe1)
+        );
+    }
+
+    #[test]
+    fn ticks() {
+        static CLOCK: TickClock<1_000> = TickClock::new();
+
+        let start = CLOCK.try_now().unwrap();
+        CLOCK.tick();
+        CLOCK.advance(9);
+        let elapsed: Milliseconds<u32> = CLOCK
+            .try_now()
+            .unwrap()
+            .checked_duration_since(&start)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(elapsed, Milliseconds(10_u32));
+    }
+}
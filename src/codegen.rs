@@ -0,0 +1,650 @@
+//! Generating machine statics from a description, for build scripts.
+//!
+//! A [`MachineDescription`] is a machine as plain data, with states referred
+//! to by name, so that it can be deserialized from a data file. [`generate`]
+//! turns it into Rust source declaring the [`State`](crate::State) and
+//! [`Transition`](crate::Transition) statics of the machine, in a module of
+//! its own, so that a build script can write the machine out for the
+//! firmware to `include!`:
+//!
+//! ```ignore
+//! let source = codegen::generate(&description, "keyboard_fsm")?;
+//! std::fs::write(out_dir.join("home_a.rs"), source)?;
+//! ```
+//!
+//! Reading the data file is left to the build script. State names become
+//! upper case identifiers, the module name is used as is, as a raw
+//! identifier if it's a keyword, and tunable terms are paths from inside
+//! the generated module.
+//!
+//! [`MachineDescription::check`] turns down descriptions that can't make a
+//! working machine, naming what's wrong, before any source is generated.
+//! The generated module also checks the machine with
+//! [`validate`](crate::validate), so a machine that gets stuck fails the
+//! build of the firmware. States are numbered in the order they're
+//! described, which is what keeps their ids stable as long as states are
+//! only ever appended.
+
+use std::fmt::Write;
+
+use crate::time::Duration;
+use crate::validate::{MAX_EMISSIONS, MAX_STATES};
+use crate::{
+    InternalEvent, KeyEvent, Lighting, StateFlags, TransitionCondition, Wireless, MAX_LAYERS,
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct MachineDescription {
+    /// The name of the generated module.
+    pub(crate) name: String,
+    /// The first state is the initial one.
+    pub(crate) states: Vec<StateDescription>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct StateDescription {
+    pub(crate) name: String,
+    pub(crate) transitions: Vec<TransitionDescription>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TransitionDescription {
+    pub(crate) conditions: Vec<ConditionDescription>,
+    pub(crate) key_event_emissions: Vec<KeyEvent>,
+    pub(crate) internal_event_emissions: Vec<InternalEvent>,
+    /// The name of the state to go to.
+    pub(crate) target: String,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum ConditionDescription {
+    /// Any condition but the tunable ones.
+    Condition(TransitionCondition),
+    /// [`TransitionCondition::ElapsedLessTunable`] of the named static.
+    ElapsedLessTunable(String),
+    /// [`TransitionCondition::ElapsedGreaterTunable`] of the named static.
+    ElapsedGreaterTunable(String),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub(crate) enum CodegenError {
+    /// The machine has no states.
+    Empty,
+    /// The machine has more states than [`MAX_STATES`].
+    TooManyStates,
+    /// Two states have the same name.
+    DuplicateState(String),
+    /// The named states, or a state and a transition given as the state's
+    /// name and its index, are different but their statics would have the
+    /// same identifier.
+    DuplicateIdent(String, String),
+    /// The module, state or tunable term name can't be made into an
+    /// identifier.
+    InvalidName(String),
+    /// A transition goes to a state that isn't described.
+    UnknownTarget(String),
+    /// A transition of the named state emits more than [`MAX_EMISSIONS`]
+    /// key or internal events.
+    TooManyEmissions(String),
+    /// A transition of the named state uses a layer past [`MAX_LAYERS`].
+    InvalidLayer(String),
+    /// A tunable condition was given as [`ConditionDescription::Condition`],
+    /// which can't name the term's static.
+    TunableCondition,
+    /// A transition has a [`TransitionCondition::EventMatches`], whose
+    /// predicate has no source to generate.
+    EventPredicate,
+}
+
+impl MachineDescription {
+    /// The first problem with the description, if it has one.
+    pub(crate) fn check(&self) -> Result<(), CodegenError> {
+        if self.states.is_empty() {
+            return Err(CodegenError::Empty);
+        }
+        if self.states.len() > MAX_STATES {
+            return Err(CodegenError::TooManyStates);
+        }
+        if module_ident(&self.name).is_none() {
+            return Err(CodegenError::InvalidName(self.name.clone()));
+        }
+
+        // every static, by identifier and what it's for
+        let mut statics: Vec<(String, String)> = Vec::new();
+        for (i, state) in self.states.iter().enumerate() {
+            if state.name.is_empty() {
+                return Err(CodegenError::InvalidName(state.name.clone()));
+            }
+            if self.states[..i].iter().any(|s| s.name == state.name) {
+                return Err(CodegenError::DuplicateState(state.name.clone()));
+            }
+            let state_ident = ident(&state.name);
+            let transitions = (0..state.transitions.len())
+                .map(|t| (format!("{state_ident}_{t}"), format!("{}[{t}]", state.name)));
+            for (ident, name) in [(state_ident.clone(), state.name.clone())]
+                .into_iter()
+                .chain(transitions)
+            {
+                if let Some((_, earlier)) = statics.iter().find(|(i, _)| *i == ident) {
+                    return Err(CodegenError::DuplicateIdent(earlier.clone(), name));
+                }
+                statics.push((ident, name));
+            }
+        }
+
+        for state in &self.states {
+            for transition in &state.transitions {
+                if !self.states.iter().any(|s| s.name == transition.target) {
+                    return Err(CodegenError::UnknownTarget(transition.target.clone()));
+                }
+                if transition.key_event_emissions.len() > MAX_EMISSIONS
+                    || transition.internal_event_emissions.len() > MAX_EMISSIONS
+                {
+                    return Err(CodegenError::TooManyEmissions(state.name.clone()));
+                }
+                let conditions = transition.conditions.iter().filter_map(|c| match c {
+                    ConditionDescription::Condition(condition) => condition.layer(),
+                    _ => None,
+                });
+                let internal = transition
+                    .internal_event_emissions
+                    .iter()
+                    .map(|e| e.layer());
+                if conditions
+                    .chain(internal.flatten())
+                    .any(|layer| layer as usize >= MAX_LAYERS)
+                {
+                    return Err(CodegenError::InvalidLayer(state.name.clone()));
+                }
+                let tunable = transition.conditions.iter().any(|condition| {
+                    matches!(
+                        condition,
+                        ConditionDescription::Condition(
+                            TransitionCondition::ElapsedLessTunable(_)
+                                | TransitionCondition::ElapsedGreaterTunable(_)
+                        )
+                    )
+                });
+                if tunable {
+                    return Err(CodegenError::TunableCondition);
+                }
+                let predicate = transition.conditions.iter().any(|condition| {
+                    matches!(
+                        condition,
+                        ConditionDescription::Condition(TransitionCondition::EventMatches(_))
+                    )
+                });
+                if predicate {
+                    return Err(CodegenError::EventPredicate);
+                }
+                for condition in &transition.conditions {
+                    if let ConditionDescription::ElapsedLessTunable(term)
+                    | ConditionDescription::ElapsedGreaterTunable(term) = condition
+                    {
+                        if !is_path(term) {
+                            return Err(CodegenError::InvalidName(term.clone()));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn duration(duration: Duration) -> String {
+    let micros = duration.as_micros();
+    if micros.is_multiple_of(1_000) {
+        format!("Duration::from_millis({})", micros / 1_000)
+    } else {
+        format!("Duration::from_micros({micros})")
+    }
+}
+
+fn flags(flags: StateFlags) -> String {
+    format!("StateFlags::from_bits_truncate({:#010b})", flags.bits())
+}
+
+fn condition(condition: &ConditionDescription) -> Result<String, CodegenError> {
+    use TransitionCondition as C;
+
+    let condition = match condition {
+        ConditionDescription::Condition(condition) => condition,
+        ConditionDescription::ElapsedLessTunable(term) => {
+            return Ok(format!("ElapsedLessTunable(&{term})"))
+        }
+        ConditionDescription::ElapsedGreaterTunable(term) => {
+            return Ok(format!("ElapsedGreaterTunable(&{term})"))
+        }
+    };
+
+    Ok(match condition {
+        C::StateSet(x) => format!("StateSet({})", flags(*x)),
+        C::StateNotSet(x) => format!("StateNotSet({})", flags(*x)),
+        C::Pressed(x) => format!("Pressed({}..={})", x.start(), x.end()),
+        C::Depressed(x) => format!("Depressed({}..={})", x.start(), x.end()),
+        C::PointerMoved => "PointerMoved".into(),
+        C::PointerButtonPressed(x) => format!("PointerButtonPressed({}..={})", x.start(), x.end()),
+        C::PointerButtonReleased(x) => {
+            format!("PointerButtonReleased({}..={})", x.start(), x.end())
+        }
+        C::AllReleased(x) => format!("AllReleased({}..={})", x.start(), x.end()),
+        C::AllButtonsReleased(x) => format!("AllButtonsReleased({}..={})", x.start(), x.end()),
+        C::WheelScrolled => "WheelScrolled".into(),
+        C::TravelAbove(key, x) => format!("TravelAbove({key}, {x})"),
+        C::TravelBelow(key, x) => format!("TravelBelow({key}, {x})"),
+        C::Rotated(encoder, x) => format!("Rotated({encoder}, {}..={})", x.start(), x.end()),
+        C::LayerActive(x) => format!("LayerActive({x})"),
+        C::LayerNotActive(x) => format!("LayerNotActive({x})"),
+        C::ElapsedLess(x) => format!("ElapsedLess({})", duration(*x)),
+        C::ElapsedGreater(x) => format!("ElapsedGreater({})", duration(*x)),
+        C::ElapsedLessTunable(_) | C::ElapsedGreaterTunable(_) => {
+            return Err(CodegenError::TunableCondition)
+        }
+        C::ElapsedLessMicros(x) => format!("ElapsedLessMicros({})", duration(*x)),
+        C::ElapsedGreaterMicros(x) => format!("ElapsedGreaterMicros({})", duration(*x)),
+        C::ApplicationIs(x) => format!("ApplicationIs({x})"),
+        C::WindowTitleIs(x) => format!("WindowTitleIs({x})"),
+        C::IdleGreater(x) => format!("IdleGreater({})", duration(*x)),
+        C::FlagSetSinceEntry(x) => format!("FlagSetSinceEntry({})", flags(*x)),
+        C::FlagJustSet(x) => format!("FlagJustSet({})", flags(*x)),
+        C::BatteryBelow(x) => format!("BatteryBelow({x})"),
+        C::ExternalPowered => "ExternalPowered".into(),
+        C::OnBattery => "OnBattery".into(),
+        C::SignalOn(x) => format!("SignalOn({x})"),
+        C::SignalOff(x) => format!("SignalOff({x})"),
+        C::SignalAbove(channel, x) => format!("SignalAbove({channel}, {x})"),
+        C::SignalBelow(channel, x) => format!("SignalBelow({channel}, {x})"),
+        C::EventMatches(_) => return Err(CodegenError::EventPredicate),
+    })
+}
+
+fn key_event(event: &KeyEvent) -> String {
+    match event {
+        KeyEvent::Lighting(key, lighting) => format!("Lighting({key}, Lighting::{lighting:?})"),
+        KeyEvent::Indicator(indicator, on) => format!("Indicator(Indicator::{indicator:?}, {on})"),
+        KeyEvent::Wireless(Wireless::SetTransport(transport)) => {
+            format!("Wireless(Wireless::SetTransport(Transport::{transport:?}))")
+        }
+        KeyEvent::Wireless(wireless) => format!("Wireless(Wireless::{wireless:?})"),
+        KeyEvent::Route(route) => format!("Route(Route::{route:?})"),
+        // the other variants only hold primitives, which debug print as
+        // their literals
+        event => format!("{event:?}"),
+    }
+}
+
+fn internal_event(event: &InternalEvent) -> String {
+    match event {
+        InternalEvent::SetGlobalState(x) => format!("SetGlobalState({})", flags(*x)),
+        InternalEvent::UnsetGlobalState(x) => format!("UnsetGlobalState({})", flags(*x)),
+        InternalEvent::SetRoute(route) => format!("SetRoute(Route::{route:?})"),
+        event => format!("{event:?}"),
+    }
+}
+
+/// Strict and reserved keywords, which can only be identifiers as raw ones.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+    "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+/// The keywords that can't be raw identifiers either.
+const PATH_KEYWORDS: &[&str] = &["crate", "self", "super", "Self"];
+
+/// Whether `name` is an identifier, or a keyword spelt like one.
+fn is_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    name != "_" && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The identifier of the generated module, or `None` if `name` can't be one.
+fn module_ident(name: &str) -> Option<String> {
+    if !is_ident(name) || PATH_KEYWORDS.contains(&name) {
+        return None;
+    }
+    if KEYWORDS.contains(&name) {
+        return Some(format!("r#{name}"));
+    }
+    Some(name.into())
+}
+
+/// Whether `path` names a static, such as a tunable term's. Only the
+/// leading segments can be `crate`, `self` or `super`.
+fn is_path(path: &str) -> bool {
+    let mut segments = path.split("::").peekable();
+    if let Some(&"crate" | &"self") = segments.peek() {
+        segments.next();
+    }
+    while let Some(&"super") = segments.peek() {
+        segments.next();
+    }
+    segments.peek().is_some()
+        && segments.all(|segment| {
+            is_ident(segment) && !KEYWORDS.contains(&segment) && !PATH_KEYWORDS.contains(&segment)
+        })
+}
+
+/// The identifier of a state's static. Upper case identifiers are never
+/// keywords, and ones that would start with a digit or be a lone `_` are
+/// prefixed with an underscore.
+fn ident(state: &str) -> String {
+    let ident: String = state
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if ident.starts_with(|c: char| c.is_ascii_digit()) || ident == "_" {
+        format!("_{ident}")
+    } else {
+        ident
+    }
+}
+
+/// Rust source declaring the statics of `machine` in a public module, naming
+/// this crate as `krate`.
+fn generate(machine: &MachineDescription, krate: &str) -> Result<String, CodegenError> {
+    machine.check()?;
+    let initial = &machine.states[0];
+
+    let mut out = String::new();
+    let name = &machine.name;
+    let module = module_ident(name).unwrap();
+    writeln!(out, "pub mod {module} {{").unwrap();
+    writeln!(out, "    use super::*;").unwrap();
+    writeln!(out, "    use {krate}::time::Duration;").unwrap();
+    writeln!(
+        out,
+        "    use {krate}::{{Indicator, InternalEvent, KeyEvent, Lighting, Route, State, StateFlags, StateId, Transition, TransitionCondition, Transport, Wireless}};"
+    )
+    .unwrap();
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "    const _: () = {krate}::validate::validate(&{});",
+        ident(&initial.name)
+    )
+    .unwrap();
+
+    for (id, state) in machine.states.iter().enumerate() {
+        let state_ident = ident(&state.name);
+        let transitions = (0..state.transitions.len())
+            .map(|t| format!("&{state_ident}_{t}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(out).unwrap();
+        writeln!(out, "    pub static {state_ident}: State = State {{").unwrap();
+        writeln!(
+            out,
+            "        name: \"{name}::{}\",",
+            state.name.escape_default()
+        )
+        .unwrap();
+        writeln!(out, "        id: StateId({id}),").unwrap();
+        writeln!(out, "        transitions: &[{transitions}],").unwrap();
+        writeln!(out, "    }};").unwrap();
+
+        for (t, transition) in state.transitions.iter().enumerate() {
+            let conditions = transition
+                .conditions
+                .iter()
+                .map(|c| Ok(format!("TransitionCondition::{}", condition(c)?)))
+                .collect::<Result<Vec<_>, _>>()?
+                .join(", ");
+            let key_events = transition
+                .key_event_emissions
+                .iter()
+                .map(|e| format!("KeyEvent::{}", key_event(e)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let internal_events = transition
+                .internal_event_emissions
+                .iter()
+                .map(|e| format!("InternalEvent::{}", internal_event(e)))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            writeln!(out).unwrap();
+            writeln!(
+                out,
+                "    static {state_ident}_{t}: Transition = Transition {{"
+            )
+            .unwrap();
+            writeln!(out, "        conditions: &[{conditions}],").unwrap();
+            writeln!(out, "        key_event_emissions: &[{key_events}],").unwrap();
+            writeln!(
+                out,
+                "        internal_event_emissions: &[{internal_events}],"
+            )
+            .unwrap();
+            writeln!(out, "        target: &{},", ident(&transition.target)).unwrap();
+            writeln!(out, "    }};").unwrap();
+        }
+    }
+    writeln!(out, "}}").unwrap();
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        generate, ident, CodegenError, ConditionDescription, MachineDescription, StateDescription,
+        TransitionDescription,
+    };
+    use crate::time::Duration;
+    use crate::{InternalEvent, KeyEvent, Lighting, StateFlags, TransitionCondition, TunableTerm};
+
+    fn transition(
+        conditions: Vec<ConditionDescription>,
+        key_event_emissions: Vec<KeyEvent>,
+        target: &str,
+    ) -> TransitionDescription {
+        TransitionDescription {
+            conditions,
+            key_event_emissions,
+            internal_event_emissions: vec![],
+            target: target.into(),
+        }
+    }
+
+    fn machine() -> MachineDescription {
+        MachineDescription {
+            name: "home_a".into(),
+            states: vec![
+                StateDescription {
+                    name: "idle".into(),
+                    transitions: vec![transition(
+                        vec![ConditionDescription::Condition(
+                            TransitionCondition::pressed_single(4),
+                        )],
+                        vec![],
+                        "held",
+                    )],
+                },
+                StateDescription {
+                    name: "held".into(),
+                    transitions: vec![
+                        transition(
+                            vec![
+                                ConditionDescription::Condition(
+                                    TransitionCondition::depressed_single(4),
+                                ),
+                                ConditionDescription::ElapsedLessTunable("TERM".into()),
+                            ],
+                            vec![KeyEvent::Press(6), KeyEvent::Lighting(4, Lighting::Tapped)],
+                            "idle",
+                        ),
+                        TransitionDescription {
+                            internal_event_emissions: vec![InternalEvent::SetGlobalState(
+                                StateFlags::SHFT,
+                            )],
+                            ..transition(
+                                vec![ConditionDescription::Condition(
+                                    TransitionCondition::ElapsedGreater(Duration::from_millis(200)),
+                                )],
+                                vec![],
+                                "idle",
+                            )
+                        },
+                    ],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn generates_statics() {
+        let source = generate(&machine(), "crate").unwrap();
+
+        assert!(source.starts_with("pub mod home_a {\n"));
+        assert!(source.contains("    const _: () = crate::validate::validate(&IDLE);\n"));
+        assert!(source.contains(
+            "    pub static HELD: State = State {\n        \
+             name: \"home_a::held\",\n        \
+             id: StateId(1),\n        \
+             transitions: &[&HELD_0, &HELD_1],\n    };\n"
+        ));
+        assert!(source.contains(
+            "        conditions: &[TransitionCondition::Depressed(4..=4), \
+             TransitionCondition::ElapsedLessTunable(&TERM)],\n"
+        ));
+        assert!(source.contains(
+            "        key_event_emissions: &[KeyEvent::Press(6), \
+             KeyEvent::Lighting(4, Lighting::Tapped)],\n"
+        ));
+        assert!(source.contains(
+            "        internal_event_emissions: \
+             &[InternalEvent::SetGlobalState(StateFlags::from_bits_truncate(0b00000010))],\n"
+        ));
+        assert!(source.contains(
+            "        conditions: &[TransitionCondition::ElapsedGreater(Duration::from_millis(200))],\n"
+        ));
+        assert!(source.trim_end().ends_with('}'));
+    }
+
+    #[test]
+    fn rejects_broken_descriptions() {
+        static TERM: TunableTerm = TunableTerm::new(Duration::from_millis(200));
+
+        let mut broken = machine();
+        broken.states[0].transitions[0].target = "gone".into();
+        assert_eq!(
+            generate(&broken, "crate"),
+            Err(CodegenError::UnknownTarget("gone".into()))
+        );
+
+        let mut broken = machine();
+        broken.states[1].name = "idle".into();
+        assert_eq!(
+            generate(&broken, "crate"),
+            Err(CodegenError::DuplicateState("idle".into()))
+        );
+
+        let mut broken = machine();
+        broken.states[1].name = "IDLE".into();
+        broken.states[0].transitions[0].target = "IDLE".into();
+        assert_eq!(
+            generate(&broken, "crate"),
+            Err(CodegenError::DuplicateIdent("idle".into(), "IDLE".into()))
+        );
+
+        let mut broken = machine();
+        broken.states[1].transitions[0].key_event_emissions = vec![KeyEvent::Press(6); 17];
+        assert_eq!(
+            broken.check(),
+            Err(CodegenError::TooManyEmissions("held".into()))
+        );
+
+        let mut broken = machine();
+        broken.states[1].transitions[0].internal_event_emissions =
+            vec![InternalEvent::DeactivateLayer(40)];
+        assert_eq!(
+            broken.check(),
+            Err(CodegenError::InvalidLayer("held".into()))
+        );
+
+        let mut broken = machine();
+        broken.states[1].transitions[0].conditions = vec![ConditionDescription::Condition(
+            TransitionCondition::ElapsedLessTunable(&TERM),
+        )];
+        assert_eq!(broken.check(), Err(CodegenError::TunableCondition));
+
+        let mut broken = machine();
+        broken.states[1].transitions[0].conditions = vec![ConditionDescription::Condition(
+            TransitionCondition::EventMatches(|_| true),
+        )];
+        assert_eq!(broken.check(), Err(CodegenError::EventPredicate));
+        assert_eq!(
+            generate(&broken, "crate"),
+            Err(CodegenError::EventPredicate)
+        );
+
+        let mut broken = machine();
+        broken.states.clear();
+        assert_eq!(generate(&broken, "crate"), Err(CodegenError::Empty));
+
+        let mut broken = machine();
+        broken.states[1].name = "held 0".into();
+        broken.states[0].transitions[0].target = "held 0".into();
+        broken.states.push(StateDescription {
+            name: "held".into(),
+            transitions: vec![transition(vec![], vec![], "idle")],
+        });
+        assert_eq!(
+            broken.check(),
+            Err(CodegenError::DuplicateIdent(
+                "held 0".into(),
+                "held[0]".into()
+            ))
+        );
+
+        for name in ["", "1st", "_", "a-b", "self", "crate"] {
+            let mut broken = machine();
+            broken.name = name.into();
+            assert_eq!(broken.check(), Err(CodegenError::InvalidName(name.into())));
+        }
+
+        let mut broken = machine();
+        broken.states[0].name = "".into();
+        assert_eq!(broken.check(), Err(CodegenError::InvalidName("".into())));
+
+        for term in ["", "fn", "a::", "TERM::super", "a b", "self"] {
+            let mut broken = machine();
+            broken.states[1].transitions[0].conditions =
+                vec![ConditionDescription::ElapsedGreaterTunable(term.into())];
+            assert_eq!(broken.check(), Err(CodegenError::InvalidName(term.into())));
+        }
+    }
+
+    #[test]
+    fn escapes_identifiers() {
+        let mut escaped = machine();
+        escaped.name = "match".into();
+        escaped.states[1].name = "1st".into();
+        escaped.states[0].transitions[0].target = "1st".into();
+        escaped.states[1].transitions[0].conditions =
+            vec![ConditionDescription::ElapsedLessTunable(
+                "super::super::tuning::TERM".into(),
+            )];
+        let source = generate(&escaped, "crate").unwrap();
+        assert!(source.starts_with("pub mod r#match {"));
+        assert!(source.contains("pub static _1ST: State"));
+        assert!(source.contains("static _1ST_0: Transition"));
+        assert!(source.contains("ElapsedLessTunable(&super::super::tuning::TERM)"));
+        assert_eq!(ident("_"), "__");
+    }
+}
@@ -0,0 +1,153 @@
+//! Input events of the keymap's own.
+//!
+//! Besides the built-in events, machines can be driven by events a keymap
+//! defines itself, such as messages from host software, a sensor reading or
+//! a gesture recognised elsewhere. A type implementing [`CustomEvent`] picks
+//! a kind and converts to and from a 16-bit payload, and is pushed as
+//! [`InputEvent::Custom`]. Conditions on it are written as predicates with
+//! [`TransitionCondition::EventMatches`], which can look into the payload of
+//! any event, built-in or not:
+//!
+//! ```ignore
+//! struct Volume(u8);
+//!
+//! impl CustomEvent for Volume {
+//!     const KIND: u8 = 0;
+//!
+//!     fn to_payload(&self) -> u16 {
+//!         self.0.into()
+//!     }
+//!
+//!     fn from_payload(payload: u16) -> Option<Self> {
+//!         u8::try_from(payload).ok().map(Volume)
+//!     }
+//! }
+//!
+//! static LOUD: Transition = Transition {
+//!     conditions: &[TransitionCondition::EventMatches(|event| {
+//!         matches!(Volume::from_event(event), Some(Volume(80..)))
+//!     })],
+//!     ...
+//! };
+//! ```
+//!
+//! Kinds below 16 can be sent over links with [`InputEvent::to_bytes`].
+//!
+//! [`TransitionCondition::EventMatches`]: crate::TransitionCondition::EventMatches
+
+use crate::InputEvent;
+
+pub(crate) trait CustomEvent: Sized {
+    /// Which of the keymap's event types this is, different for each.
+    const KIND: u8;
+
+    fn to_payload(&self) -> u16;
+
+    /// The event with this payload, if it's one that's valid.
+    fn from_payload(payload: u16) -> Option<Self>;
+
+    fn to_event(&self) -> InputEvent {
+        InputEvent::Custom(Self::KIND, self.to_payload())
+    }
+
+    /// The event this is, if it's one of this kind.
+    fn from_event(event: InputEvent) -> Option<Self> {
+        match event {
+            InputEvent::Custom(kind, payload) if kind == Self::KIND => Self::from_payload(payload),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CustomEvent;
+    use crate::tests::TickerClock;
+    use crate::{
+        GlobalState, InputEvent, KeyEvent, State, StateId, Transition, TransitionCondition,
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct Volume(u8);
+
+    impl CustomEvent for Volume {
+        const KIND: u8 = 3;
+
+        fn to_payload(&self) -> u16 {
+            self.0.into()
+        }
+
+        fn from_payload(payload: u16) -> Option<Self> {
+            u8::try_from(payload).ok().map(Volume)
+        }
+    }
+
+    static QUIET: State = State {
+        name: "QUIET",
+        id: StateId(0),
+        transitions: &[&GET_LOUD, &FAST_TURN],
+    };
+
+    static LOUD: State = State {
+        name: "LOUD",
+        id: StateId(1),
+        transitions: &[&GET_QUIET],
+    };
+
+    static GET_LOUD: Transition = Transition {
+        conditions: &[TransitionCondition::EventMatches(|event| {
+            matches!(Volume::from_event(event), Some(Volume(80..)))
+        })],
+        key_event_emissions: &[KeyEvent::Press(1)],
+        internal_event_emissions: &[],
+        target: &LOUD,
+    };
+
+    static GET_QUIET: Transition = Transition {
+        conditions: &[TransitionCondition::EventMatches(|event| {
+            matches!(Volume::from_event(event), Some(Volume(..80)))
+        })],
+        key_event_emissions: &[KeyEvent::Depress(1)],
+        internal_event_emissions: &[],
+        target: &QUIET,
+    };
+
+    // a predicate on a built-in event's payload
+    static FAST_TURN: Transition = Transition {
+        conditions: &[TransitionCondition::EventMatches(
+            |event| matches!(event, InputEvent::Rotate(0, delta) if delta.abs() >= 3),
+        )],
+        key_event_emissions: &[KeyEvent::Press(2)],
+        internal_event_emissions: &[],
+        target: &QUIET,
+    };
+
+    #[test]
+    fn custom_events() {
+        let clock = TickerClock(0);
+        let mut machine = GlobalState::<TickerClock>::new(QUIET.as_dyn(), clock.now());
+
+        assert_eq!(machine.push(clock.now(), Volume(50).to_event()), []);
+        assert_eq!(machine.push(clock.now(), InputEvent::Custom(4, 90)), []);
+        assert_eq!(machine.push(clock.now(), InputEvent::Custom(3, 300)), []);
+        assert_eq!(machine.push(clock.now(), InputEvent::Rotate(0, 2)), []);
+        assert_eq!(
+            machine.push(clock.now(), InputEvent::Rotate(0, -4)),
+            [KeyEvent::Press(2)]
+        );
+        assert_eq!(
+            machine.push(clock.now(), Volume(90).to_event()),
+            [KeyEvent::Press(1)]
+        );
+        assert_eq!(
+            machine.push(clock.now(), Volume(10).to_event()),
+            [KeyEvent::Depress(1)]
+        );
+        assert_eq!(machine.tick(clock.now()), []);
+
+        let event = Volume(200).to_event();
+        assert_eq!(InputEvent::from_bytes(event.to_bytes()), Some(event));
+        assert_eq!(Volume::from_event(event), Some(Volume(200)));
+        assert_eq!(InputEvent::Custom(16, 0).to_bytes()[0], 0xff);
+    }
+}
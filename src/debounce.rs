@@ -0,0 +1,249 @@
+//! Debouncing between raw matrix edges and the machine.
+//!
+//! Two algorithms are provided, both tracking each key separately:
+//!
+//! - [`EagerDebouncer`] reports an edge as soon as it is seen and then
+//!   ignores the key until it has settled.
+//! - [`DeferDebouncer`] only reports an edge once the key has stayed in its
+//!   new state for the debounce time.
+//!
+//! Either can use separate timings for presses and releases. Deferred events
+//! keep the time of the raw edge rather than the time they were reported, so
+//! debouncing doesn't eat into tapping terms.
+//!
+//! Other algorithms can be plugged in by implementing [`Debouncer`].
+
+use crate::time::{self, Duration, Instant};
+use crate::{InputEvent, TimedEvent};
+
+trait Debouncer<Clock: time::Clock> {
+    /// Feed a raw edge, returning the debounced event if it can be reported
+    /// straight away.
+    fn push(&mut self, raw: TimedEvent<Clock>) -> Option<TimedEvent<Clock>>;
+
+    /// Returns the next event that became reportable by now.
+    fn tick(&mut self, current_time: Clock::Instant) -> Option<TimedEvent<Clock>>;
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct DebounceTimings {
+    press: Duration,
+    release: Duration,
+}
+
+impl DebounceTimings {
+    const fn symmetric(time: Duration) -> Self {
+        Self {
+            press: time,
+            release: time,
+        }
+    }
+
+    fn for_state(&self, pressed: bool) -> Duration {
+        if pressed {
+            self.press
+        } else {
+            self.release
+        }
+    }
+}
+
+struct KeyDebounce<Clock: time::Clock> {
+    reported: bool,
+    raw: bool,
+    /// When the current debounce period started.
+    since: Option<Clock::Instant>,
+}
+
+impl<Clock: time::Clock> KeyDebounce<Clock> {
+    const NEW: Self = Self {
+        reported: false,
+        raw: false,
+        since: None,
+    };
+
+    fn settled(&self, current_time: Clock::Instant, wait: Duration) -> bool {
+        self.since.is_none_or(|since| {
+            let elapsed = current_time.duration_since(&since);
+            elapsed >= wait
+        })
+    }
+}
+
+fn edge(raw: &TimedEvent<impl time::Clock>) -> Option<(u8, bool)> {
+    match raw.event {
+        InputEvent::Press(key) => Some((key, true)),
+        InputEvent::Depress(key) => Some((key, false)),
+        _ => None,
+    }
+}
+
+fn event<Clock: time::Clock>(time: Clock::Instant, key: u8, pressed: bool) -> TimedEvent<Clock> {
+    TimedEvent {
+        time,
+        event: if pressed {
+            InputEvent::Press(key)
+        } else {
+            InputEvent::Depress(key)
+        },
+    }
+}
+
+struct EagerDebouncer<Clock: time::Clock, const N: usize> {
+    timings: DebounceTimings,
+    keys: [KeyDebounce<Clock>; N],
+}
+
+impl<Clock: time::Clock, const N: usize> EagerDebouncer<Clock, N> {
+    const fn new(timings: DebounceTimings) -> Self {
+        Self {
+            timings,
+            keys: [KeyDebounce::NEW; N],
+        }
+    }
+}
+
+impl<Clock: time::Clock, const N: usize> Debouncer<Clock> for EagerDebouncer<Clock, N> {
+    fn push(&mut self, raw: TimedEvent<Clock>) -> Option<TimedEvent<Clock>> {
+        let Some((key, pressed)) = edge(&raw) else {
+            return Some(raw);
+        };
+        let state = self.keys.get_mut(key as usize)?;
+        state.raw = pressed;
+
+        let locked = !state.settled(raw.time, self.timings.for_state(state.reported));
+        if locked || pressed == state.reported {
+            return None;
+        }
+
+        state.reported = pressed;
+        state.since = Some(raw.time);
+        Some(raw)
+    }
+
+    fn tick(&mut self, current_time: Clock::Instant) -> Option<TimedEvent<Clock>> {
+        // report keys that ended up in a different state while locked out
+        let (key, state) = self.keys.iter_mut().enumerate().find(|(_, k)| {
+            k.raw != k.reported && k.settled(current_time, self.timings.for_state(k.reported))
+        })?;
+
+        state.reported = state.raw;
+        state.since = Some(current_time);
+        Some(event(current_time, key as u8, state.reported))
+    }
+}
+
+struct DeferDebouncer<Clock: time::Clock, const N: usize> {
+    timings: DebounceTimings,
+    keys: [KeyDebounce<Clock>; N],
+}
+
+impl<Clock: time::Clock, const N: usize> DeferDebouncer<Clock, N> {
+    const fn new(timings: DebounceTimings) -> Self {
+        Self {
+            timings,
+            keys: [KeyDebounce::NEW; N],
+        }
+    }
+}
+
+impl<Clock: time::Clock, const N: usize> Debouncer<Clock> for DeferDebouncer<Clock, N> {
+    fn push(&mut self, raw: TimedEvent<Clock>) -> Option<TimedEvent<Clock>> {
+        let Some((key, pressed)) = edge(&raw) else {
+            return Some(raw);
+        };
+        let state = self.keys.get_mut(key as usize)?;
+
+        state.raw = pressed;
+        state.since = Some(raw.time);
+        None
+    }
+
+    fn tick(&mut self, current_time: Clock::Instant) -> Option<TimedEvent<Clock>> {
+        let (key, state) = self.keys.iter_mut().enumerate().find(|(_, k)| {
+            k.since.is_some() && k.settled(current_time, self.timings.for_state(k.raw))
+        })?;
+
+        let since = state.since.take()?;
+        if state.raw == state.reported {
+            // bounced back to where it was
+            return self.tick(current_time);
+        }
+
+        state.reported = state.raw;
+        Some(event(since, key as u8, state.reported))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DebounceTimings, Debouncer, DeferDebouncer, EagerDebouncer};
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{InputEvent, TimedEvent};
+
+    fn raw(clock: &TickerClock, event: InputEvent) -> TimedEvent<TickerClock> {
+        TimedEvent {
+            time: clock.now(),
+            event,
+        }
+    }
+
+    #[test]
+    fn eager() {
+        let mut clock = TickerClock(0);
+        let mut debouncer = EagerDebouncer::<_, 4>::new(DebounceTimings {
+            press: Duration::from_millis(5),
+            release: Duration::from_millis(2),
+        });
+
+        let press = raw(&clock, InputEvent::Press(1));
+        assert_eq!(debouncer.push(press), Some(press));
+        clock.tick();
+        assert_eq!(debouncer.push(raw(&clock, InputEvent::Depress(1))), None);
+        clock.tick();
+        assert_eq!(debouncer.push(raw(&clock, InputEvent::Press(1))), None);
+        clock.tick_n(3);
+        assert_eq!(debouncer.tick(clock.now()), None);
+
+        let release = raw(&clock, InputEvent::Depress(1));
+        assert_eq!(debouncer.push(release), Some(release));
+
+        // released during the shorter release lockout, reported when it ends
+        clock.tick();
+        assert_eq!(debouncer.push(raw(&clock, InputEvent::Press(1))), None);
+        assert_eq!(debouncer.tick(clock.now()), None);
+        clock.tick();
+        assert_eq!(
+            debouncer.tick(clock.now()),
+            Some(raw(&clock, InputEvent::Press(1)))
+        );
+    }
+
+    #[test]
+    fn defer() {
+        let mut clock = TickerClock(0);
+        let mut debouncer =
+            DeferDebouncer::<_, 4>::new(DebounceTimings::symmetric(Duration::from_millis(5)));
+
+        assert_eq!(debouncer.push(raw(&clock, InputEvent::Press(2))), None);
+        clock.tick();
+        debouncer.push(raw(&clock, InputEvent::Depress(2)));
+        clock.tick();
+        let press = raw(&clock, InputEvent::Press(2));
+        debouncer.push(press);
+
+        clock.tick_n(4);
+        assert_eq!(debouncer.tick(clock.now()), None);
+        clock.tick();
+        assert_eq!(debouncer.tick(clock.now()), Some(press));
+        assert_eq!(debouncer.tick(clock.now()), None);
+
+        // a glitch that settles back is never reported
+        debouncer.push(raw(&clock, InputEvent::Depress(2)));
+        clock.tick();
+        debouncer.push(raw(&clock, InputEvent::Press(2)));
+        clock.tick_n(10);
+        assert_eq!(debouncer.tick(clock.now()), None);
+    }
+}
@@ -0,0 +1,166 @@
+//! Several machines sharing flags and layers.
+//!
+//! [`Devices`] runs a machine per device, such as each half of a split
+//! keyboard or a keyboard and a macro pad, against one set of flags and
+//! layers. Flags and layers set by one machine are seen by the others, and
+//! when a push changes them the other machines are ticked straight away so
+//! they can react. Machines always run in index order, so their outputs are
+//! merged the same way every time.
+
+use crate::time::{self, Instant};
+use crate::{DynState, GlobalState, InputEvent, KeyEvent, Layers, StateFlags};
+
+struct Devices<Clock: time::Clock, const N: usize> {
+    machines: [GlobalState<Clock>; N],
+    flags: StateFlags,
+    layers: Layers,
+}
+
+impl<Clock: time::Clock, const N: usize> Devices<Clock, N> {
+    fn new(machines: [&'static dyn DynState; N], current_time: Clock::Instant) -> Self {
+        Self {
+            machines: machines.map(|machine| GlobalState::new(machine, current_time)),
+            flags: StateFlags::empty(),
+            layers: Layers::empty(),
+        }
+    }
+
+    fn flags(&self) -> StateFlags {
+        self.flags
+    }
+
+    fn layers(&self) -> Layers {
+        self.layers
+    }
+
+    fn run(
+        &mut self,
+        device: usize,
+        emit: &mut impl FnMut(KeyEvent),
+        f: impl FnOnce(&mut GlobalState<Clock>) -> &[KeyEvent],
+    ) {
+        let machine = &mut self.machines[device];
+        machine.flags = self.flags;
+        machine.layers = self.layers;
+
+        let events = f(machine);
+        for event in events {
+            emit(*event);
+        }
+
+        self.flags = machine.flags;
+        self.layers = machine.layers;
+    }
+
+    /// Push an event from `device` to its machine.
+    fn push(
+        &mut self,
+        device: usize,
+        current_time: Clock::Instant,
+        event: InputEvent,
+        mut emit: impl FnMut(KeyEvent),
+    ) {
+        let before = (self.flags, self.layers);
+        self.run(device, &mut emit, |m| m.push(current_time, event));
+
+        if (self.flags, self.layers) != before {
+            for other in (0..N).filter(|other| *other != device) {
+                self.run(other, &mut emit, |m| m.tick(current_time));
+            }
+        }
+    }
+
+    fn tick(&mut self, current_time: Clock::Instant, mut emit: impl FnMut(KeyEvent)) {
+        for device in 0..N {
+            self.run(device, &mut emit, |m| m.tick(current_time));
+        }
+    }
+
+    /// The earliest deadline of any of the machines.
+    fn next_deadline(&self, current_time: Clock::Instant) -> Option<Clock::Instant> {
+        self.machines
+            .iter()
+            .filter_map(|machine| machine.next_deadline(current_time))
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Devices;
+    use crate::tests::TickerClock;
+    use crate::{
+        InputEvent, InternalEvent, KeyEvent, State, StateId, Transition, TransitionCondition,
+    };
+
+    // the left half holds a layer while key 0 is held
+    static LEFT: State = State {
+        name: "left",
+        id: StateId(0),
+        transitions: &[&LEFT_0],
+    };
+
+    static LEFT_HELD: State = State {
+        name: "left held",
+        id: StateId(1),
+        transitions: &[&LEFT_1],
+    };
+
+    static LEFT_0: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(0)],
+        key_event_emissions: &[],
+        internal_event_emissions: &[InternalEvent::ActivateLayer(1)],
+        target: &LEFT_HELD,
+    };
+
+    static LEFT_1: Transition = Transition {
+        conditions: &[TransitionCondition::depressed_single(0)],
+        key_event_emissions: &[],
+        internal_event_emissions: &[InternalEvent::DeactivateLayer(1)],
+        target: &LEFT,
+    };
+
+    // the right half lights up while the layer is active
+    static RIGHT: State = State {
+        name: "right",
+        id: StateId(2),
+        transitions: &[&RIGHT_0],
+    };
+
+    static RIGHT_LAYER: State = State {
+        name: "right layer",
+        id: StateId(3),
+        transitions: &[&RIGHT_1],
+    };
+
+    static RIGHT_0: Transition = Transition {
+        conditions: &[TransitionCondition::LayerActive(1)],
+        key_event_emissions: &[KeyEvent::LayerActivated(1)],
+        internal_event_emissions: &[],
+        target: &RIGHT_LAYER,
+    };
+
+    static RIGHT_1: Transition = Transition {
+        conditions: &[TransitionCondition::LayerNotActive(1)],
+        key_event_emissions: &[KeyEvent::LayerDeactivated(1)],
+        internal_event_emissions: &[],
+        target: &RIGHT,
+    };
+
+    #[test]
+    fn shared_layers() {
+        let clock = TickerClock(0);
+        let mut devices =
+            Devices::<TickerClock, 2>::new([LEFT.as_dyn(), RIGHT.as_dyn()], clock.now());
+        let mut out = Vec::new();
+
+        devices.push(0, clock.now(), InputEvent::Press(0), |e| out.push(e));
+        assert!(devices.layers().is_active(1));
+        assert_eq!(out, [KeyEvent::LayerActivated(1)]);
+
+        out.clear();
+        devices.push(0, clock.now(), InputEvent::Depress(0), |e| out.push(e));
+        assert_eq!(out, [KeyEvent::LayerDeactivated(1)]);
+        assert_eq!(devices.next_deadline(clock.now()), None);
+    }
+}
@@ -0,0 +1,190 @@
+//! Absolute pointer output.
+//!
+//! [`KeyEvent::Digitizer`] reports a position the way a touch screen or pen
+//! tablet does, rather than as movement: x and y each run from `0` at the
+//! left or top to [`DIGITIZER_MAX`] at the right or bottom of the surface,
+//! whatever its resolution. Its buttons are a set of [`TIP`], [`BARREL`],
+//! [`ERASER`] and [`IN_RANGE`] bits, and an event with none of them lifts
+//! the pen or finger away. Transitions can emit them as they are, such as a
+//! key that taps a fixed point of the screen:
+//!
+//! ```ignore
+//! static TAP_CORNER: Transition = Transition {
+//!     key_event_emissions: &[
+//!         KeyEvent::Digitizer(DIGITIZER_MAX, 0, IN_RANGE | TIP),
+//!         KeyEvent::Digitizer(DIGITIZER_MAX, 0, 0),
+//!     ],
+//!     ...
+//! };
+//! ```
+//!
+//! A [`TouchStrip`] turns a row of touch pads into positions along a strip,
+//! and [`normalize`] scales the raw readings of other sensors. The firmware
+//! sends the events in [`report`]s for a HID digitizer.
+
+use crate::{InputEvent, KeyEvent, KeySet};
+
+/// The largest coordinate, at the right or bottom edge.
+pub(crate) const DIGITIZER_MAX: u16 = 0x7fff;
+
+/// The pen or finger is touching the surface.
+pub(crate) const TIP: u8 = 1 << 0;
+/// The pen's side button is held.
+pub(crate) const BARREL: u8 = 1 << 1;
+/// The pen is upside down, erasing.
+pub(crate) const ERASER: u8 = 1 << 2;
+/// The pen is close enough to the surface to point, touching or not.
+pub(crate) const IN_RANGE: u8 = 1 << 3;
+
+/// `value` from `0..=max` scaled onto `0..=DIGITIZER_MAX`, clamped to `max`.
+pub(crate) const fn normalize(value: u16, max: u16) -> u16 {
+    if max == 0 {
+        return 0;
+    }
+    let value = if value > max { max } else { value };
+    (value as u32 * DIGITIZER_MAX as u32 / max as u32) as u16
+}
+
+/// The input report for `event`, the buttons then x and y little endian, or
+/// `None` if it isn't a digitizer event.
+pub(crate) const fn report(event: KeyEvent) -> Option<[u8; 5]> {
+    match event {
+        KeyEvent::Digitizer(x, y, buttons) => {
+            let [x0, x1] = x.to_le_bytes();
+            let [y0, y1] = y.to_le_bytes();
+            Some([buttons, x0, x1, y0, y1])
+        }
+        _ => None,
+    }
+}
+
+/// A row of touch pads, each a key, read as a horizontal strip at a fixed
+/// height. Touching pads reports the middle of the ones touched.
+pub(crate) struct TouchStrip {
+    /// The key of the leftmost pad, the rest follow it.
+    first: u8,
+    pads: u8,
+    y: u16,
+    touched: KeySet,
+    /// Where the strip was last touched, to lift off at.
+    x: u16,
+}
+
+impl TouchStrip {
+    pub(crate) const fn new(first: u8, pads: u8, y: u16) -> Self {
+        Self {
+            first,
+            pads,
+            y,
+            touched: KeySet::empty(),
+            x: 0,
+        }
+    }
+
+    /// Which pad `key` is, if it's one of the strip's.
+    fn pad(&self, key: u8) -> Option<u8> {
+        key.checked_sub(self.first).filter(|pad| *pad < self.pads)
+    }
+
+    /// The middle of the touched pads, each pad spanning an equal part of
+    /// the strip.
+    fn position(&self) -> Option<u16> {
+        let (count, sum) = self
+            .touched
+            .keys()
+            .fold((0, 0), |(count, sum), pad| (count + 1, sum + pad as u32));
+        if count == 0 {
+            return None;
+        }
+        // in halves of a pad, so the middle of each is a whole number
+        let halves = (2 * sum + count) / count;
+        Some(normalize(halves as u16, 2 * self.pads as u16))
+    }
+
+    /// Returns the event if it should still be passed on to the machine.
+    pub(crate) fn push(
+        &mut self,
+        event: InputEvent,
+        mut emit: impl FnMut(KeyEvent),
+    ) -> Option<InputEvent> {
+        let (pad, touched) = match event {
+            InputEvent::Press(key) => (self.pad(key), true),
+            InputEvent::Depress(key) => (self.pad(key), false),
+            _ => return Some(event),
+        };
+        let Some(pad) = pad else {
+            return Some(event);
+        };
+
+        if touched {
+            self.touched.insert(pad);
+        } else if !self.touched.remove(pad) {
+            return None;
+        }
+
+        match self.position() {
+            Some(x) => {
+                self.x = x;
+                emit(KeyEvent::Digitizer(x, self.y, IN_RANGE | TIP));
+            }
+            None => emit(KeyEvent::Digitizer(self.x, self.y, 0)),
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, report, TouchStrip, DIGITIZER_MAX, IN_RANGE, TIP};
+    use crate::{InputEvent, KeyEvent};
+
+    #[test]
+    fn normalizes() {
+        assert_eq!(normalize(0, 1023), 0);
+        assert_eq!(normalize(1023, 1023), DIGITIZER_MAX);
+        assert_eq!(normalize(2000, 1023), DIGITIZER_MAX);
+        assert_eq!(normalize(512, 1024), 0x3fff);
+        assert_eq!(normalize(1, 0), 0);
+
+        assert_eq!(
+            report(KeyEvent::Digitizer(0x1234, 0x7fff, TIP)),
+            Some([1, 0x34, 0x12, 0xff, 0x7f])
+        );
+        assert_eq!(report(KeyEvent::Press(4)), None);
+    }
+
+    #[test]
+    fn touch_strip() {
+        let mut strip = TouchStrip::new(10, 4, 100);
+        let mut push = |event| {
+            let mut out = Vec::new();
+            let passed = strip.push(event, |e| out.push(e));
+            (passed, out)
+        };
+
+        // the middle of the first of four pads is an eighth of the way
+        assert_eq!(
+            push(InputEvent::Press(10)),
+            (None, vec![KeyEvent::Digitizer(0x0fff, 100, IN_RANGE | TIP)])
+        );
+        // and between the first two a quarter
+        assert_eq!(
+            push(InputEvent::Press(11)),
+            (None, vec![KeyEvent::Digitizer(0x1fff, 100, IN_RANGE | TIP)])
+        );
+        assert_eq!(
+            push(InputEvent::Depress(10)),
+            (None, vec![KeyEvent::Digitizer(0x2fff, 100, IN_RANGE | TIP)])
+        );
+        assert_eq!(
+            push(InputEvent::Depress(11)),
+            (None, vec![KeyEvent::Digitizer(0x2fff, 100, 0)])
+        );
+
+        assert_eq!(push(InputEvent::Depress(12)), (None, vec![]));
+        assert_eq!(
+            push(InputEvent::Press(14)),
+            (Some(InputEvent::Press(14)), vec![])
+        );
+    }
+}
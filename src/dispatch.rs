@@ -0,0 +1,237 @@
+//! Per key jump tables for key presses.
+//!
+//! A state with a transition per key tries every one of them in turn for each
+//! press. [`KeyDispatch`] works out ahead of time, for each state and key
+//! code, the first transition that could be taken for a press of that key, so
+//! that a push starts there instead. Transitions are still tried in order
+//! from that point, so the first one that holds is taken as before.
+//!
+//! The table takes a byte per key code per state. Transitions further than
+//! 255 into a state are reached by scanning from the 255th.
+
+use core::ops::Range;
+
+use crate::table::{Layout, StateIndex};
+use crate::{InputEvent, InternalEvent, KeyCode, KeyEvent, TransitionCondition};
+
+struct KeyDispatch<'l, L, const STATES: usize> {
+    layout: &'l L,
+    /// For each key code, how many of a state's transitions to skip for a
+    /// press of it.
+    skip: [[u8; 256]; STATES],
+}
+
+/// Whether a transition with `conditions` could be taken for a press of `key`.
+fn may_take(conditions: &[TransitionCondition], key: KeyCode) -> bool {
+    conditions.iter().all(|condition| match condition {
+        TransitionCondition::Pressed(keys) => keys.contains(&key),
+        TransitionCondition::EventMatches(predicate) => predicate(InputEvent::Press(key)),
+        condition => !condition.is_event_condition(),
+    })
+}
+
+impl<'l, L: Layout, const STATES: usize> KeyDispatch<'l, L, STATES> {
+    /// Returns `None` if `layout` has more than `STATES` states.
+    fn new(layout: &'l L) -> Option<Self> {
+        if layout.state_count() > STATES {
+            return None;
+        }
+
+        let mut skip = [[0; 256]; STATES];
+        for (index, skip) in skip.iter_mut().enumerate().take(layout.state_count()) {
+            let range = layout.transition_range(StateIndex(index as u16));
+            for (key, skip) in skip.iter_mut().enumerate() {
+                let first = range
+                    .clone()
+                    .position(|t| may_take(layout.conditions(t), key as KeyCode))
+                    .unwrap_or(range.len());
+                *skip = first.min(u8::MAX as usize) as u8;
+            }
+        }
+
+        Some(Self { layout, skip })
+    }
+}
+
+impl<L: Layout, const STATES: usize> Layout for KeyDispatch<'_, L, STATES> {
+    fn state_count(&self) -> usize {
+        self.layout.state_count()
+    }
+
+    fn transition_range(&self, state: StateIndex) -> Range<usize> {
+        self.layout.transition_range(state)
+    }
+
+    fn conditions(&self, transition: usize) -> &[TransitionCondition] {
+        self.layout.conditions(transition)
+    }
+
+    fn key_event_emissions(&self, transition: usize) -> &[KeyEvent] {
+        self.layout.key_event_emissions(transition)
+    }
+
+    fn internal_event_emissions(&self, transition: usize) -> &[InternalEvent] {
+        self.layout.internal_event_emissions(transition)
+    }
+
+    fn target(&self, transition: usize) -> StateIndex {
+        self.layout.target(transition)
+    }
+
+    fn name(&self, state: StateIndex) -> &'static str {
+        self.layout.name(state)
+    }
+
+    fn candidates(&self, state: StateIndex, event: Option<InputEvent>) -> Range<usize> {
+        let range = self.layout.candidates(state, event);
+        match event {
+            Some(InputEvent::Press(key)) => {
+                let skip = self.skip[state.0 as usize][key as usize] as usize;
+                (range.start + skip).min(range.end)..range.end
+            }
+            _ => range,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use core::ops::Range;
+
+    use super::KeyDispatch;
+    use crate::table::{Layout, StateIndex, Table, TableMachine};
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{
+        InputEvent, InternalEvent, KeyEvent, State, StateId, Transition, TransitionCondition,
+    };
+
+    /// Records which transitions have had their conditions looked at.
+    struct Counting<L> {
+        layout: L,
+        evaluated: Cell<u32>,
+    }
+
+    impl<L: Layout> Layout for Counting<L> {
+        fn state_count(&self) -> usize {
+            self.layout.state_count()
+        }
+
+        fn transition_range(&self, state: StateIndex) -> Range<usize> {
+            self.layout.transition_range(state)
+        }
+
+        fn conditions(&self, transition: usize) -> &[TransitionCondition] {
+            self.evaluated.set(self.evaluated.get() | 1 << transition);
+            self.layout.conditions(transition)
+        }
+
+        fn key_event_emissions(&self, transition: usize) -> &[KeyEvent] {
+            self.layout.key_event_emissions(transition)
+        }
+
+        fn internal_event_emissions(&self, transition: usize) -> &[InternalEvent] {
+            self.layout.internal_event_emissions(transition)
+        }
+
+        fn target(&self, transition: usize) -> StateIndex {
+            self.layout.target(transition)
+        }
+
+        fn name(&self, state: StateIndex) -> &'static str {
+            self.layout.name(state)
+        }
+    }
+
+    static A: State = State {
+        name: "A",
+        id: StateId(0),
+        transitions: &[&A_4, &A_5, &A_SHIFT, &A_6],
+    };
+
+    static A_4: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(4)],
+        key_event_emissions: &[KeyEvent::Press(4)],
+        internal_event_emissions: &[],
+        target: &A,
+    };
+
+    static A_5: Transition = Transition {
+        conditions: &[TransitionCondition::Pressed(5..=7)],
+        key_event_emissions: &[KeyEvent::Press(5)],
+        internal_event_emissions: &[],
+        target: &A,
+    };
+
+    static A_SHIFT: Transition = Transition {
+        conditions: &[TransitionCondition::ElapsedGreater(Duration::from_millis(
+            10,
+        ))],
+        key_event_emissions: &[KeyEvent::Press(0xe1)],
+        internal_event_emissions: &[],
+        target: &A,
+    };
+
+    static A_6: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(6)],
+        key_event_emissions: &[KeyEvent::Press(6)],
+        internal_event_emissions: &[],
+        target: &A,
+    };
+
+    #[test]
+    fn candidates() {
+        let table = Table::<1, 4>::compile(A.as_dyn()).unwrap();
+        let dispatch = KeyDispatch::<_, 1>::new(&table).unwrap();
+        let candidates = |event| dispatch.candidates(StateIndex(0), event);
+
+        assert_eq!(candidates(Some(InputEvent::Press(4))), 0..4);
+        assert_eq!(candidates(Some(InputEvent::Press(6))), 1..4);
+        // only the timed transition could be taken
+        assert_eq!(candidates(Some(InputEvent::Press(9))), 2..4);
+        assert_eq!(candidates(Some(InputEvent::Depress(4))), 0..4);
+        assert_eq!(candidates(None), 0..4);
+
+        assert!(KeyDispatch::<_, 0>::new(&table).is_none());
+    }
+
+    #[test]
+    fn runs_like_table() {
+        let table = Table::<1, 4>::compile(A.as_dyn()).unwrap();
+        let dispatch = KeyDispatch::<_, 1>::new(&table).unwrap();
+        let mut clock = TickerClock(0);
+        let mut dispatched = TableMachine::<TickerClock, _>::new(&dispatch, clock.now());
+        let mut scanned = TableMachine::<TickerClock, _>::new(&table, clock.now());
+
+        for (key, wait) in [(4, 0), (6, 3), (9, 12), (6, 0), (7, 20)] {
+            clock.tick_n(wait);
+            let event = InputEvent::Press(key);
+            assert_eq!(
+                dispatched.push(clock.now(), event),
+                scanned.push(clock.now(), event)
+            );
+        }
+    }
+
+    #[test]
+    fn skips_other_keys() {
+        let counting = Counting {
+            layout: Table::<1, 4>::compile(A.as_dyn()).unwrap(),
+            evaluated: Cell::new(0),
+        };
+        let dispatch = KeyDispatch::<_, 1>::new(&counting).unwrap();
+        let clock = TickerClock(0);
+        let mut machine = TableMachine::<TickerClock, _>::new(&dispatch, clock.now());
+
+        let mut evaluated = |key| {
+            counting.evaluated.set(0);
+            machine.push(clock.now(), InputEvent::Press(key));
+            counting.evaluated.get()
+        };
+        assert_eq!(evaluated(6), 0b0010);
+        // the presses of 4 to 7 are never looked at
+        assert_eq!(evaluated(9), 0b1100);
+        assert_eq!(evaluated(4), 0b0001);
+    }
+}
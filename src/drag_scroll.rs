@@ -0,0 +1,124 @@
+//! Scroll wheel emulation.
+//!
+//! While the drag scroll key is held, pointer movement is turned into wheel
+//! events instead of reaching the machine. Faster movement scrolls
+//! proportionally further, and each axis has its own divisor so that the
+//! usually much finer pointer resolution can be scaled down to wheel clicks.
+
+use core::num::NonZeroI16;
+
+use crate::{InputEvent, KeyEvent};
+
+struct DragScroll {
+    key: u8,
+    /// Pointer movement per wheel click on each axis, a negative divisor
+    /// inverts the axis.
+    divisor: (NonZeroI16, NonZeroI16),
+    /// Extra movement in sixteenths per unit of speed.
+    acceleration: u8,
+    held: bool,
+    remainder: (i32, i32),
+}
+
+impl DragScroll {
+    const fn new(key: u8, divisor: (NonZeroI16, NonZeroI16), acceleration: u8) -> Self {
+        Self {
+            key,
+            divisor,
+            acceleration,
+            held: false,
+            remainder: (0, 0),
+        }
+    }
+
+    fn accelerate(&self, delta: i8) -> i32 {
+        let delta = delta as i32;
+        delta * (16 + delta.abs() * self.acceleration as i32) / 16
+    }
+
+    fn scroll(remainder: &mut i32, divisor: NonZeroI16) -> i8 {
+        let divisor = divisor.get() as i32;
+        let clicks = *remainder / divisor;
+        *remainder -= clicks * divisor;
+        clicks.clamp(i8::MIN as i32, i8::MAX as i32) as i8
+    }
+
+    /// Returns the event if it should still be passed on to the machine.
+    fn push(&mut self, event: InputEvent, mut emit: impl FnMut(KeyEvent)) -> Option<InputEvent> {
+        match event {
+            InputEvent::Press(key) if key == self.key => {
+                self.held = true;
+                None
+            }
+            InputEvent::Depress(key) if key == self.key => {
+                self.held = false;
+                self.remainder = (0, 0);
+                None
+            }
+            InputEvent::PointerMove(dx, dy) if self.held => {
+                self.remainder.0 += self.accelerate(dx);
+                self.remainder.1 += self.accelerate(dy);
+
+                let horizontal = Self::scroll(&mut self.remainder.0, self.divisor.0);
+                let vertical = Self::scroll(&mut self.remainder.1, self.divisor.1);
+
+                if horizontal != 0 || vertical != 0 {
+                    emit(KeyEvent::Wheel(horizontal, vertical));
+                }
+
+                None
+            }
+            event => Some(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::num::NonZeroI16;
+
+    use super::DragScroll;
+    use crate::{InputEvent, KeyEvent};
+
+    const fn divisor(horizontal: i16, vertical: i16) -> (NonZeroI16, NonZeroI16) {
+        match (NonZeroI16::new(horizontal), NonZeroI16::new(vertical)) {
+            (Some(horizontal), Some(vertical)) => (horizontal, vertical),
+            _ => panic!("zero divisor"),
+        }
+    }
+
+    #[test]
+    fn scrolls_while_held() {
+        let mut scroll = DragScroll::new(5, divisor(8, -8), 0);
+        let mut out = Vec::new();
+
+        assert_eq!(
+            scroll.push(InputEvent::PointerMove(4, 4), |e| out.push(e)),
+            Some(InputEvent::PointerMove(4, 4))
+        );
+
+        assert_eq!(scroll.push(InputEvent::Press(5), |e| out.push(e)), None);
+        scroll.push(InputEvent::PointerMove(4, 4), |e| out.push(e));
+        assert!(out.is_empty());
+        scroll.push(InputEvent::PointerMove(4, 12), |e| out.push(e));
+        assert_eq!(out, [KeyEvent::Wheel(1, -2)]);
+
+        assert_eq!(scroll.push(InputEvent::Depress(5), |e| out.push(e)), None);
+        assert_eq!(
+            scroll.push(InputEvent::Press(6), |e| out.push(e)),
+            Some(InputEvent::Press(6))
+        );
+    }
+
+    #[test]
+    fn accelerates_fast_movement() {
+        let mut scroll = DragScroll::new(5, divisor(8, 8), 4);
+        let mut out = Vec::new();
+
+        scroll.push(InputEvent::Press(5), |e| out.push(e));
+        scroll.push(InputEvent::PointerMove(2, 0), |e| out.push(e));
+        assert!(out.is_empty());
+        scroll.push(InputEvent::PointerMove(0, 16), |e| out.push(e));
+        assert_eq!(out, [KeyEvent::Wheel(0, 10)]);
+    }
+}
@@ -0,0 +1,437 @@
+//! Dynamic macros.
+//!
+//! Key events emitted by the machine can be recorded into numbered slots,
+//! along with the time between them, and played back later. Slots can be
+//! saved to and restored from [`Storage`].
+//!
+//! Playback can be humanized, drawing the delay before each event from a
+//! range using an [`Entropy`] source. Recorded delays longer than the drawn
+//! one are kept, so deliberate pauses survive.
+//!
+//! A recorded event is stored as its key code followed by a LEB128 varint of
+//! the delay before it in milliseconds, shifted left once with the low bit
+//! set for releases. A slot is stored as the event count, a single byte as
+//! slots hold at most 255 events, followed by its events, in a record of the
+//! version in [`MACRO_SCHEMA`]. Slots saved by older firmware are upgraded
+//! with [`DynamicMacros::restore_with`] and the [`Schema`] that describes
+//! them.
+
+use std::convert::Infallible;
+use std::ops::RangeInclusive;
+
+use crate::entropy::Entropy;
+use crate::storage::{read_migrated, write_record, Schema, Storage, StorageError};
+use crate::time::{self, Duration, Instant};
+use crate::{KeyCode, KeyEvent};
+
+/// Record keys used for macro slots, slot `n` is stored at `MACRO_RECORD_BASE + n`.
+const MACRO_RECORD_BASE: u16 = 0x100;
+pub(crate) const MACRO_SCHEMA: Schema = Schema {
+    version: 1,
+    migrations: &[],
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct RecordedEvent {
+    key: KeyCode,
+    released: bool,
+    /// Milliseconds since the previous event.
+    delay: u16,
+}
+
+impl RecordedEvent {
+    const EMPTY: Self = Self {
+        key: 0,
+        released: false,
+        delay: 0,
+    };
+
+    fn key_event(&self) -> KeyEvent {
+        if self.released {
+            KeyEvent::Depress(self.key)
+        } else {
+            KeyEvent::Press(self.key)
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct MacroSlot<const LEN: usize> {
+    events: [RecordedEvent; LEN],
+    len: usize,
+}
+
+struct Cursor<Clock: time::Clock> {
+    slot: usize,
+    index: usize,
+    last: Clock::Instant,
+    /// Delay before the event at `index` when playing.
+    delay: u16,
+}
+
+struct DynamicMacros<
+    Clock: time::Clock,
+    const SLOTS: usize,
+    const LEN: usize,
+    E: Entropy = Infallible,
+> {
+    slots: [MacroSlot<LEN>; SLOTS],
+    recording: Option<Cursor<Clock>>,
+    playing: Option<Cursor<Clock>>,
+    humanize: Option<(RangeInclusive<u16>, E)>,
+}
+
+impl<Clock: time::Clock, const SLOTS: usize, const LEN: usize, E: Entropy>
+    DynamicMacros<Clock, SLOTS, LEN, E>
+{
+    const fn new() -> Self {
+        const {
+            assert!(
+                LEN <= u8::MAX as usize,
+                "slots are stored with a one byte count"
+            )
+        };
+
+        Self {
+            slots: [MacroSlot {
+                events: [RecordedEvent::EMPTY; LEN],
+                len: 0,
+            }; SLOTS],
+            recording: None,
+            playing: None,
+            humanize: None,
+        }
+    }
+
+    /// Play macros back with delays drawn from `delays`.
+    fn humanized(delays: RangeInclusive<u16>, entropy: E) -> Self {
+        Self {
+            humanize: Some((delays, entropy)),
+            ..Self::new()
+        }
+    }
+
+    fn delay(humanize: &mut Option<(RangeInclusive<u16>, E)>, event: &RecordedEvent) -> u16 {
+        match humanize {
+            Some((delays, entropy)) => {
+                let drawn = entropy.in_range(*delays.start() as u32..=*delays.end() as u32);
+                event.delay.max(drawn as u16)
+            }
+            None => event.delay,
+        }
+    }
+
+    fn elapsed(current_time: Clock::Instant, since: &Clock::Instant) -> Duration {
+        current_time.duration_since(since)
+    }
+
+    /// Start recording into `slot`, replacing what it held.
+    fn start_recording(&mut self, slot: usize, current_time: Clock::Instant) {
+        self.slots[slot].len = 0;
+        self.recording = Some(Cursor {
+            slot,
+            index: 0,
+            last: current_time,
+            delay: 0,
+        });
+    }
+
+    fn stop_recording(&mut self) {
+        self.recording = None;
+    }
+
+    /// Record the events emitted by the machine, if recording. Events past
+    /// the capacity of a slot are dropped.
+    fn observe(&mut self, current_time: Clock::Instant, events: &[KeyEvent]) {
+        let Some(cursor) = self.recording.as_mut() else {
+            return;
+        };
+        let slot = &mut self.slots[cursor.slot];
+
+        for event in events {
+            let (key, released) = match *event {
+                KeyEvent::Press(key) => (key, false),
+                KeyEvent::Depress(key) => (key, true),
+                _ => continue,
+            };
+
+            if slot.len == LEN {
+                return;
+            }
+
+            let delay = Self::elapsed(current_time, &cursor.last).as_millis();
+            slot.events[slot.len] = RecordedEvent {
+                key,
+                released,
+                delay: delay.min(u16::MAX as u32) as u16,
+            };
+            slot.len += 1;
+            cursor.last = current_time;
+        }
+    }
+
+    fn play(&mut self, slot: usize, current_time: Clock::Instant) {
+        let delay = match self.slots[slot].events[..self.slots[slot].len].first() {
+            Some(event) => Self::delay(&mut self.humanize, event),
+            None => 0,
+        };
+
+        self.playing = Some(Cursor {
+            slot,
+            index: 0,
+            last: current_time,
+            delay,
+        });
+    }
+
+    /// Returns the next event of the playing macro once its delay has passed.
+    fn tick(&mut self, current_time: Clock::Instant) -> Option<KeyEvent> {
+        let cursor = self.playing.as_mut()?;
+        let slot = &self.slots[cursor.slot];
+
+        let Some(event) = slot.events[..slot.len].get(cursor.index) else {
+            self.playing = None;
+            return None;
+        };
+
+        if Self::elapsed(current_time, &cursor.last) < Duration::from_millis(cursor.delay as u32) {
+            return None;
+        }
+
+        cursor.index += 1;
+        cursor.last = current_time;
+        if let Some(next) = slot.events[..slot.len].get(cursor.index) {
+            cursor.delay = Self::delay(&mut self.humanize, next);
+        }
+
+        Some(event.key_event())
+    }
+
+    fn encode(&self, slot: usize, out: &mut [u8]) -> Option<usize> {
+        let slot = &self.slots[slot];
+        let mut len = 0;
+        let mut put = |byte: u8| {
+            *out.get_mut(len)? = byte;
+            len += 1;
+            Some(())
+        };
+
+        put(slot.len as u8)?;
+        for event in &slot.events[..slot.len] {
+            put(event.key)?;
+
+            let mut value = (event.delay as u32) << 1 | event.released as u32;
+            loop {
+                let byte = (value & 0x7f) as u8;
+                value >>= 7;
+                if value == 0 {
+                    put(byte)?;
+                    break;
+                }
+                put(byte | 0x80)?;
+            }
+        }
+
+        Some(len)
+    }
+
+    fn decode(&mut self, slot: usize, bytes: &[u8]) -> Option<()> {
+        let mut bytes = bytes.iter().copied();
+        let count = bytes.next()? as usize;
+        if count > LEN {
+            return None;
+        }
+
+        let mut events = [RecordedEvent::EMPTY; LEN];
+        for event in &mut events[..count] {
+            let key = bytes.next()?;
+
+            let mut value = 0u32;
+            for shift in (0..).step_by(7).take(3) {
+                let byte = bytes.next()?;
+                value |= ((byte & 0x7f) as u32) << shift;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+
+            *event = RecordedEvent {
+                key,
+                released: value & 1 != 0,
+                delay: u16::try_from(value >> 1).ok()?,
+            };
+        }
+
+        if bytes.next().is_some() {
+            return None;
+        }
+
+        self.slots[slot] = MacroSlot { events, len: count };
+        Some(())
+    }
+
+    /// Save `slot`, using `buf` to serialize it.
+    fn save<S: Storage>(
+        &self,
+        slot: usize,
+        storage: &mut S,
+        buf: &mut [u8],
+    ) -> Result<(), StorageError<S::Error>> {
+        self.save_with(&MACRO_SCHEMA, slot, storage, buf)
+    }
+
+    fn save_with<S: Storage>(
+        &self,
+        schema: &Schema,
+        slot: usize,
+        storage: &mut S,
+        buf: &mut [u8],
+    ) -> Result<(), StorageError<S::Error>> {
+        write_record(
+            storage,
+            MACRO_RECORD_BASE + slot as u16,
+            schema.version,
+            buf,
+            |out| self.encode(slot, out),
+        )
+    }
+
+    /// Restore `slot`, leaving it unchanged if nothing was saved.
+    fn restore<S: Storage>(
+        &mut self,
+        slot: usize,
+        storage: &mut S,
+        buf: &mut [u8],
+    ) -> Result<(), StorageError<S::Error>> {
+        self.restore_with(&MACRO_SCHEMA, slot, storage, buf)
+    }
+
+    /// Restore `slot` as saved at `schema`'s version or, upgrading it, at an
+    /// earlier one.
+    fn restore_with<S: Storage>(
+        &mut self,
+        schema: &Schema,
+        slot: usize,
+        storage: &mut S,
+        buf: &mut [u8],
+    ) -> Result<(), StorageError<S::Error>> {
+        match read_migrated(storage, MACRO_RECORD_BASE + slot as u16, schema, buf)? {
+            None => Ok(()),
+            Some(data) => self.decode(slot, data).ok_or(StorageError::Malformed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DynamicMacros;
+    use crate::entropy::tests::Sequence;
+    use crate::storage::tests::MemoryStorage;
+    use crate::storage::{Schema, StorageError};
+    use crate::tests::TickerClock;
+    use crate::KeyEvent;
+
+    #[test]
+    fn record_and_play() {
+        let mut clock = TickerClock(0);
+        let mut macros = DynamicMacros::<TickerClock, 2, 8>::new();
+
+        macros.start_recording(1, clock.now());
+        clock.tick_n(3);
+        macros.observe(clock.now(), &[KeyEvent::Press(4)]);
+        clock.tick_n(200);
+        macros.observe(clock.now(), &[KeyEvent::Depress(4), KeyEvent::Press(5)]);
+        macros.stop_recording();
+        macros.observe(clock.now(), &[KeyEvent::Depress(5)]);
+
+        macros.play(1, clock.now());
+        clock.tick_n(2);
+        assert_eq!(macros.tick(clock.now()), None);
+        clock.tick();
+        assert_eq!(macros.tick(clock.now()), Some(KeyEvent::Press(4)));
+        clock.tick_n(200);
+        assert_eq!(macros.tick(clock.now()), Some(KeyEvent::Depress(4)));
+        assert_eq!(macros.tick(clock.now()), Some(KeyEvent::Press(5)));
+        assert_eq!(macros.tick(clock.now()), None);
+        assert!(macros.playing.is_none());
+    }
+
+    #[test]
+    fn humanized_playback() {
+        let mut clock = TickerClock(0);
+        let mut macros =
+            DynamicMacros::<TickerClock, 1, 8, _>::humanized(10..=20, Sequence([5, 0, 10], 0));
+
+        macros.start_recording(0, clock.now());
+        macros.observe(clock.now(), &[KeyEvent::Press(4), KeyEvent::Depress(4)]);
+        clock.tick_n(50);
+        macros.observe(clock.now(), &[KeyEvent::Press(5)]);
+
+        macros.play(0, clock.now());
+        clock.tick_n(14);
+        assert_eq!(macros.tick(clock.now()), None);
+        clock.tick();
+        assert_eq!(macros.tick(clock.now()), Some(KeyEvent::Press(4)));
+        clock.tick_n(9);
+        assert_eq!(macros.tick(clock.now()), None);
+        clock.tick();
+        assert_eq!(macros.tick(clock.now()), Some(KeyEvent::Depress(4)));
+
+        // the recorded pause is longer than the drawn delay
+        clock.tick_n(49);
+        assert_eq!(macros.tick(clock.now()), None);
+        clock.tick();
+        assert_eq!(macros.tick(clock.now()), Some(KeyEvent::Press(5)));
+    }
+
+    #[test]
+    fn persist_slots() {
+        let mut clock = TickerClock(0);
+        let mut macros = DynamicMacros::<TickerClock, 2, 8>::new();
+        let mut storage = MemoryStorage::default();
+        let mut buf = [0; 32];
+
+        macros.start_recording(0, clock.now());
+        macros.observe(clock.now(), &[KeyEvent::Press(4)]);
+        clock.tick_n(1000);
+        macros.observe(clock.now(), &[KeyEvent::Depress(4)]);
+        macros.save(0, &mut storage, &mut buf).unwrap();
+
+        // version and count, then key and varint delay for each event
+        assert_eq!(storage.0[&0x100], [1, 2, 4, 0, 4, 0xd1, 0x0f]);
+
+        let mut restored = DynamicMacros::<TickerClock, 2, 8>::new();
+        restored.restore(0, &mut storage, &mut buf).unwrap();
+        restored.restore(1, &mut storage, &mut buf).unwrap();
+        assert_eq!(restored.slots[0].events[..2], macros.slots[0].events[..2]);
+        assert_eq!(restored.slots[1].len, 0);
+
+        assert_eq!(
+            macros.save(0, &mut storage, &mut [0; 4]),
+            Err(StorageError::BufferTooSmall)
+        );
+
+        storage.0.insert(0x101, vec![1, 1, 4]);
+        assert_eq!(
+            restored.restore(1, &mut storage, &mut buf),
+            Err(StorageError::Malformed)
+        );
+
+        storage.0.insert(0x101, vec![2, 0]);
+        assert_eq!(
+            restored.restore(1, &mut storage, &mut buf),
+            Err(StorageError::UnknownVersion(2))
+        );
+
+        // a version 1 slot read by firmware on version 2
+        let later = Schema {
+            version: 2,
+            migrations: &[|_, len| Some(len)],
+        };
+        restored
+            .restore_with(&later, 0, &mut storage, &mut buf)
+            .unwrap();
+        assert_eq!(restored.slots[0].events[..2], macros.slots[0].events[..2]);
+        assert_eq!(storage.0[&0x100][0], 2);
+    }
+}
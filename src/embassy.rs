@@ -0,0 +1,192 @@
+//! Async driver for embassy firmware.
+//!
+//! [`Runner`] waits on an input channel and a timer at the same time,
+//! pushing events as they arrive and ticking the machine only when
+//! [`GlobalState::next_deadline`] says a timed transition may be due. Every
+//! emitted event is forwarded to an output channel.
+//!
+//! The channel and timer are small traits rather than the embassy types
+//! themselves, an embassy `Receiver`, `Sender` and `Timer::at` each fit one
+//! with a single line implementation.
+
+use core::future::{pending, poll_fn, Future};
+use core::pin::pin;
+use core::task::Poll;
+
+use crate::time::{self, Instant};
+use crate::{GlobalState, InputEvent, KeyEvent};
+
+trait InputChannel {
+    async fn receive(&mut self) -> InputEvent;
+}
+
+trait OutputChannel {
+    async fn send(&mut self, event: KeyEvent);
+}
+
+trait Timer<Clock: time::Clock> {
+    fn now(&self) -> Clock::Instant;
+
+    /// Completes once `deadline` has been reached.
+    async fn at(&mut self, deadline: Clock::Instant);
+}
+
+enum Either<A, B> {
+    First(A),
+    Second(B),
+}
+
+/// Polls both futures until one completes, preferring `a` when both are
+/// ready.
+async fn select<A: Future, B: Future>(a: A, b: B) -> Either<A::Output, B::Output> {
+    let mut a = pin!(a);
+    let mut b = pin!(b);
+
+    poll_fn(|cx| {
+        if let Poll::Ready(x) = a.as_mut().poll(cx) {
+            return Poll::Ready(Either::First(x));
+        }
+        if let Poll::Ready(x) = b.as_mut().poll(cx) {
+            return Poll::Ready(Either::Second(x));
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+struct Runner<Clock: time::Clock, I, O, T> {
+    machine: GlobalState<Clock>,
+    input: I,
+    output: O,
+    timer: T,
+}
+
+impl<Clock, I, O, T> Runner<Clock, I, O, T>
+where
+    Clock: time::Clock,
+    I: InputChannel,
+    O: OutputChannel,
+    T: Timer<Clock>,
+{
+    const fn new(machine: GlobalState<Clock>, input: I, output: O, timer: T) -> Self {
+        Self {
+            machine,
+            input,
+            output,
+            timer,
+        }
+    }
+
+    /// Wait for the next input event or deadline and handle it.
+    async fn step(&mut self) {
+        let deadline = self.machine.next_deadline(self.timer.now());
+        let timer = &mut self.timer;
+        let wake = async move {
+            match deadline {
+                Some(deadline) => timer.at(deadline).await,
+                None => pending().await,
+            }
+        };
+
+        let emitted = match select(self.input.receive(), wake).await {
+            Either::First(event) => self.machine.push(self.timer.now(), event),
+            Either::Second(()) => self.machine.tick(self.timer.now()),
+        };
+
+        for event in emitted {
+            self.output.send(*event).await;
+        }
+    }
+
+    async fn run(&mut self) -> ! {
+        loop {
+            self.step().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::{pending, Future};
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    use embedded_time::Instant;
+
+    use super::{InputChannel, OutputChannel, Runner, Timer};
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{GlobalState, InputEvent, KeyEvent};
+
+    crate::behaviors::turbo_key! {
+        mod turbo {
+            key: 3,
+            output: 4,
+            rate: Duration::from_millis(5),
+        }
+    }
+
+    struct Inputs(Vec<InputEvent>);
+
+    impl InputChannel for Inputs {
+        async fn receive(&mut self) -> InputEvent {
+            match self.0.pop() {
+                Some(event) => event,
+                None => pending().await,
+            }
+        }
+    }
+
+    struct Outputs(Vec<KeyEvent>);
+
+    impl OutputChannel for Outputs {
+        async fn send(&mut self, event: KeyEvent) {
+            self.0.push(event);
+        }
+    }
+
+    /// Jumps straight to the deadline.
+    impl Timer<TickerClock> for TickerClock {
+        fn now(&self) -> Instant<TickerClock> {
+            TickerClock::now(self)
+        }
+
+        async fn at(&mut self, deadline: Instant<TickerClock>) {
+            self.0 = deadline.duration_since_epoch().integer();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(x) = future.as_mut().poll(&mut cx) {
+                return x;
+            }
+        }
+    }
+
+    #[test]
+    fn ticks_at_deadlines() {
+        let clock = TickerClock(0);
+        let machine = GlobalState::<TickerClock>::new(turbo::IDLE.as_dyn(), clock.now());
+        let mut runner = Runner::new(
+            machine,
+            Inputs(vec![InputEvent::Press(3)]),
+            Outputs(Vec::new()),
+            clock,
+        );
+
+        block_on(runner.step());
+        assert_eq!(runner.output.0, [KeyEvent::Press(4), KeyEvent::Depress(4)]);
+
+        block_on(runner.step());
+        assert_eq!(runner.timer.0, 5);
+        assert_eq!(runner.output.0.len(), 4);
+
+        runner.input.0.push(InputEvent::Depress(3));
+        block_on(runner.step());
+        assert_eq!(runner.timer.0, 5);
+        assert_eq!(runner.machine.current_state, turbo::IDLE.as_dyn());
+    }
+}
@@ -0,0 +1,118 @@
+//! Rotary encoders.
+//!
+//! [`QuadratureDecoder`] turns the two phase signals of an encoder into
+//! [`InputEvent::Rotate`] events, which machines match with
+//! [`TransitionCondition::Rotated`](crate::TransitionCondition::Rotated).
+
+use crate::InputEvent;
+
+/// Steps for each pair of previous and current phase states, indexed by
+/// `previous << 2 | current`. Impossible transitions, where both phases
+/// changed at once, count as no movement.
+const STEPS: [i8; 16] = [0, 1, -1, 0, -1, 0, 0, 1, 1, 0, 0, -1, 0, -1, 1, 0];
+
+struct QuadratureDecoder {
+    id: u8,
+    /// Steps per detent, usually 4 or 2.
+    resolution: i8,
+    phases: u8,
+    steps: i8,
+}
+
+impl QuadratureDecoder {
+    /// Panics unless `resolution` is between 1 and 127 steps per detent.
+    const fn new(id: u8, resolution: u8) -> Self {
+        assert!(
+            resolution >= 1 && resolution <= i8::MAX as u8,
+            "resolution must be 1 to 127 steps"
+        );
+        let resolution = resolution as i8;
+        Self {
+            id,
+            resolution,
+            phases: 0,
+            steps: 0,
+        }
+    }
+
+    /// Feed the current state of the phase pins, returning a rotation once a
+    /// full detent has been turned.
+    fn update(&mut self, a: bool, b: bool) -> Option<InputEvent> {
+        let phases = (a as u8) << 1 | b as u8;
+        self.steps += STEPS[(self.phases << 2 | phases) as usize];
+        self.phases = phases;
+
+        let detents = self.steps / self.resolution;
+        if detents == 0 {
+            return None;
+        }
+
+        self.steps -= detents * self.resolution;
+        Some(InputEvent::Rotate(self.id, detents))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QuadratureDecoder;
+    use crate::{InputEvent, TransitionCondition};
+
+    #[test]
+    fn decode_detents() {
+        let mut decoder = QuadratureDecoder::new(1, 4);
+
+        let clockwise = [(false, true), (true, true), (true, false)];
+        for (a, b) in clockwise {
+            assert_eq!(decoder.update(a, b), None);
+        }
+        assert_eq!(decoder.update(false, false), Some(InputEvent::Rotate(1, 1)));
+
+        // a bounce back and forth doesn't add up to a detent
+        assert_eq!(decoder.update(true, false), None);
+        assert_eq!(decoder.update(false, false), None);
+
+        let counter_clockwise = [(true, false), (true, true), (false, true)];
+        for (a, b) in counter_clockwise {
+            assert_eq!(decoder.update(a, b), None);
+        }
+        assert_eq!(
+            decoder.update(false, false),
+            Some(InputEvent::Rotate(1, -1))
+        );
+    }
+
+    #[test]
+    fn single_step_detents() {
+        let mut decoder = QuadratureDecoder::new(1, 1);
+        assert_eq!(decoder.update(false, true), Some(InputEvent::Rotate(1, 1)));
+        assert_eq!(
+            decoder.update(false, false),
+            Some(InputEvent::Rotate(1, -1))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "resolution must be 1 to 127 steps")]
+    fn zero_resolution() {
+        QuadratureDecoder::new(1, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "resolution must be 1 to 127 steps")]
+    fn resolution_past_i8() {
+        QuadratureDecoder::new(1, 128);
+    }
+
+    #[test]
+    fn match_rotation() {
+        let clockwise = TransitionCondition::rotated_clockwise(1);
+        let fast = TransitionCondition::Rotated(1, 3..=i8::MAX);
+        let context = crate::tests::context();
+
+        assert!(clockwise.evaluate(&context, Some(InputEvent::Rotate(1, 1))));
+        assert!(!clockwise.evaluate(&context, Some(InputEvent::Rotate(1, -1))));
+        assert!(!clockwise.evaluate(&context, Some(InputEvent::Rotate(0, 1))));
+        assert!(!fast.evaluate(&context, Some(InputEvent::Rotate(1, 2))));
+        assert!(fast.evaluate(&context, Some(InputEvent::Rotate(1, 4))));
+    }
+}
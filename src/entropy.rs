@@ -0,0 +1,48 @@
+//! Randomness supplied by the integrator, for behaviors that shouldn't look
+//! mechanical.
+
+use std::convert::Infallible;
+use std::ops::RangeInclusive;
+
+/// A source of random numbers, such as a hardware RNG peripheral.
+pub(crate) trait Entropy {
+    fn next_u32(&mut self) -> u32;
+
+    /// A number in `range`, slightly biased towards its start.
+    fn in_range(&mut self, range: RangeInclusive<u32>) -> u32 {
+        let span = range.end().saturating_sub(*range.start()) as u64 + 1;
+        range.start() + (self.next_u32() as u64 % span) as u32
+    }
+}
+
+/// Stands in for an entropy source where none is used.
+impl Entropy for Infallible {
+    fn next_u32(&mut self) -> u32 {
+        match *self {}
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::Entropy;
+
+    /// Cycles through a fixed list of numbers.
+    pub(crate) struct Sequence<const N: usize>(pub(crate) [u32; N], pub(crate) usize);
+
+    impl<const N: usize> Entropy for Sequence<N> {
+        fn next_u32(&mut self) -> u32 {
+            let n = self.0[self.1 % N];
+            self.1 += 1;
+            n
+        }
+    }
+
+    #[test]
+    fn in_range() {
+        let mut entropy = Sequence([0, 5, 11, u32::MAX], 0);
+        assert_eq!(entropy.in_range(10..=20), 10);
+        assert_eq!(entropy.in_range(10..=20), 15);
+        assert_eq!(entropy.in_range(10..=20), 10);
+        assert_eq!(entropy.in_range(0..=u32::MAX), u32::MAX);
+    }
+}
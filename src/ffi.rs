@@ -0,0 +1,285 @@
+//! A C ABI for embedding machines in C firmware.
+//!
+//! A machine is an opaque `fsm_machine` handle made from an initial state with
+//! [`fsm_new`]. Events are pushed into it and it's ticked with timestamps in
+//! milliseconds, which must never go backwards. Emitted events are queued in
+//! the handle until [`fsm_drain`] hands them to the output callback, so the
+//! callback never runs from inside whatever interrupt pushed the event.
+//! Events in both directions use the three byte encodings of
+//! [`InputEvent::to_bytes`] and [`KeyEvent::to_bytes`]. `PressCurrent` and
+//! `DepressCurrent` are passed on as the press or release of the pushed key,
+//! and events with no encoding aren't passed on.
+//!
+//! The machines are Rust statics, so the C side links a static library built
+//! from a crate defining the keymap, which exports its initial states under
+//! unmangled names:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! static HOME_A: &State = &home_a::IDLE;
+//! ```
+//!
+//! and declares them as `extern const fsm_state *const HOME_A;`. The C
+//! declarations are in `include/keyboard_fsm.h`.
+
+use core::ffi::c_void;
+use core::time::Duration;
+
+use crate::time::HostClock;
+use crate::{GlobalState, InputEvent, KeyEvent, State};
+
+/// The output callback, called with the `user_data` given to [`fsm_new`] and
+/// an encoded event.
+type OutputFn = unsafe extern "C" fn(user_data: *mut c_void, tag: u8, x: u8, y: u8);
+
+struct Machine {
+    state: GlobalState<HostClock>,
+    output: OutputFn,
+    user_data: *mut c_void,
+    /// Emitted events not yet drained.
+    pending: Vec<KeyEvent>,
+}
+
+impl Machine {
+    /// Queue what the machine emitted for `event`, or for a tick.
+    fn queue(&mut self, event: Option<InputEvent>) {
+        for &emitted in self.state.emitted() {
+            let emitted = emitted.resolve_current(event);
+            if emitted.to_bytes()[0] != 0xff {
+                self.pending.push(emitted);
+            }
+        }
+    }
+}
+
+/// A handle running the machine starting at `initial`, or null if `initial`
+/// is null. Free it with [`fsm_free`].
+///
+/// # Safety
+///
+/// `initial` must be null or point to a `State` static.
+#[no_mangle]
+unsafe extern "C" fn fsm_new(
+    initial: *const State,
+    now_ms: u64,
+    output: OutputFn,
+    user_data: *mut c_void,
+) -> *mut Machine {
+    // SAFETY: the caller passes a pointer to a static.
+    let Some(initial) = (unsafe { initial.as_ref::<'static>() }) else {
+        return core::ptr::null_mut();
+    };
+    Box::into_raw(Box::new(Machine {
+        state: GlobalState::new(initial.as_dyn(), Duration::from_millis(now_ms)),
+        output,
+        user_data,
+        pending: Vec::new(),
+    }))
+}
+
+/// Free a handle made by [`fsm_new`]. Events not yet drained are dropped.
+///
+/// # Safety
+///
+/// `machine` must be null or a handle from [`fsm_new`] that hasn't been
+/// freed.
+#[no_mangle]
+unsafe extern "C" fn fsm_free(machine: *mut Machine) {
+    if !machine.is_null() {
+        // SAFETY: the handle came from `Box::into_raw` and is freed once.
+        drop(unsafe { Box::from_raw(machine) });
+    }
+}
+
+/// Push an encoded event that happened at `now_ms`. Returns `false` if it
+/// isn't a valid encoding.
+///
+/// # Safety
+///
+/// `machine` must be a live handle from [`fsm_new`].
+#[no_mangle]
+unsafe extern "C" fn fsm_push(machine: *mut Machine, now_ms: u64, tag: u8, x: u8, y: u8) -> bool {
+    // SAFETY: the caller passes a live handle.
+    let machine = unsafe { &mut *machine };
+    let Some(event) = InputEvent::from_bytes([tag, x, y]) else {
+        return false;
+    };
+    machine.state.push(Duration::from_millis(now_ms), event);
+    machine.queue(Some(event));
+    true
+}
+
+/// Take any timed transition due at `now_ms`.
+///
+/// # Safety
+///
+/// `machine` must be a live handle from [`fsm_new`].
+#[no_mangle]
+unsafe extern "C" fn fsm_tick(machine: *mut Machine, now_ms: u64) {
+    // SAFETY: the caller passes a live handle.
+    let machine = unsafe { &mut *machine };
+    machine.state.tick(Duration::from_millis(now_ms));
+    machine.queue(None);
+}
+
+/// Write the next time [`fsm_tick`] is needed to `deadline_ms`, returning
+/// `false` and leaving it alone if no timed transition is waiting. The time
+/// is rounded up to the millisecond, so that ticking then is never too early
+/// for a deadline between milliseconds.
+///
+/// # Safety
+///
+/// `machine` must be a live handle from [`fsm_new`] and `deadline_ms` must
+/// be valid for writes.
+#[no_mangle]
+unsafe extern "C" fn fsm_next_deadline(
+    machine: *const Machine,
+    now_ms: u64,
+    deadline_ms: *mut u64,
+) -> bool {
+    // SAFETY: the caller passes a live handle.
+    let machine = unsafe { &*machine };
+    let Some(deadline) = machine.state.next_deadline(Duration::from_millis(now_ms)) else {
+        return false;
+    };
+    let deadline = deadline.as_micros().div_ceil(1_000);
+    // SAFETY: the caller passes a writable pointer.
+    unsafe { deadline_ms.write(deadline.try_into().unwrap_or(u64::MAX)) };
+    true
+}
+
+/// Call the output callback with every event emitted since the last drain,
+/// in order, returning how many there were.
+///
+/// # Safety
+///
+/// `machine` must be a live handle from [`fsm_new`], and the callback must
+/// not use it.
+#[no_mangle]
+unsafe extern "C" fn fsm_drain(machine: *mut Machine) -> usize {
+    // SAFETY: the caller passes a live handle.
+    let machine = unsafe { &mut *machine };
+    let count = machine.pending.len();
+    for event in machine.pending.drain(..) {
+        let [tag, x, y] = event.to_bytes();
+        // SAFETY: the callback and its data are the caller's.
+        unsafe { (machine.output)(machine.user_data, tag, x, y) };
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ffi::c_void;
+
+    use super::{fsm_drain, fsm_free, fsm_new, fsm_next_deadline, fsm_push, fsm_tick};
+    use crate::behaviors::hold_tap;
+    use crate::time::Duration;
+    use crate::{
+        InputEvent, InternalEvent, KeyEvent, State, StateId, Transition, TransitionCondition,
+    };
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(200),
+        }
+    }
+
+    unsafe extern "C" fn collect(user_data: *mut c_void, tag: u8, x: u8, y: u8) {
+        let emitted = unsafe { &mut *(user_data as *mut Vec<[u8; 3]>) };
+        emitted.push([tag, x, y]);
+    }
+
+    #[test]
+    fn drive_machine() {
+        let mut emitted: Vec<[u8; 3]> = Vec::new();
+        let user_data = &mut emitted as *mut Vec<[u8; 3]> as *mut c_void;
+
+        unsafe {
+            assert!(fsm_new(core::ptr::null(), 0, collect, user_data).is_null());
+
+            let machine = fsm_new(&home_a::IDLE, 1_000, collect, user_data);
+            let [tag, x, y] = InputEvent::Press(1).to_bytes();
+            assert!(fsm_push(machine, 1_000, tag, x, y));
+            assert!(!fsm_push(machine, 1_000, 0xfe, 0, 0));
+
+            let mut deadline = 0;
+            assert!(fsm_next_deadline(machine, 1_000, &mut deadline));
+            assert!(deadline > 1_000 && deadline <= 1_201);
+            fsm_tick(machine, 1_100);
+            assert_eq!(fsm_drain(machine), 0);
+
+            fsm_tick(machine, deadline);
+            assert_eq!(fsm_drain(machine), 1);
+            assert_eq!(fsm_drain(machine), 0);
+
+            let [tag, x, y] = InputEvent::Press(3).to_bytes();
+            assert!(fsm_push(machine, deadline, tag, x, y));
+            assert_eq!(fsm_drain(machine), 1);
+            fsm_free(machine);
+            fsm_free(core::ptr::null_mut());
+        }
+
+        assert_eq!(
+            emitted,
+            [
+                KeyEvent::Press(0xe1).to_bytes(),
+                KeyEvent::Press(3).to_bytes()
+            ]
+        );
+    }
+
+    static WAITING: State = State {
+        name: "WAITING",
+        id: StateId(0),
+        transitions: &[&WAITED],
+    };
+
+    static WAITED: Transition = Transition {
+        conditions: &[TransitionCondition::ElapsedGreaterMicros(
+            Duration::from_micros(1_500),
+        )],
+        key_event_emissions: &[KeyEvent::Press(2)],
+        internal_event_emissions: &[],
+        target: &WAITING,
+    };
+
+    #[test]
+    fn deadline_rounds_up() {
+        let mut emitted: Vec<[u8; 3]> = Vec::new();
+        let user_data = &mut emitted as *mut Vec<[u8; 3]> as *mut c_void;
+
+        unsafe {
+            let machine = fsm_new(&WAITING, 1_000, collect, user_data);
+            let mut deadline = 0;
+            assert!(fsm_next_deadline(machine, 1_000, &mut deadline));
+            assert_eq!(deadline, 1_002);
+            fsm_tick(machine, deadline);
+            assert_eq!(fsm_drain(machine), 1);
+            fsm_free(machine);
+        }
+
+        assert_eq!(emitted, [KeyEvent::Press(2).to_bytes()]);
+    }
+
+    #[test]
+    fn header_declares_everything() {
+        let header = include_str!("../include/keyboard_fsm.h");
+        for name in [
+            "fsm_new",
+            "fsm_free",
+            "fsm_push",
+            "fsm_tick",
+            "fsm_next_deadline",
+            "fsm_drain",
+        ] {
+            assert!(
+                header.contains(&format!("{name}(")),
+                "{name} isn't declared"
+            );
+        }
+    }
+}
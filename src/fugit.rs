@@ -0,0 +1,98 @@
+//! Timestamps from fugit monotonics.
+//!
+//! Most RTIC and embassy HALs hand out fugit instants, which count ticks of
+//! `NOM / DENOM` seconds. [`FugitClock`] reads such a monotonic and converts
+//! its instants, so they can be passed to the machines as they are.
+//!
+//! Instants and durations are converted through their tick count,
+//! `ticks()` in fugit, so the two crates' types never have to meet. Durations
+//! too long for a [`Duration`] saturate rather than wrap.
+
+use core::fmt;
+
+use embedded_time::rate::Fraction;
+use embedded_time::{clock, Instant};
+
+use crate::time::Duration;
+
+struct FugitClock<F, const NOM: u32, const DENOM: u32> {
+    /// The monotonic's current tick count.
+    now: F,
+}
+
+impl<F: Fn() -> u64, const NOM: u32, const DENOM: u32> FugitClock<F, NOM, DENOM> {
+    const fn new(now: F) -> Self {
+        Self { now }
+    }
+
+    /// Convert a fugit instant, given its tick count.
+    fn instant(ticks: u64) -> Instant<Self> {
+        Instant::new(ticks)
+    }
+
+    /// Convert a fugit duration, such as a tapping term, given its tick
+    /// count.
+    fn duration(ticks: u64) -> Duration {
+        let micros = ticks as u128 * NOM as u128 * 1_000_000 / DENOM as u128;
+        Duration::from_micros(u64::try_from(micros).unwrap_or(u64::MAX))
+    }
+}
+
+impl<F, const NOM: u32, const DENOM: u32> fmt::Debug for FugitClock<F, NOM, DENOM> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FugitClock").finish_non_exhaustive()
+    }
+}
+
+impl<F: Fn() -> u64, const NOM: u32, const DENOM: u32> embedded_time::Clock
+    for FugitClock<F, NOM, DENOM>
+{
+    type T = u64;
+    const SCALING_FACTOR: Fraction = Fraction::new(NOM, DENOM);
+
+    fn try_now(&self) -> Result<Instant<Self>, clock::Error> {
+        Ok(Self::instant((self.now)()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use embedded_time::duration::Milliseconds;
+    use embedded_time::Clock;
+
+    use super::FugitClock;
+    use crate::time::Duration;
+
+    type Mono<'a> = FugitClock<&'a dyn Fn() -> u64, 1, 32_768>;
+
+    #[test]
+    fn conversion() {
+        let ticks = Cell::new(32_768_u64);
+        let get = || ticks.get();
+        let clock = Mono::new(&get);
+
+        let start = clock.try_now().unwrap();
+        let later = Mono::instant(32_768 * 3 / 2);
+        let elapsed: Milliseconds<u32> = later
+            .checked_duration_since(&start)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(elapsed, Milliseconds(500_u32));
+        assert_eq!(Mono::duration(6_554).as_millis(), 200);
+    }
+
+    #[test]
+    fn long_durations_saturate() {
+        type Slow = FugitClock<fn() -> u64, { u32::MAX }, 1>;
+        assert_eq!(Slow::duration(u64::MAX), Duration::from_micros(u64::MAX));
+        assert_eq!(Mono::duration(u64::MAX), Duration::from_micros(u64::MAX));
+        // past what fits in u32 milliseconds, but not in a duration
+        assert_eq!(
+            Mono::duration(32_768 * 10_000_000),
+            Duration::from_micros(10_000_000_000_000)
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! Phantom key suppression for matrices without diodes.
+//!
+//! Without diodes, holding three keys on the corners of a rectangle in the
+//! matrix makes the fourth corner read as pressed too. [`GhostFilter`] drops
+//! presses that complete such a rectangle, since it can't tell them apart
+//! from a phantom. A suppressed key stays suppressed until it is released,
+//! even if the rectangle is broken first.
+//!
+//! Positions are numbered `row * COLS + col`, as produced by
+//! [`Matrix`](crate::matrix::Matrix).
+
+use crate::time::{self, Instant};
+use crate::{InputEvent, KeySet, TimedEvent};
+
+struct GhostFilter<const ROWS: usize, const COLS: usize> {
+    /// Keys the host has been told are held.
+    reported: KeySet,
+    suppressed: KeySet,
+}
+
+impl<const ROWS: usize, const COLS: usize> GhostFilter<ROWS, COLS> {
+    const fn new() -> Self {
+        Self {
+            reported: KeySet::empty(),
+            suppressed: KeySet::empty(),
+        }
+    }
+
+    fn held(&self, row: usize, col: usize) -> bool {
+        self.reported.contains((row * COLS + col) as u8)
+    }
+
+    /// Whether pressing `position` would complete a rectangle of held keys.
+    fn ghosted(&self, position: usize) -> bool {
+        let (row, col) = (position / COLS, position % COLS);
+
+        (0..ROWS).filter(|r| *r != row).any(|other_row| {
+            self.held(other_row, col)
+                && (0..COLS)
+                    .filter(|c| *c != col)
+                    .any(|other_col| self.held(row, other_col) && self.held(other_row, other_col))
+        })
+    }
+
+    /// Returns the event if it should be passed on.
+    fn push<Clock: time::Clock>(&mut self, raw: TimedEvent<Clock>) -> Option<TimedEvent<Clock>> {
+        match raw.event {
+            InputEvent::Press(key) if (key as usize) < ROWS * COLS => {
+                if self.ghosted(key as usize) {
+                    self.suppressed.insert(key);
+                    return None;
+                }
+                self.reported.insert(key);
+            }
+            InputEvent::Depress(key) if (key as usize) < ROWS * COLS => {
+                if self.suppressed.remove(key) {
+                    return None;
+                }
+                self.reported.remove(key);
+            }
+            _ => {}
+        }
+
+        Some(raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GhostFilter;
+    use crate::tests::TickerClock;
+    use crate::{InputEvent, TimedEvent};
+
+    #[test]
+    fn suppress_fourth_corner() {
+        let clock = TickerClock(0);
+        let mut filter = GhostFilter::<3, 3>::new();
+        let mut push = |event| {
+            filter
+                .push(TimedEvent::<TickerClock> {
+                    time: clock.now(),
+                    event,
+                })
+                .map(|e| e.event)
+        };
+
+        // (0, 0), (0, 2) and (2, 0) held
+        assert_eq!(push(InputEvent::Press(0)), Some(InputEvent::Press(0)));
+        assert_eq!(push(InputEvent::Press(2)), Some(InputEvent::Press(2)));
+        assert_eq!(push(InputEvent::Press(6)), Some(InputEvent::Press(6)));
+
+        // (2, 2) completes the rectangle, (1, 1) doesn't
+        assert_eq!(push(InputEvent::Press(8)), None);
+        assert_eq!(push(InputEvent::Press(4)), Some(InputEvent::Press(4)));
+
+        // stays suppressed after the rectangle is broken
+        assert_eq!(push(InputEvent::Depress(0)), Some(InputEvent::Depress(0)));
+        assert_eq!(push(InputEvent::Depress(8)), None);
+        assert_eq!(push(InputEvent::Press(8)), Some(InputEvent::Press(8)));
+
+        assert_eq!(push(InputEvent::Press(20)), Some(InputEvent::Press(20)));
+    }
+}
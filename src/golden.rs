@@ -0,0 +1,194 @@
+//! Golden trace tests.
+//!
+//! A scenario is a script of events and waits, one step per line:
+//!
+//! ```text
+//! # a tap within the term
+//! press 1
+//! wait 5
+//! release 1
+//! tick
+//! ```
+//!
+//! `press` and `release` push a press or release of a key, `wait` moves the
+//! clock on by some milliseconds and `tick` ticks the machine. Blank lines
+//! and lines starting with `#` are comments.
+//!
+//! A recorded scenario is kept in `tests/golden/<name>.trace`, with each
+//! `press`, `release` and `tick` followed by ` => ` and the state the machine
+//! was left in and what it emitted. [`check`] runs the script again and
+//! panics with a diff of the trace if anything changed. Setting
+//! `GOLDEN_RECORD=1` makes [`check`] write the new trace instead, which is
+//! also how a new scenario is recorded: write the script alone to its file
+//! and run the test once with it set.
+
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use crate::tests::TickerClock;
+use crate::{DynState, GlobalState, InputEvent};
+
+const SEPARATOR: &str = " => ";
+
+fn path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name)
+        .with_extension("trace")
+}
+
+/// Run `script` on the machine starting at `initial`, ignoring any recorded
+/// results in it, and return the trace.
+fn run(initial: &'static dyn DynState, script: &str) -> Result<String, String> {
+    let mut clock = TickerClock(0);
+    let mut machine = GlobalState::<TickerClock>::new(initial, clock.now());
+    let mut trace = String::new();
+
+    for (number, line) in script.lines().enumerate() {
+        let step = line.split(SEPARATOR).next().unwrap_or_default().trim_end();
+        let mut words = step.split_whitespace();
+        let bad = || format!("line {}: bad argument in `{step}`", number + 1);
+
+        let emitted = match words.next() {
+            None => None,
+            Some(word) if word.starts_with('#') => None,
+            Some("press") => {
+                let key = words.next().and_then(|w| w.parse().ok()).ok_or_else(bad)?;
+                Some(machine.push(clock.now(), InputEvent::Press(key)).to_vec())
+            }
+            Some("release") => {
+                let key = words.next().and_then(|w| w.parse().ok()).ok_or_else(bad)?;
+                Some(machine.push(clock.now(), InputEvent::Depress(key)).to_vec())
+            }
+            Some("tick") => Some(machine.tick(clock.now()).to_vec()),
+            Some("wait") => {
+                clock.tick_n(words.next().and_then(|w| w.parse().ok()).ok_or_else(bad)?);
+                None
+            }
+            Some(_) => return Err(format!("line {}: unknown step `{step}`", number + 1)),
+        };
+
+        match emitted {
+            Some(emitted) => writeln!(
+                trace,
+                "{step}{SEPARATOR}{} {emitted:?}",
+                machine.current_state.name()
+            ),
+            None => writeln!(trace, "{step}"),
+        }
+        .unwrap();
+    }
+
+    Ok(trace)
+}
+
+/// The lines of `expected` and `actual`, marking those that differ.
+fn diff(expected: &str, actual: &str) -> String {
+    let (expected, actual) = (
+        expected.lines().collect::<Vec<_>>(),
+        actual.lines().collect::<Vec<_>>(),
+    );
+    let mut out = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) if e == a => writeln!(out, "  {e}"),
+            (e, a) => {
+                if let Some(e) = e {
+                    writeln!(out, "- {e}").unwrap();
+                }
+                match a {
+                    Some(a) => writeln!(out, "+ {a}"),
+                    None => Ok(()),
+                }
+            }
+        }
+        .unwrap();
+    }
+    out
+}
+
+/// Record `script` run on the machine starting at `initial` as the scenario
+/// `name`.
+fn record(name: &str, initial: &'static dyn DynState, script: &str) {
+    let trace = run(initial, script).unwrap_or_else(|error| panic!("{name}: {error}"));
+    std::fs::write(path(name), trace).unwrap();
+}
+
+/// Replay the scenario `name` on the machine starting at `initial`,
+/// panicking if the trace differs from the recorded one.
+pub(crate) fn check(name: &str, initial: &'static dyn DynState) {
+    let path = path(name);
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|error| panic!("{name}: can't read {}: {error}", path.display()));
+
+    if std::env::var_os("GOLDEN_RECORD").is_some() {
+        record(name, initial, &expected);
+        return;
+    }
+
+    let actual = run(initial, &expected).unwrap_or_else(|error| panic!("{name}: {error}"));
+    if actual != expected {
+        panic!(
+            "{name}: trace differs from {}, rerun with GOLDEN_RECORD=1 if that's \
+             intended\n{}",
+            path.display(),
+            diff(&expected, &actual)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, diff, run};
+    use crate::behaviors::{hold_tap, turbo_key};
+    use crate::time::Duration;
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    turbo_key! {
+        mod turbo {
+            key: 3,
+            output: 4,
+            rate: Duration::from_millis(5),
+        }
+    }
+
+    #[test]
+    fn hold_tap() {
+        check("hold_tap", home_a::IDLE.as_dyn());
+    }
+
+    #[test]
+    fn turbo() {
+        check("turbo", turbo::IDLE.as_dyn());
+    }
+
+    #[test]
+    fn trace_format() {
+        let trace = run(
+            home_a::IDLE.as_dyn(),
+            "# tap\npress 1 => stale\nwait 2\n\ntick\n",
+        )
+        .unwrap();
+        assert_eq!(
+            trace,
+            "# tap\npress 1 => home_a::UNDECIDED []\nwait 2\n\ntick => home_a::UNDECIDED []\n"
+        );
+        assert_eq!(
+            run(home_a::IDLE.as_dyn(), "press\n"),
+            Err("line 1: bad argument in `press`".into())
+        );
+        assert_eq!(
+            run(home_a::IDLE.as_dyn(), "jump 1\n"),
+            Err("line 1: unknown step `jump 1`".into())
+        );
+        assert_eq!(diff("a\nb\n", "a\nc\nd\n"), "  a\n- b\n+ c\n+ d\n");
+    }
+}
@@ -0,0 +1,148 @@
+//! What was last typed.
+//!
+//! An [`OutputHistory`] follows the key presses sent to the host and keeps
+//! the last `N` that typed something, so that features working on the text
+//! being written, such as [snippets](crate::snippets), can look back at it.
+//! Backspace takes the last key back off, and keys that move the cursor or
+//! otherwise leave what was typed behind, like arrows and Escape, forget it
+//! all, as the host's text no longer ends in it. Modifiers are ignored, so
+//! `a` and `A` are the same key here.
+//!
+//! Key codes are HID keyboard usage ids.
+
+use crate::{KeyCode, KeyEvent};
+
+const BACKSPACE: KeyCode = 0x2a;
+const ESCAPE: KeyCode = 0x29;
+
+/// Whether `key` types something, from the letters through to `/`.
+const fn types(key: KeyCode) -> bool {
+    matches!(key, 0x04..=0x38) && key != ESCAPE && key != BACKSPACE
+}
+
+const fn is_modifier(key: KeyCode) -> bool {
+    matches!(key, 0xe0..=0xe7)
+}
+
+pub(crate) struct OutputHistory<const N: usize> {
+    /// A ring, the oldest key at `start`.
+    keys: [KeyCode; N],
+    start: usize,
+    len: usize,
+    /// Keys were typed before the oldest one kept, since it was last
+    /// cleared.
+    dropped: bool,
+}
+
+impl<const N: usize> OutputHistory<N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            keys: [0; N],
+            start: 0,
+            len: 0,
+            dropped: false,
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+        self.dropped = false;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The `i`th key counting back from the last typed, from 0.
+    pub(crate) fn back(&self, i: usize) -> Option<KeyCode> {
+        (i < self.len).then(|| self.keys[(self.start + self.len - 1 - i) % N])
+    }
+
+    /// Whether the keys typed last are `keys`.
+    pub(crate) fn ends_with(&self, keys: &[KeyCode]) -> bool {
+        keys.len() <= self.len
+            && keys
+                .iter()
+                .rev()
+                .enumerate()
+                .all(|(i, key)| self.back(i) == Some(*key))
+    }
+
+    /// Whether the key before the last `len` is in `boundaries`, or there's
+    /// nothing before them.
+    pub(crate) fn starts_word(&self, len: usize, boundaries: &[KeyCode]) -> bool {
+        match self.back(len) {
+            Some(key) => boundaries.contains(&key),
+            None => len == self.len && !self.dropped,
+        }
+    }
+
+    fn push(&mut self, key: KeyCode) {
+        if N == 0 {
+            self.dropped = true;
+        } else if self.len == N {
+            self.keys[self.start] = key;
+            self.start = (self.start + 1) % N;
+            self.dropped = true;
+        } else {
+            self.keys[(self.start + self.len) % N] = key;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) {
+        if self.len > 0 {
+            self.len -= 1;
+        } else {
+            // something older than what's kept was deleted
+            self.dropped = false;
+        }
+    }
+
+    /// Follow `event` being sent to the host.
+    pub(crate) fn observe(&mut self, event: KeyEvent) {
+        match event {
+            KeyEvent::Press(BACKSPACE) => self.pop(),
+            KeyEvent::Press(key) if types(key) => self.push(key),
+            KeyEvent::Press(key) if is_modifier(key) => {}
+            KeyEvent::Press(_)
+            | KeyEvent::MouseMove(..)
+            | KeyEvent::Wheel(..)
+            | KeyEvent::Digitizer(..) => self.clear(),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OutputHistory;
+    use crate::KeyEvent;
+
+    fn typed(history: &mut OutputHistory<4>, keys: &[u8]) {
+        for &key in keys {
+            history.observe(KeyEvent::Press(key));
+            history.observe(KeyEvent::Depress(key));
+        }
+    }
+
+    #[test]
+    fn history() {
+        let mut history = OutputHistory::<4>::new();
+        typed(&mut history, &[0x04, 0xe1, 0x05, 0x2a, 0x06]);
+        assert!(history.ends_with(&[0x04, 0x06]));
+        assert!(history.starts_word(2, &[]));
+        assert_eq!(history.len(), 2);
+
+        typed(&mut history, &[0x2c, 0x07, 0x08, 0x09]);
+        assert!(history.ends_with(&[0x2c, 0x07, 0x08, 0x09]));
+        assert!(history.starts_word(3, &[0x2c]));
+        assert!(!history.starts_word(4, &[0x2c]));
+        assert_eq!(history.back(4), None);
+
+        // arrows leave the text behind
+        typed(&mut history, &[0x50, 0x0a]);
+        assert!(history.ends_with(&[0x0a]));
+        assert!(!history.ends_with(&[0x09, 0x0a]));
+    }
+}
@@ -0,0 +1,348 @@
+//! Translating output for the host's keyboard layout.
+//!
+//! The host turns key codes into symbols through its own layout, so a keymap
+//! written for a US host types the wrong letters on an AZERTY or German one.
+//! [`HostLayouts`] sits on the output side and rewrites the key codes of
+//! presses and releases so that each key code stands for the symbol it has
+//! on a US layout, whatever layout the host is using.
+//!
+//! The layout in use is picked by index with [`HostLayouts::select`], or by
+//! the host itself with an [`InputEvent::HostLayout`], sent as host context
+//! over [raw HID](crate::raw_hid) and passed to [`HostLayouts::handle`]. Keys
+//! held when the layout changes are released as they were sent and pressed
+//! again as the new layout needs, so nothing is left stuck down.
+//!
+//! The tables here only move letters. Symbols that need a different modifier
+//! on the host, like the digits on AZERTY, aren't translated.
+//!
+//! Each layout also lists how the host types characters that US keys don't
+//! have, such as `é` as the `´` dead key followed by `e` on a German host or
+//! as a key of its own on a French one. [`HostLayouts::output`] expands a
+//! [`KeyEvent::Unicode`] into those key strokes when the active layout has
+//! them, leaving other characters for whatever handles unicode input. The
+//! strokes are in the host's key codes, so they aren't translated again.
+
+use crate::{InputEvent, KeyCode, KeyEvent, KeySet};
+
+const LEFT_SHIFT: KeyCode = 0xe1;
+
+/// A key tapped to type part of a character, with shift held if `shift`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct Stroke {
+    pub(crate) key: KeyCode,
+    pub(crate) shift: bool,
+}
+
+impl Stroke {
+    /// Press and release the key, inside Shift if the stroke needs it.
+    pub(crate) fn tap(self, mut emit: impl FnMut(KeyEvent)) {
+        if self.shift {
+            emit(KeyEvent::Press(LEFT_SHIFT));
+        }
+        emit(KeyEvent::Press(self.key));
+        emit(KeyEvent::Depress(self.key));
+        if self.shift {
+            emit(KeyEvent::Depress(LEFT_SHIFT));
+        }
+    }
+}
+
+pub(crate) const fn key(key: KeyCode) -> Stroke {
+    Stroke { key, shift: false }
+}
+
+pub(crate) const fn shifted(key: KeyCode) -> Stroke {
+    Stroke { key, shift: true }
+}
+
+/// A host layout, as the key code to send in place of each one that differs
+/// from a US layout, and the strokes that type characters US keys lack.
+#[derive(Debug)]
+pub(crate) struct HostLayout {
+    pub(crate) name: &'static str,
+    pub(crate) remap: &'static [(KeyCode, KeyCode)],
+    pub(crate) sequences: &'static [(char, &'static [Stroke])],
+}
+
+impl HostLayout {
+    fn translate(&self, key: KeyCode) -> KeyCode {
+        self.remap
+            .iter()
+            .find(|&&(from, _)| from == key)
+            .map_or(key, |&(_, to)| to)
+    }
+
+    fn sequence(&self, c: char) -> Option<&'static [Stroke]> {
+        self.sequences
+            .iter()
+            .find(|&&(from, _)| from == c)
+            .map(|&(_, strokes)| strokes)
+    }
+}
+
+pub(crate) static US: HostLayout = HostLayout {
+    name: "US",
+    remap: &[],
+    sequences: &[],
+};
+
+/// US international, with `'`, `` ` ``, `"` and `^` as dead keys.
+pub(crate) static US_INTERNATIONAL: HostLayout = HostLayout {
+    name: "US international",
+    remap: &[],
+    sequences: &[
+        ('á', &[key(0x34), key(0x04)]),
+        ('é', &[key(0x34), key(0x08)]),
+        ('í', &[key(0x34), key(0x0c)]),
+        ('ó', &[key(0x34), key(0x12)]),
+        ('ú', &[key(0x34), key(0x18)]),
+        ('à', &[key(0x35), key(0x04)]),
+        ('è', &[key(0x35), key(0x08)]),
+        ('ä', &[shifted(0x34), key(0x04)]),
+        ('ë', &[shifted(0x34), key(0x08)]),
+        ('ö', &[shifted(0x34), key(0x12)]),
+        ('ü', &[shifted(0x34), key(0x18)]),
+        ('â', &[shifted(0x23), key(0x04)]),
+        ('ê', &[shifted(0x23), key(0x08)]),
+    ],
+};
+
+/// French AZERTY, with A and Q, Z and W swapped and M where US has `;`.
+pub(crate) static AZERTY: HostLayout = HostLayout {
+    name: "AZERTY",
+    remap: &[
+        (0x04, 0x14),
+        (0x14, 0x04),
+        (0x1d, 0x1a),
+        (0x1a, 0x1d),
+        (0x10, 0x33),
+    ],
+    // the letters are the host's keys, so A is where US has Q
+    sequences: &[
+        ('é', &[key(0x1f)]),
+        ('è', &[key(0x24)]),
+        ('ç', &[key(0x26)]),
+        ('à', &[key(0x27)]),
+        ('ù', &[key(0x34)]),
+        ('â', &[key(0x2f), key(0x14)]),
+        ('ê', &[key(0x2f), key(0x08)]),
+        ('ä', &[shifted(0x2f), key(0x14)]),
+        ('ë', &[shifted(0x2f), key(0x08)]),
+    ],
+};
+
+/// German QWERTZ, with Y and Z swapped.
+pub(crate) static QWERTZ_DE: HostLayout = HostLayout {
+    name: "QWERTZ (DE)",
+    remap: &[(0x1c, 0x1d), (0x1d, 0x1c)],
+    sequences: &[
+        ('ä', &[key(0x34)]),
+        ('ö', &[key(0x33)]),
+        ('ü', &[key(0x2f)]),
+        ('ß', &[key(0x2d)]),
+        ('é', &[key(0x2e), key(0x08)]),
+        ('á', &[key(0x2e), key(0x04)]),
+        ('è', &[shifted(0x2e), key(0x08)]),
+        ('à', &[shifted(0x2e), key(0x04)]),
+        ('ê', &[key(0x35), key(0x08)]),
+        ('â', &[key(0x35), key(0x04)]),
+    ],
+};
+
+pub(crate) struct HostLayouts<const N: usize> {
+    layouts: [&'static HostLayout; N],
+    active: usize,
+    /// Keys pressed and not yet released, before translation.
+    held: KeySet,
+}
+
+impl<const N: usize> HostLayouts<N> {
+    /// Translate for `layouts`, by index, starting with the first.
+    pub(crate) const fn new(layouts: [&'static HostLayout; N]) -> Self {
+        Self {
+            layouts,
+            active: 0,
+            held: KeySet::empty(),
+        }
+    }
+
+    pub(crate) fn active(&self) -> Option<&'static HostLayout> {
+        self.layouts.get(self.active).copied()
+    }
+
+    /// Switch to the layout at `index`, re-sending the held keys whose
+    /// translation changes. Returns `false` and changes nothing if there's
+    /// no such layout.
+    pub(crate) fn select(&mut self, index: usize, mut emit: impl FnMut(KeyEvent)) -> bool {
+        let (Some(old), Some(&new)) = (self.active(), self.layouts.get(index)) else {
+            return false;
+        };
+        self.active = index;
+        for key in self.held.keys() {
+            let (before, after) = (old.translate(key), new.translate(key));
+            if before != after {
+                emit(KeyEvent::Depress(before));
+                emit(KeyEvent::Press(after));
+            }
+        }
+        true
+    }
+
+    /// Select the layout the host asked for, if `event` is the host saying
+    /// which it's using.
+    pub(crate) fn handle(&mut self, event: InputEvent, emit: impl FnMut(KeyEvent)) {
+        if let InputEvent::HostLayout(index) = event {
+            self.select(index as usize, emit);
+        }
+    }
+
+    /// `event` as it should be sent to the host.
+    pub(crate) fn translate(&mut self, event: KeyEvent) -> KeyEvent {
+        let Some(layout) = self.active() else {
+            return event;
+        };
+        match event {
+            KeyEvent::Press(key) => {
+                self.held.insert(key);
+                KeyEvent::Press(layout.translate(key))
+            }
+            KeyEvent::Depress(key) => {
+                self.held.remove(key);
+                KeyEvent::Depress(layout.translate(key))
+            }
+            event => event,
+        }
+    }
+
+    /// Emit `event` as it should be sent to the host, with characters the
+    /// active layout has strokes for typed with them.
+    pub(crate) fn output(&mut self, event: KeyEvent, mut emit: impl FnMut(KeyEvent)) {
+        let strokes = match (event, self.active()) {
+            (KeyEvent::Unicode(c), Some(layout)) => layout.sequence(c),
+            _ => None,
+        };
+        let Some(strokes) = strokes else {
+            emit(self.translate(event));
+            return;
+        };
+        for stroke in strokes {
+            stroke.tap(&mut emit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HostLayouts, AZERTY, QWERTZ_DE, US, US_INTERNATIONAL};
+    use crate::{InputEvent, KeyEvent};
+
+    #[test]
+    fn translate() {
+        let mut layouts = HostLayouts::new([&US, &AZERTY, &QWERTZ_DE]);
+        assert_eq!(
+            layouts.translate(KeyEvent::Press(0x04)),
+            KeyEvent::Press(0x04)
+        );
+        assert_eq!(
+            layouts.translate(KeyEvent::Depress(0x04)),
+            KeyEvent::Depress(0x04)
+        );
+
+        let mut out = Vec::new();
+        layouts.handle(InputEvent::HostLayout(1), |e| out.push(e));
+        assert_eq!(layouts.active().unwrap().name, "AZERTY");
+        assert_eq!(
+            layouts.translate(KeyEvent::Press(0x04)),
+            KeyEvent::Press(0x14)
+        );
+        assert_eq!(
+            layouts.translate(KeyEvent::Press(0x10)),
+            KeyEvent::Press(0x33)
+        );
+        assert_eq!(
+            layouts.translate(KeyEvent::Wheel(1, 0)),
+            KeyEvent::Wheel(1, 0)
+        );
+        assert!(out.is_empty());
+
+        // held keys are sent again as they are on QWERTZ
+        assert!(layouts.select(2, |e| out.push(e)));
+        assert_eq!(
+            out,
+            [
+                KeyEvent::Depress(0x14),
+                KeyEvent::Press(0x04),
+                KeyEvent::Depress(0x33),
+                KeyEvent::Press(0x10)
+            ]
+        );
+        assert_eq!(
+            layouts.translate(KeyEvent::Press(0x1d)),
+            KeyEvent::Press(0x1c)
+        );
+        assert_eq!(
+            layouts.translate(KeyEvent::Depress(0x04)),
+            KeyEvent::Depress(0x04)
+        );
+
+        out.clear();
+        assert!(!layouts.select(3, |e| out.push(e)));
+        layouts.handle(InputEvent::Press(1), |e| out.push(e));
+        assert!(out.is_empty());
+        assert_eq!(layouts.active().unwrap().name, "QWERTZ (DE)");
+    }
+
+    #[test]
+    fn sequences() {
+        let mut layouts = HostLayouts::new([&US, &US_INTERNATIONAL, &AZERTY, &QWERTZ_DE]);
+        let mut output = |layouts: &mut HostLayouts<4>, event| {
+            let mut out = Vec::new();
+            layouts.output(event, |e| out.push(e));
+            out
+        };
+
+        assert_eq!(
+            output(&mut layouts, KeyEvent::Unicode('é')),
+            [KeyEvent::Unicode('é')]
+        );
+
+        layouts.select(1, |_| {});
+        assert_eq!(
+            output(&mut layouts, KeyEvent::Unicode('ü')),
+            [
+                KeyEvent::Press(0xe1),
+                KeyEvent::Press(0x34),
+                KeyEvent::Depress(0x34),
+                KeyEvent::Depress(0xe1),
+                KeyEvent::Press(0x18),
+                KeyEvent::Depress(0x18),
+            ]
+        );
+
+        layouts.select(2, |_| {});
+        assert_eq!(
+            output(&mut layouts, KeyEvent::Unicode('é')),
+            [KeyEvent::Press(0x1f), KeyEvent::Depress(0x1f)]
+        );
+        // strokes aren't translated, other events are
+        assert_eq!(
+            output(&mut layouts, KeyEvent::Unicode('â')),
+            [
+                KeyEvent::Press(0x2f),
+                KeyEvent::Depress(0x2f),
+                KeyEvent::Press(0x14),
+                KeyEvent::Depress(0x14),
+            ]
+        );
+        assert_eq!(
+            output(&mut layouts, KeyEvent::Press(0x04)),
+            [KeyEvent::Press(0x14)]
+        );
+
+        layouts.select(3, |_| {});
+        assert_eq!(
+            output(&mut layouts, KeyEvent::Unicode('€')),
+            [KeyEvent::Unicode('€')]
+        );
+    }
+}
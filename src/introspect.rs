@@ -0,0 +1,133 @@
+//! Describing the flashed machines to host tools.
+//!
+//! A configurator renders the keymap on the keyboard from the keyboard
+//! itself rather than a copy of its source: [`Keymap::machine`] gives each
+//! machine's initial and current state, and the states of a machine are
+//! numbered from `0`, its initial state, in the order a breadth first walk
+//! of its transitions reaches them. The numbering only depends on the
+//! machine, so it's the same every time it's asked for and a host can fetch
+//! the states one at a time with [`state`]. Over [raw HID](crate::raw_hid)
+//! transitions are then described by their target, what they emit and their
+//! conditions as a [`PackedCondition`].
+//!
+//! Machines with more than [`MAX_STATES`] states, which don't validate,
+//! only have their first [`MAX_STATES`] numbered.
+//!
+//! [`Keymap::machine`]: crate::keymap::Keymap::machine
+//! [`PackedCondition`]: crate::packed::PackedCondition
+
+use crate::validate::MAX_STATES;
+use crate::{DynState, State, StateId};
+
+/// Walk the states of the machine starting at `initial` in order, until
+/// `visit` returns `false`, returning how many were visited.
+fn walk(
+    initial: &'static dyn DynState,
+    mut visit: impl FnMut(usize, &'static dyn DynState) -> bool,
+) -> usize {
+    // every state but the initial one is some transition's target
+    let mut found: [Option<&'static State>; MAX_STATES - 1] = [None; MAX_STATES - 1];
+    let mut len = 1;
+
+    let mut next = 0;
+    while next < len {
+        let state = match next {
+            0 => initial,
+            i => found[i - 1].unwrap() as &dyn DynState,
+        };
+        if !visit(next, state) {
+            return next;
+        }
+        for transition in state.transitions() {
+            let target = transition.target;
+            let seen = target.id == initial.id()
+                || found[..len - 1].iter().flatten().any(|s| s.id == target.id);
+            if !seen && len < MAX_STATES {
+                found[len - 1] = Some(target);
+                len += 1;
+            }
+        }
+        next += 1;
+    }
+    len
+}
+
+/// How many states the machine starting at `initial` has.
+pub(crate) fn state_count(initial: &'static dyn DynState) -> usize {
+    walk(initial, |_, _| true)
+}
+
+/// State number `index` of the machine starting at `initial`.
+pub(crate) fn state(initial: &'static dyn DynState, index: usize) -> Option<&'static dyn DynState> {
+    let mut found = None;
+    walk(initial, |i, state| {
+        if i == index {
+            found = Some(state);
+        }
+        i < index
+    });
+    found
+}
+
+/// The state of the machine starting at `initial` with the id `id`.
+pub(crate) fn find(initial: &'static dyn DynState, id: StateId) -> Option<&'static dyn DynState> {
+    let mut found = None;
+    walk(initial, |_, state| {
+        if state.id() == id {
+            found = Some(state);
+        }
+        found.is_none()
+    });
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, state, state_count};
+    use crate::{State, StateId, Transition};
+
+    static A: State = State {
+        name: "A",
+        id: StateId(0),
+        transitions: &[&TO_B, &TO_C],
+    };
+
+    static B: State = State {
+        name: "B",
+        id: StateId(4),
+        transitions: &[&TO_C, &TO_A],
+    };
+
+    static C: State = State {
+        name: "C",
+        id: StateId(2),
+        transitions: &[&TO_A],
+    };
+
+    static TO_A: Transition = Transition {
+        conditions: &[],
+        key_event_emissions: &[],
+        internal_event_emissions: &[],
+        target: &A,
+    };
+
+    static TO_B: Transition = Transition { target: &B, ..TO_A };
+
+    static TO_C: Transition = Transition { target: &C, ..TO_A };
+
+    #[test]
+    fn numbers_states() {
+        assert_eq!(state_count(A.as_dyn()), 3);
+        let names: Vec<_> = (0..3)
+            .map(|i| state(A.as_dyn(), i).unwrap().name().to_owned())
+            .collect();
+        assert_eq!(names, ["A", "B", "C"]);
+        assert!(state(A.as_dyn(), 3).is_none());
+
+        // numbered from wherever the machine starts
+        assert_eq!(state(B.as_dyn(), 1).unwrap().name(), "C");
+
+        assert_eq!(find(A.as_dyn(), StateId(2)).unwrap().name(), "C");
+        assert!(find(A.as_dyn(), StateId(1)).is_none());
+    }
+}
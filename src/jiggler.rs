@@ -0,0 +1,138 @@
+//! Mouse jiggler.
+//!
+//! While active, the pointer is nudged by a pixel and straight back at a
+//! random interval so the host doesn't consider itself idle. The toggle key
+//! switches it on and off, reported through [`Indicator::MouseJiggler`].
+
+use std::ops::RangeInclusive;
+
+use crate::entropy::Entropy;
+use crate::time::{self, Duration, Instant};
+use crate::{Indicator, InputEvent, KeyCode, KeyEvent};
+
+struct Active<Clock: time::Clock> {
+    since: Clock::Instant,
+    /// Milliseconds until the next nudge.
+    wait: u32,
+}
+
+struct MouseJiggler<Clock: time::Clock, E: Entropy> {
+    toggle: KeyCode,
+    interval: RangeInclusive<u32>,
+    entropy: E,
+    active: Option<Active<Clock>>,
+}
+
+impl<Clock: time::Clock, E: Entropy> MouseJiggler<Clock, E> {
+    /// Nudge the pointer every `interval` milliseconds.
+    const fn new(toggle: KeyCode, interval: RangeInclusive<u32>, entropy: E) -> Self {
+        Self {
+            toggle,
+            interval,
+            entropy,
+            active: None,
+        }
+    }
+
+    fn arm(&mut self, current_time: Clock::Instant) {
+        self.active = Some(Active {
+            since: current_time,
+            wait: self.entropy.in_range(self.interval.clone()),
+        });
+    }
+
+    /// Returns the event if it should still be passed on to the machine.
+    fn push(
+        &mut self,
+        current_time: Clock::Instant,
+        event: InputEvent,
+        mut emit: impl FnMut(KeyEvent),
+    ) -> Option<InputEvent> {
+        match event {
+            InputEvent::Press(key) if key == self.toggle => {
+                if self.active.take().is_none() {
+                    self.arm(current_time);
+                }
+                emit(KeyEvent::Indicator(
+                    Indicator::MouseJiggler,
+                    self.active.is_some(),
+                ));
+                None
+            }
+            InputEvent::Depress(key) if key == self.toggle => None,
+            event => Some(event),
+        }
+    }
+
+    fn tick(&mut self, current_time: Clock::Instant, mut emit: impl FnMut(KeyEvent)) {
+        let Some(active) = &self.active else {
+            return;
+        };
+
+        let elapsed = current_time.duration_since(&active.since);
+
+        if elapsed < Duration::from_millis(active.wait) {
+            return;
+        }
+
+        let (dx, dy) = match self.entropy.next_u32() % 4 {
+            0 => (1, 0),
+            1 => (-1, 0),
+            2 => (0, 1),
+            _ => (0, -1),
+        };
+        emit(KeyEvent::MouseMove(dx, dy));
+        emit(KeyEvent::MouseMove(-dx, -dy));
+
+        self.arm(current_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MouseJiggler;
+    use crate::entropy::tests::Sequence;
+    use crate::tests::TickerClock;
+    use crate::{Indicator, InputEvent, KeyEvent};
+
+    #[test]
+    fn jiggles_while_active() {
+        let mut clock = TickerClock(0);
+        let mut jiggler =
+            MouseJiggler::<TickerClock, _>::new(9, 100..=200, Sequence([50, 2, 0], 0));
+        let mut out = Vec::new();
+
+        clock.tick_n(500);
+        jiggler.tick(clock.now(), |e| out.push(e));
+        assert!(out.is_empty());
+
+        assert_eq!(
+            jiggler.push(clock.now(), InputEvent::Press(9), |e| out.push(e)),
+            None
+        );
+        assert_eq!(
+            jiggler.push(clock.now(), InputEvent::Depress(9), |e| out.push(e)),
+            None
+        );
+        assert_eq!(out, [KeyEvent::Indicator(Indicator::MouseJiggler, true)]);
+        out.clear();
+
+        clock.tick_n(149);
+        jiggler.tick(clock.now(), |e| out.push(e));
+        assert!(out.is_empty());
+        clock.tick();
+        jiggler.tick(clock.now(), |e| out.push(e));
+        assert_eq!(out, [KeyEvent::MouseMove(0, 1), KeyEvent::MouseMove(0, -1)]);
+        out.clear();
+
+        jiggler.push(clock.now(), InputEvent::Press(9), |e| out.push(e));
+        clock.tick_n(500);
+        jiggler.tick(clock.now(), |e| out.push(e));
+        assert_eq!(out, [KeyEvent::Indicator(Indicator::MouseJiggler, false)]);
+
+        assert_eq!(
+            jiggler.push(clock.now(), InputEvent::Press(1), |e| out.push(e)),
+            Some(InputEvent::Press(1))
+        );
+    }
+}
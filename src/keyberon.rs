@@ -0,0 +1,118 @@
+//! Adapter for keyberon firmware.
+//!
+//! [`Layout`] stands in for a keyberon `Layout`: it takes the same matrix
+//! events and reports the key codes currently held, so a keyberon firmware
+//! can hand its key handling over to a [`Keymap`] without changing how it
+//! scans the matrix or builds HID reports.
+//!
+//! [`Event`] has the same shape as `keyberon::layout::Event`, for the
+//! firmware to convert with a `match`, and the key codes returned are HID
+//! usage ids, which is what keyberon's `KeyCode` is represented as. Outputs
+//! keyberon has no place for, such as layer changes, are dropped, as are
+//! events for columns past the last or positions past the keymap's keys.
+
+use crate::keymap::Keymap;
+use crate::time::{self, Instant};
+use crate::{InputEvent, KeyCode, KeyEvent};
+
+/// A keyberon matrix event, by row and column.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Event {
+    Press(u8, u8),
+    Release(u8, u8),
+}
+
+struct Layout<
+    Clock: time::Clock,
+    const COLS: usize,
+    const LAYERS: usize,
+    const KEYS: usize,
+    const MACHINES: usize,
+> {
+    keymap: Keymap<Clock, LAYERS, KEYS, MACHINES>,
+}
+
+impl<
+        Clock: time::Clock,
+        const COLS: usize,
+        const LAYERS: usize,
+        const KEYS: usize,
+        const MACHINES: usize,
+    > Layout<Clock, COLS, LAYERS, KEYS, MACHINES>
+{
+    fn new(keymap: Keymap<Clock, LAYERS, KEYS, MACHINES>) -> Self {
+        const { assert!(KEYS <= 256, "positions past 255 can't be pushed") };
+        Self { keymap }
+    }
+
+    /// The keymap position of the key at `row` and `col`, if it has one.
+    fn position(row: u8, col: u8) -> Option<u8> {
+        let position = row as usize * COLS + col as usize;
+        ((col as usize) < COLS && position < KEYS).then_some(position as u8)
+    }
+
+    fn event(&mut self, current_time: Clock::Instant, event: Event) {
+        let event = match event {
+            Event::Press(row, col) => Self::position(row, col).map(InputEvent::Press),
+            Event::Release(row, col) => Self::position(row, col).map(InputEvent::Depress),
+        };
+        if let Some(event) = event {
+            self.keymap.push(current_time, event, |_| {});
+        }
+    }
+
+    /// Call at the same rate keyberon's `Layout::tick` would be.
+    fn tick(&mut self, current_time: Clock::Instant) {
+        self.keymap.tick(current_time, |_| {});
+    }
+
+    /// The key codes to put in the next HID report.
+    fn keycodes(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        self.keymap.reported().keys()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Event, Layout};
+    use crate::behaviors::hold_tap;
+    use crate::keymap::{Action, Keymap};
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    static LAYERS: [[Action; 2]; 1] = [[Action::Key(4), Action::Machine(0)]];
+
+    #[test]
+    fn keycodes() {
+        let mut clock = TickerClock(0);
+        let keymap = Keymap::new(&LAYERS, [home_a::IDLE.as_dyn()], clock.now());
+        let mut layout = Layout::<TickerClock, 2, 1, 2, 1>::new(keymap);
+
+        layout.event(clock.now(), Event::Press(0, 0));
+        layout.event(clock.now(), Event::Press(0, 1));
+        assert!(layout.keycodes().eq([4]));
+
+        clock.tick_n(20);
+        layout.tick(clock.now());
+        assert!(layout.keycodes().eq([4, 0xe1]));
+
+        layout.event(clock.now(), Event::Release(0, 0));
+        layout.event(clock.now(), Event::Release(0, 1));
+        assert_eq!(layout.keycodes().count(), 0);
+
+        // past the last column, past the keymap, and past what fits a byte
+        layout.event(clock.now(), Event::Press(0, 2));
+        layout.event(clock.now(), Event::Press(1, 0));
+        layout.event(clock.now(), Event::Press(200, 1));
+        assert_eq!(layout.keycodes().count(), 0);
+    }
+}
@@ -0,0 +1,1361 @@
+//! Keymap front end.
+//!
+//! A [`Keymap`] resolves each matrix position through its layer tables to an
+//! [`Action`]. Plain keys are emitted directly while positions bound to a
+//! machine are driven through that machine's own runner, so a keymap with a
+//! few hold-tap keys doesn't have to be one big hand written machine.
+//!
+//! Flags and active layers are shared between the keymap and every runner.
+//! Presses and releases are also passed to every runner that has left its
+//! initial state before the key itself is handled, so that for example a
+//! pending hold-tap can resolve to its hold key first. Runners emitting
+//! `PressCurrent`/`DepressCurrent` are ignored, the keymap handles the
+//! current key itself.
+//!
+//! [`KeyEvent::Lighting`] events, both for every press and release and those
+//! emitted by runners, are only passed on once enabled with
+//! [`Keymap::set_lighting`].
+//!
+//! The base of the layer stack is the default layer, initially layer 0. It
+//! is saved along with the toggled flags and any tunable terms with
+//! [`Keymap::settings`].
+//!
+//! What the keymap is doing, down to the state of each machine, can be
+//! carried across a reboot with [`Keymap::session`] and
+//! [`Keymap::resume_session`], see [`session`](crate::session).
+//!
+//! [`Keymap::suspend`] releases every key and layer the host was told about
+//! and returns the runners to their initial states. Until
+//! [`Keymap::resume`], events are ignored except presses of the wake keys
+//! given to [`Keymap::set_wake_keys`], which emit [`KeyEvent::Wake`] without
+//! being resolved. The first such press is replayed on resume, along with
+//! its release if that came while still suspended, so the key that woke the
+//! host isn't lost.
+//!
+//! Events from the same scan can be pushed together with
+//! [`Keymap::push_all`], which puts those at the same instant in the order
+//! set with [`Keymap::set_simultaneous_order`], releases first by default.
+//!
+//! [`Keymap::reload`] swaps in new layer tables and machines at runtime,
+//! such as ones sent by a configurator, releasing everything held first.
+//!
+//! A layer can be given a modifier with [`Keymap::set_layer_modifier`], such
+//! as Shift for a layer of shifted symbols, which is applied weakly to the
+//! keys that layer's [`Action::Key`]s press: it's pressed just before the
+//! key unless the host already has it held, and released with the key or as
+//! soon as another key is pressed, so it never leaks onto the keys typed
+//! after.
+//!
+//! An [`Action::LockingKey`] is for a switch that latches down, which taps
+//! its lock key both when it latches and when it's released, so the host's
+//! Caps, Num or Scroll Lock follows the latch. Once the host has reported
+//! its LEDs with [`InputEvent::HostLeds`] a lock already in the right state
+//! isn't tapped, and a lock that the host toggled some other way is tapped
+//! back to match the latch.
+//!
+//! The host can lock the keyboard's output with [`InputEvent::OutputLock`],
+//! such as while it's being cleaned or the screen is locked. Every key and
+//! layer the host was told about is released, the runners go back to their
+//! initial states, and [`KeyEvent::Indicator`] of [`Indicator::OutputLock`]
+//! is emitted. Until it's unlocked again every other event is ignored, keys
+//! held across the lock are released as nothing.
+
+use crate::introspect;
+use crate::session::{settled, Session};
+use crate::settings::{Settings, PERSISTED_FLAGS};
+use crate::signals::{SignalSource, Signals};
+use crate::simultaneous::{order_simultaneous, SimultaneousOrder};
+use crate::time::{self, Instant};
+use crate::{
+    DynState, GlobalState, Indicator, InputEvent, KeyCode, KeyEvent, KeySet, Layer, Layers,
+    Lighting, StateFlags, StateId, TimedEvent, Transport, TunableTerm, Wireless, MAX_LAYERS,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Action {
+    None,
+    /// Use the action of the next active layer down.
+    Transparent,
+    Key(KeyCode),
+    /// Activate a layer while held.
+    MomentaryLayer(Layer),
+    /// Drive the machine with this index.
+    Machine(usize),
+    /// Emitted on press only.
+    Wireless(Wireless),
+    /// A physically latching lock key, such as a vintage locking Caps Lock,
+    /// tapping the key when it latches down and again when it's released.
+    LockingKey(KeyCode),
+}
+
+impl Action {
+    /// A compact encoding for configuring the keymap from the host, a tag
+    /// followed by up to two arguments.
+    pub(crate) const fn to_bytes(self) -> [u8; 3] {
+        match self {
+            Action::None => [0, 0, 0],
+            Action::Transparent => [1, 0, 0],
+            Action::Key(key) => [2, key, 0],
+            Action::MomentaryLayer(layer) => [3, layer, 0],
+            Action::Machine(machine) => [4, machine as u8, 0],
+            Action::Wireless(wireless) => {
+                let (tag, arg) = match wireless {
+                    Wireless::SelectProfile(profile) => (0, profile),
+                    Wireless::NextProfile => (1, 0),
+                    Wireless::PreviousProfile => (2, 0),
+                    Wireless::ClearBond => (3, 0),
+                    Wireless::ClearAllBonds => (4, 0),
+                    Wireless::SetTransport(Transport::Usb) => (5, 0),
+                    Wireless::SetTransport(Transport::Ble) => (5, 1),
+                    Wireless::ToggleTransport => (6, 0),
+                };
+                [5, tag, arg]
+            }
+            Action::LockingKey(key) => [6, key, 0],
+        }
+    }
+
+    pub(crate) const fn from_bytes([tag, x, y]: [u8; 3]) -> Option<Self> {
+        Some(match (tag, x, y) {
+            (0, ..) => Action::None,
+            (1, ..) => Action::Transparent,
+            (2, key, _) => Action::Key(key),
+            (3, layer, _) => Action::MomentaryLayer(layer),
+            (4, machine, _) => Action::Machine(machine as usize),
+            (5, 0, profile) => Action::Wireless(Wireless::SelectProfile(profile)),
+            (5, 1, _) => Action::Wireless(Wireless::NextProfile),
+            (5, 2, _) => Action::Wireless(Wireless::PreviousProfile),
+            (5, 3, _) => Action::Wireless(Wireless::ClearBond),
+            (5, 4, _) => Action::Wireless(Wireless::ClearAllBonds),
+            (5, 5, 0) => Action::Wireless(Wireless::SetTransport(Transport::Usb)),
+            (5, 5, 1) => Action::Wireless(Wireless::SetTransport(Transport::Ble)),
+            (5, 6, _) => Action::Wireless(Wireless::ToggleTransport),
+            (6, key, _) => Action::LockingKey(key),
+            _ => return None,
+        })
+    }
+}
+
+/// The host LED showing what a lock key toggles, as its bit of
+/// [`InputEvent::HostLeds`].
+const fn lock_led(key: KeyCode) -> Option<u8> {
+    match key {
+        0x53 => Some(1 << 0),
+        0x39 => Some(1 << 1),
+        0x47 => Some(1 << 2),
+        _ => None,
+    }
+}
+
+/// Why [`Keymap::reload`] turned down a replacement.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum ReloadError {
+    /// An action drives a machine that isn't given.
+    UnknownMachine(usize),
+    /// An action activates a layer that isn't in the tables.
+    UnknownLayer(Layer),
+    /// The initial state of the machine with this index has no transitions.
+    NoTransitions(usize),
+}
+
+pub(crate) struct Keymap<
+    Clock: time::Clock,
+    const LAYERS: usize,
+    const KEYS: usize,
+    const MACHINES: usize,
+> {
+    /// Copied from the initial tables so they can be changed at runtime.
+    layers: [[Action; KEYS]; LAYERS],
+    machines: [&'static dyn DynState; MACHINES],
+    runners: [GlobalState<Clock>; MACHINES],
+    flags: StateFlags,
+    default_layer: Layer,
+    active_layers: Layers,
+    /// What each held key resolved to when pressed, so that it is released
+    /// the same way even if the active layers changed since.
+    held: [Option<Action>; KEYS],
+    /// Keys the host has been told are pressed.
+    reported: KeySet,
+    lighting: bool,
+    suspended: bool,
+    /// Positions that wake the host while suspended.
+    wake_keys: &'static [u8],
+    /// The wake key pressed while suspended, to replay on resume, and
+    /// whether it's been released since.
+    waking: Option<(u8, bool)>,
+    /// How [`Keymap::push_all`] orders events at the same instant.
+    order: SimultaneousOrder,
+    /// The modifier applied to the keys of each layer, see
+    /// [`Keymap::set_layer_modifier`].
+    layer_modifiers: [Option<KeyCode>; LAYERS],
+    /// A modifier pressed for the key after it, and that key.
+    weak: Option<(KeyCode, KeyCode)>,
+    /// The lock LEDs as the host last set them.
+    host_leds: Option<u8>,
+    /// A lock key was tapped and the host hasn't set its LEDs since, so the
+    /// next report may be from before the tap.
+    leds_pending: bool,
+    /// Set by [`InputEvent::OutputLock`].
+    output_locked: bool,
+}
+
+impl<Clock: time::Clock, const LAYERS: usize, const KEYS: usize, const MACHINES: usize>
+    Keymap<Clock, LAYERS, KEYS, MACHINES>
+{
+    pub(crate) fn new(
+        layers: &'static [[Action; KEYS]; LAYERS],
+        machines: [&'static dyn DynState; MACHINES],
+        current_time: Clock::Instant,
+    ) -> Self {
+        const { assert!(LAYERS <= MAX_LAYERS, "too many layers") };
+        Self {
+            layers: *layers,
+            machines,
+            runners: machines.map(|machine| GlobalState::new(machine, current_time)),
+            flags: StateFlags::empty(),
+            default_layer: 0,
+            active_layers: Layers::empty(),
+            held: [None; KEYS],
+            reported: KeySet::empty(),
+            lighting: false,
+            suspended: false,
+            wake_keys: &[],
+            waking: None,
+            order: SimultaneousOrder::default(),
+            layer_modifiers: [None; LAYERS],
+            weak: None,
+            host_leds: None,
+            leds_pending: false,
+            output_locked: false,
+        }
+    }
+
+    /// Set the layer below all the others, returning whether the keymap has
+    /// it.
+    pub(crate) fn set_default_layer(&mut self, layer: Layer) -> bool {
+        if layer as usize >= LAYERS {
+            return false;
+        }
+        self.default_layer = layer;
+        true
+    }
+
+    pub(crate) fn default_layer(&self) -> Layer {
+        self.default_layer
+    }
+
+    pub(crate) fn active_layers(&self) -> Layers {
+        self.active_layers
+    }
+
+    /// Keys the host has been told are pressed.
+    pub(crate) fn reported(&self) -> KeySet {
+        self.reported
+    }
+
+    /// The initial state of machine `index`, and the state it's in now.
+    pub(crate) fn machine(
+        &self,
+        index: usize,
+    ) -> Option<(&'static dyn DynState, &'static dyn DynState)> {
+        Some((
+            *self.machines.get(index)?,
+            self.runners[index].current_state,
+        ))
+    }
+
+    pub(crate) fn action(&self, layer: usize, position: usize) -> Option<Action> {
+        self.layers.get(layer)?.get(position).copied()
+    }
+
+    /// Replace the action at `position` on `layer`, returning whether it
+    /// exists and the action is for a machine and layer the keymap has.
+    /// Held keys are still released as what they were pressed as.
+    pub(crate) fn set_action(&mut self, layer: usize, position: usize, action: Action) -> bool {
+        if Self::check_action(action).is_err() {
+            return false;
+        }
+        let Some(slot) = self.layers.get_mut(layer).and_then(|l| l.get_mut(position)) else {
+            return false;
+        };
+
+        *slot = action;
+        true
+    }
+
+    fn settings<const TERMS: usize>(&self, terms: &[&TunableTerm; TERMS]) -> Settings<TERMS> {
+        Settings {
+            default_layer: self.default_layer,
+            flags: self.flags & PERSISTED_FLAGS,
+            terms: terms.map(|term| term.get()),
+        }
+    }
+
+    fn apply_settings<const TERMS: usize>(
+        &mut self,
+        settings: &Settings<TERMS>,
+        terms: &[&TunableTerm; TERMS],
+    ) {
+        // saved by firmware with more layers, or corrupt
+        if (settings.default_layer as usize) < LAYERS {
+            self.default_layer = settings.default_layer;
+        }
+        self.flags = (self.flags - PERSISTED_FLAGS) | (settings.flags & PERSISTED_FLAGS);
+        settings.apply_terms(terms);
+    }
+
+    fn session(&self) -> Session<MACHINES> {
+        let mut layers = self.active_layers;
+        for action in self.held.iter().flatten() {
+            if let Action::MomentaryLayer(layer) = action {
+                layers.deactivate(*layer);
+            }
+        }
+
+        let mut states = [StateId(0); MACHINES];
+        for (machine, id) in states.iter_mut().enumerate() {
+            let current = self.runners[machine].current_state;
+            *id = if settled(current) {
+                current.id()
+            } else {
+                self.machines[machine].id()
+            };
+        }
+
+        Session {
+            default_layer: self.default_layer,
+            flags: self.flags & PERSISTED_FLAGS,
+            layers,
+            states,
+        }
+    }
+
+    /// Pick up where `session` left off, at boot before any events. Restored
+    /// layers are announced as activated.
+    fn resume_session(
+        &mut self,
+        session: &Session<MACHINES>,
+        current_time: Clock::Instant,
+        mut emit: impl FnMut(KeyEvent),
+    ) {
+        if (session.default_layer as usize) < LAYERS {
+            self.default_layer = session.default_layer;
+        }
+        self.flags = (self.flags - PERSISTED_FLAGS) | (session.flags & PERSISTED_FLAGS);
+        // layers the keymap doesn't have are left off
+        for layer in 0..LAYERS as Layer {
+            if session.layers.is_active(layer) && !self.active_layers.is_active(layer) {
+                self.active_layers.activate(layer);
+                emit(KeyEvent::LayerActivated(layer));
+            }
+        }
+
+        for (machine, id) in session.states.iter().enumerate() {
+            let initial = self.machines[machine];
+            let state = introspect::find(initial, *id).filter(|state| settled(*state));
+            let runner = &mut self.runners[machine];
+            runner.current_state = state.unwrap_or(initial);
+            runner.entered_state = current_time;
+        }
+    }
+
+    /// Apply `modifier` to the keys pressed from `layer`, or nothing if
+    /// `None`. Returns `false` if there's no such layer.
+    fn set_layer_modifier(&mut self, layer: usize, modifier: Option<KeyCode>) -> bool {
+        let Some(slot) = self.layer_modifiers.get_mut(layer) else {
+            return false;
+        };
+
+        *slot = modifier;
+        true
+    }
+
+    fn set_wake_keys(&mut self, positions: &'static [u8]) {
+        self.wake_keys = positions;
+    }
+
+    /// Pass an event on, keeping track of which keys the host sees pressed.
+    fn report(&mut self, event: KeyEvent, emit: &mut impl FnMut(KeyEvent)) {
+        if let (Some((modifier, _)), KeyEvent::Press(key)) = (self.weak, event) {
+            if key == modifier {
+                // held for real now, so it stays down
+                self.weak = None;
+                return;
+            }
+            self.release_weak(emit);
+        }
+
+        match event {
+            KeyEvent::Press(key) => self.reported.insert(key),
+            KeyEvent::Depress(key) => {
+                self.reported.remove(key);
+            }
+            _ => {}
+        }
+        emit(event);
+
+        if let (Some((_, weak_key)), KeyEvent::Depress(key)) = (self.weak, event) {
+            if key == weak_key {
+                self.release_weak(emit);
+            }
+        }
+    }
+
+    /// Press `key` with `modifier` applied weakly, unless the host already
+    /// has it held.
+    fn press_modified(&mut self, key: KeyCode, modifier: KeyCode, emit: &mut impl FnMut(KeyEvent)) {
+        match self.weak.take() {
+            // still pressed for the key before
+            Some((weak, _)) if weak == modifier => {}
+            weak => {
+                self.weak = weak;
+                self.release_weak(emit);
+                if self.reported.contains(modifier) {
+                    self.report(KeyEvent::Press(key), emit);
+                    return;
+                }
+                self.reported.insert(modifier);
+                emit(KeyEvent::Press(modifier));
+            }
+        }
+
+        self.report(KeyEvent::Press(key), emit);
+        self.weak = Some((modifier, key));
+    }
+
+    fn release_weak(&mut self, emit: &mut impl FnMut(KeyEvent)) {
+        if let Some((modifier, _)) = self.weak.take() {
+            self.reported.remove(modifier);
+            emit(KeyEvent::Depress(modifier));
+        }
+    }
+
+    fn tap(&mut self, key: KeyCode, emit: &mut impl FnMut(KeyEvent)) {
+        self.report(KeyEvent::Press(key), emit);
+        self.report(KeyEvent::Depress(key), emit);
+        self.leds_pending = true;
+    }
+
+    /// Tap a locking key's key unless the host's LED already shows it as
+    /// `latched`.
+    fn sync_lock(&mut self, key: KeyCode, latched: bool, emit: &mut impl FnMut(KeyEvent)) {
+        let on = match (lock_led(key), self.host_leds) {
+            (Some(led), Some(leds)) => Some(leds & led != 0),
+            _ => None,
+        };
+        if on != Some(latched) {
+            self.tap(key, emit);
+        }
+    }
+
+    /// Bring the host's toggles back in line with the latches of the locking
+    /// keys, after it set its LEDs to `leds`.
+    fn resync_locks(&mut self, leds: u8, emit: &mut impl FnMut(KeyEvent)) {
+        self.host_leds = Some(leds);
+        if core::mem::take(&mut self.leds_pending) {
+            return;
+        }
+
+        for key in [0x53, 0x39, 0x47] {
+            let locking = Some(Action::LockingKey(key));
+            if !self.layers.iter().flatten().any(|a| Some(*a) == locking) {
+                continue;
+            }
+            let latched = self.held.contains(&locking);
+            self.sync_lock(key, latched, emit);
+        }
+    }
+
+    /// Release every key and layer the host was told about, and forget what
+    /// the held keys were pressed as.
+    fn release_all(&mut self, emit: &mut impl FnMut(KeyEvent)) {
+        for key in 0..=u8::MAX {
+            if self.reported.remove(key) {
+                emit(KeyEvent::Depress(key));
+            }
+        }
+        // machines can activate any layer, not only the keymap's
+        for layer in 0..MAX_LAYERS as Layer {
+            if self.active_layers.is_active(layer) {
+                emit(KeyEvent::LayerDeactivated(layer));
+            }
+        }
+
+        self.active_layers = Layers::empty();
+        self.held = [None; KEYS];
+        self.weak = None;
+    }
+
+    /// Release everything and return the runners to their initial states,
+    /// stopping them until [`Keymap::restart_runners`].
+    fn stop_runners(&mut self, emit: &mut impl FnMut(KeyEvent)) {
+        self.release_all(emit);
+        for (runner, machine) in self.runners.iter_mut().zip(self.machines) {
+            runner.current_state = machine;
+            runner.suspend();
+        }
+    }
+
+    fn restart_runners(&mut self, current_time: Clock::Instant) {
+        for runner in &mut self.runners {
+            runner.resume(current_time);
+        }
+    }
+
+    fn suspend(&mut self, mut emit: impl FnMut(KeyEvent)) {
+        self.stop_runners(&mut emit);
+        self.suspended = true;
+        self.waking = None;
+    }
+
+    /// Start handling events again, replaying the press of the wake key that
+    /// woke the host, if one did.
+    fn resume(&mut self, current_time: Clock::Instant, mut emit: impl FnMut(KeyEvent)) {
+        if !self.output_locked {
+            self.restart_runners(current_time);
+        }
+        self.suspended = false;
+
+        if let Some((position, released)) = self.waking.take() {
+            self.push(current_time, InputEvent::Press(position), &mut emit);
+            if released {
+                self.push(current_time, InputEvent::Depress(position), &mut emit);
+            }
+        }
+    }
+
+    /// Swap in new layer tables and machines, or leave the keymap as it was
+    /// if they're malformed.
+    ///
+    /// Every held key and layer is released first, and the runners start
+    /// over in their new initial states. The persisted flags carry over, as
+    /// does the default layer if the new tables have it. Keys still held
+    /// across the swap are released as nothing, like across a suspend.
+    fn reload(
+        &mut self,
+        layers: &[[Action; KEYS]; LAYERS],
+        machines: [&'static dyn DynState; MACHINES],
+        current_time: Clock::Instant,
+        mut emit: impl FnMut(KeyEvent),
+    ) -> Result<(), ReloadError> {
+        if let Some(machine) = machines.iter().position(|m| m.transitions().is_empty()) {
+            return Err(ReloadError::NoTransitions(machine));
+        }
+        for action in layers.iter().flatten() {
+            Self::check_action(*action)?;
+        }
+
+        self.release_all(&mut emit);
+        self.layers = *layers;
+        self.machines = machines;
+        self.runners = machines.map(|machine| GlobalState::new(machine, current_time));
+        if self.suspended || self.output_locked {
+            for runner in &mut self.runners {
+                runner.suspend();
+            }
+        }
+        self.flags &= PERSISTED_FLAGS;
+        if self.default_layer as usize >= LAYERS {
+            self.default_layer = 0;
+        }
+        Ok(())
+    }
+
+    /// Turn down an action for a machine or layer the keymap doesn't have.
+    fn check_action(action: Action) -> Result<(), ReloadError> {
+        match action {
+            Action::Machine(machine) if machine >= MACHINES => {
+                Err(ReloadError::UnknownMachine(machine))
+            }
+            Action::MomentaryLayer(layer) if layer as usize >= LAYERS => {
+                Err(ReloadError::UnknownLayer(layer))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn set_lighting(&mut self, enabled: bool) {
+        self.lighting = enabled;
+    }
+
+    /// The action of `position` on the highest active layer, the default
+    /// layer is always active, and the modifier of the layer it's on.
+    fn resolve(&self, position: u8) -> (Action, Option<KeyCode>) {
+        (0..LAYERS)
+            .rev()
+            .filter(|layer| {
+                *layer == self.default_layer as usize
+                    || self.active_layers.is_active(*layer as Layer)
+            })
+            .filter_map(|layer| Some((*self.layers[layer].get(position as usize)?, layer)))
+            .find(|(action, _)| *action != Action::Transparent)
+            .map_or((Action::None, None), |(action, layer)| {
+                (action, self.layer_modifiers[layer])
+            })
+    }
+
+    fn run(
+        &mut self,
+        machine: usize,
+        emit: &mut impl FnMut(KeyEvent),
+        f: impl FnOnce(&mut GlobalState<Clock>) -> &[KeyEvent],
+    ) {
+        let runner = &mut self.runners[machine];
+        runner.flags = self.flags;
+        runner.layers = self.active_layers;
+
+        let len = f(runner).len();
+
+        self.flags = runner.flags;
+        self.active_layers = runner.layers;
+
+        for i in 0..len {
+            match self.runners[machine].emitted()[i] {
+                KeyEvent::PressCurrent | KeyEvent::DepressCurrent => {}
+                KeyEvent::Lighting(..) if !self.lighting => {}
+                event => self.report(event, emit),
+            }
+        }
+    }
+
+    pub(crate) fn push(
+        &mut self,
+        current_time: Clock::Instant,
+        event: InputEvent,
+        mut emit: impl FnMut(KeyEvent),
+    ) {
+        if let InputEvent::OutputLock(locked) = event {
+            return self.lock_output(current_time, locked, &mut emit);
+        }
+        if self.output_locked {
+            return;
+        }
+        if self.suspended {
+            match event {
+                InputEvent::Press(position) if self.wake_keys.contains(&position) => {
+                    // a wake key still held is the one to replay
+                    if !matches!(self.waking, Some((_, false))) {
+                        self.waking = Some((position, false));
+                    }
+                    emit(KeyEvent::Wake);
+                }
+                InputEvent::Depress(position) if self.waking == Some((position, false)) => {
+                    self.waking = Some((position, true));
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        let (position, pressed) = match event {
+            InputEvent::Press(position) => (position, true),
+            InputEvent::Depress(position) => (position, false),
+            event => {
+                if let InputEvent::HostLeds(leds) = event {
+                    self.resync_locks(leds, &mut emit);
+                }
+                for machine in 0..MACHINES {
+                    self.run(machine, &mut emit, |r| r.push(current_time, event));
+                }
+                return;
+            }
+        };
+
+        if position as usize >= KEYS {
+            return;
+        }
+
+        let (action, modifier) = if pressed {
+            let (action, modifier) = self.resolve(position);
+            self.held[position as usize] = Some(action);
+            (action, modifier)
+        } else {
+            (
+                self.held[position as usize].take().unwrap_or(Action::None),
+                None,
+            )
+        };
+
+        if self.lighting {
+            let kind = if pressed {
+                Lighting::Pressed
+            } else {
+                Lighting::Released
+            };
+            emit(KeyEvent::Lighting(position, kind));
+        }
+
+        for machine in 0..MACHINES {
+            let busy = self.runners[machine].current_state != self.machines[machine];
+            if busy && action != Action::Machine(machine) {
+                self.run(machine, &mut emit, |r| r.push(current_time, event));
+            }
+        }
+
+        match action {
+            Action::None | Action::Transparent => {}
+            Action::Key(key) if pressed => match modifier {
+                Some(modifier) => self.press_modified(key, modifier, &mut emit),
+                None => self.report(KeyEvent::Press(key), &mut emit),
+            },
+            Action::Key(key) => self.report(KeyEvent::Depress(key), &mut emit),
+            Action::MomentaryLayer(layer) if pressed => {
+                self.active_layers.activate(layer);
+                emit(KeyEvent::LayerActivated(layer));
+            }
+            // held up by another key for the same layer
+            Action::MomentaryLayer(layer)
+                if self.held.contains(&Some(Action::MomentaryLayer(layer))) => {}
+            Action::MomentaryLayer(layer) => {
+                self.active_layers.deactivate(layer);
+                emit(KeyEvent::LayerDeactivated(layer));
+            }
+            Action::Machine(machine) if machine < MACHINES => {
+                self.run(machine, &mut emit, |r| r.push(current_time, event));
+            }
+            Action::Machine(_) => {}
+            Action::Wireless(wireless) if pressed => emit(KeyEvent::Wireless(wireless)),
+            Action::Wireless(_) => {}
+            Action::LockingKey(key) => self.sync_lock(key, pressed, &mut emit),
+        }
+    }
+
+    /// Lock or unlock the output, see [`InputEvent::OutputLock`].
+    fn lock_output(
+        &mut self,
+        current_time: Clock::Instant,
+        locked: bool,
+        emit: &mut impl FnMut(KeyEvent),
+    ) {
+        if locked == self.output_locked {
+            return;
+        }
+        if locked {
+            self.stop_runners(emit);
+        } else if !self.suspended {
+            self.restart_runners(current_time);
+        }
+        self.output_locked = locked;
+        emit(KeyEvent::Indicator(Indicator::OutputLock, locked));
+    }
+
+    fn set_simultaneous_order(&mut self, order: SimultaneousOrder) {
+        self.order = order;
+    }
+
+    /// Push a batch of events, such as the edges found by one matrix scan,
+    /// each at its own time. Events at the same instant are pushed in the
+    /// order set with [`Keymap::set_simultaneous_order`].
+    fn push_all(&mut self, events: &mut [TimedEvent<Clock>], mut emit: impl FnMut(KeyEvent)) {
+        order_simultaneous(self.order, events);
+        for event in events {
+            self.push(event.time, event.event, &mut emit);
+        }
+    }
+
+    pub(crate) fn tick(&mut self, current_time: Clock::Instant, mut emit: impl FnMut(KeyEvent)) {
+        for machine in 0..MACHINES {
+            self.run(machine, &mut emit, |r| r.tick(current_time));
+        }
+    }
+
+    /// Take new readings from `source` for every machine, then tick them.
+    fn poll_signals(
+        &mut self,
+        current_time: Clock::Instant,
+        source: &mut impl SignalSource,
+        mut emit: impl FnMut(KeyEvent),
+    ) {
+        // every runner has the same readings, those kept for the channels
+        // that have none this time
+        let mut signals = self
+            .runners
+            .first()
+            .map_or(Signals::default(), |r| r.signals);
+        signals.poll(source);
+        for machine in 0..MACHINES {
+            self.run(machine, &mut emit, |r| {
+                r.signals = signals;
+                r.tick(current_time)
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Keymap, ReloadError};
+    use crate::behaviors::hold_tap;
+    use crate::session::Session;
+    use crate::settings::Settings;
+    use crate::simultaneous::SimultaneousOrder;
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{
+        Indicator, InputEvent, KeyEvent, Layers, Lighting, State, StateFlags, StateId, TimedEvent,
+        Transition, TransitionCondition, Transport, TunableTerm, Wireless,
+    };
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    static LAYERS: [[Action; 3]; 2] = [
+        [
+            Action::Key(4),
+            Action::Machine(0),
+            Action::MomentaryLayer(1),
+        ],
+        [Action::Key(5), Action::Transparent, Action::None],
+    ];
+
+    fn keymap(clock: &TickerClock) -> Keymap<TickerClock, 2, 3, 1> {
+        Keymap::new(&LAYERS, [home_a::IDLE.as_dyn()], clock.now())
+    }
+
+    fn push(
+        keymap: &mut Keymap<TickerClock, 2, 3, 1>,
+        clock: &TickerClock,
+        event: InputEvent,
+    ) -> Vec<KeyEvent> {
+        let mut out = Vec::new();
+        keymap.push(clock.now(), event, |e| out.push(e));
+        out
+    }
+
+    #[test]
+    fn layers() {
+        let clock = TickerClock(0);
+        let mut keymap = keymap(&clock);
+
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Press(0)),
+            [KeyEvent::Press(4)]
+        );
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Depress(0)),
+            [KeyEvent::Depress(4)]
+        );
+
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Press(2)),
+            [KeyEvent::LayerActivated(1)]
+        );
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Press(0)),
+            [KeyEvent::Press(5)]
+        );
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Depress(2)),
+            [KeyEvent::LayerDeactivated(1)]
+        );
+
+        // released as what it was pressed as
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Depress(0)),
+            [KeyEvent::Depress(5)]
+        );
+    }
+
+    #[test]
+    fn machines() {
+        let mut clock = TickerClock(0);
+        let mut keymap = keymap(&clock);
+
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Press(1)), []);
+        clock.tick();
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Depress(1)),
+            [KeyEvent::Press(6), KeyEvent::Depress(6)]
+        );
+
+        push(&mut keymap, &clock, InputEvent::Press(1));
+        clock.tick();
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Press(0)),
+            [KeyEvent::Press(0xe1), KeyEvent::Press(4)]
+        );
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Depress(0)),
+            [KeyEvent::Depress(4)]
+        );
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Depress(1)),
+            [KeyEvent::Depress(0xe1)]
+        );
+
+        push(&mut keymap, &clock, InputEvent::Press(1));
+        clock.tick_n(10);
+        let mut out = Vec::new();
+        keymap.tick(clock.now(), |e| out.push(e));
+        assert_eq!(out, [KeyEvent::Press(0xe1)]);
+    }
+
+    #[test]
+    fn wireless() {
+        static LAYERS: [[Action; 2]; 1] = [[
+            Action::Wireless(Wireless::SelectProfile(2)),
+            Action::Wireless(Wireless::SetTransport(Transport::Ble)),
+        ]];
+        let clock = TickerClock(0);
+        let mut keymap = Keymap::<TickerClock, 1, 2, 0>::new(&LAYERS, [], clock.now());
+        let mut out = Vec::new();
+
+        keymap.push(clock.now(), InputEvent::Press(0), |e| out.push(e));
+        keymap.push(clock.now(), InputEvent::Depress(0), |e| out.push(e));
+        keymap.push(clock.now(), InputEvent::Press(1), |e| out.push(e));
+        assert_eq!(
+            out,
+            [
+                KeyEvent::Wireless(Wireless::SelectProfile(2)),
+                KeyEvent::Wireless(Wireless::SetTransport(Transport::Ble)),
+            ]
+        );
+    }
+
+    #[test]
+    fn suspend() {
+        let mut clock = TickerClock(0);
+        let mut keymap = keymap(&clock);
+        keymap.set_wake_keys(&[0]);
+
+        push(&mut keymap, &clock, InputEvent::Press(2));
+        push(&mut keymap, &clock, InputEvent::Press(0));
+        push(&mut keymap, &clock, InputEvent::Press(1));
+        clock.tick_n(10);
+        keymap.tick(clock.now(), |_| {});
+
+        let mut out = Vec::new();
+        keymap.suspend(|e| out.push(e));
+        assert_eq!(
+            out,
+            [
+                KeyEvent::Depress(5),
+                KeyEvent::Depress(0xe1),
+                KeyEvent::LayerDeactivated(1),
+            ]
+        );
+
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Press(1)), []);
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Depress(0)), []);
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Press(0)),
+            [KeyEvent::Wake]
+        );
+
+        // the press that woke the host is replayed, releases of keys held
+        // over the suspend are dropped
+        let mut out = Vec::new();
+        keymap.resume(clock.now(), |e| out.push(e));
+        assert_eq!(out, [KeyEvent::Press(4)]);
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Depress(2)), []);
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Depress(0)),
+            [KeyEvent::Depress(4)]
+        );
+
+        // a wake key tapped while suspended is replayed as a tap
+        keymap.suspend(|_| {});
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Press(0)),
+            [KeyEvent::Wake]
+        );
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Depress(0)), []);
+        out.clear();
+        keymap.resume(clock.now(), |e| out.push(e));
+        assert_eq!(out, [KeyEvent::Press(4), KeyEvent::Depress(4)]);
+
+        // nothing is replayed without a wake
+        keymap.suspend(|_| {});
+        out.clear();
+        keymap.resume(clock.now(), |e| out.push(e));
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn output_lock() {
+        let mut clock = TickerClock(0);
+        let mut keymap = keymap(&clock);
+
+        push(&mut keymap, &clock, InputEvent::Press(0));
+        push(&mut keymap, &clock, InputEvent::Press(1));
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::OutputLock(true)),
+            [
+                KeyEvent::Depress(4),
+                KeyEvent::Indicator(Indicator::OutputLock, true)
+            ]
+        );
+        assert_eq!(push(&mut keymap, &clock, InputEvent::OutputLock(true)), []);
+
+        // the pending hold-tap was dropped, and nothing gets through
+        clock.tick_n(20);
+        let mut out = Vec::new();
+        keymap.tick(clock.now(), |e| out.push(e));
+        assert!(out.is_empty());
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Press(2)), []);
+        assert_eq!(push(&mut keymap, &clock, InputEvent::HostLeds(0)), []);
+
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::OutputLock(false)),
+            [KeyEvent::Indicator(Indicator::OutputLock, false)]
+        );
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Depress(0)), []);
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Depress(1)), []);
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Press(0)),
+            [KeyEvent::Press(4)]
+        );
+
+        // unlocking while suspended leaves the runners stopped
+        keymap.suspend(|_| {});
+        push(&mut keymap, &clock, InputEvent::OutputLock(true));
+        push(&mut keymap, &clock, InputEvent::OutputLock(false));
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Press(1)), []);
+        keymap.resume(clock.now(), |_| {});
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Press(1)), []);
+        clock.tick_n(20);
+        let mut out = Vec::new();
+        keymap.tick(clock.now(), |e| out.push(e));
+        assert_eq!(out, [KeyEvent::Press(0xe1)]);
+    }
+
+    #[test]
+    fn shared_momentary_layer() {
+        let clock = TickerClock(0);
+        let mut keymap = keymap(&clock);
+        assert!(keymap.set_action(0, 0, Action::MomentaryLayer(1)));
+        assert!(keymap.set_action(1, 2, Action::Transparent));
+
+        push(&mut keymap, &clock, InputEvent::Press(0));
+        push(&mut keymap, &clock, InputEvent::Press(2));
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Depress(0)), []);
+        assert!(keymap.active_layers().is_active(1));
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Depress(2)),
+            [KeyEvent::LayerDeactivated(1)]
+        );
+        assert!(!keymap.active_layers().is_active(1));
+
+        assert!(!keymap.set_default_layer(2));
+        assert_eq!(keymap.default_layer(), 0);
+    }
+
+    #[test]
+    fn reload() {
+        static REPLACEMENT: [[Action; 3]; 2] = [
+            [Action::Key(7), Action::Machine(0), Action::None],
+            [Action::None; 3],
+        ];
+        let mut clock = TickerClock(0);
+        let mut keymap = keymap(&clock);
+        keymap.flags = StateFlags::GAME_MODE | StateFlags::SHFT;
+
+        push(&mut keymap, &clock, InputEvent::Press(2));
+        keymap.set_default_layer(1);
+        push(&mut keymap, &clock, InputEvent::Press(0));
+        push(&mut keymap, &clock, InputEvent::Press(1));
+
+        let mut broken = REPLACEMENT;
+        broken[1][2] = Action::Machine(1);
+        let mut out = Vec::new();
+        assert_eq!(
+            keymap.reload(&broken, [home_a::IDLE.as_dyn()], clock.now(), |e| out
+                .push(e)),
+            Err(ReloadError::UnknownMachine(1))
+        );
+        broken[1][2] = Action::MomentaryLayer(2);
+        assert_eq!(
+            keymap.reload(&broken, [home_a::IDLE.as_dyn()], clock.now(), |e| out
+                .push(e)),
+            Err(ReloadError::UnknownLayer(2))
+        );
+        assert_eq!(out, []);
+        assert_eq!(keymap.action(0, 0), Some(Action::Key(4)));
+
+        keymap
+            .reload(&REPLACEMENT, [home_a::IDLE.as_dyn()], clock.now(), |e| {
+                out.push(e)
+            })
+            .unwrap();
+        assert_eq!(out, [KeyEvent::Depress(5), KeyEvent::LayerDeactivated(1)]);
+        assert_eq!(keymap.flags, StateFlags::GAME_MODE);
+        assert_eq!(keymap.default_layer(), 1);
+
+        // the hold-tap key pressed before the swap is forgotten
+        clock.tick_n(10);
+        let mut out = Vec::new();
+        keymap.tick(clock.now(), |e| out.push(e));
+        assert_eq!(out, []);
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Depress(1)), []);
+
+        keymap.set_default_layer(0);
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Press(0)),
+            [KeyEvent::Press(7)]
+        );
+    }
+
+    #[test]
+    fn simultaneous() {
+        let mut clock = TickerClock(0);
+        let mut keymap = keymap(&clock);
+        let mut scan = |keymap: &mut Keymap<TickerClock, 2, 3, 1>, clock: &TickerClock| {
+            let mut events =
+                [InputEvent::Press(0), InputEvent::Depress(1)].map(|event| TimedEvent {
+                    time: clock.now(),
+                    event,
+                });
+            let mut out = Vec::new();
+            keymap.push_all(&mut events, |e| out.push(e));
+            out
+        };
+
+        // the hold-tap is released before the other key is pressed, a tap
+        push(&mut keymap, &clock, InputEvent::Press(1));
+        clock.tick();
+        assert_eq!(
+            scan(&mut keymap, &clock),
+            [KeyEvent::Press(6), KeyEvent::Depress(6), KeyEvent::Press(4)]
+        );
+        push(&mut keymap, &clock, InputEvent::Depress(0));
+
+        // the other key is pressed while the hold-tap is held, a hold
+        keymap.set_simultaneous_order(SimultaneousOrder::PressesFirst);
+        push(&mut keymap, &clock, InputEvent::Press(1));
+        clock.tick();
+        assert_eq!(
+            scan(&mut keymap, &clock),
+            [
+                KeyEvent::Press(0xe1),
+                KeyEvent::Press(4),
+                KeyEvent::Depress(0xe1)
+            ]
+        );
+    }
+
+    #[test]
+    fn settings() {
+        static TERM: TunableTerm = TunableTerm::new(Duration::from_millis(200));
+        let clock = TickerClock(0);
+        let mut original = keymap(&clock);
+
+        original.set_default_layer(1);
+        original.flags = StateFlags::GAME_MODE | StateFlags::SHFT;
+        let settings = original.settings(&[&TERM]);
+        assert_eq!(settings.flags, StateFlags::GAME_MODE);
+
+        let mut restored = keymap(&clock);
+        TERM.set(Duration::from_millis(150));
+        restored.apply_settings(&settings, &[&TERM]);
+        assert_eq!(restored.flags, StateFlags::GAME_MODE);
+        assert_eq!(TERM.get(), Duration::from_millis(200));
+        assert_eq!(
+            push(&mut restored, &clock, InputEvent::Press(0)),
+            [KeyEvent::Press(5)]
+        );
+        // transparent with nothing active below the default
+        assert_eq!(push(&mut restored, &clock, InputEvent::Press(1)), []);
+
+        let mut restored = keymap(&clock);
+        restored.apply_settings(
+            &Settings {
+                default_layer: 2,
+                ..settings
+            },
+            &[&TERM],
+        );
+        assert_eq!(restored.default_layer(), 0);
+        assert_eq!(restored.flags, StateFlags::GAME_MODE);
+    }
+
+    #[test]
+    fn session() {
+        static OFF: State = State {
+            name: "OFF",
+            id: StateId(0),
+            transitions: &[&TURN_ON],
+        };
+
+        static ON: State = State {
+            name: "ON",
+            id: StateId(1),
+            transitions: &[&TURN_OFF],
+        };
+
+        static TURN_ON: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(1)],
+            key_event_emissions: &[KeyEvent::Press(7)],
+            internal_event_emissions: &[],
+            target: &ON,
+        };
+
+        static TURN_OFF: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(1)],
+            key_event_emissions: &[KeyEvent::Depress(7)],
+            internal_event_emissions: &[],
+            target: &OFF,
+        };
+
+        let clock = TickerClock(0);
+        let mut original = keymap(&clock);
+        original.set_default_layer(1);
+        original.flags = StateFlags::GAME_MODE | StateFlags::SHFT;
+        original.active_layers.activate(0);
+        push(&mut original, &clock, InputEvent::Press(2));
+        push(&mut original, &clock, InputEvent::Press(1));
+
+        // the pending hold-tap and the held layer are left behind
+        let session = original.session();
+        assert_eq!(session.default_layer, 1);
+        assert_eq!(session.flags, StateFlags::GAME_MODE);
+        assert_eq!(session.layers.0, 0b1);
+        assert_eq!(session.states, [StateId(0)]);
+
+        let mut toggle = Keymap::<TickerClock, 2, 3, 1>::new(&LAYERS, [OFF.as_dyn()], clock.now());
+        push(&mut toggle, &clock, InputEvent::Press(1));
+        let session = toggle.session();
+        assert_eq!(session.states, [StateId(1)]);
+
+        let mut restored =
+            Keymap::<TickerClock, 2, 3, 1>::new(&LAYERS, [OFF.as_dyn()], clock.now());
+        let mut out = Vec::new();
+        restored.resume_session(
+            &Session {
+                layers: Layers(0b110),
+                ..session
+            },
+            clock.now(),
+            |e| out.push(e),
+        );
+        assert_eq!(out, [KeyEvent::LayerActivated(1)]);
+        assert_eq!(restored.machine(0).unwrap().1.id(), StateId(1));
+        push(&mut restored, &clock, InputEvent::Depress(1));
+        assert_eq!(
+            push(&mut restored, &clock, InputEvent::Press(1)),
+            [KeyEvent::Depress(7)]
+        );
+
+        // a saved state that's pending, or gone, starts over
+        for id in [StateId(1), StateId(9)] {
+            let mut restored = keymap(&clock);
+            let session = Session {
+                states: [id],
+                ..session
+            };
+            restored.resume_session(&session, clock.now(), |_| {});
+            assert_eq!(restored.machine(0).unwrap().1.id(), StateId(0));
+        }
+    }
+
+    #[test]
+    fn layer_modifiers() {
+        let clock = TickerClock(0);
+        let mut keymap = keymap(&clock);
+        assert!(keymap.set_layer_modifier(1, Some(0xe1)));
+        assert!(!keymap.set_layer_modifier(2, Some(0xe1)));
+
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Press(2)),
+            [KeyEvent::LayerActivated(1)]
+        );
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Press(0)),
+            [KeyEvent::Press(0xe1), KeyEvent::Press(5)]
+        );
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Depress(0)),
+            [KeyEvent::Depress(5), KeyEvent::Depress(0xe1)]
+        );
+
+        // the modifier comes off before another key goes down, here the tap
+        // of the machine under the transparent key
+        push(&mut keymap, &clock, InputEvent::Press(0));
+        push(&mut keymap, &clock, InputEvent::Press(1));
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Depress(1)),
+            [
+                KeyEvent::Depress(0xe1),
+                KeyEvent::Press(6),
+                KeyEvent::Depress(6)
+            ]
+        );
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Depress(0)),
+            [KeyEvent::Depress(5)]
+        );
+        assert!(keymap.reported().keys().next().is_none());
+    }
+
+    #[test]
+    fn locking_keys() {
+        static LOCKING: [[Action; 3]; 2] = [
+            [Action::LockingKey(0x39), Action::Key(4), Action::None],
+            [Action::None; 3],
+        ];
+        const CAPS: u8 = 1 << 1;
+        let clock = TickerClock(0);
+        let mut keymap: Keymap<TickerClock, 2, 3, 1> =
+            Keymap::new(&LOCKING, [home_a::IDLE.as_dyn()], clock.now());
+        let caps_tap = [KeyEvent::Press(0x39), KeyEvent::Depress(0x39)];
+
+        // tapped both ways until the host says otherwise
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Press(0)), caps_tap);
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Depress(0)), caps_tap);
+        assert_eq!(push(&mut keymap, &clock, InputEvent::HostLeds(0)), []);
+
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Press(0)), caps_tap);
+        assert_eq!(push(&mut keymap, &clock, InputEvent::HostLeds(CAPS)), []);
+        // turned off from another keyboard, so turned back on
+        assert_eq!(push(&mut keymap, &clock, InputEvent::HostLeds(0)), caps_tap);
+        // a report from before that tap is let through
+        assert_eq!(push(&mut keymap, &clock, InputEvent::HostLeds(0)), []);
+        assert_eq!(push(&mut keymap, &clock, InputEvent::HostLeds(CAPS)), []);
+
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Depress(0)), caps_tap);
+        // from before the release, so caps is already on when it latches
+        assert_eq!(push(&mut keymap, &clock, InputEvent::HostLeds(CAPS)), []);
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Press(0)), []);
+        assert_eq!(push(&mut keymap, &clock, InputEvent::HostLeds(CAPS)), []);
+
+        assert_eq!(push(&mut keymap, &clock, InputEvent::Depress(0)), caps_tap);
+        assert_eq!(push(&mut keymap, &clock, InputEvent::HostLeds(0)), []);
+        // turned on from elsewhere while released
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::HostLeds(CAPS)),
+            caps_tap
+        );
+
+        let action = Action::LockingKey(0x39);
+        assert_eq!(Action::from_bytes(action.to_bytes()), Some(action));
+    }
+
+    #[test]
+    fn lighting() {
+        let mut clock = TickerClock(0);
+        let mut keymap = keymap(&clock);
+        keymap.set_lighting(true);
+
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Press(0)),
+            [KeyEvent::Lighting(0, Lighting::Pressed), KeyEvent::Press(4)]
+        );
+
+        push(&mut keymap, &clock, InputEvent::Press(1));
+        clock.tick();
+        assert_eq!(
+            push(&mut keymap, &clock, InputEvent::Depress(1)),
+            [
+                KeyEvent::Lighting(1, Lighting::Released),
+                KeyEvent::Press(6),
+                KeyEvent::Depress(6),
+                KeyEvent::Lighting(1, Lighting::Tapped),
+            ]
+        );
+    }
+}
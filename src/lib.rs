@@ -1,58 +1,637 @@
-#![cfg_attr(test, feature(assert_matches))]
 #![allow(unused)]
 
 use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicU32, Ordering};
 
-use embedded_time::duration::Milliseconds;
-use embedded_time::Instant;
+use observer::Observer;
+use routing::Route;
+use signals::{SignalSource, Signals};
+use time::{Duration, Instant};
+use validate::MAX_EMISSIONS;
+
+#[cfg(test)]
+macro_rules! assert_matches {
+    ($e:expr, $p:pat) => {
+        match $e {
+            $p => {}
+            ref e => panic!(
+                "assertion failed: `{:?}` does not match `{}`",
+                e,
+                stringify!($p)
+            ),
+        }
+    };
+}
+
+mod accessibility;
+mod actuation;
+mod arena;
+mod autocorrect;
+mod behaviors;
+mod budget;
+mod chain;
+mod chatter;
+mod clock;
+#[cfg(feature = "codegen")]
+mod codegen;
+mod custom;
+mod debounce;
+mod devices;
+mod digitizer;
+mod dispatch;
+mod drag_scroll;
+mod dynamic_macro;
+#[cfg(feature = "embassy")]
+mod embassy;
+mod encoder;
+mod entropy;
+#[cfg(feature = "ffi")]
+mod ffi;
+#[cfg(feature = "fugit")]
+mod fugit;
+mod ghosting;
+#[cfg(test)]
+mod golden;
+mod history;
+mod host_layout;
+mod introspect;
+mod jiggler;
+#[cfg(feature = "keyberon")]
+mod keyberon;
+mod keymap;
+mod matrix;
+mod metrics;
+#[cfg(target_has_atomic = "ptr")]
+mod mpsc;
+mod observer;
+mod packed;
+mod privacy;
+#[cfg(kani)]
+mod proofs;
+#[cfg(feature = "python")]
+mod python;
+mod rapid_trigger;
+mod raw_hid;
+mod remap;
+#[cfg(feature = "rmk")]
+mod rmk;
+mod routing;
+mod schedule;
+mod session;
+mod settings;
+mod shared;
+mod signals;
+mod simultaneous;
+mod snippets;
+mod socd;
+mod split;
+mod spsc;
+#[cfg(feature = "stats")]
+mod stats;
+mod sticky_keys;
+mod storage;
+mod table;
+#[cfg(any(test, kani, feature = "testing"))]
+pub mod testing;
+mod time;
+mod trace;
+mod typematic;
+mod unicode;
+mod validate;
+mod via;
+#[cfg(feature = "wasm")]
+mod wasm;
 
 bitflags::bitflags! {
-    struct StateFlags: u8 {
+    pub struct StateFlags: u8 {
         const CTRL = 0b00001;
         const SHFT = 0b00010;
+        const STICKY_KEYS = 0b00100;
+        /// Resolve hold-tap keys as their tap key, and disable one-shot behaviors.
+        const GAME_MODE = 0b01000;
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Copy)]
-enum InputEvent {
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InputEvent {
     Press(u8),
     Depress(u8),
+    PointerMove(i8, i8),
+    /// A pointing device button went down (`true`) or up.
+    PointerButton(u8, bool),
+    /// A pointing device scrolled, horizontally then vertically.
+    Wheel(i8, i8),
+    /// How far an analog key is pressed down, from 0 (released) to 255
+    /// (bottomed out).
+    Travel(u8, u8),
+    /// An encoder turned by some number of detents, positive is clockwise.
+    Rotate(u8, i8),
+    /// The host's focused application changed, identified by an id the host
+    /// side software assigns.
+    Application(u16),
+    /// The focused window's title changed, identified by a hash of it.
+    WindowTitle(u16),
+    /// The battery's charge, in percent.
+    Battery(u8),
+    /// USB or a charger started (`true`) or stopped supplying power.
+    ExternalPower(bool),
+    /// The host says it's using the host layout with this index, see
+    /// [`host_layout::HostLayouts`].
+    HostLayout(u8),
+    /// An event of a kind the keymap defines, with a payload, see
+    /// [`custom::CustomEvent`]. Only kinds below 16 have an encoding.
+    Custom(u8, u16),
+    /// The host set its lock LEDs, as the HID LED report's bits: num lock,
+    /// caps lock then scroll lock from the lowest.
+    HostLeds(u8),
+    /// The host locked (`true`) or unlocked the keyboard's output, see
+    /// [`keymap::Keymap`].
+    OutputLock(bool),
+}
+
+impl InputEvent {
+    /// A compact encoding for sending events over links and to the host, a
+    /// tag followed by up to two arguments. Events with no encoding are
+    /// `[0xff, 0, 0]`.
+    const fn to_bytes(self) -> [u8; 3] {
+        match self {
+            InputEvent::Press(key) => [0, key, 0],
+            InputEvent::Depress(key) => [1, key, 0],
+            InputEvent::PointerMove(dx, dy) => [2, dx as u8, dy as u8],
+            InputEvent::PointerButton(button, pressed) => [3, button, pressed as u8],
+            InputEvent::Wheel(h, v) => [4, h as u8, v as u8],
+            InputEvent::Travel(key, travel) => [5, key, travel],
+            InputEvent::Rotate(id, delta) => [6, id, delta as u8],
+            InputEvent::Application(id) => [7, id as u8, (id >> 8) as u8],
+            InputEvent::WindowTitle(hash) => [8, hash as u8, (hash >> 8) as u8],
+            InputEvent::HostLayout(layout) => [9, layout, 0],
+            InputEvent::Battery(level) => [10, level, 0],
+            InputEvent::ExternalPower(on) => [11, on as u8, 0],
+            InputEvent::HostLeds(leds) => [12, leds, 0],
+            InputEvent::OutputLock(locked) => [13, locked as u8, 0],
+            InputEvent::Custom(kind, payload) if kind < 16 => {
+                let [a, b] = payload.to_le_bytes();
+                [0x10 + kind, a, b]
+            }
+            InputEvent::Custom(..) => [0xff, 0, 0],
+        }
+    }
+
+    const fn from_bytes([tag, x, y]: [u8; 3]) -> Option<Self> {
+        Some(match tag {
+            0 => InputEvent::Press(x),
+            1 => InputEvent::Depress(x),
+            2 => InputEvent::PointerMove(x as i8, y as i8),
+            3 => InputEvent::PointerButton(x, y != 0),
+            4 => InputEvent::Wheel(x as i8, y as i8),
+            5 => InputEvent::Travel(x, y),
+            6 => InputEvent::Rotate(x, y as i8),
+            7 => InputEvent::Application(u16::from_le_bytes([x, y])),
+            8 => InputEvent::WindowTitle(u16::from_le_bytes([x, y])),
+            9 => InputEvent::HostLayout(x),
+            10 => InputEvent::Battery(x),
+            11 => InputEvent::ExternalPower(x != 0),
+            12 => InputEvent::HostLeds(x),
+            13 => InputEvent::OutputLock(x != 0),
+            0x10..=0x1f => InputEvent::Custom(tag - 0x10, u16::from_le_bytes([x, y])),
+            _ => return None,
+        })
+    }
+}
+
+/// An input event along with when it happened.
+#[derive(Debug)]
+struct TimedEvent<Clock: time::Clock> {
+    time: Clock::Instant,
+    event: InputEvent,
+}
+
+// derives would require the clock itself to be `Copy` and `PartialEq`
+impl<Clock: time::Clock> Clone for TimedEvent<Clock> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Clock: time::Clock> Copy for TimedEvent<Clock> {}
+
+impl<Clock: time::Clock> PartialEq for TimedEvent<Clock> {
+    fn eq(&self, other: &Self) -> bool {
+        self.time == other.time && self.event == other.event
+    }
 }
 
+impl<Clock: time::Clock> Eq for TimedEvent<Clock> {}
+
 type KeyCode = u8;
 
-#[derive(Debug, PartialEq, Eq)]
-enum KeyEvent {
+type Layer = u8;
+
+/// How many layers there can be, one for each bit of [`Layers`].
+const MAX_LAYERS: usize = u32::BITS as usize;
+
+/// The set of currently active layers, one bit per layer. Layers past
+/// [`MAX_LAYERS`] are never active.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+struct Layers(u32);
+
+impl Layers {
+    const fn empty() -> Self {
+        Self(0)
+    }
+
+    const fn bit(layer: Layer) -> u32 {
+        match 1_u32.checked_shl(layer as u32) {
+            Some(bit) => bit,
+            None => 0,
+        }
+    }
+
+    fn activate(&mut self, layer: Layer) {
+        self.0 |= Self::bit(layer);
+    }
+
+    fn deactivate(&mut self, layer: Layer) {
+        self.0 &= !Self::bit(layer);
+    }
+
+    const fn is_active(&self, layer: Layer) -> bool {
+        self.0 & Self::bit(layer) != 0
+    }
+}
+
+/// A set of key codes, one bit per key.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+struct KeySet([u32; 8]);
+
+impl KeySet {
+    const fn empty() -> Self {
+        Self([0; 8])
+    }
+
+    fn insert(&mut self, key: KeyCode) {
+        self.0[key as usize / 32] |= 1 << (key % 32);
+    }
+
+    /// Returns whether the key was in the set.
+    fn remove(&mut self, key: KeyCode) -> bool {
+        let present = self.contains(key);
+        self.0[key as usize / 32] &= !(1 << (key % 32));
+        present
+    }
+
+    const fn contains(&self, key: KeyCode) -> bool {
+        self.0[key as usize / 32] & (1 << (key % 32)) != 0
+    }
+
+    /// Whether any of the keys in `keys` is in the set.
+    fn any_in(&self, keys: &RangeInclusive<KeyCode>) -> bool {
+        keys.clone().any(|key| self.contains(key))
+    }
+
+    fn keys(self) -> impl Iterator<Item = KeyCode> {
+        (0..=u8::MAX).filter(move |key| self.contains(*key))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KeyEvent {
     Press(KeyCode),
     Depress(KeyCode),
     PressCurrent,
     DepressCurrent,
+    /// Scroll the mouse wheel, horizontally then vertically.
+    Wheel(i8, i8),
+    LayerActivated(Layer),
+    LayerDeactivated(Layer),
+    /// Type a unicode character, however the host expects that to be done,
+    /// such as with the dead keys of its layout, see
+    /// [`host_layout::HostLayouts::output`].
+    Unicode(char),
+    /// Something happened to the key at a matrix position, for driving
+    /// reactive lighting.
+    Lighting(u8, Lighting),
+    /// Move the mouse pointer.
+    MouseMove(i8, i8),
+    /// Touch or point at an absolute position, x then y, with the digitizer
+    /// buttons held, see [`digitizer`].
+    Digitizer(u16, u16, u8),
+    /// An indicator should be turned on or off.
+    Indicator(Indicator, bool),
+    /// Bluetooth profile or output selection, carried out by the firmware.
+    Wireless(Wireless),
+    /// A wake key was pressed while suspended, the host should be woken.
+    Wake,
+    /// The current typing speed in words per minute, for a display.
+    TypingSpeed(u16),
+    /// Jump to the bootloader for flashing, carried out by the firmware.
+    EnterBootloader,
+    /// Restart the keyboard, carried out by the firmware.
+    SystemReset,
+    /// Send the rest of the transition's emissions this way, see [`routing`].
+    Route(Route),
 }
 
+impl KeyEvent {
+    /// The encoding of the events a host is told about, alongside
+    /// [`InputEvent::to_bytes`]. Events with no encoding are `[0xff, 0, 0]`.
+    const fn to_bytes(self) -> [u8; 3] {
+        match self {
+            KeyEvent::Press(key) => [0, key, 0],
+            KeyEvent::Depress(key) => [1, key, 0],
+            KeyEvent::LayerActivated(layer) => [2, layer, 0],
+            KeyEvent::LayerDeactivated(layer) => [3, layer, 0],
+            KeyEvent::EnterBootloader => [4, 0, 0],
+            KeyEvent::SystemReset => [5, 0, 0],
+            _ => [0xff, 0, 0],
+        }
+    }
+
+    /// `PressCurrent` and `DepressCurrent` as the press or release of the key
+    /// `event` is for, when it's a press or release of one.
+    const fn resolve_current(self, event: Option<InputEvent>) -> Self {
+        match (self, event) {
+            (KeyEvent::PressCurrent, Some(InputEvent::Press(key))) => KeyEvent::Press(key),
+            (KeyEvent::DepressCurrent, Some(InputEvent::Depress(key))) => KeyEvent::Depress(key),
+            (event, _) => event,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Transport {
+    Usb,
+    Ble,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Wireless {
+    /// Switch to the bond profile with this index.
+    SelectProfile(u8),
+    NextProfile,
+    PreviousProfile,
+    /// Forget the bond of the current profile.
+    ClearBond,
+    ClearAllBonds,
+    /// Send reports over this transport.
+    SetTransport(Transport),
+    ToggleTransport,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Indicator {
+    MouseJiggler,
+    /// The host has locked the keyboard's output.
+    OutputLock,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Lighting {
+    Pressed,
+    Released,
+    /// A hold-tap resolved as its tap.
+    Tapped,
+    /// A hold-tap resolved as its hold.
+    Held,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 enum InternalEvent {
     SetGlobalState(StateFlags),
     UnsetGlobalState(StateFlags),
+    ActivateLayer(Layer),
+    DeactivateLayer(Layer),
+    /// Restart the activity timer checked by [`TransitionCondition::IdleGreater`].
+    RecordActivity,
+    /// Send the machine's emissions this way from now on, see [`routing`].
+    SetRoute(Route),
 }
 
 impl InternalEvent {
-    fn apply<Clock: embedded_time::Clock>(&self, state: &mut GlobalState<Clock>) {
+    /// The layer the event changes, if it changes one.
+    const fn layer(&self) -> Option<Layer> {
+        match self {
+            InternalEvent::ActivateLayer(layer) | InternalEvent::DeactivateLayer(layer) => {
+                Some(*layer)
+            }
+            _ => None,
+        }
+    }
+
+    fn apply<Clock: time::Clock, S, O>(
+        &self,
+        state: &mut GlobalState<Clock, S, O>,
+        current_time: Clock::Instant,
+    ) {
         match self {
             InternalEvent::SetGlobalState(flags) => state.flags.insert(*flags),
             InternalEvent::UnsetGlobalState(flags) => state.flags.remove(*flags),
+            InternalEvent::ActivateLayer(layer) => state.layers.activate(*layer),
+            InternalEvent::DeactivateLayer(layer) => state.layers.deactivate(*layer),
+            InternalEvent::RecordActivity => state.last_activity = current_time,
+            InternalEvent::SetRoute(route) => state.route = *route,
         }
     }
 }
 
+#[derive(Debug, Clone)]
 enum TransitionCondition {
     StateSet(StateFlags),
     StateNotSet(StateFlags),
     Pressed(RangeInclusive<u8>),
     Depressed(RangeInclusive<u8>),
-    ElapsedLess(Milliseconds),
-    ElapsedGreater(Milliseconds),
+    PointerMoved,
+    PointerButtonPressed(RangeInclusive<u8>),
+    PointerButtonReleased(RangeInclusive<u8>),
+    /// None of the keys is held, counting the event being pushed, so this
+    /// holds on the release of the last of them.
+    AllReleased(RangeInclusive<u8>),
+    /// None of the pointing device buttons is held, as
+    /// [`TransitionCondition::AllReleased`].
+    AllButtonsReleased(RangeInclusive<u8>),
+    WheelScrolled,
+    /// An analog key travelled to at least the value.
+    TravelAbove(KeyCode, u8),
+    /// An analog key travelled to less than the value.
+    TravelBelow(KeyCode, u8),
+    /// An encoder turned by an amount within the range.
+    Rotated(u8, RangeInclusive<i8>),
+    LayerActive(Layer),
+    LayerNotActive(Layer),
+    ElapsedLess(Duration),
+    /// Holds from the moment the time in the state reaches the value, so
+    /// `ElapsedGreater(x)` and `ElapsedLess(x)` never hold together. A
+    /// transition taken on it fires once per entry into the state, however
+    /// many ticks arrive after its deadline, see [`GlobalState::entered_at`].
+    ElapsedGreater(Duration),
+    ElapsedLessTunable(&'static TunableTerm),
+    ElapsedGreaterTunable(&'static TunableTerm),
+    /// The same as [`TransitionCondition::ElapsedLess`] and
+    /// [`TransitionCondition::ElapsedGreater`], for timings finer than a
+    /// millisecond, which are packed and generated in microseconds.
+    ElapsedLessMicros(Duration),
+    ElapsedGreaterMicros(Duration),
+    /// The host's focused application, as last sent with
+    /// [`InputEvent::Application`].
+    ApplicationIs(u16),
+    WindowTitleIs(u16),
+    /// Time since the last [`InternalEvent::RecordActivity`], unlike the
+    /// elapsed conditions this isn't reset by entering a state.
+    IdleGreater(Duration),
+    /// The flags are set but weren't all set when the current state was
+    /// entered. Taking a transition back into the same state counts as
+    /// entering it, so a looping transition on this is taken once.
+    FlagSetSinceEntry(StateFlags),
+    /// The flags became set since the last push or tick, whether one was
+    /// taken then or not. Flags set by the machine's own transitions don't
+    /// count, so this is for reacting to flags set from outside, such as by
+    /// another machine sharing them.
+    FlagJustSet(StateFlags),
+    /// The battery's charge, as last sent with [`InputEvent::Battery`], is
+    /// below the percentage.
+    BatteryBelow(u8),
+    /// Power is supplied over USB or by a charger, as last sent with
+    /// [`InputEvent::ExternalPower`].
+    ExternalPowered,
+    OnBattery,
+    /// The reading of a [`signals`] channel is non-zero.
+    SignalOn(u8),
+    SignalOff(u8),
+    /// The reading of a [`signals`] channel is at least the value.
+    SignalAbove(u8, i16),
+    /// The reading of a [`signals`] channel is below the value.
+    SignalBelow(u8, i16),
+    /// The event satisfies a predicate, for conditions on payloads that the
+    /// others can't express, such as on a [`custom::CustomEvent`].
+    EventMatches(fn(InputEvent) -> bool),
+}
+
+/// A duration that can be changed at runtime, for timing conditions that
+/// should be tunable without reflashing.
+#[derive(Debug)]
+struct TunableTerm(AtomicU32);
+
+impl TunableTerm {
+    /// Terms are kept in whole milliseconds.
+    const fn new(term: Duration) -> Self {
+        Self(AtomicU32::new(term.as_millis()))
+    }
+
+    fn get(&self) -> Duration {
+        Duration::from_millis(self.0.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, term: Duration) {
+        self.0.store(term.as_millis(), Ordering::Relaxed);
+    }
+}
+
+/// What conditions are evaluated against, besides the event itself.
+#[derive(Clone, Copy)]
+struct Context {
+    /// Time since the current state was entered.
+    elapsed: Duration,
+    /// Time since activity was last recorded.
+    idle: Duration,
+    flags: StateFlags,
+    /// The flags when the current state was entered.
+    entry_flags: StateFlags,
+    /// The flags as of the last push or tick.
+    previous_flags: StateFlags,
+    layers: Layers,
+    host: HostContext,
+    power: Power,
+    signals: Signals,
+    /// The keys and pointing device buttons held, counting the event being
+    /// pushed.
+    held: KeySet,
+    held_buttons: KeySet,
+}
+
+/// What the host last said it was doing, `0` until it says otherwise.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+struct HostContext {
+    application: u16,
+    window_title: u16,
+}
+
+/// What the power supply last reported. Until it does, the battery is taken
+/// to be full and power to be from the battery, so that nothing acts as if
+/// the battery were low.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Power {
+    /// In percent.
+    battery: u8,
+    external: bool,
+}
+
+impl Default for Power {
+    fn default() -> Self {
+        Self {
+            battery: 100,
+            external: false,
+        }
+    }
 }
 
 impl TransitionCondition {
+    /// The layer the condition checks, if it checks one.
+    const fn layer(&self) -> Option<Layer> {
+        match self {
+            TransitionCondition::LayerActive(layer)
+            | TransitionCondition::LayerNotActive(layer) => Some(*layer),
+            _ => None,
+        }
+    }
+
+    /// A copy of the condition, for const fns, which can't call `clone`.
+    const fn copy(&self) -> Self {
+        use TransitionCondition as C;
+
+        match self {
+            C::StateSet(flags) => C::StateSet(*flags),
+            C::StateNotSet(flags) => C::StateNotSet(*flags),
+            C::Pressed(keys) => C::Pressed(*keys.start()..=*keys.end()),
+            C::Depressed(keys) => C::Depressed(*keys.start()..=*keys.end()),
+            C::PointerMoved => C::PointerMoved,
+            C::PointerButtonPressed(buttons) => {
+                C::PointerButtonPressed(*buttons.start()..=*buttons.end())
+            }
+            C::PointerButtonReleased(buttons) => {
+                C::PointerButtonReleased(*buttons.start()..=*buttons.end())
+            }
+            C::AllReleased(keys) => C::AllReleased(*keys.start()..=*keys.end()),
+            C::AllButtonsReleased(buttons) => {
+                C::AllButtonsReleased(*buttons.start()..=*buttons.end())
+            }
+            C::WheelScrolled => C::WheelScrolled,
+            C::TravelAbove(key, travel) => C::TravelAbove(*key, *travel),
+            C::TravelBelow(key, travel) => C::TravelBelow(*key, *travel),
+            C::Rotated(encoder, delta) => C::Rotated(*encoder, *delta.start()..=*delta.end()),
+            C::LayerActive(layer) => C::LayerActive(*layer),
+            C::LayerNotActive(layer) => C::LayerNotActive(*layer),
+            C::ElapsedLess(x) => C::ElapsedLess(*x),
+            C::ElapsedGreater(x) => C::ElapsedGreater(*x),
+            C::ElapsedLessTunable(term) => C::ElapsedLessTunable(term),
+            C::ElapsedGreaterTunable(term) => C::ElapsedGreaterTunable(term),
+            C::ElapsedLessMicros(x) => C::ElapsedLessMicros(*x),
+            C::ElapsedGreaterMicros(x) => C::ElapsedGreaterMicros(*x),
+            C::ApplicationIs(id) => C::ApplicationIs(*id),
+            C::WindowTitleIs(hash) => C::WindowTitleIs(*hash),
+            C::IdleGreater(x) => C::IdleGreater(*x),
+            C::FlagSetSinceEntry(flags) => C::FlagSetSinceEntry(*flags),
+            C::FlagJustSet(flags) => C::FlagJustSet(*flags),
+            C::BatteryBelow(level) => C::BatteryBelow(*level),
+            C::ExternalPowered => C::ExternalPowered,
+            C::OnBattery => C::OnBattery,
+            C::SignalOn(channel) => C::SignalOn(*channel),
+            C::SignalOff(channel) => C::SignalOff(*channel),
+            C::SignalAbove(channel, x) => C::SignalAbove(*channel, *x),
+            C::SignalBelow(channel, x) => C::SignalBelow(*channel, *x),
+            C::EventMatches(predicate) => C::EventMatches(*predicate),
+        }
+    }
+
     const fn pressed_single(key: u8) -> Self {
         Self::Pressed(key..=key)
     }
@@ -61,12 +640,63 @@ impl TransitionCondition {
         Self::Depressed(key..=key)
     }
 
-    fn evaluate(&self, elapsed: Milliseconds, key: Option<InputEvent>, state: StateFlags) -> bool {
+    const fn rotated_clockwise(encoder: u8) -> Self {
+        Self::Rotated(encoder, 1..=i8::MAX)
+    }
+
+    const fn rotated_counter_clockwise(encoder: u8) -> Self {
+        Self::Rotated(encoder, i8::MIN..=-1)
+    }
+
+    /// Whether the condition is about the event being pushed rather than the
+    /// context, so holds for the same events whatever the context is.
+    fn is_event_condition(&self) -> bool {
+        matches!(
+            self,
+            TransitionCondition::Pressed(..)
+                | TransitionCondition::Depressed(..)
+                | TransitionCondition::PointerMoved
+                | TransitionCondition::PointerButtonPressed(..)
+                | TransitionCondition::PointerButtonReleased(..)
+                | TransitionCondition::WheelScrolled
+                | TransitionCondition::TravelAbove(..)
+                | TransitionCondition::TravelBelow(..)
+                | TransitionCondition::Rotated(..)
+                | TransitionCondition::EventMatches(_)
+        )
+    }
+
+    fn evaluate(&self, context: &Context, key: Option<InputEvent>) -> bool {
+        let elapsed = context.elapsed;
+
         match (self, key) {
-            (TransitionCondition::StateSet(mask), _) => state.contains(*mask),
-            (TransitionCondition::StateNotSet(mask), _) => !state.contains(*mask),
+            (TransitionCondition::StateSet(mask), _) => context.flags.contains(*mask),
+            (TransitionCondition::StateNotSet(mask), _) => !context.flags.contains(*mask),
             (TransitionCondition::Pressed(x), Some(InputEvent::Press(key))) => x.contains(&key),
             (TransitionCondition::Depressed(x), Some(InputEvent::Depress(key))) => x.contains(&key),
+            (TransitionCondition::PointerMoved, Some(InputEvent::PointerMove(..))) => true,
+            (
+                TransitionCondition::PointerButtonPressed(x),
+                Some(InputEvent::PointerButton(button, true)),
+            ) => x.contains(&button),
+            (
+                TransitionCondition::PointerButtonReleased(x),
+                Some(InputEvent::PointerButton(button, false)),
+            ) => x.contains(&button),
+            (TransitionCondition::AllReleased(x), _) => !context.held.any_in(x),
+            (TransitionCondition::AllButtonsReleased(x), _) => !context.held_buttons.any_in(x),
+            (TransitionCondition::WheelScrolled, Some(InputEvent::Wheel(..))) => true,
+            (TransitionCondition::TravelAbove(key, x), Some(InputEvent::Travel(k, travel))) => {
+                *key == k && travel >= *x
+            }
+            (TransitionCondition::TravelBelow(key, x), Some(InputEvent::Travel(k, travel))) => {
+                *key == k && travel < *x
+            }
+            (TransitionCondition::Rotated(encoder, x), Some(InputEvent::Rotate(id, delta))) => {
+                *encoder == id && x.contains(&delta)
+            }
+            (TransitionCondition::LayerActive(layer), _) => context.layers.is_active(*layer),
+            (TransitionCondition::LayerNotActive(layer), _) => !context.layers.is_active(*layer),
             (TransitionCondition::ElapsedLess(x), _) => {
                 eprintln!("{} < {}", elapsed, x);
                 &elapsed < x
@@ -75,97 +705,533 @@ impl TransitionCondition {
                 eprintln!("{} >= {}", elapsed, x);
                 &elapsed >= x
             }
+            (TransitionCondition::ElapsedLessTunable(x), _) => elapsed < x.get(),
+            (TransitionCondition::ElapsedGreaterTunable(x), _) => elapsed >= x.get(),
+            (TransitionCondition::ElapsedLessMicros(x), _) => &elapsed < x,
+            (TransitionCondition::ElapsedGreaterMicros(x), _) => &elapsed >= x,
+            (TransitionCondition::ApplicationIs(x), _) => context.host.application == *x,
+            (TransitionCondition::WindowTitleIs(x), _) => context.host.window_title == *x,
+            (TransitionCondition::IdleGreater(x), _) => &context.idle >= x,
+            (TransitionCondition::FlagSetSinceEntry(mask), _) => {
+                context.flags.contains(*mask) && !context.entry_flags.contains(*mask)
+            }
+            (TransitionCondition::FlagJustSet(mask), _) => {
+                context.flags.contains(*mask) && !context.previous_flags.contains(*mask)
+            }
+            (TransitionCondition::BatteryBelow(x), _) => context.power.battery < *x,
+            (TransitionCondition::ExternalPowered, _) => context.power.external,
+            (TransitionCondition::OnBattery, _) => !context.power.external,
+            (TransitionCondition::SignalOn(channel), _) => context.signals.get(*channel) != 0,
+            (TransitionCondition::SignalOff(channel), _) => context.signals.get(*channel) == 0,
+            (TransitionCondition::SignalAbove(channel, x), _) => {
+                context.signals.get(*channel) >= *x
+            }
+            (TransitionCondition::SignalBelow(channel, x), _) => context.signals.get(*channel) < *x,
+            (TransitionCondition::EventMatches(predicate), Some(event)) => predicate(event),
             _ => false,
         }
     }
 }
 
-struct GlobalState<Clock: embedded_time::Clock> {
+/// When a condition on the time in a state entered at `entered` starts to
+/// hold, if it's one.
+fn elapsed_deadline<I: Instant>(condition: &TransitionCondition, entered: I) -> Option<I> {
+    match condition {
+        TransitionCondition::ElapsedGreater(x) => entered.checked_add(*x),
+        TransitionCondition::ElapsedGreaterTunable(x) => entered.checked_add(x.get()),
+        TransitionCondition::ElapsedGreaterMicros(x) => entered.checked_add(*x),
+        _ => None,
+    }
+}
+
+/// Which of a state's transitions are taken when more than one matches.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+enum MatchPolicy {
+    /// Only the first, in the order the state lists them.
+    #[default]
+    First,
+    /// All of them, for generated machines that merge orthogonal behaviours
+    /// into one state. Their internal events and then their emissions are
+    /// applied in the order the state lists them, and the machine moves to
+    /// the first one's target. Such machines are checked with
+    /// [`validate::validate_merged`], so that whatever matches together fits
+    /// in [`MAX_EMISSIONS`]. For one that isn't, a match that doesn't fit
+    /// isn't taken, which [`GlobalState::overflowed`] reports.
+    All,
+}
+
+/// What a machine emitted when it was last pushed an event or ticked, see
+/// [`GlobalState::emitted`].
+#[derive(Clone, Copy)]
+enum Emitted {
+    Transition(&'static [KeyEvent]),
+    /// The first this many of [`GlobalState::merged`].
+    Merged(usize),
+}
+
+/// The state of a running machine. `S` is how the current state is referred
+/// to: a [`DynState`] for machines built from statics, or a
+/// [`table::StateIndex`] for ones run from a [`table::Table`]. `O` is told
+/// what the machine does, see [`observer::Observer`].
+pub struct GlobalState<Clock: time::Clock, S = &'static dyn DynState, O = ()> {
     flags: StateFlags,
-    entered_state: Instant<Clock>,
-    current_state: &'static dyn DynState,
+    /// See [`Context::entry_flags`] and [`Context::previous_flags`].
+    entry_flags: StateFlags,
+    previous_flags: StateFlags,
+    layers: Layers,
+    entered_state: Clock::Instant,
+    last_activity: Clock::Instant,
+    /// When an event was last pushed.
+    last_input: Clock::Instant,
+    host: HostContext,
+    power: Power,
+    /// The last readings taken with [`GlobalState::poll_signals`].
+    signals: Signals,
+    /// See [`Context::held`].
+    held: KeySet,
+    held_buttons: KeySet,
+    /// Where emissions go, see [`routing`].
+    route: Route,
+    current_state: S,
+    /// The transition last taken, for [`stats::MachineStats`] to collect.
+    #[cfg(feature = "stats")]
+    last_transition: Option<&'static dyn DynTransition>,
+    /// Set between [`GlobalState::suspend`] and [`GlobalState::resume`].
+    suspended: bool,
+    /// Whether the observer and traces are given masked events, see
+    /// [`privacy`].
+    private: bool,
+    match_policy: MatchPolicy,
+    emitted: Emitted,
+    /// The emissions of the transitions last taken together, see
+    /// [`MatchPolicy::All`].
+    merged: [KeyEvent; MAX_EMISSIONS],
+    /// See [`GlobalState::overflowed`].
+    overflowed: bool,
+    observer: O,
 }
 
-impl<Clock: embedded_time::Clock> GlobalState<Clock>
-where
-    u32: TryFrom<Clock::T>,
-{
-    fn tick(&mut self, current_time: Instant<Clock>) -> &'static [KeyEvent] {
-        let elapsed = current_time
-            .checked_duration_since(&self.entered_state)
-            .unwrap()
-            .try_into()
-            .unwrap();
-
-        if let Some((key_events, internal_events, next_state)) = self
-            .current_state
-            .transitions()
-            .iter()
-            .flat_map(|t| t.evaluate(elapsed, None, self.flags))
-            .next()
-        {
-            self.do_transition(internal_events, next_state, current_time);
+impl<Clock: time::Clock, S: Copy> GlobalState<Clock, S> {
+    fn new(initial_state: S, current_time: Clock::Instant) -> Self {
+        Self::with_observer(initial_state, current_time, ())
+    }
+}
 
-            return key_events;
+impl<Clock: time::Clock, S: Copy, O: Observer<S>> GlobalState<Clock, S, O> {
+    fn with_observer(initial_state: S, current_time: Clock::Instant, observer: O) -> Self {
+        Self {
+            flags: StateFlags::empty(),
+            entry_flags: StateFlags::empty(),
+            previous_flags: StateFlags::empty(),
+            layers: Layers::empty(),
+            entered_state: current_time,
+            last_activity: current_time,
+            last_input: current_time,
+            host: HostContext::default(),
+            power: Power::default(),
+            signals: Signals::default(),
+            held: KeySet::empty(),
+            held_buttons: KeySet::empty(),
+            route: Route::default(),
+            current_state: initial_state,
+            private: false,
+            #[cfg(feature = "stats")]
+            last_transition: None,
+            suspended: false,
+            match_policy: MatchPolicy::First,
+            emitted: Emitted::Transition(&[]),
+            merged: [KeyEvent::Press(0); MAX_EMISSIONS],
+            overflowed: false,
+            observer,
         }
+    }
 
-        &[]
+    fn observer(&self) -> &O {
+        &self.observer
     }
 
-    fn push(&mut self, current_time: Instant<Clock>, event: InputEvent) -> &'static [KeyEvent] {
-        let elapsed = current_time
-            .checked_duration_since(&self.entered_state)
-            .unwrap()
-            .try_into()
-            .unwrap();
+    fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
 
-        if let Some((key_events, internal_events, next_state)) = self
-            .current_state
-            .transitions()
+    /// Give the observer, and traces recorded through
+    /// [`GlobalState::push_traced`] and [`GlobalState::tick_traced`], events
+    /// with what was typed masked out, see [`privacy`].
+    fn set_private(&mut self, private: bool) {
+        self.private = private;
+    }
+
+    /// Choose which transitions are taken when more than one matches. Only
+    /// machines built from statics go by it, a [`table::TableMachine`] always
+    /// takes the first.
+    fn set_match_policy(&mut self, policy: MatchPolicy) {
+        self.match_policy = policy;
+    }
+
+    /// Whether matches were left out when the machine was last pushed an
+    /// event or ticked, because they didn't fit in [`MAX_EMISSIONS`] with
+    /// the others, see [`MatchPolicy::All`].
+    fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    /// The route this machine's emissions are for, unless a transition says
+    /// otherwise with a [`KeyEvent::Route`].
+    fn route(&self) -> Route {
+        self.route
+    }
+
+    fn context(&self, current_time: Clock::Instant) -> Context {
+        let since = |instant: &Clock::Instant| current_time.duration_since(instant);
+
+        Context {
+            elapsed: since(&self.entered_state),
+            idle: since(&self.last_activity),
+            flags: self.flags,
+            entry_flags: self.entry_flags,
+            previous_flags: self.previous_flags,
+            layers: self.layers,
+            host: self.host,
+            power: self.power,
+            signals: self.signals,
+            held: self.held,
+            held_buttons: self.held_buttons,
+        }
+    }
+
+    /// Keep the timers within what the clock can measure, so that a state
+    /// held for longer reads as held for the maximum rather than wrapping
+    /// around to a shorter time.
+    fn clamp_timers(&mut self, current_time: Clock::Instant) {
+        let max = Clock::Instant::max_duration();
+        for instant in [
+            &mut self.entered_state,
+            &mut self.last_activity,
+            &mut self.last_input,
+        ] {
+            if current_time.duration_since(instant) >= max {
+                *instant = current_time.checked_sub(max).unwrap_or(current_time);
+            }
+        }
+    }
+
+    /// Get ready to evaluate the current state's transitions against `event`,
+    /// or a tick if there is none. Returns `None` while suspended.
+    fn prepare(
+        &mut self,
+        current_time: Clock::Instant,
+        event: Option<InputEvent>,
+    ) -> Option<Context> {
+        // what's held is kept track of while suspended too, so that a key
+        // released then isn't taken to be held forever after
+        match event {
+            Some(InputEvent::Press(key)) => self.held.insert(key),
+            Some(InputEvent::Depress(key)) => {
+                self.held.remove(key);
+            }
+            Some(InputEvent::PointerButton(button, true)) => self.held_buttons.insert(button),
+            Some(InputEvent::PointerButton(button, false)) => {
+                self.held_buttons.remove(button);
+            }
+            _ => {}
+        }
+        if self.suspended {
+            return None;
+        }
+
+        self.clamp_timers(current_time);
+        if let Some(event) = event {
+            self.last_input = current_time;
+            match event {
+                InputEvent::Application(id) => self.host.application = id,
+                InputEvent::WindowTitle(hash) => self.host.window_title = hash,
+                InputEvent::Battery(level) => self.power.battery = level,
+                InputEvent::ExternalPower(on) => self.power.external = on,
+                _ => {}
+            }
+        }
+
+        let context = self.context(current_time);
+        self.previous_flags = self.flags;
+        Some(context)
+    }
+
+    /// When a transition with `conditions` taken on a tick happened.
+    ///
+    /// A timed transition is taken as happening at its deadline rather than at
+    /// this tick, so that ticking coarsely doesn't push back the timers of the
+    /// states after it.
+    fn taken_at(
+        &self,
+        conditions: &[TransitionCondition],
+        current_time: Clock::Instant,
+    ) -> Clock::Instant {
+        conditions
             .iter()
-            .flat_map(|t| t.evaluate(elapsed, Some(event), self.flags))
-            .next()
-        {
-            self.do_transition(internal_events, next_state, current_time);
+            .filter_map(|condition| self.deadline(condition))
+            .max()
+            .map_or(current_time, |deadline| {
+                deadline.max(self.last_input).min(current_time)
+            })
+    }
 
-            return key_events;
+    /// Stop handling events and ticks, for when the host suspends.
+    fn suspend(&mut self) {
+        self.suspended = true;
+    }
+
+    /// Start handling events again. The elapsed and activity timers restart
+    /// at `current_time`, so the time spent suspended doesn't count towards
+    /// any timeouts.
+    fn resume(&mut self, current_time: Clock::Instant) {
+        self.suspended = false;
+        self.entered_state = current_time;
+        self.last_activity = current_time;
+        self.last_input = current_time;
+    }
+
+    /// When a timed condition starts to hold.
+    fn deadline(&self, condition: &TransitionCondition) -> Option<Clock::Instant> {
+        match condition {
+            TransitionCondition::IdleGreater(x) => self.last_activity.checked_add(*x),
+            condition => elapsed_deadline(condition, self.entered_state),
         }
+    }
 
-        &[]
+    /// When a state entered by a transition taken on a tick at `at`, as given
+    /// by [`GlobalState::taken_at`], should count as entered. `conditions`
+    /// are those of the state's transitions.
+    ///
+    /// That's `at`, unless one of the state's timeouts would then already
+    /// have passed by `current_time`. Then it's `current_time`, so that a
+    /// timed transition back into the same state fires once for a late tick
+    /// rather than again on every tick until it has caught up.
+    fn entered_at<'c>(
+        &self,
+        at: Clock::Instant,
+        mut conditions: impl Iterator<Item = &'c TransitionCondition>,
+        current_time: Clock::Instant,
+    ) -> Clock::Instant {
+        let late = at < current_time
+            && conditions.any(|condition| {
+                elapsed_deadline(condition, at).is_some_and(|deadline| deadline <= current_time)
+            });
+        if late {
+            current_time
+        } else {
+            at
+        }
     }
 
+    /// The earliest time after `current_time` at which one of `conditions`
+    /// could start to hold.
+    fn earliest_deadline<'c>(
+        &self,
+        conditions: impl Iterator<Item = &'c TransitionCondition>,
+        current_time: Clock::Instant,
+    ) -> Option<Clock::Instant> {
+        conditions
+            .filter_map(|condition| self.deadline(condition))
+            .filter(|deadline| *deadline > current_time)
+            .min()
+    }
+
+    /// Take a transition triggered by `trigger`, or by a tick if there's
+    /// none, telling the observer about it.
     fn do_transition(
         &mut self,
+        trigger: Option<InputEvent>,
         internal_events: &[InternalEvent],
-        next_state: &'static dyn DynState,
-        current_time: Instant<Clock>,
+        key_events: &[KeyEvent],
+        next_state: S,
+        current_time: Clock::Instant,
     ) {
+        let flags = self.flags;
         for event in internal_events {
-            event.apply(self);
+            event.apply(self, current_time);
         }
 
+        let previous_state = self.current_state;
         self.current_state = next_state;
         self.entered_state = current_time;
+        self.entry_flags = self.flags;
+        self.previous_flags = self.flags;
+
+        let trigger = if self.private {
+            trigger.map(InputEvent::masked)
+        } else {
+            trigger
+        };
+        self.observer
+            .on_transition(previous_state, next_state, trigger);
+        if self.flags != flags {
+            self.observer.on_flags_changed(flags, self.flags);
+        }
+        if self.private {
+            privacy::masked_chunks(key_events, |events| self.observer.on_emit(events));
+        } else if !key_events.is_empty() {
+            self.observer.on_emit(key_events);
+        }
+    }
+}
+
+impl<Clock: time::Clock, O: Observer<&'static dyn DynState>>
+    GlobalState<Clock, &'static dyn DynState, O>
+{
+    fn tick(&mut self, current_time: Clock::Instant) -> &[KeyEvent] {
+        self.step(current_time, None)
+    }
+
+    fn push(&mut self, current_time: Clock::Instant, event: InputEvent) -> &[KeyEvent] {
+        self.step(current_time, Some(event))
+    }
+
+    /// [`GlobalState::tick`], recording what was emitted in `trace`.
+    fn tick_traced<const N: usize>(
+        &mut self,
+        current_time: Clock::Instant,
+        trace: &mut trace::TraceBuffer<Clock, N>,
+    ) -> &[KeyEvent] {
+        let private = self.private;
+        let events = self.tick(current_time);
+        trace.record_outputs(current_time, events, private);
+        events
+    }
+
+    /// [`GlobalState::push`], recording `event` and what was emitted in
+    /// `trace`.
+    fn push_traced<const N: usize>(
+        &mut self,
+        current_time: Clock::Instant,
+        event: InputEvent,
+        trace: &mut trace::TraceBuffer<Clock, N>,
+    ) -> &[KeyEvent] {
+        let private = self.private;
+        trace.record_input(current_time, event, private);
+        let events = self.push(current_time, event);
+        trace.record_outputs(current_time, events, private);
+        events
+    }
+
+    /// Take new readings from `source`, then tick so that transitions on
+    /// them are taken.
+    fn poll_signals(
+        &mut self,
+        current_time: Clock::Instant,
+        source: &mut impl SignalSource,
+    ) -> &[KeyEvent] {
+        self.signals.poll(source);
+        self.tick(current_time)
+    }
+
+    fn step(&mut self, current_time: Clock::Instant, event: Option<InputEvent>) -> &[KeyEvent] {
+        self.emitted = Emitted::Transition(&[]);
+        self.overflowed = false;
+        let Some(context) = self.prepare(current_time, event) else {
+            return &[];
+        };
+
+        let mut matching = self
+            .current_state
+            .transitions()
+            .iter()
+            .filter(|t| t.evaluate(&context, event).is_some());
+        let Some(transition) = matching.next() else {
+            return &[];
+        };
+
+        let at = match event {
+            Some(_) => current_time,
+            None => self.entered_at(
+                self.taken_at(transition.conditions(), current_time),
+                transition
+                    .target()
+                    .transitions()
+                    .iter()
+                    .flat_map(|t| t.conditions()),
+                current_time,
+            ),
+        };
+        #[cfg(feature = "stats")]
+        {
+            self.last_transition = Some(*transition);
+        }
+        let transition: &'static dyn DynTransition = *transition;
+        if self.match_policy == MatchPolicy::First {
+            self.do_transition(
+                event,
+                transition.internal_event_emissions(),
+                transition.key_event_emissions(),
+                transition.target(),
+                at,
+            );
+            self.emitted = Emitted::Transition(transition.key_event_emissions());
+            return transition.key_event_emissions();
+        }
+
+        // every match is found before any of them is applied
+        let mut internal_events = [InternalEvent::RecordActivity; MAX_EMISSIONS];
+        let (mut internal_len, mut key_len) = (0, 0);
+        for taken in core::iter::once(transition).chain(matching.map(|t| *t as &dyn DynTransition))
+        {
+            let internal = taken.internal_event_emissions();
+            let key = taken.key_event_emissions();
+            if internal_len + internal.len() > MAX_EMISSIONS || key_len + key.len() > MAX_EMISSIONS
+            {
+                self.overflowed = true;
+                continue;
+            }
+            internal_events[internal_len..][..internal.len()].copy_from_slice(internal);
+            self.merged[key_len..][..key.len()].copy_from_slice(key);
+            internal_len += internal.len();
+            key_len += key.len();
+        }
+        let merged = self.merged;
+        self.do_transition(
+            event,
+            &internal_events[..internal_len],
+            &merged[..key_len],
+            transition.target(),
+            at,
+        );
+        self.emitted = Emitted::Merged(key_len);
+        self.emitted()
+    }
+
+    /// What the machine emitted when it was last pushed an event or ticked,
+    /// for callers that can't hold on to what [`GlobalState::push`] returns.
+    fn emitted(&self) -> &[KeyEvent] {
+        match self.emitted {
+            Emitted::Transition(events) => events,
+            Emitted::Merged(len) => &self.merged[..len],
+        }
+    }
+
+    /// The earliest time after `current_time` at which a timed condition of the
+    /// current state could start to hold, for callers that sleep until the
+    /// next [`GlobalState::tick`] is needed rather than ticking continuously.
+    fn next_deadline(&self, current_time: Clock::Instant) -> Option<Clock::Instant> {
+        self.earliest_deadline(
+            self.current_state
+                .transitions()
+                .iter()
+                .flat_map(|t| t.conditions()),
+            current_time,
+        )
     }
 }
 
-struct Transition<
-    const CONDITION_COUNT: usize,
-    const KEY_EMIT_COUNT: usize,
-    const INTERNAL_EMIT_COUNT: usize,
-> {
-    conditions: [TransitionCondition; CONDITION_COUNT],
-    key_event_emissions: [KeyEvent; KEY_EMIT_COUNT],
-    internal_event_emissions: [InternalEvent; INTERNAL_EMIT_COUNT],
-    target: &'static dyn DynState,
+/// A transition, taken when all of its conditions hold. Every transition has
+/// the same type whatever the lengths of its slices, so they all share one
+/// copy of the code that evaluates them.
+pub struct Transition {
+    conditions: &'static [TransitionCondition],
+    key_event_emissions: &'static [KeyEvent],
+    internal_event_emissions: &'static [InternalEvent],
+    target: &'static State,
 }
 
-impl<
-        const CONDITION_COUNT: usize,
-        const KEY_EMIT_COUNT: usize,
-        const INTERNAL_EMIT_COUNT: usize,
-    > Transition<CONDITION_COUNT, KEY_EMIT_COUNT, INTERNAL_EMIT_COUNT>
-{
+impl Transition {
     const fn as_dyn(&self) -> &dyn DynTransition {
         self
     }
@@ -178,15 +1244,10 @@ trait DynTransition: Send + Sync + 'static {
     fn target(&self) -> &'static dyn DynState;
     fn evaluate(
         &self,
-        elapsed: Milliseconds,
+        context: &Context,
         key: Option<InputEvent>,
-        state: StateFlags,
     ) -> Option<(&[KeyEvent], &[InternalEvent], &'static dyn DynState)> {
-        if self
-            .conditions()
-            .iter()
-            .all(|c| c.evaluate(elapsed, key, state))
-        {
+        if self.conditions().iter().all(|c| c.evaluate(context, key)) {
             Some((
                 self.key_event_emissions(),
                 self.internal_event_emissions(),
@@ -198,22 +1259,17 @@ trait DynTransition: Send + Sync + 'static {
     }
 }
 
-impl<
-        const CONDITION_COUNT: usize,
-        const KEY_EMIT_COUNT: usize,
-        const INTERNAL_EMIT_COUNT: usize,
-    > DynTransition for Transition<CONDITION_COUNT, KEY_EMIT_COUNT, INTERNAL_EMIT_COUNT>
-{
+impl DynTransition for Transition {
     fn conditions(&self) -> &[TransitionCondition] {
-        &self.conditions
+        self.conditions
     }
 
     fn key_event_emissions(&self) -> &[KeyEvent] {
-        &self.key_event_emissions
+        self.key_event_emissions
     }
 
     fn internal_event_emissions(&self) -> &[InternalEvent] {
-        &self.internal_event_emissions
+        self.internal_event_emissions
     }
 
     fn target(&self) -> &'static dyn DynState {
@@ -221,118 +1277,121 @@ impl<
     }
 }
 
-struct State<const TRANSITION_COUNT: usize> {
+/// Identifies a state within its machine. Unlike names, which are only for
+/// debugging, ids must be unique within a machine, and should stay the same
+/// across firmware versions so that saved ids still refer to the same state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StateId(u16);
+
+struct State {
     name: &'static str,
-    transitions: [&'static dyn DynTransition; TRANSITION_COUNT],
+    id: StateId,
+    transitions: &'static [&'static Transition],
 }
 
-impl<const TRANSITION_COUNT: usize> State<TRANSITION_COUNT> {
+impl State {
     const fn as_dyn(&self) -> &dyn DynState {
         self
     }
 }
 
-trait DynState: Send + Sync + 'static {
-    fn transitions(&self) -> &[&'static dyn DynTransition];
+pub trait DynState: Send + Sync + 'static {
+    fn transitions(&self) -> &'static [&'static Transition];
     fn name(&self) -> &str;
+    fn id(&self) -> StateId;
 }
 
-impl<const SIZE: usize> DynState for State<SIZE> {
-    fn transitions(&self) -> &[&'static dyn DynTransition] {
-        &self.transitions
+impl DynState for State {
+    fn transitions(&self) -> &'static [&'static Transition] {
+        self.transitions
     }
 
     fn name(&self) -> &str {
         self.name
     }
+
+    fn id(&self) -> StateId {
+        self.id
+    }
 }
 
 impl PartialEq for dyn DynState {
     fn eq(&self, other: &Self) -> bool {
-        self.name() == other.name()
+        self.id() == other.id()
     }
 }
 
 impl std::fmt::Debug for &dyn DynState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "DynState {{{}}}", self.name())
+        write!(f, "DynState {{{} ({})}}", self.name(), self.id().0)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    struct TickerClock(u32);
-
-    impl TickerClock {
-        fn tick(&mut self) {
-            self.0 += 1;
-        }
-
-        fn tick_n(&mut self, n: u32) {
-            self.0 += n;
-        }
-
-        fn now(&self) -> Instant<TickerClock> {
-            self.try_now().unwrap()
-        }
-    }
-
-    impl embedded_time::Clock for TickerClock {
-        type T = u32;
-        const SCALING_FACTOR: embedded_time::rate::Fraction =
-            embedded_time::rate::Fraction::new(1, 1_000);
+    pub(crate) use crate::testing::TickerClock;
 
-        fn try_now(&self) -> Result<embedded_time::Instant<Self>, embedded_time::clock::Error> {
-            Ok(embedded_time::Instant::new(self.0))
+    /// A context with no time elapsed, flags set or layers active.
+    pub(crate) fn context() -> Context {
+        Context {
+            elapsed: time::Duration::ZERO,
+            idle: time::Duration::ZERO,
+            flags: StateFlags::empty(),
+            entry_flags: StateFlags::empty(),
+            previous_flags: StateFlags::empty(),
+            layers: Layers::empty(),
+            host: HostContext::default(),
+            power: Power::default(),
+            signals: Signals::default(),
+            held: KeySet::empty(),
+            held_buttons: KeySet::empty(),
         }
     }
 
-    use std::assert_matches::assert_matches;
     use std::sync::atomic::AtomicU32;
 
-    use embedded_time::duration::Milliseconds;
     use embedded_time::Instant;
     use embedded_time::{duration::Extensions, Clock};
 
+    use crate::time::Duration;
     use crate::{
-        DynState, DynTransition, GlobalState, InternalEvent, KeyEvent, State, StateFlags,
-        Transition, TransitionCondition,
+        time, Context, DynState, DynTransition, GlobalState, HostContext, InputEvent,
+        InternalEvent, KeyEvent, KeySet, Layers, MatchPolicy, Power, Signals, State, StateFlags,
+        StateId, Transition, TransitionCondition,
     };
 
     #[test]
     fn basic() {
-        static A: State<1> = State {
+        static A: State = State {
             name: "A",
-            transitions: [A_0.as_dyn()],
+            id: StateId(0),
+            transitions: &[&A_0],
         };
 
-        static A_0: Transition<1, 1, 0> = Transition {
-            conditions: [TransitionCondition::pressed_single(0)],
-            key_event_emissions: [KeyEvent::Press(0)],
-            internal_event_emissions: [],
-            target: B.as_dyn(),
+        static A_0: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(0)],
+            key_event_emissions: &[KeyEvent::Press(0)],
+            internal_event_emissions: &[],
+            target: &B,
         };
 
-        static B: State<1> = State {
+        static B: State = State {
             name: "B",
-            transitions: [B_0.as_dyn()],
+            id: StateId(1),
+            transitions: &[&B_0],
         };
 
-        static B_0: Transition<1, 1, 0> = Transition {
-            conditions: [TransitionCondition::depressed_single(0)],
-            key_event_emissions: [KeyEvent::Depress(0)],
-            internal_event_emissions: [],
-            target: A.as_dyn(),
+        static B_0: Transition = Transition {
+            conditions: &[TransitionCondition::depressed_single(0)],
+            key_event_emissions: &[KeyEvent::Depress(0)],
+            internal_event_emissions: &[],
+            target: &A,
         };
 
         let clock = TickerClock(0);
         let now = clock.now();
 
-        let mut state = GlobalState {
-            flags: StateFlags::empty(),
-            entered_state: now,
-            current_state: A.as_dyn(),
-        };
+        let mut state = GlobalState::<TickerClock>::new(A.as_dyn(), now);
 
         for _ in 0..10 {
             let s = state.push(now, crate::InputEvent::Press(0));
@@ -343,35 +1402,552 @@ mod tests {
         }
     }
 
+    #[test]
+    fn layers_past_the_last() {
+        let mut layers = Layers::empty();
+        layers.activate(31);
+        layers.activate(32);
+        layers.activate(255);
+        assert_eq!(layers, Layers(1 << 31));
+        assert!(!layers.is_active(32));
+        layers.deactivate(200);
+        assert!(layers.is_active(31));
+    }
+
+    #[test]
+    fn next_deadline() {
+        static A: State = State {
+            name: "A",
+            id: StateId(2),
+            transitions: &[&A_0, &A_1, &A_2],
+        };
+
+        static A_0: Transition = Transition {
+            conditions: &[
+                TransitionCondition::ElapsedGreater(Duration::from_millis(20)),
+                TransitionCondition::StateSet(StateFlags::CTRL),
+            ],
+            key_event_emissions: &[],
+            internal_event_emissions: &[],
+            target: &A,
+        };
+
+        static A_1: Transition = Transition {
+            conditions: &[TransitionCondition::IdleGreater(Duration::from_millis(30))],
+            key_event_emissions: &[],
+            internal_event_emissions: &[],
+            target: &A,
+        };
+
+        static A_2: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(0)],
+            key_event_emissions: &[],
+            internal_event_emissions: &[],
+            target: &A,
+        };
+
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(A.as_dyn(), clock.now());
+
+        clock.tick_n(5);
+        state.push(clock.now(), crate::InputEvent::Press(0));
+        assert_eq!(
+            state.next_deadline(clock.now()),
+            Some(TickerClock(25).now())
+        );
+
+        // deadlines that passed without a transition are skipped
+        clock.tick_n(20);
+        assert_eq!(
+            state.next_deadline(clock.now()),
+            Some(TickerClock(30).now())
+        );
+        clock.tick_n(5);
+        assert_eq!(state.next_deadline(clock.now()), None);
+    }
+
+    #[test]
+    fn microsecond_conditions() {
+        use core::time::Duration;
+
+        static A: State = State {
+            name: "A",
+            id: StateId(3),
+            transitions: &[&A_0, &A_1],
+        };
+
+        static A_0: Transition = Transition {
+            conditions: &[
+                TransitionCondition::pressed_single(0),
+                TransitionCondition::ElapsedLessMicros(time::Duration::from_micros(250)),
+            ],
+            key_event_emissions: &[KeyEvent::Press(4)],
+            internal_event_emissions: &[],
+            target: &A,
+        };
+
+        static A_1: Transition = Transition {
+            conditions: &[TransitionCondition::ElapsedGreaterMicros(
+                time::Duration::from_micros(250),
+            )],
+            key_event_emissions: &[KeyEvent::Press(5)],
+            internal_event_emissions: &[],
+            target: &A,
+        };
+
+        let mut state = GlobalState::<time::HostClock>::new(A.as_dyn(), Duration::ZERO);
+
+        let now = Duration::from_micros(200);
+        assert_eq!(state.push(now, InputEvent::Press(0)), &[KeyEvent::Press(4)]);
+        assert_eq!(state.next_deadline(now), Some(Duration::from_micros(450)));
+        assert_eq!(state.tick(Duration::from_micros(449)), &[]);
+        assert_eq!(
+            state.tick(Duration::from_micros(450)),
+            &[KeyEvent::Press(5)]
+        );
+    }
+
+    #[test]
+    fn host_context() {
+        static A: State = State {
+            name: "A",
+            id: StateId(4),
+            transitions: &[&A_0],
+        };
+
+        static A_0: Transition = Transition {
+            conditions: &[
+                TransitionCondition::pressed_single(0),
+                TransitionCondition::ApplicationIs(3),
+            ],
+            key_event_emissions: &[KeyEvent::Press(4)],
+            internal_event_emissions: &[],
+            target: &A,
+        };
+
+        let clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(A.as_dyn(), clock.now());
+
+        assert_eq!(state.push(clock.now(), InputEvent::Press(0)), &[]);
+        state.push(clock.now(), InputEvent::Application(3));
+        state.push(clock.now(), InputEvent::WindowTitle(0xbeef));
+        assert_eq!(
+            state.push(clock.now(), InputEvent::Press(0)),
+            &[KeyEvent::Press(4)]
+        );
+        assert_eq!(
+            InputEvent::from_bytes(InputEvent::WindowTitle(0xbeef).to_bytes()),
+            Some(InputEvent::WindowTitle(0xbeef))
+        );
+    }
+
+    #[test]
+    fn power() {
+        static POWERED: State = State {
+            name: "POWERED",
+            id: StateId(17),
+            transitions: &[&LOW_BATTERY],
+        };
+
+        static SAVING: State = State {
+            name: "SAVING",
+            id: StateId(18),
+            transitions: &[&PLUGGED_IN],
+        };
+
+        static LOW_BATTERY: Transition = Transition {
+            conditions: &[
+                TransitionCondition::BatteryBelow(15),
+                TransitionCondition::OnBattery,
+            ],
+            key_event_emissions: &[KeyEvent::Press(1)],
+            internal_event_emissions: &[],
+            target: &SAVING,
+        };
+
+        static PLUGGED_IN: Transition = Transition {
+            conditions: &[TransitionCondition::ExternalPowered],
+            key_event_emissions: &[KeyEvent::Depress(1)],
+            internal_event_emissions: &[],
+            target: &POWERED,
+        };
+
+        let clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(POWERED.as_dyn(), clock.now());
+
+        assert_eq!(state.tick(clock.now()), &[]);
+        assert_eq!(state.push(clock.now(), InputEvent::Battery(40)), &[]);
+        state.push(clock.now(), InputEvent::ExternalPower(true));
+        assert_eq!(state.push(clock.now(), InputEvent::Battery(10)), &[]);
+        assert_eq!(
+            state.push(clock.now(), InputEvent::ExternalPower(false)),
+            &[KeyEvent::Press(1)]
+        );
+        assert_eq!(
+            state.push(clock.now(), InputEvent::ExternalPower(true)),
+            &[KeyEvent::Depress(1)]
+        );
+        for event in [InputEvent::Battery(10), InputEvent::ExternalPower(true)] {
+            assert_eq!(InputEvent::from_bytes(event.to_bytes()), Some(event));
+        }
+    }
+
+    #[test]
+    fn match_all() {
+        static MERGED: State = State {
+            name: "MERGED",
+            id: StateId(0),
+            transitions: &[&GAME, &LAYER, &FLOOD, &TAP],
+        };
+
+        static GAMING: State = State {
+            name: "GAMING",
+            id: StateId(1),
+            transitions: &[],
+        };
+
+        static GAME: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(1)],
+            key_event_emissions: &[KeyEvent::Press(0xe1)],
+            internal_event_emissions: &[InternalEvent::SetGlobalState(StateFlags::GAME_MODE)],
+            target: &GAMING,
+        };
+
+        static LAYER: Transition = Transition {
+            internal_event_emissions: &[InternalEvent::ActivateLayer(2)],
+            target: &MERGED,
+            ..GAME
+        };
+
+        static FLOOD: Transition = Transition {
+            key_event_emissions: &[KeyEvent::Press(4); 16],
+            internal_event_emissions: &[],
+            target: &MERGED,
+            ..GAME
+        };
+
+        static TAP: Transition = Transition {
+            key_event_emissions: &[KeyEvent::Press(6), KeyEvent::Depress(6)],
+            internal_event_emissions: &[],
+            target: &MERGED,
+            ..GAME
+        };
+
+        let clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(MERGED.as_dyn(), clock.now());
+        assert_eq!(
+            state.push(clock.now(), InputEvent::Press(1)),
+            &[KeyEvent::Press(0xe1)]
+        );
+        assert!(!state.layers.is_active(2));
+
+        let mut state = GlobalState::<TickerClock>::new(MERGED.as_dyn(), clock.now());
+        state.set_match_policy(MatchPolicy::All);
+        assert_eq!(state.push(clock.now(), InputEvent::Press(2)), &[]);
+        // in order, leaving out the match with too many to fit
+        assert_eq!(
+            state.push(clock.now(), InputEvent::Press(1)),
+            &[
+                KeyEvent::Press(0xe1),
+                KeyEvent::Press(0xe1),
+                KeyEvent::Press(6),
+                KeyEvent::Depress(6)
+            ]
+        );
+        assert_eq!(state.emitted().len(), 4);
+        assert!(state.overflowed());
+        assert!(state.flags.contains(StateFlags::GAME_MODE));
+        assert!(state.layers.is_active(2));
+        assert_eq!(state.current_state, GAMING.as_dyn());
+        assert_eq!(state.tick(clock.now()), &[]);
+        assert!(state.emitted().is_empty());
+        assert!(!state.overflowed());
+    }
+
+    #[test]
+    fn bootloader() {
+        static RUNNING: State = State {
+            name: "RUNNING",
+            id: StateId(19),
+            transitions: &[&FLASH, &RESET],
+        };
+
+        // only reachable with the chord's flag held
+        static FLASH: Transition = Transition {
+            conditions: &[
+                TransitionCondition::StateSet(StateFlags::CTRL.union(StateFlags::SHFT)),
+                TransitionCondition::Pressed(1..=1),
+            ],
+            key_event_emissions: &[KeyEvent::EnterBootloader],
+            internal_event_emissions: &[],
+            target: &RUNNING,
+        };
+
+        static RESET: Transition = Transition {
+            conditions: &[
+                TransitionCondition::StateSet(StateFlags::CTRL.union(StateFlags::SHFT)),
+                TransitionCondition::Pressed(2..=2),
+            ],
+            key_event_emissions: &[KeyEvent::SystemReset],
+            internal_event_emissions: &[],
+            target: &RUNNING,
+        };
+
+        let clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(RUNNING.as_dyn(), clock.now());
+
+        assert_eq!(state.push(clock.now(), InputEvent::Press(1)), &[]);
+        state.flags = StateFlags::CTRL | StateFlags::SHFT;
+        assert_eq!(
+            state.push(clock.now(), InputEvent::Press(1)),
+            &[KeyEvent::EnterBootloader]
+        );
+        assert_eq!(
+            state.push(clock.now(), InputEvent::Press(2)),
+            &[KeyEvent::SystemReset]
+        );
+        assert_eq!(KeyEvent::EnterBootloader.to_bytes(), [4, 0, 0]);
+        assert_eq!(KeyEvent::SystemReset.to_bytes(), [5, 0, 0]);
+    }
+
+    #[test]
+    fn suspend_restarts_timers() {
+        static A: State = State {
+            name: "A",
+            id: StateId(5),
+            transitions: &[&A_0],
+        };
+
+        static A_0: Transition = Transition {
+            conditions: &[TransitionCondition::ElapsedGreater(Duration::from_millis(
+                10,
+            ))],
+            key_event_emissions: &[KeyEvent::Press(0)],
+            internal_event_emissions: &[],
+            target: &A,
+        };
+
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(A.as_dyn(), clock.now());
+
+        clock.tick_n(5);
+        state.suspend();
+        clock.tick_n(1000);
+        assert_matches!(state.tick(clock.now()), []);
+        assert_matches!(state.push(clock.now(), crate::InputEvent::Press(0)), []);
+
+        state.resume(clock.now());
+        assert_matches!(state.tick(clock.now()), []);
+        clock.tick_n(10);
+        assert_matches!(state.tick(clock.now()), [KeyEvent::Press(0)]);
+    }
+
+    #[test]
+    fn idle_timer_ignores_state_entry() {
+        static A: State = State {
+            name: "A",
+            id: StateId(6),
+            transitions: &[&A_IDLE, &A_TOGGLE],
+        };
+
+        static A_IDLE: Transition = Transition {
+            conditions: &[TransitionCondition::IdleGreater(Duration::from_millis(10))],
+            key_event_emissions: &[KeyEvent::Press(1)],
+            internal_event_emissions: &[],
+            target: &A,
+        };
+
+        static A_TOGGLE: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(0)],
+            key_event_emissions: &[],
+            internal_event_emissions: &[],
+            target: &A,
+        };
+
+        static B: State = State {
+            name: "B",
+            id: StateId(7),
+            transitions: &[&B_0],
+        };
+
+        static B_0: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(0)],
+            key_event_emissions: &[],
+            internal_event_emissions: &[InternalEvent::RecordActivity],
+            target: &A,
+        };
+
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(B.as_dyn(), clock.now());
+
+        clock.tick_n(5);
+        state.push(clock.now(), crate::InputEvent::Press(0));
+        assert_eq!(state.current_state, A.as_dyn());
+
+        // re-entering the state doesn't restart the activity timer
+        clock.tick_n(5);
+        state.push(clock.now(), crate::InputEvent::Press(0));
+        clock.tick_n(4);
+        assert_matches!(state.tick(clock.now()), []);
+
+        clock.tick();
+        assert_matches!(state.tick(clock.now()), [KeyEvent::Press(1)]);
+    }
+
+    #[test]
+    fn flag_edges() {
+        static WATCH: State = State {
+            name: "WATCH",
+            id: StateId(14),
+            transitions: &[&WATCH_JUST_SET, &WATCH_SINCE_ENTRY, &WATCH_SET_SHIFT],
+        };
+
+        static WATCH_JUST_SET: Transition = Transition {
+            conditions: &[TransitionCondition::FlagJustSet(StateFlags::GAME_MODE)],
+            key_event_emissions: &[KeyEvent::Press(1)],
+            internal_event_emissions: &[],
+            target: &WAIT,
+        };
+
+        static WATCH_SINCE_ENTRY: Transition = Transition {
+            conditions: &[TransitionCondition::FlagSetSinceEntry(StateFlags::CTRL)],
+            key_event_emissions: &[KeyEvent::Press(2)],
+            internal_event_emissions: &[],
+            target: &WATCH,
+        };
+
+        static WATCH_SET_SHIFT: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(0)],
+            key_event_emissions: &[],
+            internal_event_emissions: &[InternalEvent::SetGlobalState(StateFlags::SHFT)],
+            target: &WAIT,
+        };
+
+        static WAIT: State = State {
+            name: "WAIT",
+            id: StateId(15),
+            transitions: &[&WAIT_SHIFT, &WAIT_BACK],
+        };
+
+        static WAIT_SHIFT: Transition = Transition {
+            conditions: &[TransitionCondition::FlagJustSet(StateFlags::SHFT)],
+            key_event_emissions: &[KeyEvent::Press(3)],
+            internal_event_emissions: &[],
+            target: &WAIT,
+        };
+
+        static WAIT_BACK: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(9)],
+            key_event_emissions: &[],
+            internal_event_emissions: &[],
+            target: &WATCH,
+        };
+
+        let clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(WATCH.as_dyn(), clock.now());
+        assert_matches!(state.tick(clock.now()), []);
+
+        // set from outside, seen on the next step only
+        state.flags.insert(StateFlags::GAME_MODE);
+        assert_matches!(state.tick(clock.now()), [KeyEvent::Press(1)]);
+        state.push(clock.now(), InputEvent::Press(9));
+        assert_matches!(state.tick(clock.now()), []);
+
+        // a looping transition on a flag set since entry is taken once
+        state.flags.insert(StateFlags::CTRL);
+        assert_matches!(state.tick(clock.now()), [KeyEvent::Press(2)]);
+        assert_matches!(state.tick(clock.now()), []);
+
+        // the machine's own flags aren't an edge
+        state.push(clock.now(), InputEvent::Press(0));
+        assert_eq!(state.current_state, WAIT.as_dyn());
+        assert_matches!(state.tick(clock.now()), []);
+
+        // but setting it again from outside is
+        state.flags.remove(StateFlags::SHFT);
+        state.tick(clock.now());
+        state.flags.insert(StateFlags::SHFT);
+        assert_matches!(
+            state.push(clock.now(), InputEvent::Press(5)),
+            [KeyEvent::Press(3)]
+        );
+        assert_matches!(state.tick(clock.now()), []);
+    }
+
+    #[test]
+    fn timeouts_fire_once_per_entry() {
+        static REPEAT: State = State {
+            name: "REPEAT",
+            id: StateId(16),
+            transitions: &[&REPEAT_0],
+        };
+
+        static REPEAT_0: Transition = Transition {
+            conditions: &[TransitionCondition::ElapsedGreater(Duration::from_millis(
+                5,
+            ))],
+            key_event_emissions: &[KeyEvent::Press(4)],
+            internal_event_emissions: &[],
+            target: &REPEAT,
+        };
+
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(REPEAT.as_dyn(), clock.now());
+
+        // holds from the deadline itself
+        clock.tick_n(4);
+        assert_matches!(state.tick(clock.now()), []);
+        clock.tick();
+        assert_matches!(state.tick(clock.now()), [KeyEvent::Press(4)]);
+
+        // a coarse tick keeps to the deadlines
+        clock.tick_n(7);
+        assert_matches!(state.tick(clock.now()), [KeyEvent::Press(4)]);
+        clock.tick_n(3);
+        assert_matches!(state.tick(clock.now()), [KeyEvent::Press(4)]);
+
+        // a tick several deadlines late fires once rather than catching up
+        clock.tick_n(20);
+        assert_matches!(state.tick(clock.now()), [KeyEvent::Press(4)]);
+        for _ in 0..4 {
+            clock.tick();
+            assert_matches!(state.tick(clock.now()), []);
+        }
+        clock.tick();
+        assert_matches!(state.tick(clock.now()), [KeyEvent::Press(4)]);
+    }
+
     #[test]
     fn mod_tap_better() {
-        static ROOT: State<3> = State {
+        static ROOT: State = State {
             name: "ROOT",
-            transitions: [ROOT_0.as_dyn(), ROOT_PRESS_1.as_dyn(), ROOT_RESET.as_dyn()],
+            id: StateId(8),
+            transitions: &[&ROOT_0, &ROOT_PRESS_1, &ROOT_RESET],
         };
 
-        static ROOT_0: Transition<1, 0, 0> = Transition {
-            conditions: [TransitionCondition::pressed_single(0)],
-            key_event_emissions: [],
-            internal_event_emissions: [],
-            target: MOD.as_dyn(),
+        static ROOT_0: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(0)],
+            key_event_emissions: &[],
+            internal_event_emissions: &[],
+            target: &MOD,
         };
 
-        static ROOT_PRESS_1: Transition<1, 1, 0> = Transition {
-            conditions: [TransitionCondition::pressed_single(1)],
-            key_event_emissions: [KeyEvent::Press(1)],
-            internal_event_emissions: [],
-            target: PRESS_1.as_dyn(),
+        static ROOT_PRESS_1: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(1)],
+            key_event_emissions: &[KeyEvent::Press(1)],
+            internal_event_emissions: &[],
+            target: &PRESS_1,
         };
 
         // we'll probably have it so that if a normal key is currently being pressed, you can't enter a mod-tap, instead it will
         // press the tap key of the mod tap
-        static PRESS_1: State<1> = State {
+        static PRESS_1: State = State {
             name: "PRESS_1",
-            transitions: [PRESS_1_DEPRESS.as_dyn()], //, PRESS_1_OTHER.as_dyn()]
+            id: StateId(9),
+            transitions: &[&PRESS_1_DEPRESS], //, PRESS_1_OTHER.as_dyn()]
         };
 
-        // static PRESS_1_OTHER: Transition<1, 1, 0> = Transition {
+        // static PRESS_1_OTHER: Transition = Transition {
         //     conditions: [
         //         TransitionCondition::pressed_single(3),
         //     ],
@@ -380,143 +1956,138 @@ mod tests {
         //     target: ROOT.as_dyn(),
         // };
 
-        static PRESS_1_DEPRESS: Transition<1, 1, 0> = Transition {
-            conditions: [TransitionCondition::depressed_single(1)],
-            key_event_emissions: [KeyEvent::Depress(1)],
-            internal_event_emissions: [],
-            target: ROOT.as_dyn(),
+        static PRESS_1_DEPRESS: Transition = Transition {
+            conditions: &[TransitionCondition::depressed_single(1)],
+            key_event_emissions: &[KeyEvent::Depress(1)],
+            internal_event_emissions: &[],
+            target: &ROOT,
         };
 
-        static ROOT_RESET: Transition<2, 1, 1> = Transition {
-            conditions: [
+        static ROOT_RESET: Transition = Transition {
+            conditions: &[
                 TransitionCondition::StateSet(StateFlags::SHFT),
                 TransitionCondition::depressed_single(0),
             ],
-            key_event_emissions: [KeyEvent::Depress(2)],
-            internal_event_emissions: [InternalEvent::UnsetGlobalState(StateFlags::SHFT)],
-            target: ROOT.as_dyn(),
+            key_event_emissions: &[KeyEvent::Depress(2)],
+            internal_event_emissions: &[InternalEvent::UnsetGlobalState(StateFlags::SHFT)],
+            target: &ROOT,
         };
 
-        static MOD: State<3> = State {
+        static MOD: State = State {
             name: "MOD",
-            transitions: [
-                MOD_TAP_TRANS.as_dyn(),
-                MOD_TAP_OTHER_TRANS.as_dyn(),
-                MOD_HOLD_TRANS.as_dyn(),
-            ],
+            id: StateId(10),
+            transitions: &[&MOD_TAP_TRANS, &MOD_TAP_OTHER_TRANS, &MOD_HOLD_TRANS],
         };
 
-        static MOD_TAP_TRANS: Transition<2, 2, 0> = Transition {
-            conditions: [
+        static MOD_TAP_TRANS: Transition = Transition {
+            conditions: &[
                 TransitionCondition::depressed_single(0),
-                TransitionCondition::ElapsedLess(Milliseconds(5_u32)),
+                TransitionCondition::ElapsedLess(Duration::from_millis(5)),
             ],
-            key_event_emissions: [KeyEvent::Press(0), KeyEvent::Depress(0)],
-            internal_event_emissions: [],
-            target: ROOT.as_dyn(),
+            key_event_emissions: &[KeyEvent::Press(0), KeyEvent::Depress(0)],
+            internal_event_emissions: &[],
+            target: &ROOT,
         };
 
-        static MOD_TAP_OTHER_TRANS: Transition<1, 2, 1> = Transition {
-            conditions: [TransitionCondition::pressed_single(1)],
-            key_event_emissions: [KeyEvent::Press(2), KeyEvent::Press(1)],
-            internal_event_emissions: [InternalEvent::SetGlobalState(StateFlags::SHFT)],
-            target: PRESS_1.as_dyn(),
+        static MOD_TAP_OTHER_TRANS: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(1)],
+            key_event_emissions: &[KeyEvent::Press(2), KeyEvent::Press(1)],
+            internal_event_emissions: &[InternalEvent::SetGlobalState(StateFlags::SHFT)],
+            target: &PRESS_1,
         };
 
-        static MOD_HOLD_TRANS: Transition<1, 1, 1> = Transition {
-            conditions: [TransitionCondition::ElapsedGreater(Milliseconds(5_u32))],
-            key_event_emissions: [KeyEvent::Press(2)],
-            internal_event_emissions: [InternalEvent::SetGlobalState(StateFlags::SHFT)],
-            target: ROOT.as_dyn(),
+        static MOD_HOLD_TRANS: Transition = Transition {
+            conditions: &[TransitionCondition::ElapsedGreater(Duration::from_millis(
+                5,
+            ))],
+            key_event_emissions: &[KeyEvent::Press(2)],
+            internal_event_emissions: &[InternalEvent::SetGlobalState(StateFlags::SHFT)],
+            target: &ROOT,
         };
 
         let mut clock = TickerClock(0);
 
-        let mut state = GlobalState {
-            flags: StateFlags::empty(),
-            entered_state: clock.now(),
-            current_state: ROOT.as_dyn(),
-        };
+        let mut state = GlobalState::<TickerClock>::new(ROOT.as_dyn(), clock.now());
 
         for _ in 0..10 {
             assert_eq!(state.flags, StateFlags::empty());
             assert_eq!(state.current_state, ROOT.as_dyn());
 
             let s = state.push(clock.now(), crate::InputEvent::Press(0));
-            assert_eq!(state.current_state, MOD.as_dyn());
             assert_matches!(s, []);
+            assert_eq!(state.current_state, MOD.as_dyn());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Depress(0));
+            assert_matches!(s, [KeyEvent::Press(0), KeyEvent::Depress(0)]);
             assert_eq!(state.current_state, ROOT.as_dyn());
             assert_eq!(state.flags, StateFlags::empty());
-            assert_matches!(s, [KeyEvent::Press(0), KeyEvent::Depress(0)]);
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Press(0));
-            assert_eq!(state.current_state, MOD.as_dyn());
             assert_matches!(s, []);
+            assert_eq!(state.current_state, MOD.as_dyn());
 
             clock.tick_n(8);
 
             let s = state.tick(clock.now());
-            assert_eq!(state.current_state, ROOT.as_dyn());
             assert_matches!(s, [KeyEvent::Press(2)]);
+            assert_eq!(state.current_state, ROOT.as_dyn());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Press(1));
-            assert_eq!(state.current_state, PRESS_1.as_dyn());
             assert_matches!(s, [KeyEvent::Press(1)]);
+            assert_eq!(state.current_state, PRESS_1.as_dyn());
 
             clock.tick();
             let s = state.push(clock.now(), crate::InputEvent::Depress(1));
-            assert_eq!(state.current_state, ROOT.as_dyn());
             assert_matches!(s, [KeyEvent::Depress(1)]);
+            assert_eq!(state.current_state, ROOT.as_dyn());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Depress(0));
-            assert_eq!(state.current_state, ROOT.as_dyn());
             assert_matches!(s, [KeyEvent::Depress(2)]);
+            assert_eq!(state.current_state, ROOT.as_dyn());
             assert_eq!(state.flags, StateFlags::empty());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Press(0));
-            assert_eq!(state.current_state, MOD.as_dyn());
             assert_matches!(s, []);
+            assert_eq!(state.current_state, MOD.as_dyn());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Press(1));
-            assert_eq!(state.current_state, PRESS_1.as_dyn());
             assert_matches!(s, [KeyEvent::Press(2), KeyEvent::Press(1)]);
+            assert_eq!(state.current_state, PRESS_1.as_dyn());
 
             clock.tick();
             let s = state.push(clock.now(), crate::InputEvent::Depress(1));
-            assert_eq!(state.current_state, ROOT.as_dyn());
             assert_matches!(s, [KeyEvent::Depress(1)]);
+            assert_eq!(state.current_state, ROOT.as_dyn());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Press(1));
-            assert_eq!(state.current_state, PRESS_1.as_dyn());
             assert_matches!(s, [KeyEvent::Press(1)]);
+            assert_eq!(state.current_state, PRESS_1.as_dyn());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Depress(1));
-            assert_eq!(state.current_state, ROOT.as_dyn());
             assert_matches!(s, [KeyEvent::Depress(1)]);
+            assert_eq!(state.current_state, ROOT.as_dyn());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Depress(0));
-            assert_eq!(state.current_state, ROOT.as_dyn());
             assert_matches!(s, [KeyEvent::Depress(2)]);
+            assert_eq!(state.current_state, ROOT.as_dyn());
             assert_eq!(state.flags, StateFlags::empty());
 
             clock.tick()
@@ -525,141 +2096,135 @@ mod tests {
 
     #[test]
     fn mod_tap() {
-        static ROOT: State<1> = State {
+        static ROOT: State = State {
             name: "ROOT",
-            transitions: [ROOT_0.as_dyn()],
+            id: StateId(11),
+            transitions: &[&ROOT_0],
         };
 
-        static ROOT_0: Transition<1, 0, 0> = Transition {
-            conditions: [TransitionCondition::pressed_single(0)],
-            key_event_emissions: [],
-            internal_event_emissions: [],
-            target: MOD.as_dyn(),
+        static ROOT_0: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(0)],
+            key_event_emissions: &[],
+            internal_event_emissions: &[],
+            target: &MOD,
         };
 
-        static MOD: State<3> = State {
+        static MOD: State = State {
             name: "MOD",
-            transitions: [
-                MOD_TAP_TRANS.as_dyn(),
-                MOD_TAP_OTHER_TRANS.as_dyn(),
-                MOD_HOLD_TRANS.as_dyn(),
-            ],
+            id: StateId(12),
+            transitions: &[&MOD_TAP_TRANS, &MOD_TAP_OTHER_TRANS, &MOD_HOLD_TRANS],
         };
 
-        static MOD_TAP_TRANS: Transition<2, 2, 0> = Transition {
-            conditions: [
+        static MOD_TAP_TRANS: Transition = Transition {
+            conditions: &[
                 TransitionCondition::depressed_single(0),
-                TransitionCondition::ElapsedLess(Milliseconds(5_u32)),
+                TransitionCondition::ElapsedLess(Duration::from_millis(5)),
             ],
-            key_event_emissions: [KeyEvent::Press(0), KeyEvent::Depress(0)],
-            internal_event_emissions: [],
-            target: ROOT.as_dyn(),
+            key_event_emissions: &[KeyEvent::Press(0), KeyEvent::Depress(0)],
+            internal_event_emissions: &[],
+            target: &ROOT,
         };
 
-        static MOD_TAP_OTHER_TRANS: Transition<1, 3, 1> = Transition {
-            conditions: [TransitionCondition::pressed_single(1)],
-            key_event_emissions: [KeyEvent::Press(2), KeyEvent::Press(1), KeyEvent::Depress(1)],
-            internal_event_emissions: [InternalEvent::SetGlobalState(StateFlags::SHFT)],
-            target: MOD_HOLD.as_dyn(),
+        static MOD_TAP_OTHER_TRANS: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(1)],
+            key_event_emissions: &[KeyEvent::Press(2), KeyEvent::Press(1), KeyEvent::Depress(1)],
+            internal_event_emissions: &[InternalEvent::SetGlobalState(StateFlags::SHFT)],
+            target: &MOD_HOLD,
         };
 
-        static MOD_HOLD_TRANS: Transition<1, 1, 1> = Transition {
-            conditions: [TransitionCondition::ElapsedGreater(Milliseconds(5_u32))],
-            key_event_emissions: [KeyEvent::Press(2)],
-            internal_event_emissions: [InternalEvent::SetGlobalState(StateFlags::SHFT)],
-            target: MOD_HOLD.as_dyn(),
+        static MOD_HOLD_TRANS: Transition = Transition {
+            conditions: &[TransitionCondition::ElapsedGreater(Duration::from_millis(
+                5,
+            ))],
+            key_event_emissions: &[KeyEvent::Press(2)],
+            internal_event_emissions: &[InternalEvent::SetGlobalState(StateFlags::SHFT)],
+            target: &MOD_HOLD,
         };
 
-        static MOD_HOLD: State<2> = State {
+        static MOD_HOLD: State = State {
             name: "MOD_HOLD",
-            transitions: [
-                MOD_HOLD_DEPRESS_TRANS.as_dyn(),
-                MOD_HOLD_OTHER_TRANS.as_dyn(),
-            ],
+            id: StateId(13),
+            transitions: &[&MOD_HOLD_DEPRESS_TRANS, &MOD_HOLD_OTHER_TRANS],
         };
 
-        static MOD_HOLD_DEPRESS_TRANS: Transition<1, 1, 1> = Transition {
-            conditions: [TransitionCondition::depressed_single(0)],
-            key_event_emissions: [KeyEvent::Depress(2)],
-            internal_event_emissions: [InternalEvent::UnsetGlobalState(StateFlags::SHFT)],
-            target: ROOT.as_dyn(),
+        static MOD_HOLD_DEPRESS_TRANS: Transition = Transition {
+            conditions: &[TransitionCondition::depressed_single(0)],
+            key_event_emissions: &[KeyEvent::Depress(2)],
+            internal_event_emissions: &[InternalEvent::UnsetGlobalState(StateFlags::SHFT)],
+            target: &ROOT,
         };
 
-        static MOD_HOLD_OTHER_TRANS: Transition<1, 2, 0> = Transition {
-            conditions: [TransitionCondition::pressed_single(1)],
-            key_event_emissions: [KeyEvent::Press(1), KeyEvent::Depress(1)],
-            internal_event_emissions: [],
-            target: MOD_HOLD.as_dyn(),
+        static MOD_HOLD_OTHER_TRANS: Transition = Transition {
+            conditions: &[TransitionCondition::pressed_single(1)],
+            key_event_emissions: &[KeyEvent::Press(1), KeyEvent::Depress(1)],
+            internal_event_emissions: &[],
+            target: &MOD_HOLD,
         };
 
         let mut clock = TickerClock(0);
 
-        let mut state = GlobalState {
-            flags: StateFlags::empty(),
-            entered_state: clock.now(),
-            current_state: ROOT.as_dyn(),
-        };
+        let mut state = GlobalState::<TickerClock>::new(ROOT.as_dyn(), clock.now());
 
         for _ in 0..10 {
             let s = state.push(clock.now(), crate::InputEvent::Press(0));
-            assert_eq!(state.current_state, MOD.as_dyn());
             assert_matches!(s, []);
+            assert_eq!(state.current_state, MOD.as_dyn());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Depress(0));
-            assert_eq!(state.current_state, ROOT.as_dyn());
             assert_matches!(s, [KeyEvent::Press(0), KeyEvent::Depress(0)]);
+            assert_eq!(state.current_state, ROOT.as_dyn());
 
             let s = state.push(clock.now(), crate::InputEvent::Press(0));
-            assert_eq!(state.current_state, MOD.as_dyn());
             assert_matches!(s, []);
+            assert_eq!(state.current_state, MOD.as_dyn());
 
             clock.tick_n(8);
 
             let s = state.tick(clock.now());
-            assert_eq!(state.current_state, MOD_HOLD.as_dyn());
             assert_matches!(s, [KeyEvent::Press(2)]);
+            assert_eq!(state.current_state, MOD_HOLD.as_dyn());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Press(1));
-            assert_eq!(state.current_state, MOD_HOLD.as_dyn());
             assert_matches!(s, [KeyEvent::Press(1), KeyEvent::Depress(1)]);
+            assert_eq!(state.current_state, MOD_HOLD.as_dyn());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Depress(0));
-            assert_eq!(state.current_state, ROOT.as_dyn());
             assert_matches!(s, [KeyEvent::Depress(2)]);
+            assert_eq!(state.current_state, ROOT.as_dyn());
             assert_eq!(state.flags, StateFlags::empty());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Press(0));
-            assert_eq!(state.current_state, MOD.as_dyn());
             assert_matches!(s, []);
+            assert_eq!(state.current_state, MOD.as_dyn());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Press(1));
-            assert_eq!(state.current_state, MOD_HOLD.as_dyn());
             assert_matches!(
                 s,
                 [KeyEvent::Press(2), KeyEvent::Press(1), KeyEvent::Depress(1)]
             );
+            assert_eq!(state.current_state, MOD_HOLD.as_dyn());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Press(1));
-            assert_eq!(state.current_state, MOD_HOLD.as_dyn());
             assert_matches!(s, [KeyEvent::Press(1), KeyEvent::Depress(1)]);
+            assert_eq!(state.current_state, MOD_HOLD.as_dyn());
 
             clock.tick();
 
             let s = state.push(clock.now(), crate::InputEvent::Depress(0));
-            assert_eq!(state.current_state, ROOT.as_dyn());
             assert_matches!(s, [KeyEvent::Depress(2)]);
+            assert_eq!(state.current_state, ROOT.as_dyn());
             assert_eq!(state.flags, StateFlags::empty());
         }
     }
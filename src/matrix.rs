@@ -0,0 +1,187 @@
+//! Key matrix scanning.
+//!
+//! Rows are driven low one at a time while the columns, pulled up, are read
+//! back, so a low column means the key at that row and column is down.
+//! Changes since the previous scan are reported as [`TimedEvent`]s for
+//! position `row * COLS + col`, stamped with the time of the scan, so a
+//! matrix has at most 256 keys.
+//!
+//! The pin traits have the same shape as the `embedded-hal` digital traits,
+//! so HAL pins only need a thin wrapper.
+
+use crate::time::{self, Instant};
+use crate::{InputEvent, TimedEvent};
+
+trait InputPin {
+    type Error;
+
+    fn is_low(&mut self) -> Result<bool, Self::Error>;
+}
+
+trait OutputPin {
+    type Error;
+
+    fn set_low(&mut self) -> Result<(), Self::Error>;
+    fn set_high(&mut self) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum MatrixError<I, O> {
+    Input(I),
+    Output(O),
+}
+
+struct Matrix<In: InputPin, Out: OutputPin, const ROWS: usize, const COLS: usize> {
+    rows: [Out; ROWS],
+    cols: [In; COLS],
+    state: [[bool; COLS]; ROWS],
+}
+
+impl<In: InputPin, Out: OutputPin, const ROWS: usize, const COLS: usize>
+    Matrix<In, Out, ROWS, COLS>
+{
+    fn new(rows: [Out; ROWS], cols: [In; COLS]) -> Self {
+        const { assert!(ROWS * COLS <= 256, "positions past 255 can't be reported") };
+        Self {
+            rows,
+            cols,
+            state: [[false; COLS]; ROWS],
+        }
+    }
+
+    /// Scan every row, reporting each key that changed since the last scan.
+    fn scan<Clock: time::Clock>(
+        &mut self,
+        current_time: Clock::Instant,
+        mut emit: impl FnMut(TimedEvent<Clock>),
+    ) -> Result<(), MatrixError<In::Error, Out::Error>> {
+        for (r, row) in self.rows.iter_mut().enumerate() {
+            row.set_low().map_err(MatrixError::Output)?;
+
+            for (c, col) in self.cols.iter_mut().enumerate() {
+                let pressed = match col.is_low() {
+                    Ok(pressed) => pressed,
+                    Err(error) => {
+                        // not left driven, whether or not it can be released
+                        let _ = row.set_high();
+                        return Err(MatrixError::Input(error));
+                    }
+                };
+                if pressed == self.state[r][c] {
+                    continue;
+                }
+                self.state[r][c] = pressed;
+
+                let position = (r * COLS + c) as u8;
+                emit(TimedEvent {
+                    time: current_time,
+                    event: if pressed {
+                        InputEvent::Press(position)
+                    } else {
+                        InputEvent::Depress(position)
+                    },
+                });
+            }
+
+            row.set_high().map_err(MatrixError::Output)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::convert::Infallible;
+
+    use super::{InputPin, Matrix, MatrixError, OutputPin};
+    use crate::tests::TickerClock;
+    use crate::{InputEvent, TimedEvent};
+
+    thread_local! {
+        /// The driven row, and which keys are held.
+        static DRIVEN: Cell<Option<usize>> = const { Cell::new(None) };
+        static HELD: Cell<[[bool; 3]; 2]> = const { Cell::new([[false; 3]; 2]) };
+    }
+
+    struct Row(usize);
+    struct Col(usize);
+    struct Broken;
+
+    impl OutputPin for Row {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            DRIVEN.set(Some(self.0));
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            DRIVEN.set(None);
+            Ok(())
+        }
+    }
+
+    impl InputPin for Col {
+        type Error = Infallible;
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            Ok(DRIVEN.get().is_some_and(|row| HELD.get()[row][self.0]))
+        }
+    }
+
+    impl InputPin for Broken {
+        type Error = ();
+
+        fn is_low(&mut self) -> Result<bool, ()> {
+            Err(())
+        }
+    }
+
+    #[test]
+    fn releases_row_on_error() {
+        let mut matrix = Matrix::new([Row(1)], [Broken]);
+        let result = matrix.scan(TickerClock(0).now(), |_: TimedEvent<TickerClock>| {});
+        assert_eq!(result, Err(MatrixError::Input(())));
+        assert_eq!(DRIVEN.get(), None);
+    }
+
+    #[test]
+    fn reports_edges() {
+        let mut clock = TickerClock(0);
+        let mut matrix = Matrix::new([Row(0), Row(1)], [Col(0), Col(1), Col(2)]);
+        let mut out = Vec::new();
+
+        matrix
+            .scan(clock.now(), |e: TimedEvent<TickerClock>| out.push(e))
+            .unwrap();
+        assert!(out.is_empty());
+
+        HELD.set([[false, true, false], [false, false, true]]);
+        clock.tick();
+        matrix.scan(clock.now(), |e| out.push(e)).unwrap();
+        assert_eq!(
+            out,
+            [
+                TimedEvent {
+                    time: clock.now(),
+                    event: InputEvent::Press(1)
+                },
+                TimedEvent {
+                    time: clock.now(),
+                    event: InputEvent::Press(5)
+                },
+            ]
+        );
+        out.clear();
+
+        matrix.scan(clock.now(), |e| out.push(e)).unwrap();
+        assert!(out.is_empty());
+
+        HELD.set([[false; 3], [false, false, true]]);
+        matrix.scan(clock.now(), |e| out.push(e)).unwrap();
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].event, InputEvent::Depress(1));
+    }
+}
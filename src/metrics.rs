@@ -0,0 +1,193 @@
+//! Typing metrics.
+//!
+//! [`TypingMetrics`] watches the key events a keymap emits and keeps a words
+//! per minute figure over a sliding window, a press count per key code and a
+//! count of presses made with a modifier held. The window is `BUCKETS`
+//! buckets of `bucket` milliseconds, and speed is reported every `interval`
+//! as [`KeyEvent::TypingSpeed`] for a display to show.
+//!
+//! Counts saturate rather than wrap. Everything is fixed size, about 700
+//! bytes with a 60 bucket window.
+
+use crate::time::{self, Duration, Instant};
+use crate::{KeyCode, KeyEvent, KeySet};
+
+/// A word is taken to be five key presses.
+const WORD: u32 = 5;
+
+const MODIFIERS: core::ops::RangeInclusive<KeyCode> = 0xe0..=0xe7;
+
+pub(crate) struct TypingMetrics<Clock: time::Clock, const BUCKETS: usize> {
+    bucket: Duration,
+    interval: Duration,
+    /// Presses in each bucket of the window, `current` is the newest.
+    buckets: [u16; BUCKETS],
+    current: usize,
+    bucket_start: Clock::Instant,
+    last_report: Clock::Instant,
+    key_counts: [u16; 256],
+    modified: u32,
+    held: KeySet,
+}
+
+impl<Clock: time::Clock, const BUCKETS: usize> TypingMetrics<Clock, BUCKETS> {
+    pub(crate) fn new(bucket: Duration, interval: Duration, current_time: Clock::Instant) -> Self {
+        const { assert!(BUCKETS > 0) };
+
+        Self {
+            bucket: bucket.max(Duration::from_millis(1)),
+            interval,
+            buckets: [0; BUCKETS],
+            current: 0,
+            bucket_start: current_time,
+            last_report: current_time,
+            key_counts: [0; 256],
+            modified: 0,
+            held: KeySet::empty(),
+        }
+    }
+
+    /// Move the window along to `current_time`, clearing buckets that fell out
+    /// of it.
+    fn advance(&mut self, current_time: Clock::Instant) {
+        let elapsed = current_time.duration_since(&self.bucket_start);
+        let steps = elapsed.as_micros() / self.bucket.as_micros();
+        if steps == 0 {
+            return;
+        }
+
+        for _ in 0..(steps as usize).min(BUCKETS) {
+            self.current = (self.current + 1) % BUCKETS;
+            self.buckets[self.current] = 0;
+        }
+        self.bucket_start = self
+            .bucket_start
+            .checked_add(self.bucket.saturating_mul(steps))
+            .unwrap_or(current_time);
+    }
+
+    fn observe(&mut self, current_time: Clock::Instant, event: KeyEvent) {
+        match event {
+            KeyEvent::Press(key) => {
+                self.advance(current_time);
+                self.buckets[self.current] = self.buckets[self.current].saturating_add(1);
+                self.key_counts[key as usize] = self.key_counts[key as usize].saturating_add(1);
+
+                let modifier_held = MODIFIERS.into_iter().any(|m| self.held.contains(m));
+                if !MODIFIERS.contains(&key) && modifier_held {
+                    self.modified = self.modified.saturating_add(1);
+                }
+                self.held.insert(key);
+            }
+            KeyEvent::Depress(key) => {
+                self.held.remove(key);
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn observe_all(&mut self, current_time: Clock::Instant, events: &[KeyEvent]) {
+        for event in events {
+            self.observe(current_time, *event);
+        }
+    }
+
+    /// Words per minute over the window.
+    pub(crate) fn wpm(&self) -> u16 {
+        let presses: u64 = self.buckets.iter().map(|b| *b as u64).sum();
+        let window = (self.bucket.as_millis() as u64).saturating_mul(BUCKETS as u64);
+        let wpm = presses.saturating_mul(60_000) / WORD as u64 / window.max(1);
+        u16::try_from(wpm).unwrap_or(u16::MAX)
+    }
+
+    pub(crate) fn key_count(&self, key: KeyCode) -> u16 {
+        self.key_counts[key as usize]
+    }
+
+    /// Presses of each modifier, left control first.
+    fn modifier_counts(&self) -> [u16; 8] {
+        core::array::from_fn(|i| self.key_counts[0xe0 + i])
+    }
+
+    /// Presses of other keys made while a modifier was held.
+    pub(crate) fn modified_presses(&self) -> u32 {
+        self.modified
+    }
+
+    pub(crate) fn tick(&mut self, current_time: Clock::Instant, mut emit: impl FnMut(KeyEvent)) {
+        self.advance(current_time);
+
+        if current_time.duration_since(&self.last_report) >= self.interval {
+            self.last_report = current_time;
+            emit(KeyEvent::TypingSpeed(self.wpm()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TypingMetrics;
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::KeyEvent;
+
+    #[test]
+    fn metrics() {
+        let mut clock = TickerClock(0);
+        // a 10 second window in 1 second buckets
+        let mut metrics = TypingMetrics::<TickerClock, 10>::new(
+            Duration::from_millis(1_000),
+            Duration::from_millis(500),
+            clock.now(),
+        );
+
+        // 50 presses in 5 seconds, 60 wpm over the window
+        for _ in 0..50 {
+            metrics.observe_all(clock.now(), &[KeyEvent::Press(4), KeyEvent::Depress(4)]);
+            clock.tick_n(100);
+        }
+        assert_eq!(metrics.wpm(), 60);
+        assert_eq!(metrics.key_count(4), 50);
+
+        metrics.observe_all(clock.now(), &[KeyEvent::Press(0xe1), KeyEvent::Press(5)]);
+        metrics.observe_all(
+            clock.now(),
+            &[
+                KeyEvent::Depress(5),
+                KeyEvent::Depress(0xe1),
+                KeyEvent::Press(5),
+            ],
+        );
+        assert_eq!(metrics.modifier_counts()[1], 1);
+        assert_eq!(metrics.modified_presses(), 1);
+
+        let mut out = Vec::new();
+        metrics.tick(clock.now(), |e| out.push(e));
+        assert_eq!(out, [KeyEvent::TypingSpeed(63)]);
+
+        // the presses fall out of the window
+        clock.tick_n(10_000);
+        out.clear();
+        metrics.tick(clock.now(), |e| out.push(e));
+        assert_eq!(out, [KeyEvent::TypingSpeed(0)]);
+    }
+
+    #[test]
+    fn wpm_saturates() {
+        let mut clock = TickerClock(0);
+        let mut metrics = TypingMetrics::<TickerClock, 2>::new(
+            Duration::from_millis(1_000),
+            Duration::from_millis(500),
+            clock.now(),
+        );
+
+        // 80,000 presses in 2 seconds, far more than fits
+        for _ in 0..2 {
+            for _ in 0..40_000 {
+                metrics.observe_all(clock.now(), &[KeyEvent::Press(4)]);
+            }
+            clock.tick_n(1_000);
+        }
+        assert_eq!(metrics.wpm(), u16::MAX);
+    }
+}
@@ -0,0 +1,195 @@
+//! Lock-free multi producer queue for input events.
+//!
+//! An alternative to [`SharedMachine`](crate::shared::SharedMachine) for
+//! targets where critical sections are costly, such as one core scanning
+//! while the other runs the machine. Any number of contexts enqueue into an
+//! [`MpscQueue`] without masking interrupts, and the main loop owns the
+//! [`GlobalState`](crate::GlobalState) and drains the queue into it.
+//!
+//! Events that don't fit are dropped and counted rather than blocking the
+//! producer. The queue needs compare and swap, so it isn't available on
+//! targets such as `thumbv6m` that only have atomic loads and stores.
+//!
+//! This is the bounded queue by Dmitry Vyukov, each slot carries a sequence
+//! number saying whether it is ready to be written or read for a given lap.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::TimedEvent;
+
+type LockFreeInputQueue<Clock, const N: usize> = MpscQueue<TimedEvent<Clock>, N>;
+
+struct Slot<T> {
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+struct MpscQueue<T, const N: usize> {
+    slots: [Slot<T>; N],
+    enqueue_position: AtomicUsize,
+    dequeue_position: AtomicUsize,
+    overflows: AtomicU32,
+}
+
+// SAFETY: a slot's value is only accessed by whoever won the position for it,
+// and the sequence number orders the write before the read.
+unsafe impl<T: Send, const N: usize> Sync for MpscQueue<T, N> {}
+
+impl<T: Copy, const N: usize> MpscQueue<T, N> {
+    /// `N` must be a power of two.
+    const fn new() -> Self {
+        const { assert!(N.is_power_of_two()) };
+
+        let mut slots = [const {
+            Slot {
+                sequence: AtomicUsize::new(0),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            }
+        }; N];
+
+        let mut i = 0;
+        while i < N {
+            slots[i].sequence = AtomicUsize::new(i);
+            i += 1;
+        }
+
+        Self {
+            slots,
+            enqueue_position: AtomicUsize::new(0),
+            dequeue_position: AtomicUsize::new(0),
+            overflows: AtomicU32::new(0),
+        }
+    }
+
+    /// Queue an item, handing it back and counting an overflow if the queue
+    /// is full.
+    fn enqueue(&self, item: T) -> Result<(), T> {
+        let mut position = self.enqueue_position.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[position & (N - 1)];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            match (sequence as isize).wrapping_sub(position as isize) {
+                0 => match self.enqueue_position.compare_exchange_weak(
+                    position,
+                    position.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // SAFETY: winning the position gives exclusive access
+                        // to the slot until the sequence is published.
+                        unsafe { (*slot.value.get()).write(item) };
+                        slot.sequence
+                            .store(position.wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => position = current,
+                },
+                diff if diff < 0 => {
+                    self.overflows.fetch_add(1, Ordering::Relaxed);
+                    return Err(item);
+                }
+                _ => position = self.enqueue_position.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    fn dequeue(&self) -> Option<T> {
+        let mut position = self.dequeue_position.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[position & (N - 1)];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+
+            match (sequence as isize).wrapping_sub(position.wrapping_add(1) as isize) {
+                0 => match self.dequeue_position.compare_exchange_weak(
+                    position,
+                    position.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        // SAFETY: the producer published the slot, and it
+                        // isn't rewritten until the next lap's sequence is.
+                        let item = unsafe { (*slot.value.get()).assume_init() };
+                        slot.sequence
+                            .store(position.wrapping_add(N), Ordering::Release);
+                        return Some(item);
+                    }
+                    Err(current) => position = current,
+                },
+                diff if diff < 0 => return None,
+                _ => position = self.dequeue_position.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    /// Events dropped because the queue was full since this was last called.
+    fn take_overflows(&self) -> u32 {
+        self.overflows.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LockFreeInputQueue, MpscQueue};
+    use crate::tests::TickerClock;
+    use crate::{InputEvent, TimedEvent};
+
+    #[test]
+    fn overflow_counts() {
+        let queue = MpscQueue::<u8, 2>::new();
+
+        assert_eq!(queue.enqueue(1), Ok(()));
+        assert_eq!(queue.enqueue(2), Ok(()));
+        assert_eq!(queue.enqueue(3), Err(3));
+        assert_eq!(queue.enqueue(4), Err(4));
+        assert_eq!(queue.take_overflows(), 2);
+        assert_eq!(queue.take_overflows(), 0);
+
+        assert_eq!(queue.dequeue(), Some(1));
+        assert_eq!(queue.enqueue(3), Ok(()));
+        assert_eq!(queue.dequeue(), Some(2));
+        assert_eq!(queue.dequeue(), Some(3));
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn many_producers() {
+        let queue = LockFreeInputQueue::<TickerClock, 8>::new();
+        let mut seen = [0; 4];
+
+        std::thread::scope(|s| {
+            for producer in 0..4u8 {
+                let queue = &queue;
+                s.spawn(move || {
+                    for i in 0..50 {
+                        let event = TimedEvent {
+                            time: TickerClock(i).now(),
+                            event: InputEvent::Press(producer),
+                        };
+                        while queue.enqueue(event).is_err() {}
+                    }
+                });
+            }
+
+            let mut received = 0;
+            while received < 200 {
+                if let Some(event) = queue.dequeue() {
+                    let InputEvent::Press(producer) = event.event else {
+                        unreachable!()
+                    };
+                    // each producer's events arrive in order
+                    let expected = seen[producer as usize];
+                    assert_eq!(event.time, TickerClock(expected).now());
+                    seen[producer as usize] += 1;
+                    received += 1;
+                }
+            }
+        });
+
+        assert_eq!(seen, [50; 4]);
+    }
+}
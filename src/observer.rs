@@ -0,0 +1,155 @@
+//! Being told what a machine does as it does it.
+//!
+//! A [`GlobalState`](crate::GlobalState) made with
+//! [`GlobalState::with_observer`](crate::GlobalState::with_observer) calls
+//! its [`Observer`] on every transition it takes, so LEDs, displays, metrics
+//! and logging can follow the machine without polling it after every event.
+//! A transition calls [`Observer::on_transition`], then
+//! [`Observer::on_flags_changed`] if its internal events changed the flags,
+//! then [`Observer::on_emit`] if it emitted anything. Every callback does
+//! nothing by default.
+//!
+//! The observer is called from inside `push` and `tick`, so it should be
+//! quick. `()` observes nothing and is what [`GlobalState::new`] uses, so
+//! machines without an observer cost nothing extra.
+//!
+//! [`GlobalState::new`]: crate::GlobalState::new
+
+use crate::{InputEvent, KeyEvent, StateFlags};
+
+/// Callbacks for a machine whose states are referred to as `S`.
+pub trait Observer<S> {
+    /// The machine went from `from` to `to`, on `trigger` or on a tick if
+    /// there's none. Called for transitions back into the same state too.
+    fn on_transition(&mut self, from: S, to: S, trigger: Option<InputEvent>) {}
+
+    fn on_flags_changed(&mut self, old: StateFlags, new: StateFlags) {}
+
+    /// The key events the transition emitted, in order.
+    fn on_emit(&mut self, events: &[KeyEvent]) {}
+}
+
+impl<S> Observer<S> for () {}
+
+#[cfg(test)]
+mod tests {
+    use super::Observer;
+    use crate::behaviors::hold_tap;
+    use crate::table::StateIndex;
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{
+        DynState, GlobalState, InputEvent, InternalEvent, KeyEvent, Lighting, State, StateFlags,
+        StateId, Transition, TransitionCondition,
+    };
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    static GAME: State = State {
+        name: "GAME",
+        id: StateId(0),
+        transitions: &[&GAME_TOGGLE],
+    };
+
+    static GAME_TOGGLE: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(0)],
+        key_event_emissions: &[],
+        internal_event_emissions: &[InternalEvent::SetGlobalState(StateFlags::GAME_MODE)],
+        target: &GAME,
+    };
+
+    #[derive(Default)]
+    struct Log {
+        transitions: Vec<(&'static str, &'static str, Option<InputEvent>)>,
+        flags: Vec<(StateFlags, StateFlags)>,
+        emitted: Vec<KeyEvent>,
+    }
+
+    impl Observer<&'static dyn DynState> for Log {
+        fn on_transition(
+            &mut self,
+            from: &'static dyn DynState,
+            to: &'static dyn DynState,
+            trigger: Option<InputEvent>,
+        ) {
+            self.transitions.push((from.name(), to.name(), trigger));
+        }
+
+        fn on_flags_changed(&mut self, old: StateFlags, new: StateFlags) {
+            self.flags.push((old, new));
+        }
+
+        fn on_emit(&mut self, events: &[KeyEvent]) {
+            self.emitted.extend_from_slice(events);
+        }
+    }
+
+    #[test]
+    fn observe() {
+        let mut clock = TickerClock(0);
+        let mut machine = GlobalState::<TickerClock, _, _>::with_observer(
+            home_a::IDLE.as_dyn(),
+            clock.now(),
+            Log::default(),
+        );
+
+        machine.push(clock.now(), InputEvent::Press(1));
+        clock.tick_n(10);
+        machine.tick(clock.now());
+        assert_eq!(
+            machine.observer().transitions,
+            [
+                (
+                    "home_a::IDLE",
+                    "home_a::UNDECIDED",
+                    Some(InputEvent::Press(1))
+                ),
+                ("home_a::UNDECIDED", "home_a::HOLD", None),
+            ]
+        );
+        assert_eq!(
+            machine.observer().emitted,
+            [KeyEvent::Press(0xe1), KeyEvent::Lighting(1, Lighting::Held)]
+        );
+        assert!(machine.observer().flags.is_empty());
+
+        let mut game = GlobalState::<TickerClock, _, _>::with_observer(
+            GAME.as_dyn(),
+            clock.now(),
+            Log::default(),
+        );
+        game.push(clock.now(), InputEvent::Press(0));
+        game.push(clock.now(), InputEvent::Press(0));
+        assert_eq!(
+            game.observer().flags,
+            [(StateFlags::empty(), StateFlags::GAME_MODE)]
+        );
+        assert_eq!(game.observer().transitions.len(), 2);
+    }
+
+    #[derive(Default)]
+    struct Count(u32);
+
+    impl Observer<StateIndex> for Count {
+        fn on_transition(&mut self, from: StateIndex, to: StateIndex, _: Option<InputEvent>) {
+            self.0 += 1;
+        }
+    }
+
+    #[test]
+    fn default_callbacks() {
+        let clock = TickerClock(0);
+        let mut machine =
+            GlobalState::<TickerClock, _, _>::with_observer(StateIndex(0), clock.now(), Count(0));
+        machine.do_transition(None, &[], &[KeyEvent::Press(1)], StateIndex(1), clock.now());
+        assert_eq!(machine.observer().0, 1);
+        assert_eq!(machine.current_state, StateIndex(1));
+    }
+}
@@ -0,0 +1,409 @@
+//! A four byte encoding of [`TransitionCondition`].
+//!
+//! A condition takes 16 bytes as an enum, as it's as large as its largest
+//! variant and a `RangeInclusive` carries more than its two ends. A
+//! [`PackedCondition`] is a tag byte and three bytes of operands, which is
+//! enough for everything but the longest durations: millisecond durations
+//! are limited to about 4.6 hours and microsecond ones to about 16 seconds.
+//!
+//! Tunable terms can't be packed as a pointer, so they're packed as an index
+//! into a slice of terms passed in when evaluating, and the predicates of
+//! [`TransitionCondition::EventMatches`] can't be packed at all. Build
+//! packed conditions in statics with [`pack_all`], which fails the build
+//! for a condition that can't be packed:
+//!
+//! ```ignore
+//! static TAP: [PackedCondition; 2] = pack_all([
+//!     TransitionCondition::depressed_single(4),
+//!     TransitionCondition::ElapsedLess(Duration::from_millis(200)),
+//! ]);
+//! ```
+
+use crate::time::Duration;
+use crate::{Context, InputEvent, StateFlags, TransitionCondition, TunableTerm};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u8)]
+enum Tag {
+    StateSet,
+    StateNotSet,
+    Pressed,
+    Depressed,
+    PointerMoved,
+    PointerButtonPressed,
+    PointerButtonReleased,
+    WheelScrolled,
+    TravelAbove,
+    TravelBelow,
+    Rotated,
+    LayerActive,
+    LayerNotActive,
+    ElapsedLess,
+    ElapsedGreater,
+    ElapsedLessTunable,
+    ElapsedGreaterTunable,
+    ElapsedLessMicros,
+    ElapsedGreaterMicros,
+    ApplicationIs,
+    WindowTitleIs,
+    IdleGreater,
+    FlagSetSinceEntry,
+    FlagJustSet,
+    BatteryBelow,
+    ExternalPowered,
+    OnBattery,
+    SignalOn,
+    SignalOff,
+    SignalAbove,
+    SignalBelow,
+    AllReleased,
+    AllButtonsReleased,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct PackedCondition {
+    tag: Tag,
+    operands: [u8; 3],
+}
+
+const U24_MAX: u32 = (1 << 24) - 1;
+
+const fn u24(value: u64) -> Option<[u8; 3]> {
+    if value > U24_MAX as u64 {
+        return None;
+    }
+    let [a, b, c, ..] = value.to_le_bytes();
+    Some([a, b, c])
+}
+
+impl PackedCondition {
+    const fn new(tag: Tag, operands: [u8; 3]) -> Self {
+        Self { tag, operands }
+    }
+
+    /// A duration packed as a count of `unit`s, which it has to be a whole
+    /// number of.
+    const fn duration(tag: Tag, value: Duration, unit: u64) -> Option<Self> {
+        let micros = value.as_micros();
+        if !micros.is_multiple_of(unit) {
+            return None;
+        }
+        match u24(micros / unit) {
+            Some(operands) => Some(Self::new(tag, operands)),
+            None => None,
+        }
+    }
+
+    /// Pack `condition`, or `None` if it's a tunable term or a duration too
+    /// long to pack, or too fine for the unit it's packed in.
+    const fn pack(condition: &TransitionCondition) -> Option<Self> {
+        use TransitionCondition as C;
+
+        Some(match condition {
+            C::StateSet(flags) => Self::new(Tag::StateSet, [flags.bits(), 0, 0]),
+            C::StateNotSet(flags) => Self::new(Tag::StateNotSet, [flags.bits(), 0, 0]),
+            C::Pressed(keys) => Self::new(Tag::Pressed, [*keys.start(), *keys.end(), 0]),
+            C::Depressed(keys) => Self::new(Tag::Depressed, [*keys.start(), *keys.end(), 0]),
+            C::PointerMoved => Self::new(Tag::PointerMoved, [0; 3]),
+            C::PointerButtonPressed(buttons) => Self::new(
+                Tag::PointerButtonPressed,
+                [*buttons.start(), *buttons.end(), 0],
+            ),
+            C::PointerButtonReleased(buttons) => Self::new(
+                Tag::PointerButtonReleased,
+                [*buttons.start(), *buttons.end(), 0],
+            ),
+            C::AllReleased(keys) => Self::new(Tag::AllReleased, [*keys.start(), *keys.end(), 0]),
+            C::AllButtonsReleased(buttons) => Self::new(
+                Tag::AllButtonsReleased,
+                [*buttons.start(), *buttons.end(), 0],
+            ),
+            C::WheelScrolled => Self::new(Tag::WheelScrolled, [0; 3]),
+            C::TravelAbove(key, travel) => Self::new(Tag::TravelAbove, [*key, *travel, 0]),
+            C::TravelBelow(key, travel) => Self::new(Tag::TravelBelow, [*key, *travel, 0]),
+            C::Rotated(encoder, delta) => Self::new(
+                Tag::Rotated,
+                [*encoder, *delta.start() as u8, *delta.end() as u8],
+            ),
+            C::LayerActive(layer) => Self::new(Tag::LayerActive, [*layer, 0, 0]),
+            C::LayerNotActive(layer) => Self::new(Tag::LayerNotActive, [*layer, 0, 0]),
+            C::ElapsedLess(x) => return Self::duration(Tag::ElapsedLess, *x, 1_000),
+            C::ElapsedGreater(x) => return Self::duration(Tag::ElapsedGreater, *x, 1_000),
+            C::ElapsedLessTunable(_) | C::ElapsedGreaterTunable(_) | C::EventMatches(_) => {
+                return None
+            }
+            C::ElapsedLessMicros(x) => return Self::duration(Tag::ElapsedLessMicros, *x, 1),
+            C::ElapsedGreaterMicros(x) => return Self::duration(Tag::ElapsedGreaterMicros, *x, 1),
+            C::ApplicationIs(id) => {
+                let [a, b] = id.to_le_bytes();
+                Self::new(Tag::ApplicationIs, [a, b, 0])
+            }
+            C::WindowTitleIs(hash) => {
+                let [a, b] = hash.to_le_bytes();
+                Self::new(Tag::WindowTitleIs, [a, b, 0])
+            }
+            C::IdleGreater(x) => return Self::duration(Tag::IdleGreater, *x, 1_000),
+            C::FlagSetSinceEntry(flags) => Self::new(Tag::FlagSetSinceEntry, [flags.bits(), 0, 0]),
+            C::FlagJustSet(flags) => Self::new(Tag::FlagJustSet, [flags.bits(), 0, 0]),
+            C::BatteryBelow(level) => Self::new(Tag::BatteryBelow, [*level, 0, 0]),
+            C::ExternalPowered => Self::new(Tag::ExternalPowered, [0; 3]),
+            C::OnBattery => Self::new(Tag::OnBattery, [0; 3]),
+            C::SignalOn(channel) => Self::new(Tag::SignalOn, [*channel, 0, 0]),
+            C::SignalOff(channel) => Self::new(Tag::SignalOff, [*channel, 0, 0]),
+            C::SignalAbove(channel, x) => {
+                let [a, b] = x.to_le_bytes();
+                Self::new(Tag::SignalAbove, [*channel, a, b])
+            }
+            C::SignalBelow(channel, x) => {
+                let [a, b] = x.to_le_bytes();
+                Self::new(Tag::SignalBelow, [*channel, a, b])
+            }
+        })
+    }
+
+    /// Pack `condition` as [`PackedCondition::pack`], with a tunable term
+    /// packed as its index in `terms`, or `None` if it isn't one of them.
+    pub(crate) fn pack_with_terms(
+        condition: &TransitionCondition,
+        terms: &[&'static TunableTerm],
+    ) -> Option<Self> {
+        let index = |term: &TunableTerm| {
+            let index = terms.iter().position(|t| core::ptr::eq(*t, term))?;
+            u8::try_from(index).ok()
+        };
+        match condition {
+            TransitionCondition::ElapsedLessTunable(term) => {
+                index(term).map(Self::elapsed_less_tunable)
+            }
+            TransitionCondition::ElapsedGreaterTunable(term) => {
+                index(term).map(Self::elapsed_greater_tunable)
+            }
+            condition => Self::pack(condition),
+        }
+    }
+
+    /// The tag, numbered in the order of [`Tag`], then the operands.
+    pub(crate) fn to_bytes(self) -> [u8; 4] {
+        let [a, b, c] = self.operands;
+        [self.tag as u8, a, b, c]
+    }
+
+    /// [`TransitionCondition::ElapsedLessTunable`] of `terms[term]`.
+    const fn elapsed_less_tunable(term: u8) -> Self {
+        Self::new(Tag::ElapsedLessTunable, [term, 0, 0])
+    }
+
+    /// [`TransitionCondition::ElapsedGreaterTunable`] of `terms[term]`.
+    const fn elapsed_greater_tunable(term: u8) -> Self {
+        Self::new(Tag::ElapsedGreaterTunable, [term, 0, 0])
+    }
+
+    fn u24(&self) -> u32 {
+        let [a, b, c] = self.operands;
+        u32::from_le_bytes([a, b, c, 0])
+    }
+
+    fn u16(&self) -> u16 {
+        u16::from_le_bytes([self.operands[0], self.operands[1]])
+    }
+
+    /// The condition this was packed from. Tunable terms are looked up in
+    /// `terms`, `None` if the index is out of its range.
+    fn unpack(&self, terms: &[&'static TunableTerm]) -> Option<TransitionCondition> {
+        use TransitionCondition as C;
+
+        let [a, b, c] = self.operands;
+        Some(match self.tag {
+            Tag::StateSet => C::StateSet(StateFlags::from_bits_truncate(a)),
+            Tag::StateNotSet => C::StateNotSet(StateFlags::from_bits_truncate(a)),
+            Tag::Pressed => C::Pressed(a..=b),
+            Tag::Depressed => C::Depressed(a..=b),
+            Tag::PointerMoved => C::PointerMoved,
+            Tag::PointerButtonPressed => C::PointerButtonPressed(a..=b),
+            Tag::PointerButtonReleased => C::PointerButtonReleased(a..=b),
+            Tag::WheelScrolled => C::WheelScrolled,
+            Tag::TravelAbove => C::TravelAbove(a, b),
+            Tag::TravelBelow => C::TravelBelow(a, b),
+            Tag::Rotated => C::Rotated(a, b as i8..=c as i8),
+            Tag::LayerActive => C::LayerActive(a),
+            Tag::LayerNotActive => C::LayerNotActive(a),
+            Tag::ElapsedLess => C::ElapsedLess(Duration::from_millis(self.u24())),
+            Tag::ElapsedGreater => C::ElapsedGreater(Duration::from_millis(self.u24())),
+            Tag::ElapsedLessTunable => C::ElapsedLessTunable(terms.get(a as usize)?),
+            Tag::ElapsedGreaterTunable => C::ElapsedGreaterTunable(terms.get(a as usize)?),
+            Tag::ElapsedLessMicros => {
+                C::ElapsedLessMicros(Duration::from_micros(self.u24() as u64))
+            }
+            Tag::ElapsedGreaterMicros => {
+                C::ElapsedGreaterMicros(Duration::from_micros(self.u24() as u64))
+            }
+            Tag::ApplicationIs => C::ApplicationIs(self.u16()),
+            Tag::WindowTitleIs => C::WindowTitleIs(self.u16()),
+            Tag::IdleGreater => C::IdleGreater(Duration::from_millis(self.u24())),
+            Tag::FlagSetSinceEntry => C::FlagSetSinceEntry(StateFlags::from_bits_truncate(a)),
+            Tag::FlagJustSet => C::FlagJustSet(StateFlags::from_bits_truncate(a)),
+            Tag::BatteryBelow => C::BatteryBelow(a),
+            Tag::ExternalPowered => C::ExternalPowered,
+            Tag::OnBattery => C::OnBattery,
+            Tag::SignalOn => C::SignalOn(a),
+            Tag::SignalOff => C::SignalOff(a),
+            Tag::SignalAbove => C::SignalAbove(a, i16::from_le_bytes([b, c])),
+            Tag::SignalBelow => C::SignalBelow(a, i16::from_le_bytes([b, c])),
+            Tag::AllReleased => C::AllReleased(a..=b),
+            Tag::AllButtonsReleased => C::AllButtonsReleased(a..=b),
+        })
+    }
+
+    /// Whether the condition holds, as [`TransitionCondition::evaluate`]. A
+    /// tunable term out of the range of `terms` never holds.
+    fn evaluate(
+        &self,
+        context: &Context,
+        event: Option<InputEvent>,
+        terms: &[&'static TunableTerm],
+    ) -> bool {
+        self.unpack(terms)
+            .is_some_and(|condition| condition.evaluate(context, event))
+    }
+}
+
+/// Pack every one of `conditions`, failing at compile time in a const context
+/// if one can't be.
+const fn pack_all<const N: usize>(conditions: [TransitionCondition; N]) -> [PackedCondition; N] {
+    let mut packed = [PackedCondition::new(Tag::PointerMoved, [0; 3]); N];
+    let mut i = 0;
+    while i < N {
+        packed[i] = match PackedCondition::pack(&conditions[i]) {
+            Some(condition) => condition,
+            None => panic!("condition can't be packed"),
+        };
+        i += 1;
+    }
+    packed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pack_all, PackedCondition};
+    use crate::tests::context;
+    use crate::time::Duration;
+    use crate::{InputEvent, Layers, StateFlags, TransitionCondition, TunableTerm};
+
+    static TERM: TunableTerm = TunableTerm::new(Duration::from_millis(50));
+
+    static PACKED: [PackedCondition; 3] = pack_all([
+        TransitionCondition::Pressed(4..=9),
+        TransitionCondition::StateNotSet(StateFlags::SHFT),
+        TransitionCondition::ElapsedLess(Duration::from_millis(100_000)),
+    ]);
+
+    #[test]
+    fn size() {
+        assert_eq!(core::mem::size_of::<PackedCondition>(), 4);
+        assert!(core::mem::size_of::<TransitionCondition>() > 4);
+    }
+
+    #[test]
+    fn evaluates_like_unpacked() {
+        let conditions = [
+            TransitionCondition::Pressed(4..=9),
+            TransitionCondition::Depressed(5..=5),
+            TransitionCondition::StateSet(StateFlags::CTRL),
+            TransitionCondition::Rotated(1, -3..=-1),
+            TransitionCondition::LayerActive(2),
+            TransitionCondition::ElapsedGreater(Duration::from_millis(20)),
+            TransitionCondition::ElapsedLessMicros(Duration::from_micros(20_500)),
+            TransitionCondition::ApplicationIs(0x1234),
+            TransitionCondition::IdleGreater(Duration::from_millis(10)),
+            TransitionCondition::FlagSetSinceEntry(StateFlags::CTRL),
+            TransitionCondition::FlagJustSet(StateFlags::CTRL),
+            TransitionCondition::BatteryBelow(20),
+            TransitionCondition::OnBattery,
+            TransitionCondition::SignalOn(1),
+            TransitionCondition::SignalAbove(0, -300),
+            TransitionCondition::AllReleased(4..=9),
+        ];
+        let events = [
+            None,
+            Some(InputEvent::Press(4)),
+            Some(InputEvent::Press(10)),
+            Some(InputEvent::Depress(5)),
+            Some(InputEvent::Rotate(1, -2)),
+            Some(InputEvent::Rotate(1, 2)),
+        ];
+        let mut contexts = [context(); 3];
+        contexts[1].flags = StateFlags::CTRL;
+        contexts[1].elapsed = Duration::from_millis(30);
+        contexts[1].idle = Duration::from_millis(30);
+        contexts[1].host.application = 0x1234;
+        contexts[1].power.battery = 10;
+        contexts[1].signals.0[1] = 1;
+        contexts[1].held.insert(6);
+        contexts[2].signals.0[0] = -400;
+        contexts[2].layers = Layers::empty();
+        contexts[2].layers.activate(2);
+        contexts[2].elapsed = Duration::from_micros(30_000);
+        contexts[2].power.external = true;
+
+        for condition in &conditions {
+            let packed = PackedCondition::pack(condition).unwrap();
+            for context in &contexts {
+                for event in events {
+                    assert_eq!(
+                        packed.evaluate(context, event, &[]),
+                        condition.evaluate(context, event)
+                    );
+                }
+            }
+        }
+
+        let mut past_term = context();
+        past_term.elapsed = Duration::from_millis(60);
+        let packed = PackedCondition::elapsed_greater_tunable(0);
+        assert!(packed.evaluate(&past_term, None, &[&TERM]));
+        assert!(!packed.evaluate(&context(), None, &[&TERM]));
+        assert!(!packed.evaluate(&past_term, None, &[]));
+
+        assert!(PACKED[0].evaluate(&context(), Some(InputEvent::Press(9)), &[]));
+        assert_eq!(
+            PackedCondition::pack(&TransitionCondition::ElapsedLess(Duration::from_millis(
+                1 << 24
+            ))),
+            None
+        );
+        // packed in whole milliseconds
+        let fine = Duration::from_micros(1_500);
+        assert_eq!(
+            PackedCondition::pack(&TransitionCondition::ElapsedLess(fine)),
+            None
+        );
+        let packed = PackedCondition::pack(&TransitionCondition::ElapsedLessMicros(fine)).unwrap();
+        assert!(matches!(
+            packed.unpack(&[]),
+            Some(TransitionCondition::ElapsedLessMicros(x)) if x == fine
+        ));
+        assert_eq!(
+            PackedCondition::pack(&TransitionCondition::ElapsedLessTunable(&TERM)),
+            None
+        );
+    }
+
+    #[test]
+    fn with_terms() {
+        static OTHER: TunableTerm = TunableTerm::new(Duration::from_millis(50));
+
+        let condition = TransitionCondition::ElapsedGreaterTunable(&TERM);
+        let packed = PackedCondition::pack_with_terms(&condition, &[&OTHER, &TERM]).unwrap();
+        assert_eq!(packed, PackedCondition::elapsed_greater_tunable(1));
+        assert_eq!(packed.to_bytes(), [16, 1, 0, 0]);
+        assert_eq!(
+            PackedCondition::pack_with_terms(&condition, &[&OTHER]),
+            None
+        );
+        assert_eq!(
+            PackedCondition::pack_with_terms(&TransitionCondition::Pressed(4..=9), &[])
+                .map(|packed| packed.to_bytes()),
+            Some([2, 4, 9, 0])
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Masking what was typed out of diagnostics.
+//!
+//! Traces of timing bugs are only useful if they can be shared, and a trace
+//! of real typing leaks whatever was typed. With privacy on, the events that
+//! diagnostics are given keep their kind and their timing but lose anything
+//! that says which key or character it was: key codes, matrix positions,
+//! pointer buttons, characters and host context (the application, window,
+//! keyboard layout and lock LEDs) become zero, while pointer motion, layers
+//! and analog travel are kept. The machine itself still sees and emits the real
+//! events.
+//!
+//! A machine is switched with [`GlobalState::set_private`], which masks
+//! the trigger and emissions its observer is given, and the events recorded
+//! in a trace through [`GlobalState::push_traced`].
+//!
+//! [`GlobalState::set_private`]: crate::GlobalState::set_private
+//! [`GlobalState::push_traced`]: crate::GlobalState::push_traced
+
+use crate::validate::MAX_EMISSIONS;
+use crate::{InputEvent, KeyEvent};
+
+impl InputEvent {
+    /// The event with which key it was for, and any host context, masked.
+    pub(crate) const fn masked(self) -> Self {
+        match self {
+            InputEvent::Press(_) => InputEvent::Press(0),
+            InputEvent::Depress(_) => InputEvent::Depress(0),
+            InputEvent::PointerButton(_, pressed) => InputEvent::PointerButton(0, pressed),
+            InputEvent::Travel(_, travel) => InputEvent::Travel(0, travel),
+            InputEvent::Application(_) => InputEvent::Application(0),
+            InputEvent::WindowTitle(_) => InputEvent::WindowTitle(0),
+            InputEvent::HostLayout(_) => InputEvent::HostLayout(0),
+            InputEvent::HostLeds(_) => InputEvent::HostLeds(0),
+            InputEvent::Custom(kind, _) => InputEvent::Custom(kind, 0),
+            event => event,
+        }
+    }
+}
+
+impl KeyEvent {
+    /// The event with which key or character it was masked.
+    pub(crate) const fn masked(self) -> Self {
+        match self {
+            KeyEvent::Press(_) => KeyEvent::Press(0),
+            KeyEvent::Depress(_) => KeyEvent::Depress(0),
+            KeyEvent::Unicode(_) => KeyEvent::Unicode('\0'),
+            KeyEvent::Lighting(_, lighting) => KeyEvent::Lighting(0, lighting),
+            event => event,
+        }
+    }
+}
+
+/// Call `f` with `events` masked, in chunks of up to [`MAX_EMISSIONS`] so
+/// they fit on the stack, which for a validated machine is one call.
+pub(crate) fn masked_chunks(events: &[KeyEvent], mut f: impl FnMut(&[KeyEvent])) {
+    let mut buffer = [KeyEvent::PressCurrent; MAX_EMISSIONS];
+    for chunk in events.chunks(MAX_EMISSIONS) {
+        for (masked, event) in buffer.iter_mut().zip(chunk) {
+            *masked = event.masked();
+        }
+        f(&buffer[..chunk.len()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::masked_chunks;
+    use crate::observer::Observer;
+    use crate::tests::TickerClock;
+    use crate::trace::{TraceBuffer, TraceEvent};
+    use crate::{
+        DynState, GlobalState, InputEvent, KeyEvent, Lighting, State, StateId, Transition,
+        TransitionCondition,
+    };
+
+    static TYPING: State = State {
+        name: "TYPING",
+        id: StateId(0),
+        transitions: &[&TYPE],
+    };
+
+    static TYPE: Transition = Transition {
+        conditions: &[TransitionCondition::Pressed(0..=u8::MAX)],
+        key_event_emissions: &[
+            KeyEvent::PressCurrent,
+            KeyEvent::Unicode('é'),
+            KeyEvent::Lighting(4, Lighting::Pressed),
+            KeyEvent::LayerActivated(2),
+        ],
+        internal_event_emissions: &[],
+        target: &TYPING,
+    };
+
+    #[derive(Default)]
+    struct Log {
+        triggers: Vec<Option<InputEvent>>,
+        emitted: Vec<KeyEvent>,
+    }
+
+    impl Observer<&'static dyn DynState> for Log {
+        fn on_transition(
+            &mut self,
+            _: &'static dyn DynState,
+            _: &'static dyn DynState,
+            trigger: Option<InputEvent>,
+        ) {
+            self.triggers.push(trigger);
+        }
+
+        fn on_emit(&mut self, events: &[KeyEvent]) {
+            self.emitted.extend_from_slice(events);
+        }
+    }
+
+    #[test]
+    fn masks_observer() {
+        let clock = TickerClock(0);
+        let mut machine = GlobalState::<TickerClock, _, _>::with_observer(
+            TYPING.as_dyn(),
+            clock.now(),
+            Log::default(),
+        );
+        machine.set_private(true);
+
+        // the machine's own output isn't masked
+        assert_eq!(
+            machine.push(clock.now(), InputEvent::Press(9)),
+            TYPE.key_event_emissions
+        );
+        assert_eq!(machine.observer().triggers, [Some(InputEvent::Press(0))]);
+        assert_eq!(
+            machine.observer().emitted,
+            [
+                KeyEvent::PressCurrent,
+                KeyEvent::Unicode('\0'),
+                KeyEvent::Lighting(0, Lighting::Pressed),
+                KeyEvent::LayerActivated(2),
+            ]
+        );
+
+        machine.set_private(false);
+        machine.push(clock.now(), InputEvent::Press(9));
+        assert_eq!(machine.observer().triggers[1], Some(InputEvent::Press(9)));
+    }
+
+    #[test]
+    fn masks_trace() {
+        let clock = TickerClock(0);
+        let mut machine = GlobalState::<TickerClock, _, _>::with_observer(
+            TYPING.as_dyn(),
+            clock.now(),
+            Log::default(),
+        );
+        let mut trace = TraceBuffer::<TickerClock, 16>::new();
+        machine.set_private(true);
+        machine.push_traced(clock.now(), InputEvent::Travel(3, 120), &mut trace);
+        machine.push_traced(clock.now(), InputEvent::Press(9), &mut trace);
+        machine.set_private(false);
+        machine.push_traced(clock.now(), InputEvent::HostLeds(2), &mut trace);
+
+        let events: Vec<_> = std::iter::from_fn(|| trace.pop().map(|e| e.event)).collect();
+        assert_eq!(
+            events,
+            [
+                TraceEvent::Input(InputEvent::Travel(0, 120)),
+                TraceEvent::Input(InputEvent::Press(0)),
+                TraceEvent::Output(KeyEvent::PressCurrent),
+                TraceEvent::Output(KeyEvent::Unicode('\0')),
+                TraceEvent::Output(KeyEvent::Lighting(0, Lighting::Pressed)),
+                TraceEvent::Output(KeyEvent::LayerActivated(2)),
+                TraceEvent::Input(InputEvent::HostLeds(2)),
+            ]
+        );
+    }
+
+    #[test]
+    fn masks_host_context() {
+        for (event, masked) in [
+            (InputEvent::HostLayout(3), InputEvent::HostLayout(0)),
+            (InputEvent::HostLeds(2), InputEvent::HostLeds(0)),
+            (
+                InputEvent::PointerButton(1, true),
+                InputEvent::PointerButton(0, true),
+            ),
+            (
+                InputEvent::PointerMove(3, -2),
+                InputEvent::PointerMove(3, -2),
+            ),
+        ] {
+            assert_eq!(event.masked(), masked);
+        }
+    }
+
+    #[test]
+    fn chunks() {
+        let events = [KeyEvent::Press(1); 20];
+        let mut calls = Vec::new();
+        masked_chunks(&events, |chunk| calls.push(chunk.to_vec()));
+        assert_eq!(
+            calls,
+            [vec![KeyEvent::Press(0); 16], vec![KeyEvent::Press(0); 4]]
+        );
+        masked_chunks(&[], |_| panic!());
+    }
+}
@@ -0,0 +1,194 @@
+//! Proofs of the engine's invariants, for the Kani model checker.
+//!
+//! These are only built by `cargo kani`, which checks each harness for every
+//! value its `kani::any()` inputs can take rather than for a sample of them:
+//!
+//! - evaluating any condition against any event and context never panics,
+//!   and neither does working out its deadline from any instant;
+//! - activating or deactivating a layer past [`MAX_LAYERS`] leaves the
+//!   layers as they were, and such a layer is never active;
+//! - the flags only change through [`InternalEvent::SetGlobalState`] and
+//!   [`InternalEvent::UnsetGlobalState`], however the machine is pushed and
+//!   ticked;
+//! - a validated machine never emits more than [`MAX_EMISSIONS`] events from
+//!   one push or tick.
+//!
+//! The machine harnesses run a small machine for a couple of steps, which is
+//! as deep as Kani can go in reasonable time. What they show about the
+//! engine doesn't depend on the machine, as each step only looks at the
+//! current state's transitions.
+
+use crate::behaviors::hold_tap;
+use crate::routing::Route;
+use crate::signals::Signals;
+use crate::testing::TickerClock;
+use crate::time::Duration;
+use crate::validate::{validate, MAX_EMISSIONS};
+use crate::{
+    elapsed_deadline, Context, GlobalState, HostContext, InputEvent, InternalEvent, KeySet, Layer,
+    Layers, Power, StateFlags, TransitionCondition, TunableTerm, MAX_LAYERS,
+};
+
+hold_tap! {
+    mod home_a {
+        key: 1,
+        tap: 6,
+        hold: 0xe1,
+        tapping_term: Duration::from_millis(200),
+    }
+}
+
+const _: () = validate(&home_a::IDLE);
+
+static TERM: TunableTerm = TunableTerm::new(Duration::from_millis(0));
+
+fn any_flags() -> StateFlags {
+    StateFlags::from_bits_truncate(kani::any())
+}
+
+/// Any event there's an encoding for, or none for a tick.
+fn any_event() -> Option<InputEvent> {
+    InputEvent::from_bytes(kani::any())
+}
+
+fn any_context() -> Context {
+    Context {
+        elapsed: Duration::from_micros(kani::any()),
+        idle: Duration::from_micros(kani::any()),
+        flags: any_flags(),
+        entry_flags: any_flags(),
+        previous_flags: any_flags(),
+        layers: Layers(kani::any()),
+        host: HostContext {
+            application: kani::any(),
+            window_title: kani::any(),
+        },
+        power: Power {
+            battery: kani::any(),
+            external: kani::any(),
+        },
+        signals: Signals(kani::any()),
+        held: KeySet(kani::any()),
+        held_buttons: KeySet(kani::any()),
+    }
+}
+
+fn any_condition() -> TransitionCondition {
+    let range = || kani::any()..=kani::any();
+    match kani::any::<u8>() {
+        0 => TransitionCondition::StateSet(any_flags()),
+        1 => TransitionCondition::StateNotSet(any_flags()),
+        2 => TransitionCondition::Pressed(range()),
+        3 => TransitionCondition::Depressed(range()),
+        4 => TransitionCondition::PointerMoved,
+        5 => TransitionCondition::PointerButtonPressed(range()),
+        6 => TransitionCondition::PointerButtonReleased(range()),
+        7 => TransitionCondition::WheelScrolled,
+        8 => TransitionCondition::TravelAbove(kani::any(), kani::any()),
+        9 => TransitionCondition::TravelBelow(kani::any(), kani::any()),
+        10 => TransitionCondition::Rotated(kani::any(), kani::any()..=kani::any()),
+        11 => TransitionCondition::LayerActive(kani::any()),
+        12 => TransitionCondition::LayerNotActive(kani::any()),
+        13 => TransitionCondition::ElapsedLess(Duration::from_micros(kani::any())),
+        14 => TransitionCondition::ElapsedGreater(Duration::from_micros(kani::any())),
+        15 => {
+            TERM.set(Duration::from_millis(kani::any()));
+            TransitionCondition::ElapsedLessTunable(&TERM)
+        }
+        16 => {
+            TERM.set(Duration::from_millis(kani::any()));
+            TransitionCondition::ElapsedGreaterTunable(&TERM)
+        }
+        17 => TransitionCondition::ElapsedLessMicros(Duration::from_micros(kani::any())),
+        18 => TransitionCondition::ElapsedGreaterMicros(Duration::from_micros(kani::any())),
+        19 => TransitionCondition::ApplicationIs(kani::any()),
+        20 => TransitionCondition::WindowTitleIs(kani::any()),
+        21 => TransitionCondition::IdleGreater(Duration::from_micros(kani::any())),
+        22 => TransitionCondition::FlagSetSinceEntry(any_flags()),
+        23 => TransitionCondition::BatteryBelow(kani::any()),
+        24 => TransitionCondition::ExternalPowered,
+        25 => TransitionCondition::OnBattery,
+        26 => TransitionCondition::SignalOn(kani::any()),
+        27 => TransitionCondition::SignalOff(kani::any()),
+        28 => TransitionCondition::SignalAbove(kani::any(), kani::any()),
+        29 => TransitionCondition::SignalBelow(kani::any(), kani::any()),
+        30 => TransitionCondition::AllReleased(range()),
+        31 => TransitionCondition::AllButtonsReleased(range()),
+        _ => TransitionCondition::FlagJustSet(any_flags()),
+    }
+}
+
+#[kani::proof]
+fn evaluation_never_panics() {
+    let condition = any_condition();
+    condition.evaluate(&any_context(), any_event());
+    elapsed_deadline(&condition, TickerClock(kani::any()).now());
+}
+
+#[kani::proof]
+fn layers_stay_in_range() {
+    let mut layers = Layers(kani::any());
+    let before = layers;
+    let layer: Layer = kani::any();
+    let activate: bool = kani::any();
+    if activate {
+        layers.activate(layer);
+    } else {
+        layers.deactivate(layer);
+    }
+
+    if layer as usize >= MAX_LAYERS {
+        assert_eq!(layers, before);
+        assert!(!layers.is_active(layer));
+    } else {
+        assert_eq!(layers.is_active(layer), activate);
+    }
+}
+
+#[kani::proof]
+fn flags_only_change_through_flag_events() {
+    let mut machine = GlobalState::<TickerClock>::new(home_a::IDLE.as_dyn(), TickerClock(0).now());
+    let flags = any_flags();
+    machine.flags = flags;
+
+    let event = match kani::any::<u8>() {
+        0 => InternalEvent::ActivateLayer(kani::any()),
+        1 => InternalEvent::DeactivateLayer(kani::any()),
+        2 => InternalEvent::RecordActivity,
+        3 => InternalEvent::SetRoute(Route::BleHost(kani::any())),
+        4 => InternalEvent::SetGlobalState(any_flags()),
+        _ => InternalEvent::UnsetGlobalState(any_flags()),
+    };
+    event.apply(&mut machine, TickerClock(kani::any()).now());
+    match event {
+        InternalEvent::SetGlobalState(set) => assert_eq!(machine.flags, flags | set),
+        InternalEvent::UnsetGlobalState(unset) => assert_eq!(machine.flags, flags - unset),
+        _ => assert_eq!(machine.flags, flags),
+    }
+
+    // `home_a` emits no flag events, so nothing it does changes them
+    machine.flags = flags;
+    for _ in 0..2 {
+        let now = TickerClock(kani::any()).now();
+        match any_event() {
+            Some(event) => machine.push(now, event),
+            None => machine.tick(now),
+        };
+        assert_eq!(machine.flags, flags);
+    }
+}
+
+#[kani::proof]
+#[kani::unwind(17)]
+fn emissions_are_bounded() {
+    let mut machine = GlobalState::<TickerClock>::new(home_a::IDLE.as_dyn(), TickerClock(0).now());
+    machine.flags = any_flags();
+    for _ in 0..2 {
+        let now = TickerClock(kani::any()).now();
+        let emitted = match any_event() {
+            Some(event) => machine.push(now, event),
+            None => machine.tick(now),
+        };
+        assert!(emitted.len() <= MAX_EMISSIONS);
+    }
+}
@@ -0,0 +1,341 @@
+//! Running machines built at runtime, for prototyping keymaps from Python.
+//!
+//! A [`Prototype`] runs a [`MachineDescription`] directly, without going
+//! through generated statics, so a keymap can be tried out and tested from a
+//! notebook and then passed to [`generate`](crate::codegen::generate) once
+//! it behaves. Time only moves when [`Prototype::advance`] is called, so
+//! tests of timed behaviour are exact.
+//!
+//! Its methods take and return plain values so that they can be exported as
+//! a Python class. The PyO3 `#[pyclass]` isn't here, as this crate doesn't
+//! depend on PyO3; the extension module wraps a [`Prototype`] and forwards
+//! each method to the one of the same name:
+//!
+//! ```ignore
+//! let mut keymap = Prototype::new(&description)?;
+//! keymap.push(InputEvent::Press(1));
+//! keymap.advance(250);
+//! assert_eq!(keymap.state(), "home_a::HOLD");
+//! ```
+//!
+//! The tunable terms a description names start at zero and are set with
+//! [`Prototype::set_term`]. Conditions and state names are `'static`, so
+//! the terms a prototype uses are given back to be reused when it's dropped,
+//! and state names are interned, leaving a notebook that remakes the same
+//! keymap over and over using no more memory than the first time.
+
+use core::ops::Range;
+use core::time::Duration;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+
+use crate::codegen::{CodegenError, ConditionDescription, MachineDescription};
+use crate::table::{self, Layout, StateIndex};
+use crate::time::{self, HostClock};
+use crate::{GlobalState, InputEvent, InternalEvent, KeyEvent, TransitionCondition, TunableTerm};
+
+/// Every state name a prototype has had.
+static NAMES: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+/// Terms given back by dropped prototypes.
+static SPARE_TERMS: Mutex<Vec<&'static TunableTerm>> = Mutex::new(Vec::new());
+
+fn intern(name: String) -> &'static str {
+    let mut names = NAMES.lock().unwrap();
+    if let Some(name) = names.get(name.as_str()) {
+        return name;
+    }
+    let name = Box::leak(name.into_boxed_str());
+    names.insert(name);
+    name
+}
+
+/// A term set to zero, reusing a spare one if there is one.
+fn new_term() -> &'static TunableTerm {
+    let spare = SPARE_TERMS.lock().unwrap().pop();
+    match spare {
+        Some(term) => {
+            term.set(time::Duration::ZERO);
+            term
+        }
+        None => Box::leak(Box::new(TunableTerm::new(time::Duration::ZERO))),
+    }
+}
+
+struct PrototypeTransition {
+    conditions: Vec<TransitionCondition>,
+    key_event_emissions: Vec<KeyEvent>,
+    internal_event_emissions: Vec<InternalEvent>,
+    target: StateIndex,
+}
+
+/// A description laid out for [`TableMachine`], with the initial state at
+/// index 0.
+struct PrototypeLayout {
+    states: Vec<(&'static str, Range<usize>)>,
+    transitions: Vec<PrototypeTransition>,
+}
+
+impl Layout for PrototypeLayout {
+    fn state_count(&self) -> usize {
+        self.states.len()
+    }
+
+    fn transition_range(&self, state: StateIndex) -> Range<usize> {
+        self.states[state.0 as usize].1.clone()
+    }
+
+    fn conditions(&self, transition: usize) -> &[TransitionCondition] {
+        &self.transitions[transition].conditions
+    }
+
+    fn key_event_emissions(&self, transition: usize) -> &[KeyEvent] {
+        &self.transitions[transition].key_event_emissions
+    }
+
+    fn internal_event_emissions(&self, transition: usize) -> &[InternalEvent] {
+        &self.transitions[transition].internal_event_emissions
+    }
+
+    fn target(&self, transition: usize) -> StateIndex {
+        self.transitions[transition].target
+    }
+
+    fn name(&self, state: StateIndex) -> &'static str {
+        self.states[state.0 as usize].0
+    }
+}
+
+struct Prototype {
+    layout: PrototypeLayout,
+    machine: GlobalState<HostClock, StateIndex>,
+    terms: HashMap<String, &'static TunableTerm>,
+    now: Duration,
+    /// Emitted events not yet taken.
+    emitted: Vec<KeyEvent>,
+}
+
+impl Prototype {
+    /// A prototype running `description` from its first state, at time zero.
+    fn new(description: &MachineDescription) -> Result<Self, CodegenError> {
+        description.check()?;
+
+        let mut terms = HashMap::new();
+        let mut term = |name: &String| -> &'static TunableTerm {
+            terms.entry(name.clone()).or_insert_with(new_term)
+        };
+
+        let mut layout = PrototypeLayout {
+            states: Vec::new(),
+            transitions: Vec::new(),
+        };
+        for state in &description.states {
+            let first = layout.transitions.len();
+            for transition in &state.transitions {
+                let target = description
+                    .states
+                    .iter()
+                    .position(|s| s.name == transition.target)
+                    .unwrap_or_else(|| unreachable!());
+                let conditions = transition
+                    .conditions
+                    .iter()
+                    .map(|condition| match condition {
+                        ConditionDescription::Condition(condition) => condition.clone(),
+                        ConditionDescription::ElapsedLessTunable(name) => {
+                            TransitionCondition::ElapsedLessTunable(term(name))
+                        }
+                        ConditionDescription::ElapsedGreaterTunable(name) => {
+                            TransitionCondition::ElapsedGreaterTunable(term(name))
+                        }
+                    })
+                    .collect();
+                layout.transitions.push(PrototypeTransition {
+                    conditions,
+                    key_event_emissions: transition.key_event_emissions.clone(),
+                    internal_event_emissions: transition.internal_event_emissions.clone(),
+                    target: StateIndex(target as u16),
+                });
+            }
+            let name = intern(format!("{}::{}", description.name, state.name));
+            layout.states.push((name, first..layout.transitions.len()));
+        }
+
+        Ok(Self {
+            layout,
+            machine: GlobalState::new(StateIndex(0), Duration::ZERO),
+            terms,
+            now: Duration::ZERO,
+            emitted: Vec::new(),
+        })
+    }
+
+    /// Push an event at the current time.
+    fn push(&mut self, event: InputEvent) {
+        let events = table::step(&self.layout, &mut self.machine, self.now, Some(event));
+        self.emitted.extend_from_slice(events);
+    }
+
+    /// Move time forward by `ms` milliseconds. Timed transitions that come
+    /// due on the way are taken at their deadlines, in order.
+    fn advance(&mut self, ms: u32) {
+        let until = self.now + Duration::from_millis(ms as u64);
+        while let Some(deadline) = table::next_deadline(&self.layout, &self.machine, self.now) {
+            if deadline > until {
+                break;
+            }
+            self.now = deadline;
+            let events = table::step(&self.layout, &mut self.machine, self.now, None);
+            self.emitted.extend_from_slice(events);
+        }
+        self.now = until;
+        let events = table::step(&self.layout, &mut self.machine, self.now, None);
+        self.emitted.extend_from_slice(events);
+    }
+
+    /// Milliseconds since the prototype was made.
+    fn now_ms(&self) -> u64 {
+        self.now.as_millis() as u64
+    }
+
+    /// The events emitted since this was last called.
+    fn take_emissions(&mut self) -> Vec<KeyEvent> {
+        core::mem::take(&mut self.emitted)
+    }
+
+    /// The name of the current state, prefixed by the machine's name.
+    fn state(&self) -> &'static str {
+        self.layout.name(self.machine.current_state)
+    }
+
+    /// Set the named tunable term, returning `false` if no condition names
+    /// it.
+    fn set_term(&mut self, name: &str, ms: u32) -> bool {
+        let Some(term) = self.terms.get(name) else {
+            return false;
+        };
+        term.set(time::Duration::from_millis(ms));
+        true
+    }
+}
+
+impl Drop for Prototype {
+    fn drop(&mut self) {
+        SPARE_TERMS.lock().unwrap().extend(self.terms.values());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Prototype;
+    use crate::codegen::{
+        CodegenError, ConditionDescription, MachineDescription, StateDescription,
+        TransitionDescription,
+    };
+    use crate::time::Duration;
+    use crate::{InputEvent, KeyEvent, TransitionCondition};
+
+    fn transition(
+        conditions: Vec<ConditionDescription>,
+        key_event_emissions: Vec<KeyEvent>,
+        target: &str,
+    ) -> TransitionDescription {
+        TransitionDescription {
+            conditions,
+            key_event_emissions,
+            internal_event_emissions: vec![],
+            target: target.into(),
+        }
+    }
+
+    fn hold_tap() -> MachineDescription {
+        let press = ConditionDescription::Condition(TransitionCondition::pressed_single(1));
+        let release = ConditionDescription::Condition(TransitionCondition::depressed_single(1));
+        MachineDescription {
+            name: "home_a".into(),
+            states: vec![
+                StateDescription {
+                    name: "IDLE".into(),
+                    transitions: vec![transition(vec![press], vec![], "UNDECIDED")],
+                },
+                StateDescription {
+                    name: "UNDECIDED".into(),
+                    transitions: vec![
+                        transition(
+                            vec![release.clone()],
+                            vec![KeyEvent::Press(6), KeyEvent::Depress(6)],
+                            "IDLE",
+                        ),
+                        transition(
+                            vec![ConditionDescription::ElapsedGreaterTunable("TERM".into())],
+                            vec![KeyEvent::Press(0xe1)],
+                            "HOLD",
+                        ),
+                    ],
+                },
+                StateDescription {
+                    name: "HOLD".into(),
+                    transitions: vec![transition(
+                        vec![release],
+                        vec![KeyEvent::Depress(0xe1)],
+                        "IDLE",
+                    )],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn prototype() {
+        let mut keymap = Prototype::new(&hold_tap()).unwrap();
+        assert!(keymap.set_term("TERM", 200));
+        assert!(!keymap.set_term("OTHER", 200));
+
+        keymap.push(InputEvent::Press(1));
+        keymap.advance(100);
+        keymap.push(InputEvent::Depress(1));
+        assert_eq!(keymap.state(), "home_a::IDLE");
+        assert_eq!(
+            keymap.take_emissions(),
+            [KeyEvent::Press(6), KeyEvent::Depress(6)]
+        );
+
+        keymap.push(InputEvent::Press(1));
+        keymap.advance(250);
+        assert_eq!(keymap.now_ms(), 350);
+        assert_eq!(keymap.state(), "home_a::HOLD");
+        assert_eq!(keymap.take_emissions(), [KeyEvent::Press(0xe1)]);
+    }
+
+    #[test]
+    fn reuses_names_and_terms() {
+        let first = Prototype::new(&hold_tap()).unwrap();
+        first.terms["TERM"].set(Duration::from_millis(200));
+        let name = first.state();
+        drop(first);
+
+        let second = Prototype::new(&hold_tap()).unwrap();
+        assert!(core::ptr::eq(name, second.state()));
+        assert_eq!(second.terms["TERM"].get(), Duration::ZERO);
+    }
+
+    #[test]
+    fn rejects_broken_descriptions() {
+        let mut machine = hold_tap();
+        machine.states[0].transitions[0].target = "NOWHERE".into();
+        assert_eq!(
+            Prototype::new(&machine).err(),
+            Some(CodegenError::UnknownTarget("NOWHERE".into()))
+        );
+
+        let mut machine = hold_tap();
+        machine.states[2].transitions[0].key_event_emissions = vec![KeyEvent::Depress(0xe1); 17];
+        assert_eq!(
+            Prototype::new(&machine).err(),
+            Some(CodegenError::TooManyEmissions("HOLD".into()))
+        );
+
+        machine.states.clear();
+        assert_eq!(Prototype::new(&machine).err(), Some(CodegenError::Empty));
+    }
+}
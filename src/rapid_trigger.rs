@@ -0,0 +1,116 @@
+//! Rapid trigger for analog keys.
+//!
+//! Rather than actuating at fixed points, a key presses once it has travelled
+//! down `press_sensitivity` from the highest point it was released at, and
+//! releases once it has come back up `release_sensitivity` from the lowest
+//! point it was pressed at. Keys above `deadzone` are always released, so
+//! resting fingers and noise near the top don't register.
+
+use crate::InputEvent;
+
+#[derive(Clone, Copy)]
+struct KeyTravel {
+    pressed: bool,
+    /// The deepest travel while pressed, or the shallowest while released.
+    extreme: u8,
+}
+
+struct RapidTrigger<const N: usize> {
+    deadzone: u8,
+    press_sensitivity: u8,
+    release_sensitivity: u8,
+    keys: [KeyTravel; N],
+}
+
+impl<const N: usize> RapidTrigger<N> {
+    const fn new(deadzone: u8, press_sensitivity: u8, release_sensitivity: u8) -> Self {
+        Self {
+            deadzone,
+            press_sensitivity,
+            release_sensitivity,
+            keys: [KeyTravel {
+                pressed: false,
+                extreme: 0,
+            }; N],
+        }
+    }
+
+    /// Turns travel events into presses and releases, other events are passed
+    /// through.
+    fn push(&mut self, event: InputEvent) -> Option<InputEvent> {
+        let InputEvent::Travel(key, travel) = event else {
+            return Some(event);
+        };
+        let state = self.keys.get_mut(key as usize)?;
+
+        if state.pressed {
+            state.extreme = state.extreme.max(travel);
+            if travel < self.deadzone
+                || travel.saturating_add(self.release_sensitivity) <= state.extreme
+            {
+                *state = KeyTravel {
+                    pressed: false,
+                    extreme: travel,
+                };
+                return Some(InputEvent::Depress(key));
+            }
+        } else {
+            state.extreme = state.extreme.min(travel);
+            if travel >= self.deadzone
+                && travel >= state.extreme.saturating_add(self.press_sensitivity)
+            {
+                *state = KeyTravel {
+                    pressed: true,
+                    extreme: travel,
+                };
+                return Some(InputEvent::Press(key));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RapidTrigger;
+    use crate::InputEvent;
+
+    fn travel(trigger: &mut RapidTrigger<4>, key: u8, travel: &[u8]) -> Vec<InputEvent> {
+        travel
+            .iter()
+            .filter_map(|t| trigger.push(InputEvent::Travel(key, *t)))
+            .collect()
+    }
+
+    #[test]
+    fn actuates_on_relative_movement() {
+        let mut trigger = RapidTrigger::new(20, 10, 10);
+
+        // resting in the dead zone never presses
+        assert_eq!(travel(&mut trigger, 1, &[5, 15, 19, 0]), []);
+
+        assert_eq!(
+            travel(&mut trigger, 1, &[15, 25, 30]),
+            [InputEvent::Press(1)]
+        );
+        assert_eq!(
+            travel(&mut trigger, 1, &[120, 115, 110, 105, 110, 120]),
+            [InputEvent::Depress(1), InputEvent::Press(1)]
+        );
+        assert_eq!(
+            travel(&mut trigger, 1, &[200, 10]),
+            [InputEvent::Depress(1)]
+        );
+    }
+
+    #[test]
+    fn passes_other_events() {
+        let mut trigger = RapidTrigger::<4>::new(20, 10, 10);
+        assert_eq!(
+            trigger.push(InputEvent::Press(3)),
+            Some(InputEvent::Press(3))
+        );
+        assert_eq!(trigger.push(InputEvent::Travel(9, 200)), None);
+    }
+}
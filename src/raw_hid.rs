@@ -0,0 +1,535 @@
+//! Runtime configuration from the host over raw HID.
+//!
+//! Each 32 byte report from the host is a command, [`RawHid::handle`]
+//! carries it out and overwrites the report with the response to send back.
+//! A response starts with the command byte and a [`Status`], followed by the
+//! command's results.
+//!
+//! | Command | Arguments | Results |
+//! |---|---|---|
+//! | `0x01` version | | protocol version |
+//! | `0x02` keymap size | | layers, keys, machines, terms |
+//! | `0x03` get action | layer, position | action |
+//! | `0x04` set action | layer, position, action | |
+//! | `0x05` get term | index | milliseconds (`u16`) |
+//! | `0x06` set term | index, milliseconds (`u16`) | |
+//! | `0x07` get layers | | default layer, active layers (`u32`) |
+//! | `0x08` set default layer | layer | |
+//! | `0x09` read trace | | count, then that many entries |
+//! | `0x0a` host context | kind, value (`u16`) | |
+//! | `0x0b` read key counts | first key code | count, then that many `u16` |
+//! | `0x0c` read typing stats | | words per minute (`u16`), modified presses (`u32`) |
+//! | `0x0d` machine | machine | states (`u16`), initial and current state ids (`u16`) |
+//! | `0x0e` state | machine, state (`u16`) | id (`u16`), transitions, name length, name |
+//! | `0x0f` transition | machine, state (`u16`), transition | target id (`u16`), conditions, key events, internal events |
+//! | `0x10` condition | machine, state (`u16`), transition, condition | condition |
+//! | `0x11` key event | machine, state (`u16`), transition, key event | event |
+//! | `0x12` lock output | locked | |
+//! | `0x13` read chatter counts | first key | count, then that many `u16` |
+//!
+//! Integers are little endian, and sizes or counts past 255 that are sent
+//! as a byte are sent as 255. Actions are as encoded by
+//! [`Action::to_bytes`], and a trace entry is a kind byte (0 for input, 1
+//! for output), the time in milliseconds as a `u32`, and the event. Input
+//! events are encoded by [`InputEvent::to_bytes`], output events as a tag
+//! for press, release, layer activated and layer deactivated followed by the
+//! key or layer, 4 and 5 for entering the bootloader and resetting, or
+//! `0xff` for other events. Reading the trace removes the
+//! entries read, the host repeats the command until the count is zero.
+//!
+//! Key counts are the presses of each key code since startup, starting
+//! from the one asked for. A host tool reads them all for a heatmap by
+//! asking again from the next key code until the count is zero. Chatter
+//! counts are read the same way, for each matrix position, see
+//! [`chatter`](crate::chatter).
+//!
+//! States are numbered as in [`introspect`](crate::introspect), and a
+//! state's name is cut short to fit the report. A condition is a
+//! [`PackedCondition`] with its tunable term, if it has one, as the index of
+//! the term for commands `0x05` and `0x06`, and is [`Status::Invalid`] if it
+//! can't be packed. Key events are encoded like those in the trace.
+//!
+//! Host context is the focused application's id (kind 0), a hash of the
+//! window title (kind 1) or the index of the host's keyboard layout (kind 2,
+//! the low byte of the value). [`RawHid::handle`] returns it as an
+//! [`InputEvent`] for the firmware to push to the machines, as is locking
+//! the output (any non-zero byte) or unlocking it.
+//!
+//! [`PackedCondition`]: crate::packed::PackedCondition
+
+use crate::chatter::ChatterDetector;
+use crate::introspect;
+use crate::keymap::{Action, Keymap};
+use crate::metrics::TypingMetrics;
+use crate::packed::PackedCondition;
+use crate::time::{self, Duration, Instant};
+use crate::trace::{TraceBuffer, TraceEntry, TraceEvent};
+use crate::{DynState, InputEvent, KeyEvent, Transition, TunableTerm};
+
+const REPORT_LEN: usize = 32;
+const PROTOCOL_VERSION: u8 = 2;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Status {
+    Ok = 0,
+    UnknownCommand = 1,
+    /// A layer, position or index doesn't exist.
+    OutOfRange = 2,
+    /// The arguments couldn't be decoded.
+    Invalid = 3,
+}
+
+struct RawHid<const TERMS: usize> {
+    /// The terms the host can tune, by index.
+    terms: [&'static TunableTerm; TERMS],
+}
+
+impl<const TERMS: usize> RawHid<TERMS> {
+    const fn new(terms: [&'static TunableTerm; TERMS]) -> Self {
+        Self { terms }
+    }
+
+    /// Carry out the command in `report`, replacing it with the response.
+    /// Returns the event to push for commands that carry one.
+    fn handle<
+        Clock,
+        const LAYERS: usize,
+        const KEYS: usize,
+        const MACHINES: usize,
+        const N: usize,
+        const BUCKETS: usize,
+    >(
+        &self,
+        report: &mut [u8; REPORT_LEN],
+        keymap: &mut Keymap<Clock, LAYERS, KEYS, MACHINES>,
+        trace: &mut TraceBuffer<Clock, N>,
+        metrics: &TypingMetrics<Clock, BUCKETS>,
+        chatter: &ChatterDetector<Clock, KEYS>,
+    ) -> Option<InputEvent>
+    where
+        Clock: time::Clock,
+    {
+        let request = *report;
+        report[1..].fill(0);
+
+        let (status, event) =
+            match self.dispatch(&request, &mut report[2..], keymap, trace, metrics, chatter) {
+                Ok(event) => (Status::Ok, event),
+                Err(status) => (status, None),
+            };
+        report[1] = status as u8;
+
+        event
+    }
+
+    fn dispatch<
+        Clock,
+        const LAYERS: usize,
+        const KEYS: usize,
+        const MACHINES: usize,
+        const N: usize,
+        const BUCKETS: usize,
+    >(
+        &self,
+        request: &[u8; REPORT_LEN],
+        out: &mut [u8],
+        keymap: &mut Keymap<Clock, LAYERS, KEYS, MACHINES>,
+        trace: &mut TraceBuffer<Clock, N>,
+        metrics: &TypingMetrics<Clock, BUCKETS>,
+        chatter: &ChatterDetector<Clock, KEYS>,
+    ) -> Result<Option<InputEvent>, Status>
+    where
+        Clock: time::Clock,
+    {
+        let (command, args) = (request[0], &request[1..]);
+        match command {
+            0x01 => out[0] = PROTOCOL_VERSION,
+            0x02 => out[..4].copy_from_slice(&[LAYERS, KEYS, MACHINES, TERMS].map(saturate)),
+            0x03 => {
+                let action = keymap
+                    .action(args[0] as usize, args[1] as usize)
+                    .ok_or(Status::OutOfRange)?;
+                out[..3].copy_from_slice(&action.to_bytes());
+            }
+            0x04 => {
+                let action =
+                    Action::from_bytes([args[2], args[3], args[4]]).ok_or(Status::Invalid)?;
+                if !keymap.set_action(args[0] as usize, args[1] as usize, action) {
+                    return Err(Status::OutOfRange);
+                }
+            }
+            0x05 => {
+                let term = self.term(args[0])?.get().as_millis().min(u16::MAX as u32) as u16;
+                out[..2].copy_from_slice(&term.to_le_bytes());
+            }
+            0x06 => {
+                let term = u16::from_le_bytes([args[1], args[2]]);
+                self.term(args[0])?.set(Duration::from_millis(term as u32));
+            }
+            0x07 => {
+                out[0] = keymap.default_layer();
+                out[1..5].copy_from_slice(&keymap.active_layers().0.to_le_bytes());
+            }
+            0x08 => {
+                if !keymap.set_default_layer(args[0]) {
+                    return Err(Status::OutOfRange);
+                }
+            }
+            0x09 => {
+                let (count, entries) = out.split_first_mut().unwrap();
+                for chunk in entries.chunks_exact_mut(8) {
+                    let Some(entry) = trace.pop() else {
+                        break;
+                    };
+                    encode_trace_entry(&entry, chunk);
+                    *count += 1;
+                }
+            }
+            0x0a => {
+                let value = u16::from_le_bytes([args[1], args[2]]);
+                return Ok(Some(match args[0] {
+                    0 => InputEvent::Application(value),
+                    1 => InputEvent::WindowTitle(value),
+                    2 => InputEvent::HostLayout(args[1]),
+                    _ => return Err(Status::Invalid),
+                }));
+            }
+            0x0b => {
+                let (count, counts) = out.split_first_mut().unwrap();
+                for (key, chunk) in (args[0]..=u8::MAX).zip(counts.chunks_exact_mut(2)) {
+                    chunk.copy_from_slice(&metrics.key_count(key).to_le_bytes());
+                    *count += 1;
+                }
+            }
+            0x0c => {
+                out[..2].copy_from_slice(&metrics.wpm().to_le_bytes());
+                out[2..6].copy_from_slice(&metrics.modified_presses().to_le_bytes());
+            }
+            0x0d => {
+                let (initial, current) =
+                    keymap.machine(args[0] as usize).ok_or(Status::OutOfRange)?;
+                let states = introspect::state_count(initial) as u16;
+                out[..2].copy_from_slice(&states.to_le_bytes());
+                out[2..4].copy_from_slice(&initial.id().0.to_le_bytes());
+                out[4..6].copy_from_slice(&current.id().0.to_le_bytes());
+            }
+            0x0e => {
+                let state = state(keymap, args)?;
+                let name = state.name().as_bytes();
+                let name = &name[..name.len().min(out.len() - 4)];
+                out[..2].copy_from_slice(&state.id().0.to_le_bytes());
+                out[2] = saturate(state.transitions().len());
+                out[3] = name.len() as u8;
+                out[4..4 + name.len()].copy_from_slice(name);
+            }
+            0x0f => {
+                let transition = transition(keymap, args)?;
+                out[..2].copy_from_slice(&transition.target.id.0.to_le_bytes());
+                out[2] = saturate(transition.conditions.len());
+                out[3] = saturate(transition.key_event_emissions.len());
+                out[4] = saturate(transition.internal_event_emissions.len());
+            }
+            0x10 => {
+                let condition = transition(keymap, args)?
+                    .conditions
+                    .get(args[4] as usize)
+                    .ok_or(Status::OutOfRange)?;
+                let packed = PackedCondition::pack_with_terms(condition, &self.terms)
+                    .ok_or(Status::Invalid)?;
+                out[..4].copy_from_slice(&packed.to_bytes());
+            }
+            0x11 => {
+                let event = transition(keymap, args)?
+                    .key_event_emissions
+                    .get(args[4] as usize)
+                    .ok_or(Status::OutOfRange)?;
+                out[..3].copy_from_slice(&event.to_bytes());
+            }
+            0x12 => return Ok(Some(InputEvent::OutputLock(args[0] != 0))),
+            0x13 => {
+                let (count, counts) = out.split_first_mut().unwrap();
+                let keys = (args[0] as usize..KEYS).map(|key| key as u8);
+                for (key, chunk) in keys.zip(counts.chunks_exact_mut(2)) {
+                    chunk.copy_from_slice(&chatter.count(key).to_le_bytes());
+                    *count += 1;
+                }
+            }
+            _ => return Err(Status::UnknownCommand),
+        }
+
+        Ok(None)
+    }
+
+    fn term(&self, index: u8) -> Result<&'static TunableTerm, Status> {
+        self.terms
+            .get(index as usize)
+            .copied()
+            .ok_or(Status::OutOfRange)
+    }
+}
+
+/// `n` as a byte, or 255 if it's more.
+fn saturate(n: usize) -> u8 {
+    n.min(u8::MAX as usize) as u8
+}
+
+/// The state a command's machine and state arguments refer to.
+fn state<Clock, const LAYERS: usize, const KEYS: usize, const MACHINES: usize>(
+    keymap: &Keymap<Clock, LAYERS, KEYS, MACHINES>,
+    args: &[u8],
+) -> Result<&'static dyn DynState, Status>
+where
+    Clock: time::Clock,
+{
+    let (initial, _) = keymap.machine(args[0] as usize).ok_or(Status::OutOfRange)?;
+    let index = u16::from_le_bytes([args[1], args[2]]);
+    introspect::state(initial, index as usize).ok_or(Status::OutOfRange)
+}
+
+/// The transition a command's machine, state and transition arguments
+/// refer to.
+fn transition<Clock, const LAYERS: usize, const KEYS: usize, const MACHINES: usize>(
+    keymap: &Keymap<Clock, LAYERS, KEYS, MACHINES>,
+    args: &[u8],
+) -> Result<&'static Transition, Status>
+where
+    Clock: time::Clock,
+{
+    let transitions = state(keymap, args)?.transitions();
+    transitions
+        .get(args[3] as usize)
+        .copied()
+        .ok_or(Status::OutOfRange)
+}
+
+fn encode_trace_entry<Clock: time::Clock>(entry: &TraceEntry<Clock>, out: &mut [u8]) {
+    let time = entry.time.since_start();
+
+    let (kind, event) = match entry.event {
+        TraceEvent::Input(event) => (0, event.to_bytes()),
+        TraceEvent::Output(event) => (1, event.to_bytes()),
+    };
+
+    out[0] = kind;
+    out[1..5].copy_from_slice(&time.as_millis().to_le_bytes());
+    out[5..8].copy_from_slice(&event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RawHid;
+    use crate::chatter::ChatterDetector;
+    use crate::keymap::{Action, Keymap};
+    use crate::metrics::TypingMetrics;
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::trace::TraceBuffer;
+    use crate::{
+        InputEvent, KeyEvent, State, StateId, TimedEvent, Transition, TransitionCondition,
+        TunableTerm,
+    };
+
+    static LAYERS: [[Action; 2]; 2] = [
+        [Action::Key(4), Action::MomentaryLayer(1)],
+        [Action::Key(5), Action::Transparent],
+    ];
+    static TERM: TunableTerm = TunableTerm::new(Duration::from_millis(200));
+
+    static IDLE: State = State {
+        name: "IDLE",
+        id: StateId(0),
+        transitions: &[&PRESS],
+    };
+
+    static A_VERY_LONG_STATE_NAME: State = State {
+        name: "A_VERY_LONG_STATE_NAME_INDEED",
+        id: StateId(7),
+        transitions: &[&RELEASE],
+    };
+
+    static PRESS: Transition = Transition {
+        conditions: &[
+            TransitionCondition::Pressed(0..=0),
+            TransitionCondition::ElapsedLessTunable(&TERM),
+            TransitionCondition::EventMatches(|_| true),
+        ],
+        key_event_emissions: &[KeyEvent::Press(4)],
+        internal_event_emissions: &[],
+        target: &A_VERY_LONG_STATE_NAME,
+    };
+
+    static RELEASE: Transition = Transition {
+        conditions: &[TransitionCondition::Depressed(0..=0)],
+        key_event_emissions: &[KeyEvent::Depress(4)],
+        internal_event_emissions: &[],
+        target: &IDLE,
+    };
+
+    fn command(bytes: &[u8]) -> [u8; 32] {
+        let mut report = [0; 32];
+        report[..bytes.len()].copy_from_slice(bytes);
+        report
+    }
+
+    #[test]
+    fn configure_keymap() {
+        let mut clock = TickerClock(0);
+        let mut keymap = Keymap::<TickerClock, 2, 2, 0>::new(&LAYERS, [], clock.now());
+        let mut trace = TraceBuffer::<TickerClock, 4>::new();
+        let mut metrics = TypingMetrics::<TickerClock, 1>::new(
+            Duration::from_millis(60_000),
+            Duration::from_millis(1_000),
+            clock.now(),
+        );
+        let chatter = ChatterDetector::new(Duration::from_millis(10), None);
+        let hid = RawHid::new([&TERM]);
+
+        let mut run =
+            |keymap: &mut Keymap<_, 2, 2, 0>, trace: &mut TraceBuffer<_, 4>, bytes: &[u8]| {
+                let mut report = command(bytes);
+                hid.handle(&mut report, keymap, trace, &metrics, &chatter);
+                report
+            };
+
+        assert_eq!(
+            run(&mut keymap, &mut trace, &[0x02])[..6],
+            [0x02, 0, 2, 2, 0, 1]
+        );
+        assert_eq!(
+            run(&mut keymap, &mut trace, &[0x03, 1, 0])[..5],
+            [0x03, 0, 2, 5, 0]
+        );
+        // out of range, an unknown machine and unknown layers
+        assert_eq!(run(&mut keymap, &mut trace, &[0x03, 2, 0])[1], 2);
+        assert_eq!(run(&mut keymap, &mut trace, &[0x04, 0, 0, 4, 0])[1], 2);
+        assert_eq!(run(&mut keymap, &mut trace, &[0x04, 0, 0, 3, 2])[1], 2);
+        assert_eq!(run(&mut keymap, &mut trace, &[0x04, 0, 0, 3, 40])[1], 2);
+        assert_eq!(run(&mut keymap, &mut trace, &[0x42])[1], 1);
+
+        assert_eq!(run(&mut keymap, &mut trace, &[0x04, 0, 0, 2, 7])[1], 0);
+        assert_eq!(keymap.action(0, 0), Some(Action::Key(7)));
+
+        assert_eq!(run(&mut keymap, &mut trace, &[0x06, 0, 0x2c, 0x01])[1], 0);
+        assert_eq!(TERM.get(), Duration::from_millis(300));
+        assert_eq!(run(&mut keymap, &mut trace, &[0x05, 0])[2..4], [0x2c, 0x01]);
+
+        assert_eq!(run(&mut keymap, &mut trace, &[0x08, 1])[1], 0);
+        assert_eq!(run(&mut keymap, &mut trace, &[0x07])[2..7], [1, 0, 0, 0, 0]);
+
+        clock.tick_n(0x102);
+        trace.record_input(clock.now(), InputEvent::Press(0), false);
+        trace.record_outputs(clock.now(), &[KeyEvent::Press(5)], false);
+        let report = run(&mut keymap, &mut trace, &[0x09]);
+        assert_eq!(
+            report[..19],
+            [
+                0x09, 0, 2, // count
+                0, 0x02, 0x01, 0, 0, 0, 0, 0, // input press
+                1, 0x02, 0x01, 0, 0, 0, 5, 0, // output press
+            ]
+        );
+        assert_eq!(run(&mut keymap, &mut trace, &[0x09])[2], 0);
+
+        let mut report = command(&[0x0a, 0, 0x34, 0x12]);
+        let event = hid.handle(&mut report, &mut keymap, &mut trace, &metrics, &chatter);
+        assert_eq!(event, Some(InputEvent::Application(0x1234)));
+        let mut report = command(&[0x0a, 2, 1, 0]);
+        let event = hid.handle(&mut report, &mut keymap, &mut trace, &metrics, &chatter);
+        assert_eq!(event, Some(InputEvent::HostLayout(1)));
+        let mut report = command(&[0x0a, 3, 0, 0]);
+        assert_eq!(
+            hid.handle(&mut report, &mut keymap, &mut trace, &metrics, &chatter),
+            None
+        );
+        assert_eq!(report[1], 3);
+
+        let mut report = command(&[0x12, 1]);
+        let event = hid.handle(&mut report, &mut keymap, &mut trace, &metrics, &chatter);
+        assert_eq!(event, Some(InputEvent::OutputLock(true)));
+    }
+
+    #[test]
+    fn statistics() {
+        let clock = TickerClock(0);
+        let mut keymap = Keymap::<TickerClock, 2, 2, 0>::new(&LAYERS, [], clock.now());
+        let mut trace = TraceBuffer::<TickerClock, 4>::new();
+        let mut metrics = TypingMetrics::<TickerClock, 1>::new(
+            Duration::from_millis(60_000),
+            Duration::from_millis(1_000),
+            clock.now(),
+        );
+        let mut chatter = ChatterDetector::new(Duration::from_millis(10), None);
+        let hid = RawHid::new([]);
+
+        for key in [4, 4, 5, 0xff] {
+            metrics.observe_all(clock.now(), &[KeyEvent::Press(key), KeyEvent::Depress(key)]);
+        }
+
+        for event in [
+            InputEvent::Press(1),
+            InputEvent::Depress(1),
+            InputEvent::Press(1),
+        ] {
+            chatter.push(
+                TimedEvent {
+                    time: clock.now(),
+                    event,
+                },
+                |_| {},
+            );
+        }
+
+        let mut report = command(&[0x0b, 4]);
+        hid.handle(&mut report, &mut keymap, &mut trace, &metrics, &chatter);
+        assert_eq!(report[..7], [0x0b, 0, 14, 2, 0, 1, 0]);
+
+        // the last report is short
+        let mut report = command(&[0x0b, 0xfe]);
+        hid.handle(&mut report, &mut keymap, &mut trace, &metrics, &chatter);
+        assert_eq!(report[..7], [0x0b, 0, 2, 0, 0, 1, 0]);
+
+        let mut report = command(&[0x0c]);
+        hid.handle(&mut report, &mut keymap, &mut trace, &metrics, &chatter);
+        assert_eq!(report[..8], [0x0c, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut report = command(&[0x13, 0]);
+        hid.handle(&mut report, &mut keymap, &mut trace, &metrics, &chatter);
+        assert_eq!(report[..7], [0x13, 0, 2, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn introspection() {
+        let clock = TickerClock(0);
+        let mut keymap = Keymap::<TickerClock, 2, 2, 1>::new(&LAYERS, [IDLE.as_dyn()], clock.now());
+        let mut trace = TraceBuffer::<TickerClock, 4>::new();
+        let metrics = TypingMetrics::<TickerClock, 1>::new(
+            Duration::from_millis(60_000),
+            Duration::from_millis(1_000),
+            clock.now(),
+        );
+        let chatter = ChatterDetector::new(Duration::from_millis(10), None);
+        let hid = RawHid::new([&TERM]);
+        let mut run = |bytes: &[u8]| {
+            let mut report = command(bytes);
+            hid.handle(&mut report, &mut keymap, &mut trace, &metrics, &chatter);
+            report
+        };
+
+        assert_eq!(run(&[0x0d, 0])[..8], [0x0d, 0, 2, 0, 0, 0, 0, 0]);
+        assert_eq!(run(&[0x0d, 1])[1], 2);
+
+        let report = run(&[0x0e, 0, 1, 0]);
+        assert_eq!(report[..6], [0x0e, 0, 7, 0, 1, 26]);
+        assert_eq!(report[6..], *b"A_VERY_LONG_STATE_NAME_IND");
+        assert_eq!(run(&[0x0e, 0, 2, 0])[1], 2);
+
+        assert_eq!(run(&[0x0f, 0, 0, 0, 0])[..7], [0x0f, 0, 7, 0, 3, 1, 0]);
+        assert_eq!(run(&[0x0f, 0, 0, 0, 1])[1], 2);
+        assert_eq!(run(&[0x10, 0, 0, 0, 0, 0])[..6], [0x10, 0, 2, 0, 0, 0]);
+        // the term as its index
+        assert_eq!(run(&[0x10, 0, 0, 0, 0, 1])[..6], [0x10, 0, 15, 0, 0, 0]);
+        assert_eq!(run(&[0x10, 0, 0, 0, 0, 2])[1], 3);
+        assert_eq!(run(&[0x10, 0, 0, 0, 0, 3])[1], 2);
+        assert_eq!(run(&[0x11, 0, 1, 0, 0, 0])[..5], [0x11, 0, 1, 4, 0]);
+    }
+}
@@ -0,0 +1,182 @@
+//! Translating matrix positions to logical keys.
+//!
+//! Machines and keymaps are written against logical key ids, and an
+//! [`InputRemap`] in front of them maps the raw positions of a particular
+//! matrix onto those, so the same machine definition works across PCB
+//! revisions that wire the switches differently. The table is indexed by raw
+//! position and positions past its end go through as they are:
+//!
+//! ```ignore
+//! static REV_B: [Position; 4] = [
+//!     Position::Key(1),
+//!     Position::Key(0),
+//!     // a split spacebar's halves are one key here
+//!     Position::Key(2),
+//!     Position::Key(2),
+//! ];
+//! ```
+//!
+//! A raw position can also drive several logical keys at once, and several
+//! raw positions the same logical key, which is then held while any of them
+//! is, so splitting or merging switches between revisions doesn't change
+//! what a machine sees. Analog travel is mapped the same way.
+
+use crate::{InputEvent, KeySet};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Position {
+    /// Not wired to anything, events from it are dropped.
+    None,
+    Key(u8),
+    /// Each of these keys, in order.
+    Keys(&'static [u8]),
+}
+
+impl Position {
+    fn keys(&self) -> &[u8] {
+        match self {
+            Position::None => &[],
+            Position::Key(key) => core::slice::from_ref(key),
+            Position::Keys(keys) => keys,
+        }
+    }
+}
+
+pub(crate) struct InputRemap {
+    table: &'static [Position],
+    /// Raw positions held.
+    held: KeySet,
+}
+
+impl InputRemap {
+    pub(crate) const fn new(table: &'static [Position]) -> Self {
+        Self {
+            table,
+            held: KeySet::empty(),
+        }
+    }
+
+    fn keys(&self, position: u8) -> &'static [u8] {
+        match self.table.get(position as usize) {
+            Some(mapping) => mapping.keys(),
+            None => &[],
+        }
+    }
+
+    /// Whether a held position other than `except` drives `key`.
+    fn held_elsewhere(&self, key: u8, except: u8) -> bool {
+        self.held
+            .keys()
+            .any(|position| position != except && self.keys(position).contains(&key))
+    }
+
+    /// Pass on `event` with its raw position translated.
+    pub(crate) fn translate(&mut self, event: InputEvent, mut emit: impl FnMut(InputEvent)) {
+        let position = match event {
+            InputEvent::Press(position)
+            | InputEvent::Depress(position)
+            | InputEvent::Travel(position, _) => position,
+            event => return emit(event),
+        };
+        if position as usize >= self.table.len() {
+            return emit(event);
+        }
+
+        match event {
+            InputEvent::Press(_) => {
+                for &key in self.keys(position) {
+                    if !self.held_elsewhere(key, position) {
+                        emit(InputEvent::Press(key));
+                    }
+                }
+                self.held.insert(position);
+            }
+            InputEvent::Depress(_) => {
+                if !self.held.remove(position) {
+                    return;
+                }
+                for &key in self.keys(position) {
+                    if !self.held_elsewhere(key, position) {
+                        emit(InputEvent::Depress(key));
+                    }
+                }
+            }
+            InputEvent::Travel(_, travel) => {
+                for &key in self.keys(position) {
+                    emit(InputEvent::Travel(key, travel));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InputRemap, Position};
+    use crate::InputEvent;
+
+    static TABLE: [Position; 5] = [
+        Position::Key(1),
+        Position::Key(0),
+        Position::Key(2),
+        Position::Key(2),
+        Position::Keys(&[3, 4]),
+    ];
+
+    fn translate(remap: &mut InputRemap, event: InputEvent) -> Vec<InputEvent> {
+        let mut out = Vec::new();
+        remap.translate(event, |e| out.push(e));
+        out
+    }
+
+    #[test]
+    fn remaps() {
+        let mut remap = InputRemap::new(&TABLE);
+        assert_eq!(
+            translate(&mut remap, InputEvent::Press(0)),
+            [InputEvent::Press(1)]
+        );
+        assert_eq!(
+            translate(&mut remap, InputEvent::Depress(0)),
+            [InputEvent::Depress(1)]
+        );
+        assert_eq!(
+            translate(&mut remap, InputEvent::Travel(1, 40)),
+            [InputEvent::Travel(0, 40)]
+        );
+
+        // merged, held until both halves are released
+        assert_eq!(
+            translate(&mut remap, InputEvent::Press(2)),
+            [InputEvent::Press(2)]
+        );
+        assert_eq!(translate(&mut remap, InputEvent::Press(3)), []);
+        assert_eq!(translate(&mut remap, InputEvent::Depress(2)), []);
+        assert_eq!(
+            translate(&mut remap, InputEvent::Depress(3)),
+            [InputEvent::Depress(2)]
+        );
+
+        // split
+        assert_eq!(
+            translate(&mut remap, InputEvent::Press(4)),
+            [InputEvent::Press(3), InputEvent::Press(4)]
+        );
+        assert_eq!(
+            translate(&mut remap, InputEvent::Depress(4)),
+            [InputEvent::Depress(3), InputEvent::Depress(4)]
+        );
+
+        // past the table, and other events, go through
+        assert_eq!(
+            translate(&mut remap, InputEvent::Press(9)),
+            [InputEvent::Press(9)]
+        );
+        assert_eq!(
+            translate(&mut remap, InputEvent::Application(1)),
+            [InputEvent::Application(1)]
+        );
+        assert_eq!(translate(&mut remap, InputEvent::Depress(1)), []);
+    }
+}
@@ -0,0 +1,162 @@
+//! Adapter for RMK firmware.
+//!
+//! [`Processor`] sits in RMK's input pipeline in place of its own keymap
+//! processing: it takes key events by row and column and produces key and
+//! layer actions, with a flag for whether each is being pressed or released,
+//! which is how RMK hands actions to its report builder.
+//!
+//! [`KeyboardEvent`] and [`Action`] copy just the parts of
+//! `rmk::event::KeyboardEvent` and `rmk::action::Action` the processor
+//! needs, which an RMK build converts from and to. Outputs with no RMK
+//! action, such as lighting, are dropped, as are events for columns past the
+//! last or positions past the keymap's keys.
+
+use crate::keymap::Keymap;
+use crate::time::{self, Instant};
+use crate::{InputEvent, KeyCode, KeyEvent, Layer, Wireless};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct KeyboardEvent {
+    row: u8,
+    col: u8,
+    pressed: bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Action {
+    Key(KeyCode),
+    /// Active while pressed.
+    LayerOn(Layer),
+    Wireless(Wireless),
+}
+
+struct Processor<
+    Clock: time::Clock,
+    const COLS: usize,
+    const LAYERS: usize,
+    const KEYS: usize,
+    const MACHINES: usize,
+> {
+    keymap: Keymap<Clock, LAYERS, KEYS, MACHINES>,
+}
+
+fn action(event: KeyEvent) -> Option<(Action, bool)> {
+    Some(match event {
+        KeyEvent::Press(key) => (Action::Key(key), true),
+        KeyEvent::Depress(key) => (Action::Key(key), false),
+        KeyEvent::LayerActivated(layer) => (Action::LayerOn(layer), true),
+        KeyEvent::LayerDeactivated(layer) => (Action::LayerOn(layer), false),
+        KeyEvent::Wireless(wireless) => (Action::Wireless(wireless), true),
+        _ => return None,
+    })
+}
+
+impl<
+        Clock: time::Clock,
+        const COLS: usize,
+        const LAYERS: usize,
+        const KEYS: usize,
+        const MACHINES: usize,
+    > Processor<Clock, COLS, LAYERS, KEYS, MACHINES>
+{
+    fn new(keymap: Keymap<Clock, LAYERS, KEYS, MACHINES>) -> Self {
+        const { assert!(KEYS <= 256, "positions past 255 can't be pushed") };
+        Self { keymap }
+    }
+
+    fn process(
+        &mut self,
+        current_time: Clock::Instant,
+        event: KeyboardEvent,
+        mut emit: impl FnMut(Action, bool),
+    ) {
+        let position = event.row as usize * COLS + event.col as usize;
+        if event.col as usize >= COLS || position >= KEYS {
+            return;
+        }
+        let position = position as u8;
+        let event = if event.pressed {
+            InputEvent::Press(position)
+        } else {
+            InputEvent::Depress(position)
+        };
+
+        self.keymap.push(current_time, event, |event| {
+            if let Some((action, pressed)) = action(event) {
+                emit(action, pressed);
+            }
+        });
+    }
+
+    /// Call whenever RMK's scan loop runs, to let timed behaviors fire.
+    fn tick(&mut self, current_time: Clock::Instant, mut emit: impl FnMut(Action, bool)) {
+        self.keymap.tick(current_time, |event| {
+            if let Some((action, pressed)) = action(event) {
+                emit(action, pressed);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, KeyboardEvent, Processor};
+    use crate::behaviors::hold_tap;
+    use crate::keymap::{self, Keymap};
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    static LAYERS: [[keymap::Action; 2]; 1] = [[
+        keymap::Action::MomentaryLayer(1),
+        keymap::Action::Machine(0),
+    ]];
+
+    #[test]
+    fn actions() {
+        let mut clock = TickerClock(0);
+        let keymap = Keymap::new(&LAYERS, [home_a::IDLE.as_dyn()], clock.now());
+        let mut processor = Processor::<TickerClock, 1, 1, 2, 1>::new(keymap);
+        let mut out = Vec::new();
+
+        let key = |row, pressed| KeyboardEvent {
+            row,
+            col: 0,
+            pressed,
+        };
+
+        processor.process(clock.now(), key(0, true), |a, p| out.push((a, p)));
+        processor.process(clock.now(), key(1, true), |a, p| out.push((a, p)));
+        clock.tick_n(20);
+        processor.tick(clock.now(), |a, p| out.push((a, p)));
+        processor.process(clock.now(), key(1, false), |a, p| out.push((a, p)));
+        processor.process(clock.now(), key(0, false), |a, p| out.push((a, p)));
+        // past the keymap, and past what fits a byte
+        processor.process(clock.now(), key(2, true), |a, p| out.push((a, p)));
+        processor.process(clock.now(), key(255, true), |a, p| out.push((a, p)));
+        let past = KeyboardEvent {
+            row: 0,
+            col: 1,
+            pressed: true,
+        };
+        processor.process(clock.now(), past, |a, p| out.push((a, p)));
+
+        assert_eq!(
+            out,
+            [
+                (Action::LayerOn(1), true),
+                (Action::Key(0xe1), true),
+                (Action::Key(0xe1), false),
+                (Action::LayerOn(1), false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Sending emissions to particular hosts.
+//!
+//! A dongle or multi-host keyboard has several places a report can go. Each
+//! machine has a [`Route`] its emissions are for, changed by
+//! [`InternalEvent::SetRoute`] and read with [`GlobalState::route`], and a
+//! transition can send some of its own emissions elsewhere by putting a
+//! [`KeyEvent::Route`] before them. [`routed`] walks what a step emitted and
+//! gives each event with the route it's for, leaving the markers out:
+//!
+//! ```ignore
+//! static TO_HOST_2: Transition = Transition {
+//!     key_event_emissions: &[
+//!         KeyEvent::Route(Route::BleHost(2)),
+//!         KeyEvent::Press(0x04),
+//!         KeyEvent::Depress(0x04),
+//!     ],
+//!     ...
+//! };
+//!
+//! let emitted = machine.push(now, event);
+//! routed(machine.route(), emitted, |route, event| send(route, event));
+//! ```
+//!
+//! Routes are only tags, it's up to the firmware to send each event where
+//! its route says. Until a machine sets one its emissions are
+//! [`Route::Active`], for whichever host the firmware is sending to anyway.
+//!
+//! [`InternalEvent::SetRoute`]: crate::InternalEvent::SetRoute
+//! [`GlobalState::route`]: crate::GlobalState::route
+
+use crate::KeyEvent;
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Route {
+    /// Wherever the firmware is currently sending reports.
+    #[default]
+    Active,
+    Usb,
+    /// The Bluetooth host with this number, from 1 to 3.
+    BleHost(u8),
+    Serial,
+}
+
+/// Give each of `events` to `emit` with its route, starting from `route` and
+/// switching at each [`KeyEvent::Route`].
+pub(crate) fn routed(mut route: Route, events: &[KeyEvent], mut emit: impl FnMut(Route, KeyEvent)) {
+    for &event in events {
+        match event {
+            KeyEvent::Route(to) => route = to,
+            event => emit(route, event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{routed, Route};
+    use crate::tests::TickerClock;
+    use crate::{
+        GlobalState, InputEvent, InternalEvent, KeyEvent, State, StateId, Transition,
+        TransitionCondition,
+    };
+
+    static IDLE: State = State {
+        name: "IDLE",
+        id: StateId(0),
+        transitions: &[&TO_SERIAL, &MACRO],
+    };
+
+    static TO_SERIAL: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(1)],
+        key_event_emissions: &[KeyEvent::Press(1)],
+        internal_event_emissions: &[InternalEvent::SetRoute(Route::Serial)],
+        target: &IDLE,
+    };
+
+    static MACRO: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(2)],
+        key_event_emissions: &[
+            KeyEvent::Press(2),
+            KeyEvent::Route(Route::BleHost(2)),
+            KeyEvent::Press(3),
+            KeyEvent::Route(Route::Usb),
+            KeyEvent::Depress(3),
+        ],
+        internal_event_emissions: &[],
+        target: &IDLE,
+    };
+
+    #[test]
+    fn routes() {
+        let clock = TickerClock(0);
+        let mut machine = GlobalState::<TickerClock>::new(IDLE.as_dyn(), clock.now());
+        let mut step = |event| {
+            let mut out = Vec::new();
+            machine.push(clock.now(), event);
+            routed(machine.route(), machine.emitted(), |route, event| {
+                out.push((route, event))
+            });
+            out
+        };
+
+        assert_eq!(
+            step(InputEvent::Press(2)),
+            [
+                (Route::Active, KeyEvent::Press(2)),
+                (Route::BleHost(2), KeyEvent::Press(3)),
+                (Route::Usb, KeyEvent::Depress(3)),
+            ]
+        );
+        // the transition's own emissions go the way it set
+        assert_eq!(
+            step(InputEvent::Press(1)),
+            [(Route::Serial, KeyEvent::Press(1))]
+        );
+        assert_eq!(
+            step(InputEvent::Press(2))[..2],
+            [
+                (Route::Serial, KeyEvent::Press(2)),
+                (Route::BleHost(2), KeyEvent::Press(3)),
+            ]
+        );
+    }
+}
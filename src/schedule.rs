@@ -0,0 +1,120 @@
+//! Deciding when to tick.
+//!
+//! [`TickScheduler`] combines a coarse periodic tick, enough for tap-hold
+//! timings, with a fine tick armed from
+//! [`GlobalState::next_deadline`](crate::GlobalState::next_deadline), so that
+//! the main loop can run slowly and still wake up on time for a precise
+//! timeout. Ticks that come late, or at uneven intervals, don't shift the
+//! timing of the machine, as timed transitions are taken to happen at their
+//! deadline.
+
+use crate::time::{self, Duration, Instant};
+
+struct TickScheduler<Clock: time::Clock> {
+    period: Duration,
+    next_periodic: Clock::Instant,
+    armed: Option<Clock::Instant>,
+}
+
+impl<Clock: time::Clock> TickScheduler<Clock> {
+    fn new(period: Duration, current_time: Clock::Instant) -> Self {
+        let period = period.max(Duration::from_millis(1));
+        Self {
+            period,
+            next_periodic: current_time.checked_add(period).unwrap_or(current_time),
+            armed: None,
+        }
+    }
+
+    /// Ask for a tick at `deadline`, as well as the periodic ones. An earlier
+    /// armed deadline is kept.
+    fn arm(&mut self, deadline: Option<Clock::Instant>) {
+        if let Some(deadline) = deadline {
+            self.armed = Some(self.armed.map_or(deadline, |armed| armed.min(deadline)));
+        }
+    }
+
+    /// When the next tick is needed, to set a wakeup timer for.
+    fn next_wake(&self) -> Clock::Instant {
+        self.armed
+            .map_or(self.next_periodic, |armed| armed.min(self.next_periodic))
+    }
+
+    /// Whether to tick now. Missed periodic ticks are collapsed into one.
+    fn due(&mut self, current_time: Clock::Instant) -> bool {
+        let mut due = false;
+
+        if self.armed.is_some_and(|armed| current_time >= armed) {
+            self.armed = None;
+            due = true;
+        }
+
+        if current_time >= self.next_periodic {
+            let behind = current_time.duration_since(&self.next_periodic).as_micros()
+                / self.period.as_micros();
+            let skip = self.period.saturating_mul(behind + 1);
+            self.next_periodic = self.next_periodic.checked_add(skip).unwrap_or(current_time);
+            due = true;
+        }
+
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TickScheduler;
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{GlobalState, KeyEvent, State, StateId, Transition, TransitionCondition};
+
+    static REPEAT: State = State {
+        name: "repeat",
+        id: StateId(0),
+        transitions: &[&REPEAT_0],
+    };
+
+    static REPEAT_0: Transition = Transition {
+        conditions: &[TransitionCondition::ElapsedGreater(Duration::from_millis(
+            10,
+        ))],
+        key_event_emissions: &[KeyEvent::Press(4)],
+        internal_event_emissions: &[],
+        target: &REPEAT,
+    };
+
+    #[test]
+    fn schedule() {
+        let clock = TickerClock(0);
+        let mut schedule = TickScheduler::<TickerClock>::new(Duration::from_millis(5), clock.now());
+        assert_eq!(schedule.next_wake(), TickerClock(5).now());
+
+        schedule.arm(Some(TickerClock(3).now()));
+        schedule.arm(Some(TickerClock(4).now()));
+        assert_eq!(schedule.next_wake(), TickerClock(3).now());
+
+        assert!(!schedule.due(TickerClock(2).now()));
+        assert!(schedule.due(TickerClock(3).now()));
+        assert!(!schedule.due(TickerClock(4).now()));
+
+        // a late wakeup skips the missed periodic ticks
+        assert!(schedule.due(TickerClock(17).now()));
+        assert_eq!(schedule.next_wake(), TickerClock(20).now());
+    }
+
+    #[test]
+    fn coarse_ticks_dont_drift() {
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(REPEAT.as_dyn(), clock.now());
+        let mut presses = 0;
+
+        for _ in 0..33 {
+            clock.tick_n(3);
+            presses += state.tick(clock.now()).len();
+        }
+
+        // once for each 10ms up to 99ms, even though ticks only land on
+        // multiples of 3ms
+        assert_eq!(presses, 9);
+    }
+}
@@ -0,0 +1,187 @@
+//! Resuming where the keyboard left off across a reboot.
+//!
+//! A [`Session`] is what the keyboard was doing rather than how it's set up:
+//! the state each of a [`Keymap`]'s machines is in, the layers toggled on,
+//! the default layer and the flags worth keeping, see [`PERSISTED_FLAGS`].
+//! Firmware saves it through [`Storage`] on shutdown, or whenever power may
+//! be lost, and restores it at boot, so a layer or mode toggled on
+//! deliberately, such as game mode or a caps word lock, is still on. It's
+//! captured with `Keymap::session` and applied with `Keymap::resume_session`.
+//!
+//! Only settled states are resumed. A state waiting on a key or button to be
+//! released or on time passing since it was entered, such as a hold-tap
+//! waiting to resolve, can't be finished after a reboot, so its machine
+//! starts over in its initial state, as does a machine whose saved state no
+//! longer exists. Layers held by a momentary layer key aren't kept either.
+//!
+//! The record holds the default layer, the flags, the active layers as a
+//! little endian `u32` and then each machine's [`StateId`] as a little
+//! endian `u16`, at the version in [`SESSION_SCHEMA`].
+//!
+//! [`Keymap`]: crate::keymap::Keymap
+
+use crate::settings::PERSISTED_FLAGS;
+use crate::storage::{read_migrated, write_record, Schema, Storage, StorageError};
+use crate::{DynState, Layer, Layers, StateFlags, StateId, TransitionCondition};
+
+const SESSION_RECORD: u16 = 0x002;
+pub(crate) const SESSION_SCHEMA: Schema = Schema {
+    version: 1,
+    migrations: &[],
+};
+
+/// Whether `state` can be resumed after a reboot, which it can't if it's
+/// left on a release or a time since it was entered. Transitions back into
+/// the state itself don't count, they're how states follow other keys.
+pub(crate) fn settled(state: &dyn DynState) -> bool {
+    use TransitionCondition as C;
+
+    let mut leaving = state
+        .transitions()
+        .iter()
+        .filter(|transition| transition.target.id != state.id());
+    !leaving.any(|transition| {
+        transition.conditions.iter().any(|condition| {
+            matches!(
+                condition,
+                C::Depressed(_)
+                    | C::PointerButtonReleased(_)
+                    | C::AllReleased(_)
+                    | C::AllButtonsReleased(_)
+                    | C::ElapsedLess(_)
+                    | C::ElapsedGreater(_)
+                    | C::ElapsedLessTunable(_)
+                    | C::ElapsedGreaterTunable(_)
+                    | C::ElapsedLessMicros(_)
+                    | C::ElapsedGreaterMicros(_)
+            )
+        })
+    })
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct Session<const MACHINES: usize> {
+    pub(crate) default_layer: Layer,
+    pub(crate) flags: StateFlags,
+    pub(crate) layers: Layers,
+    pub(crate) states: [StateId; MACHINES],
+}
+
+impl<const MACHINES: usize> Session<MACHINES> {
+    fn encode(&self, out: &mut [u8]) -> Option<usize> {
+        let len = 6 + MACHINES * 2;
+        let out = out.get_mut(..len)?;
+
+        out[0] = self.default_layer;
+        out[1] = self.flags.bits();
+        out[2..6].copy_from_slice(&self.layers.0.to_le_bytes());
+        for (chunk, state) in out[6..].chunks_exact_mut(2).zip(self.states) {
+            chunk.copy_from_slice(&state.0.to_le_bytes());
+        }
+
+        Some(len)
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let [default_layer, flags, a, b, c, d, states @ ..] = bytes else {
+            return None;
+        };
+        if states.len() != MACHINES * 2 {
+            return None;
+        }
+
+        let mut chunks = states.chunks_exact(2);
+        Some(Self {
+            default_layer: *default_layer,
+            flags: StateFlags::from_bits_truncate(*flags) & PERSISTED_FLAGS,
+            layers: Layers(u32::from_le_bytes([*a, *b, *c, *d])),
+            states: [(); MACHINES].map(|_| {
+                let chunk = chunks.next().unwrap();
+                StateId(u16::from_le_bytes([chunk[0], chunk[1]]))
+            }),
+        })
+    }
+
+    fn save<S: Storage>(
+        &self,
+        storage: &mut S,
+        buf: &mut [u8],
+    ) -> Result<(), StorageError<S::Error>> {
+        write_record(
+            storage,
+            SESSION_RECORD,
+            SESSION_SCHEMA.version,
+            buf,
+            |out| self.encode(out),
+        )
+    }
+
+    /// Returns `None` if no session was saved.
+    fn restore<S: Storage>(
+        storage: &mut S,
+        buf: &mut [u8],
+    ) -> Result<Option<Self>, StorageError<S::Error>> {
+        match read_migrated(storage, SESSION_RECORD, &SESSION_SCHEMA, buf)? {
+            None => Ok(None),
+            Some(data) => Self::decode(data).map(Some).ok_or(StorageError::Malformed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{settled, Session};
+    use crate::behaviors::hold_tap;
+    use crate::storage::tests::MemoryStorage;
+    use crate::storage::StorageError;
+    use crate::time::Duration;
+    use crate::{Layers, StateFlags, StateId};
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut storage = MemoryStorage::default();
+        let mut buf = [0; 16];
+        let mut layers = Layers::empty();
+        layers.activate(3);
+        let session = Session {
+            default_layer: 1,
+            flags: StateFlags::GAME_MODE,
+            layers,
+            states: [StateId(0), StateId(0x102)],
+        };
+
+        assert_eq!(Session::<2>::restore(&mut storage, &mut buf), Ok(None));
+
+        session.save(&mut storage, &mut buf).unwrap();
+        assert_eq!(
+            storage.0[&0x002],
+            [1, 1, 0b01000, 0b1000, 0, 0, 0, 0, 0, 2, 1]
+        );
+        assert_eq!(
+            Session::<2>::restore(&mut storage, &mut buf),
+            Ok(Some(session))
+        );
+        assert_eq!(
+            Session::<1>::restore(&mut storage, &mut buf),
+            Err(StorageError::Malformed)
+        );
+    }
+
+    #[test]
+    fn settles() {
+        // following other keys' releases
+        assert!(settled(home_a::IDLE.as_dyn()));
+        // waiting on the tapping term, and for the key's release
+        assert!(!settled(home_a::UNDECIDED.as_dyn()));
+        assert!(!settled(home_a::HOLD.as_dyn()));
+    }
+}
@@ -0,0 +1,216 @@
+//! Settings persisted through [`Storage`].
+//!
+//! [`Settings`] holds what a user would expect to survive a power cycle: the
+//! default layer, flags that are toggled rather than held, and any tapping
+//! terms tuned at runtime through [`TunableTerm`]s. It is captured from and
+//! applied to a [`Keymap`](crate::keymap::Keymap) with
+//! `Keymap::settings` and `Keymap::apply_settings`.
+//!
+//! The record holds the default layer, the flags, and then each term in
+//! milliseconds as a little endian `u16`, in a record of the version in
+//! [`SETTINGS_SCHEMA`]. Firmware that changes what's saved, such as by
+//! adding a term, saves and restores with a [`Schema`] of its own with
+//! [`Settings::save_with`] and [`Settings::restore_with`], giving a later
+//! version and a [`Migration`](crate::storage::Migration) from the one
+//! before, so the settings an update finds are upgraded rather than lost.
+
+use crate::storage::{read_migrated, write_record, Schema, Storage, StorageError};
+use crate::time::Duration;
+use crate::{Layer, StateFlags, TunableTerm};
+
+const SETTINGS_RECORD: u16 = 0x001;
+pub(crate) const SETTINGS_SCHEMA: Schema = Schema {
+    version: 1,
+    migrations: &[],
+};
+
+/// Flags worth keeping across a power cycle, the rest only make sense while
+/// keys are held.
+pub(crate) const PERSISTED_FLAGS: StateFlags = StateFlags::STICKY_KEYS.union(StateFlags::GAME_MODE);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct Settings<const TERMS: usize> {
+    pub(crate) default_layer: Layer,
+    pub(crate) flags: StateFlags,
+    pub(crate) terms: [Duration; TERMS],
+}
+
+impl<const TERMS: usize> Settings<TERMS> {
+    pub(crate) fn apply_terms(&self, terms: &[&TunableTerm; TERMS]) {
+        for (term, value) in terms.iter().zip(self.terms) {
+            term.set(value);
+        }
+    }
+
+    fn encode(&self, out: &mut [u8]) -> Option<usize> {
+        let len = 2 + TERMS * 2;
+        let out = out.get_mut(..len)?;
+
+        out[0] = self.default_layer;
+        out[1] = self.flags.bits();
+        for (chunk, term) in out[2..].chunks_exact_mut(2).zip(self.terms) {
+            chunk.copy_from_slice(&(term.as_millis().min(u16::MAX as u32) as u16).to_le_bytes());
+        }
+
+        Some(len)
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let [default_layer, flags, terms @ ..] = bytes else {
+            return None;
+        };
+        if terms.len() != TERMS * 2 {
+            return None;
+        }
+
+        let mut chunks = terms.chunks_exact(2);
+        Some(Self {
+            default_layer: *default_layer,
+            flags: StateFlags::from_bits_truncate(*flags) & PERSISTED_FLAGS,
+            terms: [(); TERMS].map(|_| {
+                let chunk = chunks.next().unwrap();
+                Duration::from_millis(u16::from_le_bytes([chunk[0], chunk[1]]) as u32)
+            }),
+        })
+    }
+
+    fn save<S: Storage>(
+        &self,
+        storage: &mut S,
+        buf: &mut [u8],
+    ) -> Result<(), StorageError<S::Error>> {
+        self.save_with(&SETTINGS_SCHEMA, storage, buf)
+    }
+
+    fn save_with<S: Storage>(
+        &self,
+        schema: &Schema,
+        storage: &mut S,
+        buf: &mut [u8],
+    ) -> Result<(), StorageError<S::Error>> {
+        write_record(storage, SETTINGS_RECORD, schema.version, buf, |out| {
+            self.encode(out)
+        })
+    }
+
+    /// Returns `None` if no settings were saved.
+    fn restore<S: Storage>(
+        storage: &mut S,
+        buf: &mut [u8],
+    ) -> Result<Option<Self>, StorageError<S::Error>> {
+        Self::restore_with(&SETTINGS_SCHEMA, storage, buf)
+    }
+
+    /// Restore settings saved at `schema`'s version or, upgrading them, at
+    /// an earlier one.
+    fn restore_with<S: Storage>(
+        schema: &Schema,
+        storage: &mut S,
+        buf: &mut [u8],
+    ) -> Result<Option<Self>, StorageError<S::Error>> {
+        match read_migrated(storage, SETTINGS_RECORD, schema, buf)? {
+            None => Ok(None),
+            Some(data) => Self::decode(data).map(Some).ok_or(StorageError::Malformed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Settings;
+    use crate::storage::tests::MemoryStorage;
+    use crate::storage::{Schema, StorageError};
+    use crate::time::Duration;
+    use crate::StateFlags;
+
+    #[test]
+    fn round_trip() {
+        let mut storage = MemoryStorage::default();
+        let mut buf = [0; 16];
+        let settings = Settings {
+            default_layer: 2,
+            flags: StateFlags::GAME_MODE,
+            terms: [Duration::from_millis(200), Duration::from_millis(1000)],
+        };
+
+        assert_eq!(Settings::<2>::restore(&mut storage, &mut buf), Ok(None));
+
+        settings.save(&mut storage, &mut buf).unwrap();
+        assert_eq!(storage.0[&0x001], [1, 2, 0b01000, 200, 0, 0xe8, 0x03]);
+        assert_eq!(
+            Settings::<2>::restore(&mut storage, &mut buf),
+            Ok(Some(settings))
+        );
+
+        // a different number of terms doesn't fit the record
+        assert_eq!(
+            Settings::<1>::restore(&mut storage, &mut buf),
+            Err(StorageError::Malformed)
+        );
+        assert_eq!(
+            settings.save(&mut storage, &mut [0; 4]),
+            Err(StorageError::BufferTooSmall)
+        );
+    }
+
+    /// A later firmware with a third term, which old settings get at 300ms.
+    static THREE_TERMS: Schema = Schema {
+        version: 2,
+        migrations: &[|buf, len| {
+            buf.get_mut(len..len + 2)?
+                .copy_from_slice(&300_u16.to_le_bytes());
+            Some(len + 2)
+        }],
+    };
+
+    #[test]
+    fn migration() {
+        let mut storage = MemoryStorage::default();
+        let mut buf = [0; 16];
+        let old = Settings {
+            default_layer: 1,
+            flags: StateFlags::STICKY_KEYS,
+            terms: [Duration::from_millis(200), Duration::from_millis(150)],
+        };
+        old.save(&mut storage, &mut buf).unwrap();
+
+        let upgraded = Settings {
+            default_layer: 1,
+            flags: StateFlags::STICKY_KEYS,
+            terms: [
+                Duration::from_millis(200),
+                Duration::from_millis(150),
+                Duration::from_millis(300),
+            ],
+        };
+        assert_eq!(
+            Settings::<3>::restore_with(&THREE_TERMS, &mut storage, &mut buf),
+            Ok(Some(upgraded))
+        );
+        // and written back at the new version
+        assert_eq!(storage.0[&0x001][0], 2);
+        assert_eq!(
+            Settings::<3>::restore_with(&THREE_TERMS, &mut storage, &mut buf),
+            Ok(Some(upgraded))
+        );
+
+        // nothing upgrades a record from a later firmware
+        assert_eq!(
+            Settings::<2>::restore(&mut storage, &mut buf),
+            Err(StorageError::UnknownVersion(2))
+        );
+        // or one it has no migration for
+        let skipping = Schema {
+            version: 3,
+            migrations: &[],
+        };
+        assert_eq!(
+            Settings::<3>::restore_with(&skipping, &mut storage, &mut buf),
+            Err(StorageError::UnknownVersion(2))
+        );
+        assert_eq!(
+            Settings::<3>::restore_with(&THREE_TERMS, &mut storage, &mut [0; 4]),
+            Err(StorageError::Storage(()))
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Sharing a machine between interrupt handlers and the main loop.
+//!
+//! [`SharedMachine`] can live in a `static`. Interrupt handlers call
+//! [`SharedMachine::enqueue`] as keys change, and the main loop calls
+//! [`SharedMachine::process`] to feed the queued events to the machine and
+//! tick it. All access happens inside a critical section, supplied by the
+//! [`CriticalSection`] implementation for the target, so no `static mut` is
+//! needed.
+//!
+//! Where critical sections are costly, [`MpscQueue`](crate::mpsc::MpscQueue)
+//! takes events without one and leaves the machine to the main loop.
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+
+use crate::time::{self, Instant};
+use crate::validate::MAX_EMISSIONS;
+use crate::{GlobalState, KeyEvent, TimedEvent};
+
+/// Runs closures with interrupts that could touch the shared state masked,
+/// shaped like `critical_section::with`.
+///
+/// # Safety
+///
+/// While `f` runs, no other call to `with` of the same implementation may be
+/// running `f`, on any core or in any interrupt handler. [`SharedMachine`] is
+/// only `Sync` because of this.
+unsafe trait CriticalSection {
+    fn with<R>(f: impl FnOnce() -> R) -> R;
+}
+
+struct Inner<Clock: time::Clock, const N: usize> {
+    machine: Option<GlobalState<Clock>>,
+    queue: [Option<TimedEvent<Clock>>; N],
+    head: usize,
+    len: usize,
+    /// The latest time given to the machine, events are never pushed earlier
+    /// than this even if they were stamped before the last tick.
+    last: Option<Clock::Instant>,
+    /// How many of the machine's last emissions have been handed out.
+    sent: usize,
+}
+
+impl<Clock: time::Clock, const N: usize> Inner<Clock, N> {
+    /// Copy the next of the machine's emissions not yet handed out to `out`,
+    /// once they all have been pushing the next queued event or, when the
+    /// queue is empty, ticking the machine if `ticked` isn't set yet. Returns
+    /// how many were copied, `0` once there's nothing left to do.
+    fn drain(
+        &mut self,
+        current_time: Clock::Instant,
+        ticked: &mut bool,
+        out: &mut [KeyEvent],
+    ) -> usize {
+        let Some(machine) = self.machine.as_mut() else {
+            return 0;
+        };
+
+        while self.sent == machine.emitted().len() {
+            if self.len > 0 {
+                let Some(event) = self.queue[self.head].take() else {
+                    return 0;
+                };
+                self.head = (self.head + 1) % N;
+                self.len -= 1;
+
+                let time = self.last.map_or(event.time, |last| last.max(event.time));
+                self.last = Some(time);
+                machine.push(time, event.event);
+                self.sent = 0;
+            } else if !*ticked {
+                *ticked = true;
+                let time = self
+                    .last
+                    .map_or(current_time, |last| last.max(current_time));
+                self.last = Some(time);
+                machine.tick(time);
+                self.sent = 0;
+            } else {
+                return 0;
+            }
+        }
+
+        let emitted = &machine.emitted()[self.sent..];
+        let len = emitted.len().min(out.len());
+        out[..len].copy_from_slice(&emitted[..len]);
+        self.sent += len;
+        len
+    }
+}
+
+struct SharedMachine<Clock: time::Clock, CS: CriticalSection, const N: usize> {
+    inner: UnsafeCell<Inner<Clock, N>>,
+    _cs: PhantomData<CS>,
+}
+
+// SAFETY: `inner` is only accessed from within `CS::with`, which excludes
+// every other context that could access it.
+unsafe impl<Clock: time::Clock, CS: CriticalSection, const N: usize> Sync
+    for SharedMachine<Clock, CS, N>
+{
+}
+
+impl<Clock: time::Clock, CS: CriticalSection, const N: usize> SharedMachine<Clock, CS, N> {
+    /// An empty machine, events queue up until [`SharedMachine::init`] is called.
+    const fn new() -> Self {
+        Self {
+            inner: UnsafeCell::new(Inner {
+                machine: None,
+                queue: [const { None }; N],
+                head: 0,
+                len: 0,
+                last: None,
+                sent: 0,
+            }),
+            _cs: PhantomData,
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut Inner<Clock, N>) -> R) -> R {
+        // SAFETY: see the `Sync` impl, the reference doesn't outlive the
+        // critical section.
+        CS::with(|| f(unsafe { &mut *self.inner.get() }))
+    }
+
+    fn init(&self, machine: GlobalState<Clock>) {
+        self.with(|inner| {
+            inner.machine = Some(machine);
+            inner.sent = 0;
+        });
+    }
+
+    /// Queue an event, handing it back if the queue is full.
+    fn enqueue(&self, event: TimedEvent<Clock>) -> Result<(), TimedEvent<Clock>> {
+        self.with(|inner| {
+            if inner.len == N {
+                return Err(event);
+            }
+
+            inner.queue[(inner.head + inner.len) % N] = Some(event);
+            inner.len += 1;
+            Ok(())
+        })
+    }
+
+    /// Feed the queued events to the machine in order and then tick it.
+    ///
+    /// `emit` runs outside the critical section, a few events at a time, so
+    /// it may enqueue more events, which are pushed before the tick.
+    fn process(&self, current_time: Clock::Instant, mut emit: impl FnMut(KeyEvent)) {
+        let mut ticked = false;
+        let mut out = [KeyEvent::Press(0); MAX_EMISSIONS];
+        loop {
+            let len = self.with(|inner| inner.drain(current_time, &mut ticked, &mut out));
+            if len == 0 {
+                return;
+            }
+            out[..len].iter().for_each(|e| emit(*e));
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Mutex;
+
+    use super::{CriticalSection, SharedMachine};
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{GlobalState, InputEvent, KeyEvent, TimedEvent};
+
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    /// Stands in for masking interrupts on the host.
+    pub(crate) struct TestCriticalSection;
+
+    // SAFETY: every call holds the same lock.
+    unsafe impl CriticalSection for TestCriticalSection {
+        fn with<R>(f: impl FnOnce() -> R) -> R {
+            let _guard = LOCK.lock().unwrap();
+            f()
+        }
+    }
+
+    crate::behaviors::turbo_key! {
+        mod turbo {
+            key: 3,
+            output: 4,
+            rate: Duration::from_millis(5),
+        }
+    }
+
+    static MACHINE: SharedMachine<TickerClock, TestCriticalSection, 3> = SharedMachine::new();
+    static REENTERED: SharedMachine<TickerClock, TestCriticalSection, 3> = SharedMachine::new();
+
+    #[test]
+    fn queue_from_another_thread() {
+        let mut clock = TickerClock(0);
+        let at = |ticks, event| TimedEvent {
+            time: TickerClock(ticks).now(),
+            event,
+        };
+
+        // queued before init and kept
+        MACHINE.enqueue(at(0, InputEvent::Press(3))).unwrap();
+        MACHINE.init(GlobalState::new(turbo::IDLE.as_dyn(), clock.now()));
+
+        std::thread::spawn(move || {
+            MACHINE.enqueue(at(2, InputEvent::Depress(3))).unwrap();
+            assert!(MACHINE.enqueue(at(3, InputEvent::Press(3))).is_ok());
+            assert!(MACHINE.enqueue(at(4, InputEvent::Depress(3))).is_err());
+        })
+        .join()
+        .unwrap();
+
+        let mut emitted = Vec::new();
+        clock.tick_n(10);
+        MACHINE.process(clock.now(), |e| emitted.push(e));
+        assert_eq!(
+            emitted,
+            [
+                KeyEvent::Press(4),
+                KeyEvent::Depress(4),
+                KeyEvent::Press(4),
+                KeyEvent::Depress(4),
+                // repeated by the tick
+                KeyEvent::Press(4),
+                KeyEvent::Depress(4),
+            ]
+        );
+
+        // stamped before the last tick, pushed at the time of it instead
+        MACHINE.enqueue(at(1, InputEvent::Depress(3))).unwrap();
+        MACHINE.process(clock.now(), |e| emitted.push(e));
+        assert_eq!(emitted.len(), 6);
+    }
+
+    #[test]
+    fn emit_outside_critical_section() {
+        let mut clock = TickerClock(0);
+        REENTERED.init(GlobalState::new(turbo::IDLE.as_dyn(), clock.now()));
+        REENTERED
+            .enqueue(TimedEvent {
+                time: clock.now(),
+                event: InputEvent::Press(3),
+            })
+            .unwrap();
+
+        // the lock isn't held as events are emitted, so emitting can enqueue
+        clock.tick_n(2);
+        let mut emitted = Vec::new();
+        REENTERED.process(clock.now(), |e| {
+            if e == KeyEvent::Press(4) && emitted.is_empty() {
+                REENTERED
+                    .enqueue(TimedEvent {
+                        time: clock.now(),
+                        event: InputEvent::Depress(3),
+                    })
+                    .unwrap();
+            }
+            emitted.push(e);
+        });
+        assert_eq!(emitted, [KeyEvent::Press(4), KeyEvent::Depress(4)]);
+
+        // and the release was pushed, so it doesn't repeat
+        clock.tick_n(10);
+        REENTERED.process(clock.now(), |e| emitted.push(e));
+        assert_eq!(emitted.len(), 2);
+    }
+}
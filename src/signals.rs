@@ -0,0 +1,150 @@
+//! Conditions on inputs other than keys.
+//!
+//! Sensors such as an ambient light sensor, a tilt switch, a presence
+//! sensor or the time of day are read through a [`SignalSource`] the
+//! integrator implements, as up to [`MAX_SIGNALS`] numbered channels of
+//! `i16`. Switches read as `0` for off and anything else for on. Their
+//! readings are taken with [`GlobalState::poll_signals`], or
+//! [`Keymap::poll_signals`] for every machine of a keymap, which then steps
+//! the machine as a tick so that transitions on
+//! [`TransitionCondition::SignalOn`], [`TransitionCondition::SignalAbove`]
+//! and the like are taken:
+//!
+//! ```ignore
+//! struct Desk;
+//!
+//! impl SignalSource for Desk {
+//!     fn read(&mut self, channel: u8) -> Option<i16> {
+//!         (channel == PRESENCE).then(|| presence_sensor_detects() as i16)
+//!     }
+//! }
+//!
+//! // lock when whoever sits here walks away
+//! static AWAY: Transition = Transition {
+//!     conditions: &[TransitionCondition::SignalOff(PRESENCE)],
+//!     internal_event_emissions: &[InternalEvent::ActivateLayer(LOCK)],
+//!     ...
+//! };
+//!
+//! machine.poll_signals(now, &mut Desk);
+//! ```
+//!
+//! Channels a source has no reading for keep their last one, which is `0`
+//! until a reading comes.
+//!
+//! [`GlobalState::poll_signals`]: crate::GlobalState::poll_signals
+//! [`Keymap::poll_signals`]: crate::keymap::Keymap::poll_signals
+//! [`TransitionCondition::SignalOn`]: crate::TransitionCondition::SignalOn
+//! [`TransitionCondition::SignalAbove`]: crate::TransitionCondition::SignalAbove
+
+pub(crate) const MAX_SIGNALS: usize = 8;
+
+pub(crate) trait SignalSource {
+    /// The current reading of `channel`, or `None` if there isn't one.
+    fn read(&mut self, channel: u8) -> Option<i16>;
+}
+
+/// The last reading of each channel.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct Signals(pub(crate) [i16; MAX_SIGNALS]);
+
+impl Signals {
+    /// The last reading of `channel`, `0` for channels past the last.
+    pub(crate) fn get(&self, channel: u8) -> i16 {
+        self.0.get(channel as usize).copied().unwrap_or(0)
+    }
+
+    /// Take a new reading of every channel `source` has one for.
+    pub(crate) fn poll(&mut self, source: &mut impl SignalSource) {
+        for (channel, value) in self.0.iter_mut().enumerate() {
+            if let Some(reading) = source.read(channel as u8) {
+                *value = reading;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SignalSource;
+    use crate::tests::TickerClock;
+    use crate::{GlobalState, KeyEvent, State, StateId, Transition, TransitionCondition};
+
+    const LIGHT: u8 = 0;
+    const PRESENT: u8 = 1;
+
+    struct Sensors {
+        light: i16,
+        present: Option<bool>,
+    }
+
+    impl SignalSource for Sensors {
+        fn read(&mut self, channel: u8) -> Option<i16> {
+            match channel {
+                LIGHT => Some(self.light),
+                PRESENT => self.present.map(i16::from),
+                _ => None,
+            }
+        }
+    }
+
+    static HERE: State = State {
+        name: "HERE",
+        id: StateId(0),
+        transitions: &[&LEAVE],
+    };
+
+    static AWAY: State = State {
+        name: "AWAY",
+        id: StateId(1),
+        transitions: &[&RETURN],
+    };
+
+    static LEAVE: Transition = Transition {
+        conditions: &[TransitionCondition::SignalOff(PRESENT)],
+        key_event_emissions: &[KeyEvent::Press(1)],
+        internal_event_emissions: &[],
+        target: &AWAY,
+    };
+
+    // back, and with the lights on
+    static RETURN: Transition = Transition {
+        conditions: &[
+            TransitionCondition::SignalOn(PRESENT),
+            TransitionCondition::SignalAbove(LIGHT, 200),
+        ],
+        key_event_emissions: &[KeyEvent::Depress(1)],
+        internal_event_emissions: &[],
+        target: &HERE,
+    };
+
+    #[test]
+    fn signals() {
+        let mut clock = TickerClock(0);
+        let mut machine = GlobalState::<TickerClock>::new(HERE.as_dyn(), clock.now());
+        let mut sensors = Sensors {
+            light: 100,
+            present: Some(true),
+        };
+
+        assert_eq!(machine.poll_signals(clock.now(), &mut sensors), []);
+        sensors.present = Some(false);
+        clock.tick();
+        assert_eq!(
+            machine.poll_signals(clock.now(), &mut sensors),
+            [KeyEvent::Press(1)]
+        );
+
+        sensors.present = Some(true);
+        assert_eq!(machine.poll_signals(clock.now(), &mut sensors), []);
+        // no reading keeps the last one
+        sensors.present = None;
+        sensors.light = 300;
+        assert_eq!(
+            machine.poll_signals(clock.now(), &mut sensors),
+            [KeyEvent::Depress(1)]
+        );
+        assert_eq!(machine.signals.get(PRESENT), 1);
+        assert_eq!(machine.signals.get(200), 0);
+    }
+}
@@ -0,0 +1,121 @@
+//! Ordering events that happened at the same instant.
+//!
+//! A matrix scan can find several keys changed at once, and the order they
+//! are then pushed in decides which of a roll or a combo a machine sees.
+//! Rather than leaving that to however an integration walks its matrix,
+//! [`order_simultaneous`] sorts each run of events with the same time by a
+//! [`SimultaneousOrder`], so the same scan always reaches the machines the
+//! same way. Events at different times keep their order, as do events other
+//! than presses and releases, which go after the key edges at their instant.
+
+use crate::time;
+use crate::{InputEvent, TimedEvent};
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum SimultaneousOrder {
+    /// In the order they were given.
+    AsGiven,
+    /// Releases before presses, each in order of key, so that a key released
+    /// and another pressed in the same scan read as a roll.
+    #[default]
+    ReleasesFirst,
+    /// Presses before releases, each in order of key.
+    PressesFirst,
+}
+
+impl SimultaneousOrder {
+    fn rank(self, event: InputEvent) -> (u8, u8) {
+        let (press, release) = match self {
+            SimultaneousOrder::PressesFirst => (0, 1),
+            _ => (1, 0),
+        };
+        match event {
+            InputEvent::Press(key) => (press, key),
+            InputEvent::Depress(key) => (release, key),
+            _ => (2, 0),
+        }
+    }
+}
+
+/// Sort each run of `events` with the same time by `order`.
+pub(crate) fn order_simultaneous<Clock: time::Clock>(
+    order: SimultaneousOrder,
+    events: &mut [TimedEvent<Clock>],
+) {
+    if order == SimultaneousOrder::AsGiven {
+        return;
+    }
+    for run in events.chunk_by_mut(|a, b| a.time == b.time) {
+        run.sort_by_key(|e| order.rank(e.event));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{order_simultaneous, SimultaneousOrder};
+    use crate::tests::TickerClock;
+    use crate::{InputEvent, TimedEvent};
+
+    fn events(order: SimultaneousOrder) -> Vec<InputEvent> {
+        let mut clock = TickerClock(0);
+        let mut events: Vec<TimedEvent<TickerClock>> = Vec::new();
+        for event in [
+            InputEvent::Press(5),
+            InputEvent::Application(1),
+            InputEvent::Depress(7),
+            InputEvent::Press(2),
+            InputEvent::Depress(3),
+        ] {
+            events.push(TimedEvent {
+                time: clock.now(),
+                event,
+            });
+        }
+        clock.tick();
+        for event in [InputEvent::Press(1), InputEvent::Depress(2)] {
+            events.push(TimedEvent {
+                time: clock.now(),
+                event,
+            });
+        }
+
+        order_simultaneous(order, &mut events);
+        events.iter().map(|e| e.event).collect()
+    }
+
+    #[test]
+    fn orders() {
+        assert_eq!(
+            events(SimultaneousOrder::ReleasesFirst),
+            [
+                InputEvent::Depress(3),
+                InputEvent::Depress(7),
+                InputEvent::Press(2),
+                InputEvent::Press(5),
+                InputEvent::Application(1),
+                InputEvent::Depress(2),
+                InputEvent::Press(1),
+            ]
+        );
+        assert_eq!(
+            events(SimultaneousOrder::PressesFirst),
+            [
+                InputEvent::Press(2),
+                InputEvent::Press(5),
+                InputEvent::Depress(3),
+                InputEvent::Depress(7),
+                InputEvent::Application(1),
+                InputEvent::Press(1),
+                InputEvent::Depress(2),
+            ]
+        );
+        assert_eq!(
+            events(SimultaneousOrder::AsGiven)[..3],
+            [
+                InputEvent::Press(5),
+                InputEvent::Application(1),
+                InputEvent::Depress(7),
+            ]
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Text expansion.
+//!
+//! [`Snippets`] sits on the output side, following what's typed with an
+//! [`OutputHistory`]. When a terminator, such as space or enter, is pressed
+//! straight after a word that's one of the triggers, the word is deleted
+//! with backspaces and the snippet's expansion typed in its place, before
+//! the terminator itself is sent:
+//!
+//! ```ignore
+//! static SNIPPETS: [Snippet; 1] = [Snippet {
+//!     // b t w
+//!     trigger: &[0x05, 0x17, 0x1a],
+//!     expansion: &[shifted(0x05), key(0x17), key(0x1a)],
+//! }];
+//!
+//! let mut snippets = Snippets::<16>::new(&SNIPPETS, &[SPACE, ENTER]);
+//! machine_output(|event| snippets.output(event, |event| send(event)));
+//! ```
+//!
+//! Triggers are whole words, so a trigger typed at the end of a longer word
+//! isn't expanded. They're key codes, without modifiers, and the expansion
+//! is in the key codes of the host's layout.
+
+use crate::history::OutputHistory;
+use crate::host_layout::Stroke;
+use crate::{KeyCode, KeyEvent};
+
+const BACKSPACE: KeyCode = 0x2a;
+
+#[derive(Debug)]
+pub(crate) struct Snippet {
+    pub(crate) trigger: &'static [KeyCode],
+    pub(crate) expansion: &'static [Stroke],
+}
+
+pub(crate) struct Snippets<const N: usize> {
+    snippets: &'static [Snippet],
+    /// Keys that end a word, and so can expand the one before them.
+    terminators: &'static [KeyCode],
+    history: OutputHistory<N>,
+}
+
+impl<const N: usize> Snippets<N> {
+    /// Expand `snippets` when any of `terminators` is pressed after their
+    /// triggers. Triggers longer than `N` are never noticed.
+    pub(crate) const fn new(snippets: &'static [Snippet], terminators: &'static [KeyCode]) -> Self {
+        Self {
+            snippets,
+            terminators,
+            history: OutputHistory::new(),
+        }
+    }
+
+    /// The snippet whose trigger is the word just typed.
+    fn matching(&self) -> Option<&'static Snippet> {
+        self.snippets.iter().find(|snippet| {
+            !snippet.trigger.is_empty()
+                && self.history.ends_with(snippet.trigger)
+                && self
+                    .history
+                    .starts_word(snippet.trigger.len(), self.terminators)
+        })
+    }
+
+    /// Emit `event` as it should be sent to the host, expanding the word
+    /// before it first if it's a terminator.
+    pub(crate) fn output(&mut self, event: KeyEvent, mut emit: impl FnMut(KeyEvent)) {
+        let expanding = match event {
+            KeyEvent::Press(key) if self.terminators.contains(&key) => self.matching(),
+            _ => None,
+        };
+
+        if let Some(snippet) = expanding {
+            for _ in snippet.trigger {
+                self.send(KeyEvent::Press(BACKSPACE), &mut emit);
+                self.send(KeyEvent::Depress(BACKSPACE), &mut emit);
+            }
+            for stroke in snippet.expansion {
+                stroke.tap(|event| self.send(event, &mut emit));
+            }
+        }
+        self.send(event, &mut emit);
+    }
+
+    fn send(&mut self, event: KeyEvent, emit: &mut impl FnMut(KeyEvent)) {
+        self.history.observe(event);
+        emit(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Snippet, Snippets};
+    use crate::host_layout::{key, shifted};
+    use crate::KeyEvent;
+
+    const SPACE: u8 = 0x2c;
+
+    static SNIPPETS: [Snippet; 2] = [
+        Snippet {
+            // b t w
+            trigger: &[0x05, 0x17, 0x1a],
+            expansion: &[shifted(0x05), key(0x17), key(0x1a)],
+        },
+        Snippet {
+            // t y
+            trigger: &[0x17, 0x1c],
+            expansion: &[
+                key(0x17),
+                key(0x0b),
+                key(0x04),
+                key(0x11),
+                key(0x0e),
+                key(0x16),
+            ],
+        },
+    ];
+
+    fn typed(snippets: &mut Snippets<8>, keys: &[u8]) -> Vec<KeyEvent> {
+        let mut out = Vec::new();
+        for &key in keys {
+            snippets.output(KeyEvent::Press(key), |e| out.push(e));
+            snippets.output(KeyEvent::Depress(key), |e| out.push(e));
+        }
+        out
+    }
+
+    #[test]
+    fn expands() {
+        let mut snippets = Snippets::<8>::new(&SNIPPETS, &[SPACE]);
+
+        typed(&mut snippets, &[0x05, 0x17, 0x1a]);
+        assert_eq!(
+            typed(&mut snippets, &[SPACE]),
+            [
+                KeyEvent::Press(0x2a),
+                KeyEvent::Depress(0x2a),
+                KeyEvent::Press(0x2a),
+                KeyEvent::Depress(0x2a),
+                KeyEvent::Press(0x2a),
+                KeyEvent::Depress(0x2a),
+                KeyEvent::Press(0xe1),
+                KeyEvent::Press(0x05),
+                KeyEvent::Depress(0x05),
+                KeyEvent::Depress(0xe1),
+                KeyEvent::Press(0x17),
+                KeyEvent::Depress(0x17),
+                KeyEvent::Press(0x1a),
+                KeyEvent::Depress(0x1a),
+                KeyEvent::Press(SPACE),
+                KeyEvent::Depress(SPACE),
+            ]
+        );
+
+        // the end of a longer word isn't a trigger
+        typed(&mut snippets, &[0x04, 0x17, 0x1c]);
+        assert_eq!(typed(&mut snippets, &[SPACE]).len(), 2);
+
+        // but a word put right with backspace is
+        let out = typed(&mut snippets, &[0x17, 0x04, 0x2a, 0x1c, SPACE]);
+        assert_eq!(out.len(), 2 * 5 + 2 * (2 + 6));
+        assert_eq!(out[8], KeyEvent::Press(0x2a));
+    }
+}
@@ -0,0 +1,174 @@
+//! Simultaneous Opposite Cardinal Direction cleaning.
+//!
+//! [`Socd`] rewrites the key events for configured pairs of opposing keys so
+//! that the host never sees both held at once.
+
+use crate::{KeyCode, KeyEvent};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum SocdMode {
+    /// The most recently pressed key wins, the other one resumes when it is
+    /// released.
+    LastInput,
+    /// Holding both acts as holding neither.
+    Neutral,
+    /// The key pressed first wins until it is released.
+    FirstWins,
+}
+
+#[derive(Clone, Copy)]
+struct SocdPair {
+    keys: [KeyCode; 2],
+    mode: SocdMode,
+    held: [bool; 2],
+    /// Which key of the pair the host currently sees held.
+    active: Option<usize>,
+}
+
+impl SocdPair {
+    fn press(&mut self, side: usize, emit: &mut impl FnMut(KeyEvent)) {
+        self.active = Some(side);
+        emit(KeyEvent::Press(self.keys[side]));
+    }
+
+    fn release(&mut self, emit: &mut impl FnMut(KeyEvent)) {
+        if let Some(active) = self.active.take() {
+            emit(KeyEvent::Depress(self.keys[active]));
+        }
+    }
+}
+
+struct Socd<const N: usize> {
+    pairs: [SocdPair; N],
+}
+
+impl<const N: usize> Socd<N> {
+    const fn new(pairs: [(KeyCode, KeyCode, SocdMode); N]) -> Self {
+        let mut socd = [SocdPair {
+            keys: [0, 0],
+            mode: SocdMode::LastInput,
+            held: [false; 2],
+            active: None,
+        }; N];
+
+        let mut i = 0;
+        while i < N {
+            socd[i].keys = [pairs[i].0, pairs[i].1];
+            socd[i].mode = pairs[i].2;
+            i += 1;
+        }
+
+        Self { pairs: socd }
+    }
+
+    fn process(&mut self, event: KeyEvent, mut emit: impl FnMut(KeyEvent)) {
+        let (key, pressed) = match event {
+            KeyEvent::Press(key) => (key, true),
+            KeyEvent::Depress(key) => (key, false),
+            event => return emit(event),
+        };
+
+        let Some((pair, side)) = self.pairs.iter_mut().find_map(|p| {
+            let side = p.keys.iter().position(|k| *k == key)?;
+            Some((p, side))
+        }) else {
+            return emit(event);
+        };
+
+        let other = 1 - side;
+        pair.held[side] = pressed;
+
+        if pressed {
+            if !pair.held[other] {
+                return pair.press(side, &mut emit);
+            }
+
+            match pair.mode {
+                SocdMode::LastInput => {
+                    pair.release(&mut emit);
+                    pair.press(side, &mut emit);
+                }
+                SocdMode::Neutral => pair.release(&mut emit),
+                SocdMode::FirstWins => {}
+            }
+        } else {
+            if pair.active == Some(side) {
+                pair.release(&mut emit);
+            }
+
+            if pair.held[other] && pair.active.is_none() {
+                pair.press(other, &mut emit);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Socd, SocdMode};
+    use crate::KeyEvent;
+
+    const A: u8 = 0x04;
+    const D: u8 = 0x07;
+
+    fn run(socd: &mut Socd<1>, events: impl IntoIterator<Item = KeyEvent>) -> Vec<KeyEvent> {
+        let mut out = Vec::new();
+        for event in events {
+            socd.process(event, |e| out.push(e));
+        }
+        out
+    }
+
+    const ROLL: [KeyEvent; 4] = [
+        KeyEvent::Press(A),
+        KeyEvent::Press(D),
+        KeyEvent::Depress(D),
+        KeyEvent::Depress(A),
+    ];
+
+    #[test]
+    fn last_input() {
+        let mut socd = Socd::new([(A, D, SocdMode::LastInput)]);
+        assert_eq!(
+            run(&mut socd, ROLL),
+            [
+                KeyEvent::Press(A),
+                KeyEvent::Depress(A),
+                KeyEvent::Press(D),
+                KeyEvent::Depress(D),
+                KeyEvent::Press(A),
+                KeyEvent::Depress(A),
+            ]
+        );
+    }
+
+    #[test]
+    fn neutral() {
+        let mut socd = Socd::new([(A, D, SocdMode::Neutral)]);
+        assert_eq!(
+            run(&mut socd, ROLL),
+            [
+                KeyEvent::Press(A),
+                KeyEvent::Depress(A),
+                KeyEvent::Press(A),
+                KeyEvent::Depress(A),
+            ]
+        );
+    }
+
+    #[test]
+    fn first_wins() {
+        let mut socd = Socd::new([(A, D, SocdMode::FirstWins)]);
+        assert_eq!(
+            run(
+                &mut socd,
+                [KeyEvent::Press(A), KeyEvent::Press(D), KeyEvent::Depress(A)]
+            ),
+            [KeyEvent::Press(A), KeyEvent::Depress(A), KeyEvent::Press(D)]
+        );
+        assert_eq!(
+            run(&mut socd, [KeyEvent::Press(0x05), KeyEvent::Depress(D)]),
+            [KeyEvent::Press(0x05), KeyEvent::Depress(D)]
+        );
+    }
+}
@@ -0,0 +1,336 @@
+//! Wire protocol between the halves of a split keyboard.
+//!
+//! The peripheral half sends [`Message`]s to the central half over a UART or
+//! similar byte link. Each message is followed by a CRC-16 (CCITT, initial
+//! value `0xffff`), COBS encoded so that it contains no zero bytes, and
+//! terminated by a zero byte. A corrupted or truncated frame is dropped
+//! without losing the frames after it.
+//!
+//! Input events carry their age in milliseconds when they were sent, rather
+//! than a time from the peripheral's clock, so the central half can place
+//! them on its own clock with [`Message::timed`].
+//!
+//! In the other direction the central half keeps the peripheral in sync with
+//! [`Message::Sync`], see [`sync`].
+
+mod sync;
+
+pub(crate) use sync::{SyncReceiver, SyncSender, SyncState};
+
+use crate::time::{self, Duration, Instant};
+use crate::{InputEvent, Layers, StateFlags, TimedEvent};
+
+/// Longest encoded frame, including the zero terminator.
+const MAX_FRAME: usize = 16;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Message {
+    Input {
+        /// Milliseconds between the event happening and being sent.
+        age: u16,
+        event: InputEvent,
+    },
+    Flags(StateFlags),
+    /// The central half's state, sent to the peripheral.
+    Sync(SyncState),
+    /// Sent by the peripheral while it hasn't heard from the central half,
+    /// asking for a [`Message::Sync`].
+    Hello,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum SplitError {
+    Crc,
+    Malformed,
+    /// The frame was longer than the decoder's buffer.
+    TooLong,
+}
+
+impl Message {
+    /// An input event as seen at `current_time`.
+    fn input<Clock: time::Clock>(event: TimedEvent<Clock>, current_time: Clock::Instant) -> Self {
+        let age = current_time.duration_since(&event.time);
+
+        Message::Input {
+            age: age.as_millis().min(u16::MAX as u32) as u16,
+            event: event.event,
+        }
+    }
+
+    /// The input event, placed on the receiving clock given when it arrived.
+    fn timed<Clock: time::Clock>(&self, received: Clock::Instant) -> Option<TimedEvent<Clock>> {
+        let Message::Input { age, event } = *self else {
+            return None;
+        };
+
+        Some(TimedEvent {
+            time: received
+                .checked_sub(Duration::from_millis(age as u32))
+                .unwrap_or(received),
+            event,
+        })
+    }
+
+    fn encode_payload(&self, out: &mut [u8; MAX_FRAME]) -> usize {
+        let bytes: &[u8] = match *self {
+            Message::Input { age, event } => {
+                let [a, b] = age.to_le_bytes();
+                let [tag, x, y] = event.to_bytes();
+                &[0, a, b, tag, x, y]
+            }
+            Message::Flags(flags) => &[1, flags.bits()],
+            Message::Sync(state) => {
+                let [a, b, c, d] = state.layers.0.to_le_bytes();
+                &[2, state.flags.bits(), a, b, c, d, state.indicators]
+            }
+            Message::Hello => &[3],
+        };
+
+        out[..bytes.len()].copy_from_slice(bytes);
+        bytes.len()
+    }
+
+    fn decode_payload(bytes: &[u8]) -> Option<Self> {
+        match *bytes {
+            [0, a, b, tag, x, y] => Some(Message::Input {
+                age: u16::from_le_bytes([a, b]),
+                event: InputEvent::from_bytes([tag, x, y])?,
+            }),
+            [1, flags] => Some(Message::Flags(StateFlags::from_bits_truncate(flags))),
+            [2, flags, a, b, c, d, indicators] => Some(Message::Sync(SyncState {
+                flags: StateFlags::from_bits_truncate(flags),
+                layers: Layers(u32::from_le_bytes([a, b, c, d])),
+                indicators,
+            })),
+            [3] => Some(Message::Hello),
+            _ => None,
+        }
+    }
+
+    /// Write the framed message to `out`, returning its length.
+    fn encode(&self, out: &mut [u8]) -> Option<usize> {
+        let mut payload = [0; MAX_FRAME];
+        let len = self.encode_payload(&mut payload);
+        let crc = crc16(&payload[..len]);
+        payload[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+
+        cobs_encode(&payload[..len + 2], out)
+    }
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xffff, |mut crc, byte| {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// COBS encode `bytes` into `out`, followed by the zero terminator.
+fn cobs_encode(bytes: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut code_at = 0;
+    let mut len = 1;
+
+    for byte in bytes {
+        if *byte != 0 {
+            *out.get_mut(len)? = *byte;
+            len += 1;
+        }
+        if *byte == 0 || len - code_at == 0xff {
+            *out.get_mut(code_at)? = (len - code_at) as u8;
+            code_at = len;
+            len += 1;
+        }
+    }
+
+    *out.get_mut(code_at)? = (len - code_at) as u8;
+    *out.get_mut(len)? = 0;
+    Some(len + 1)
+}
+
+/// Decodes frames on the receiving side, one byte at a time.
+struct FrameDecoder {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+    overflowed: bool,
+}
+
+impl FrameDecoder {
+    const fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME],
+            len: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Feed a received byte, returning the message once a frame ends.
+    fn push(&mut self, byte: u8) -> Option<Result<Message, SplitError>> {
+        if byte != 0 {
+            match self.buf.get_mut(self.len) {
+                Some(slot) => {
+                    *slot = byte;
+                    self.len += 1;
+                }
+                None => self.overflowed = true,
+            }
+            return None;
+        }
+
+        let len = core::mem::take(&mut self.len);
+        if core::mem::take(&mut self.overflowed) {
+            return Some(Err(SplitError::TooLong));
+        }
+        if len == 0 {
+            return None;
+        }
+
+        Some(Self::decode(&mut self.buf[..len]))
+    }
+
+    fn decode(frame: &mut [u8]) -> Result<Message, SplitError> {
+        let len = cobs_decode(frame).ok_or(SplitError::Malformed)?;
+        let (payload, crc) = frame[..len]
+            .split_last_chunk::<2>()
+            .ok_or(SplitError::Malformed)?;
+
+        if crc16(payload) != u16::from_le_bytes(*crc) {
+            return Err(SplitError::Crc);
+        }
+
+        Message::decode_payload(payload).ok_or(SplitError::Malformed)
+    }
+}
+
+/// COBS decode a frame without its terminator in place, returning the decoded
+/// length.
+fn cobs_decode(frame: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut write = 0;
+
+    while read < frame.len() {
+        let code = frame[read] as usize;
+        if code == 0 || read + code > frame.len() {
+            return None;
+        }
+        read += 1;
+
+        for _ in 1..code {
+            frame[write] = frame[read];
+            read += 1;
+            write += 1;
+        }
+
+        if code != 0xff && read != frame.len() {
+            frame[write] = 0;
+            write += 1;
+        }
+    }
+
+    Some(write)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameDecoder, Message, SplitError, SyncState};
+    use crate::tests::TickerClock;
+    use crate::{InputEvent, Layers, StateFlags, TimedEvent};
+
+    fn frame(message: Message) -> Vec<u8> {
+        let mut buf = [0; 16];
+        let len = message.encode(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn round_trip() {
+        let messages = [
+            Message::Input {
+                age: 0,
+                event: InputEvent::Press(0),
+            },
+            Message::Input {
+                age: 300,
+                event: InputEvent::PointerMove(-1, 0),
+            },
+            Message::Input {
+                age: 2,
+                event: InputEvent::Rotate(1, -3),
+            },
+            Message::Flags(StateFlags::CTRL | StateFlags::GAME_MODE),
+            Message::Sync(SyncState {
+                flags: StateFlags::SHFT,
+                layers: Layers(0x8000_0001),
+                indicators: 1,
+            }),
+            Message::Hello,
+        ];
+
+        let mut decoder = FrameDecoder::new();
+        for message in messages {
+            let bytes = frame(message);
+            assert_eq!(bytes.iter().filter(|b| **b == 0).count(), 1);
+
+            let decoded: Vec<_> = bytes.iter().filter_map(|b| decoder.push(*b)).collect();
+            assert_eq!(decoded, [Ok(message)]);
+        }
+    }
+
+    #[test]
+    fn recovers_from_corruption() {
+        let message = Message::Input {
+            age: 5,
+            event: InputEvent::Depress(7),
+        };
+        let mut bytes = frame(message);
+        bytes[2] ^= 0x10;
+        bytes.extend([2, 1, 5, 0]);
+        bytes.extend([9; 20]);
+        bytes.push(0);
+        bytes.extend(frame(message));
+
+        let mut decoder = FrameDecoder::new();
+        let decoded: Vec<_> = bytes.iter().filter_map(|b| decoder.push(*b)).collect();
+        assert_eq!(
+            decoded,
+            [
+                Err(SplitError::Crc),
+                Err(SplitError::Malformed),
+                Err(SplitError::TooLong),
+                Ok(message)
+            ]
+        );
+    }
+
+    #[test]
+    fn ages_events() {
+        let mut clock = TickerClock(100);
+        let event = TimedEvent::<TickerClock> {
+            time: clock.now(),
+            event: InputEvent::Press(1),
+        };
+
+        clock.tick_n(3);
+        let message = Message::input(event, clock.now());
+        assert_eq!(
+            message,
+            Message::Input {
+                age: 3,
+                event: InputEvent::Press(1)
+            }
+        );
+
+        let received = message.timed::<TickerClock>(TickerClock(50).now()).unwrap();
+        assert_eq!(received.time, TickerClock(47).now());
+        assert_eq!(
+            Message::Flags(StateFlags::empty()).timed::<TickerClock>(clock.now()),
+            None
+        );
+    }
+}
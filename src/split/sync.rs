@@ -0,0 +1,231 @@
+//! Keeping the peripheral half in sync with the central half.
+//!
+//! The central half owns the machine, so the peripheral only knows the
+//! flags, layers and indicators it is told about, which it needs to drive
+//! its own LEDs or display. [`SyncSender`] sends the state whenever it
+//! changes and again every `interval` as a keep-alive. [`SyncReceiver`]
+//! treats the link as lost once `timeout` passes without a sync, falling
+//! back to the default state and sending [`Message::Hello`] until the
+//! central half answers.
+
+use super::Message;
+use crate::time::{self, Duration, Instant};
+use crate::{Indicator, KeyEvent, Layers, StateFlags};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct SyncState {
+    pub(crate) flags: StateFlags,
+    pub(crate) layers: Layers,
+    /// One bit per [`Indicator`].
+    pub(crate) indicators: u8,
+}
+
+impl SyncState {
+    const fn empty() -> Self {
+        Self {
+            flags: StateFlags::empty(),
+            layers: Layers::empty(),
+            indicators: 0,
+        }
+    }
+
+    fn set_indicator(&mut self, indicator: Indicator, on: bool) {
+        if on {
+            self.indicators |= 1 << indicator as u8;
+        } else {
+            self.indicators &= !(1 << indicator as u8);
+        }
+    }
+
+    const fn indicator(&self, indicator: Indicator) -> bool {
+        self.indicators & (1 << indicator as u8) != 0
+    }
+
+    /// Track indicator changes in the events emitted by the machine.
+    fn observe(&mut self, events: &[KeyEvent]) {
+        for event in events {
+            if let KeyEvent::Indicator(indicator, on) = *event {
+                self.set_indicator(indicator, on);
+            }
+        }
+    }
+}
+
+fn elapsed<I: Instant>(current_time: I, since: &I) -> Duration {
+    current_time.duration_since(since)
+}
+
+/// The central half's side.
+pub(crate) struct SyncSender<Clock: time::Clock> {
+    interval: Duration,
+    state: SyncState,
+    /// When the state was last sent, `None` if it needs sending now.
+    sent: Option<Clock::Instant>,
+}
+
+impl<Clock: time::Clock> SyncSender<Clock> {
+    const fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            state: SyncState::empty(),
+            sent: None,
+        }
+    }
+
+    fn update(&mut self, state: SyncState) {
+        if state != self.state {
+            self.state = state;
+            self.sent = None;
+        }
+    }
+
+    /// Handle a message from the peripheral, a hello means it has just
+    /// (re)connected and gets the state straight away.
+    fn receive(&mut self, message: &Message) {
+        if *message == Message::Hello {
+            self.sent = None;
+        }
+    }
+
+    /// Returns a message to send, if one is due.
+    fn poll(&mut self, current_time: Clock::Instant) -> Option<Message> {
+        if let Some(sent) = &self.sent {
+            if elapsed(current_time, sent) < self.interval {
+                return None;
+            }
+        }
+
+        self.sent = Some(current_time);
+        Some(Message::Sync(self.state))
+    }
+}
+
+/// The peripheral half's side.
+pub(crate) struct SyncReceiver<Clock: time::Clock> {
+    timeout: Duration,
+    state: SyncState,
+    /// When a sync was last received, `None` while disconnected.
+    synced: Option<Clock::Instant>,
+    /// When a hello was last sent.
+    hello: Option<Clock::Instant>,
+}
+
+impl<Clock: time::Clock> SyncReceiver<Clock> {
+    const fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            state: SyncState::empty(),
+            synced: None,
+            hello: None,
+        }
+    }
+
+    fn receive(&mut self, current_time: Clock::Instant, message: &Message) {
+        if let Message::Sync(state) = *message {
+            self.state = state;
+            self.synced = Some(current_time);
+        }
+    }
+
+    fn connected(&self) -> bool {
+        self.synced.is_some()
+    }
+
+    /// The last synced state, or the default one while disconnected.
+    fn state(&self) -> SyncState {
+        self.state
+    }
+
+    /// Checks for the link timing out, returning a hello to send while
+    /// disconnected, at most once per `timeout`.
+    fn poll(&mut self, current_time: Clock::Instant) -> Option<Message> {
+        if let Some(synced) = &self.synced {
+            if elapsed(current_time, synced) < self.timeout {
+                return None;
+            }
+
+            self.synced = None;
+            self.state = SyncState::empty();
+        }
+
+        if let Some(hello) = &self.hello {
+            if elapsed(current_time, hello) < self.timeout {
+                return None;
+            }
+        }
+
+        self.hello = Some(current_time);
+        Some(Message::Hello)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SyncReceiver, SyncSender, SyncState};
+    use crate::split::Message;
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{Indicator, KeyEvent, Layers, StateFlags};
+
+    #[test]
+    fn sends_on_change_and_periodically() {
+        let mut clock = TickerClock(0);
+        let mut sender = SyncSender::<TickerClock>::new(Duration::from_millis(100));
+
+        assert_eq!(
+            sender.poll(clock.now()),
+            Some(Message::Sync(SyncState::empty()))
+        );
+        clock.tick_n(50);
+        assert_eq!(sender.poll(clock.now()), None);
+
+        let mut state = SyncState {
+            flags: StateFlags::GAME_MODE,
+            layers: Layers(0b10),
+            indicators: 0,
+        };
+        state.observe(&[KeyEvent::Indicator(Indicator::MouseJiggler, true)]);
+        assert!(state.indicator(Indicator::MouseJiggler));
+        sender.update(state);
+        assert_eq!(sender.poll(clock.now()), Some(Message::Sync(state)));
+
+        clock.tick_n(99);
+        assert_eq!(sender.poll(clock.now()), None);
+        clock.tick();
+        assert_eq!(sender.poll(clock.now()), Some(Message::Sync(state)));
+
+        sender.receive(&Message::Hello);
+        assert_eq!(sender.poll(clock.now()), Some(Message::Sync(state)));
+    }
+
+    #[test]
+    fn reconnects() {
+        let mut clock = TickerClock(0);
+        let mut receiver = SyncReceiver::<TickerClock>::new(Duration::from_millis(300));
+        let state = SyncState {
+            flags: StateFlags::CTRL,
+            layers: Layers(1),
+            indicators: 1,
+        };
+
+        assert_eq!(receiver.poll(clock.now()), Some(Message::Hello));
+        clock.tick_n(10);
+        assert_eq!(receiver.poll(clock.now()), None);
+
+        receiver.receive(clock.now(), &Message::Sync(state));
+        assert!(receiver.connected());
+        clock.tick_n(299);
+        assert_eq!(receiver.poll(clock.now()), None);
+        assert_eq!(receiver.state(), state);
+
+        // the link drops, back to defaults and asking to be synced
+        clock.tick();
+        assert_eq!(receiver.poll(clock.now()), Some(Message::Hello));
+        assert!(!receiver.connected());
+        assert_eq!(receiver.state(), SyncState::empty());
+        clock.tick_n(299);
+        assert_eq!(receiver.poll(clock.now()), None);
+        clock.tick();
+        assert_eq!(receiver.poll(clock.now()), Some(Message::Hello));
+    }
+}
@@ -0,0 +1,172 @@
+//! Single producer, single consumer queues for handing events between
+//! contexts.
+//!
+//! The usual pipeline is an interrupt handler producing [`InputQueue`]
+//! events from the matrix, the main loop consuming them into the machine and
+//! producing what it emits into an [`OutputQueue`], and the USB task
+//! consuming those. The API follows `heapless::spsc`: a [`Queue`] lives in a
+//! `static` or on the stack and is split into a [`Producer`] and a
+//! [`Consumer`] that can be moved to their contexts.
+//!
+//! Capacities are powers of two, so slots stay in order as the counts wrap.
+
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{KeyEvent, TimedEvent};
+
+type InputQueue<Clock, const N: usize> = Queue<TimedEvent<Clock>, N>;
+type OutputQueue<const N: usize> = Queue<KeyEvent, N>;
+
+struct Queue<T, const N: usize> {
+    /// Count of dequeued items, only written by the consumer.
+    head: AtomicUsize,
+    /// Count of enqueued items, only written by the producer.
+    tail: AtomicUsize,
+    buffer: [UnsafeCell<MaybeUninit<T>>; N],
+}
+
+// SAFETY: the producer and consumer never access the same slot at once, a
+// slot is only read after `tail` has been published past it and only
+// rewritten after `head` has.
+unsafe impl<T: Send, const N: usize> Sync for Queue<T, N> {}
+
+struct Producer<'a, T, const N: usize>(&'a Queue<T, N>);
+struct Consumer<'a, T, const N: usize>(&'a Queue<T, N>);
+
+impl<T: Copy, const N: usize> Queue<T, N> {
+    const fn new() -> Self {
+        const { assert!(N.is_power_of_two(), "capacity must be a power of two") };
+        Self {
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            buffer: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+        }
+    }
+
+    fn split(&mut self) -> (Producer<'_, T, N>, Consumer<'_, T, N>) {
+        (Producer(self), Consumer(self))
+    }
+
+    fn len(&self) -> usize {
+        let tail = self.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+}
+
+impl<T: Copy, const N: usize> Producer<'_, T, N> {
+    /// Queue an item, handing it back if the queue is full.
+    fn enqueue(&mut self, item: T) -> Result<(), T> {
+        let tail = self.0.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.0.head.load(Ordering::Acquire)) == N {
+            return Err(item);
+        }
+
+        // SAFETY: the slot isn't readable by the consumer until `tail` moves
+        // past it.
+        unsafe { (*self.0.buffer[tail & (N - 1)].get()).write(item) };
+        self.0.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    fn ready(&self) -> bool {
+        self.0.len() < N
+    }
+}
+
+impl<T: Copy, const N: usize> Consumer<'_, T, N> {
+    fn dequeue(&mut self) -> Option<T> {
+        let head = self.0.head.load(Ordering::Relaxed);
+        if head == self.0.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        // SAFETY: the producer published the slot and won't touch it again
+        // until `head` moves past it.
+        let item = unsafe { (*self.0.buffer[head & (N - 1)].get()).assume_init() };
+        self.0.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(item)
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::AtomicUsize;
+
+    use super::{InputQueue, OutputQueue};
+    use crate::tests::TickerClock;
+    use crate::{InputEvent, KeyEvent, TimedEvent};
+
+    #[test]
+    fn capacity() {
+        let mut queue = OutputQueue::<2>::new();
+        let (mut producer, mut consumer) = queue.split();
+
+        assert_eq!(producer.enqueue(KeyEvent::Press(1)), Ok(()));
+        assert_eq!(producer.enqueue(KeyEvent::Press(2)), Ok(()));
+        assert!(!producer.ready());
+        assert_eq!(
+            producer.enqueue(KeyEvent::Press(3)),
+            Err(KeyEvent::Press(3))
+        );
+
+        assert_eq!(consumer.dequeue(), Some(KeyEvent::Press(1)));
+        assert_eq!(producer.enqueue(KeyEvent::Press(3)), Ok(()));
+        assert_eq!(consumer.len(), 2);
+        assert_eq!(consumer.dequeue(), Some(KeyEvent::Press(2)));
+        assert_eq!(consumer.dequeue(), Some(KeyEvent::Press(3)));
+        assert_eq!(consumer.dequeue(), None);
+    }
+
+    #[test]
+    fn counts_wrap() {
+        let mut queue = OutputQueue::<4>::new();
+        queue.head = AtomicUsize::new(usize::MAX - 1);
+        queue.tail = AtomicUsize::new(usize::MAX - 1);
+        let (mut producer, mut consumer) = queue.split();
+
+        for key in 0..4 {
+            assert_eq!(producer.enqueue(KeyEvent::Press(key)), Ok(()));
+        }
+        assert_eq!(consumer.len(), 4);
+        assert_eq!(
+            producer.enqueue(KeyEvent::Press(4)),
+            Err(KeyEvent::Press(4))
+        );
+        for key in 0..4 {
+            assert_eq!(consumer.dequeue(), Some(KeyEvent::Press(key)));
+        }
+        assert_eq!(consumer.dequeue(), None);
+    }
+
+    #[test]
+    fn across_threads() {
+        let mut queue = InputQueue::<TickerClock, 4>::new();
+        let (mut producer, mut consumer) = queue.split();
+
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                for key in 0..100 {
+                    let event = TimedEvent {
+                        time: TickerClock(key as u32).now(),
+                        event: InputEvent::Press(key),
+                    };
+                    while producer.enqueue(event).is_err() {}
+                }
+            });
+
+            let mut received = 0;
+            while received < 100 {
+                if let Some(event) = consumer.dequeue() {
+                    assert_eq!(event.event, InputEvent::Press(received));
+                    received += 1;
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,157 @@
+//! Transition and dwell statistics.
+//!
+//! [`MachineStats`] counts how often each transition of a machine is taken
+//! and how long the machine stays in each state, to tune terms from real
+//! usage rather than guesswork. Call [`MachineStats::observe`] after every
+//! push and tick of the machine.
+//!
+//! States and transitions are told apart by address, and each table holds a
+//! fixed number of them, further ones aren't counted.
+
+use crate::time::{self, Duration, Instant};
+use crate::{DynState, DynTransition, GlobalState};
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+struct Dwell {
+    visits: u32,
+    total: Duration,
+    longest: Duration,
+}
+
+struct MachineStats<Clock: time::Clock, const STATES: usize, const TRANSITIONS: usize> {
+    states: [Option<(&'static dyn DynState, Dwell)>; STATES],
+    transitions: [Option<(&'static dyn DynTransition, u32)>; TRANSITIONS],
+    /// The state the machine was last seen in, and since when.
+    current: Option<(&'static dyn DynState, Clock::Instant)>,
+}
+
+fn slot<'a, T: ?Sized, V: Default>(
+    table: &'a mut [Option<(&'static T, V)>],
+    key: &'static T,
+) -> Option<&'a mut V> {
+    let index = table
+        .iter()
+        .position(|entry| matches!(entry, Some((k, _)) if core::ptr::addr_eq(*k, key)))
+        .or_else(|| table.iter().position(Option::is_none))?;
+    let (_, value) = table[index].get_or_insert_with(|| (key, V::default()));
+    Some(value)
+}
+
+impl<Clock: time::Clock, const STATES: usize, const TRANSITIONS: usize>
+    MachineStats<Clock, STATES, TRANSITIONS>
+{
+    fn new() -> Self {
+        Self {
+            states: [const { None }; STATES],
+            transitions: [const { None }; TRANSITIONS],
+            current: None,
+        }
+    }
+
+    fn observe(&mut self, machine: &mut GlobalState<Clock>) {
+        let Some((state, since)) = self.current else {
+            self.current = Some((machine.current_state, machine.entered_state));
+            return;
+        };
+
+        let Some(transition) = machine.last_transition.take() else {
+            return;
+        };
+
+        if let Some(count) = slot(&mut self.transitions, transition) {
+            *count = count.saturating_add(1);
+        }
+
+        let dwell = machine.entered_state.duration_since(&since);
+        if let Some(stats) = slot(&mut self.states, state) {
+            stats.visits = stats.visits.saturating_add(1);
+            stats.total = stats.total.saturating_add(dwell);
+            stats.longest = stats.longest.max(dwell);
+        }
+
+        self.current = Some((machine.current_state, machine.entered_state));
+    }
+
+    fn transition_count(&self, transition: &'static dyn DynTransition) -> u32 {
+        self.transitions
+            .iter()
+            .flatten()
+            .find(|(t, _)| core::ptr::addr_eq(*t, transition))
+            .map_or(0, |(_, count)| *count)
+    }
+
+    /// How long the machine has spent in `state`, over the visits that have
+    /// ended.
+    fn dwell(&self, state: &'static dyn DynState) -> Dwell {
+        self.states
+            .iter()
+            .flatten()
+            .find(|(s, _)| core::ptr::addr_eq(*s, state))
+            .map_or_else(Dwell::default, |(_, dwell)| *dwell)
+    }
+
+    /// Every state seen, by name, with its dwell time.
+    fn states(&self) -> impl Iterator<Item = (&str, Dwell)> + '_ {
+        self.states
+            .iter()
+            .flatten()
+            .map(|(state, dwell)| (state.name(), *dwell))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Dwell, MachineStats};
+    use crate::behaviors::hold_tap;
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{DynState, GlobalState, InputEvent};
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn dwell() {
+        let mut clock = TickerClock(0);
+        let mut machine = GlobalState::<TickerClock>::new(home_a::IDLE.as_dyn(), clock.now());
+        let mut stats = MachineStats::<TickerClock, 8, 8>::new();
+        stats.observe(&mut machine);
+
+        for held in [4, 6, 20] {
+            clock.tick_n(2);
+            machine.push(clock.now(), InputEvent::Press(1));
+            stats.observe(&mut machine);
+            for _ in 0..held {
+                clock.tick();
+                machine.tick(clock.now());
+                stats.observe(&mut machine);
+            }
+            machine.push(clock.now(), InputEvent::Depress(1));
+            stats.observe(&mut machine);
+        }
+
+        let idle = home_a::IDLE.as_dyn();
+        assert_eq!(stats.dwell(idle).visits, 3);
+        assert_eq!(stats.transition_count(idle.transitions()[1]), 3);
+        assert_eq!(stats.states().count(), 3);
+
+        let (_, undecided) = stats
+            .states()
+            .find(|(name, _)| *name == "home_a::UNDECIDED")
+            .unwrap();
+        assert_eq!(
+            undecided,
+            Dwell {
+                visits: 3,
+                total: Duration::from_millis(4 + 6 + 10),
+                longest: Duration::from_millis(10),
+            }
+        );
+    }
+}
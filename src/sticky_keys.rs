@@ -0,0 +1,300 @@
+//! Sticky Keys accessibility mode.
+//!
+//! While [`StateFlags::STICKY_KEYS`] is set (and [`StateFlags::GAME_MODE`]
+//! isn't), tapping a modifier latches it until the next key has been pressed
+//! and released, so Shift followed by A types 'A'. Tapping a latched
+//! modifier again locks it, and a third tap unlocks it. Modifiers held down
+//! while another key is pressed behave as usual.
+//!
+//! [`StickyKeys`] sits between the machine and the host, rewriting the key
+//! events the machine emits.
+
+use crate::{KeyCode, KeyEvent, StateFlags};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Stickiness {
+    Off,
+    Latched,
+    Locked,
+}
+
+#[derive(Clone, Copy)]
+struct StickyModifier {
+    key: KeyCode,
+    stickiness: Stickiness,
+    /// The modifier is physically held.
+    held: bool,
+    /// Another key was pressed while the modifier was physically held.
+    chorded: bool,
+}
+
+struct StickyKeys<const N: usize> {
+    modifiers: [StickyModifier; N],
+    /// The key latched modifiers were applied to, they are released along
+    /// with it.
+    applied_to: Option<KeyCode>,
+}
+
+impl<const N: usize> StickyKeys<N> {
+    const fn new(modifiers: [KeyCode; N]) -> Self {
+        let mut sticky = [StickyModifier {
+            key: 0,
+            stickiness: Stickiness::Off,
+            held: false,
+            chorded: false,
+        }; N];
+
+        let mut i = 0;
+        while i < N {
+            sticky[i].key = modifiers[i];
+            i += 1;
+        }
+
+        Self {
+            modifiers: sticky,
+            applied_to: None,
+        }
+    }
+
+    fn stickiness(&self, key: KeyCode) -> Option<Stickiness> {
+        self.modifiers
+            .iter()
+            .find(|m| m.key == key)
+            .map(|m| m.stickiness)
+    }
+
+    /// Release every latched or locked modifier that isn't physically held.
+    fn release_all(&mut self, emit: &mut impl FnMut(KeyEvent)) {
+        for modifier in &mut self.modifiers {
+            if modifier.stickiness != Stickiness::Off && !modifier.held {
+                emit(KeyEvent::Depress(modifier.key));
+            }
+            modifier.stickiness = Stickiness::Off;
+        }
+        self.applied_to = None;
+    }
+
+    fn process(&mut self, flags: StateFlags, event: KeyEvent, mut emit: impl FnMut(KeyEvent)) {
+        if !flags.contains(StateFlags::STICKY_KEYS) || flags.contains(StateFlags::GAME_MODE) {
+            self.release_all(&mut emit);
+            emit(event);
+            return;
+        }
+
+        match event {
+            KeyEvent::Press(key) => {
+                if let Some(modifier) = self.modifiers.iter_mut().find(|m| m.key == key) {
+                    modifier.held = true;
+                    modifier.chorded = false;
+                    modifier.stickiness = match modifier.stickiness {
+                        Stickiness::Off => {
+                            emit(event);
+                            Stickiness::Latched
+                        }
+                        Stickiness::Latched => Stickiness::Locked,
+                        // released when the key comes back up
+                        Stickiness::Locked => Stickiness::Off,
+                    };
+                    return;
+                }
+
+                for modifier in &mut self.modifiers {
+                    if modifier.held {
+                        modifier.chorded = true;
+                    }
+                }
+
+                // the first key pressed keeps the latch until it's released
+                if self.applied_to.is_none()
+                    && self
+                        .modifiers
+                        .iter()
+                        .any(|m| m.stickiness == Stickiness::Latched)
+                {
+                    self.applied_to = Some(key);
+                }
+
+                emit(event);
+            }
+            KeyEvent::Depress(key) => {
+                if let Some(modifier) = self.modifiers.iter_mut().find(|m| m.key == key) {
+                    modifier.held = false;
+                    if modifier.chorded || modifier.stickiness == Stickiness::Off {
+                        modifier.stickiness = Stickiness::Off;
+                        emit(event);
+                    }
+                    return;
+                }
+
+                emit(event);
+
+                if self.applied_to == Some(key) {
+                    self.applied_to = None;
+                    for modifier in &mut self.modifiers {
+                        if modifier.stickiness == Stickiness::Latched && !modifier.held {
+                            modifier.stickiness = Stickiness::Off;
+                            emit(KeyEvent::Depress(modifier.key));
+                        }
+                    }
+                }
+            }
+            event => emit(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Stickiness, StickyKeys};
+    use crate::{KeyEvent, StateFlags};
+
+    const SHIFT: u8 = 0xe1;
+    const A: u8 = 0x04;
+
+    fn run(
+        sticky: &mut StickyKeys<2>,
+        flags: StateFlags,
+        events: impl IntoIterator<Item = KeyEvent>,
+    ) -> Vec<KeyEvent> {
+        let mut out = Vec::new();
+        for event in events {
+            sticky.process(flags, event, |e| out.push(e));
+        }
+        out
+    }
+
+    #[test]
+    fn latches_for_next_key() {
+        let mut sticky = StickyKeys::new([0xe0, SHIFT]);
+        let out = run(
+            &mut sticky,
+            StateFlags::STICKY_KEYS,
+            [
+                KeyEvent::Press(SHIFT),
+                KeyEvent::Depress(SHIFT),
+                KeyEvent::Press(A),
+                KeyEvent::Depress(A),
+                KeyEvent::Press(A),
+                KeyEvent::Depress(A),
+            ],
+        );
+
+        assert_eq!(
+            out,
+            [
+                KeyEvent::Press(SHIFT),
+                KeyEvent::Press(A),
+                KeyEvent::Depress(A),
+                KeyEvent::Depress(SHIFT),
+                KeyEvent::Press(A),
+                KeyEvent::Depress(A),
+            ]
+        );
+    }
+
+    #[test]
+    fn latch_stays_for_first_key() {
+        const B: u8 = 0x05;
+        let mut sticky = StickyKeys::new([0xe0, SHIFT]);
+        let out = run(
+            &mut sticky,
+            StateFlags::STICKY_KEYS,
+            [
+                KeyEvent::Press(SHIFT),
+                KeyEvent::Depress(SHIFT),
+                KeyEvent::Press(A),
+                KeyEvent::Press(B),
+                KeyEvent::Depress(B),
+                KeyEvent::Depress(A),
+            ],
+        );
+
+        assert_eq!(
+            out,
+            [
+                KeyEvent::Press(SHIFT),
+                KeyEvent::Press(A),
+                KeyEvent::Press(B),
+                KeyEvent::Depress(B),
+                KeyEvent::Depress(A),
+                KeyEvent::Depress(SHIFT),
+            ]
+        );
+    }
+
+    #[test]
+    fn double_tap_locks() {
+        let mut sticky = StickyKeys::new([0xe0, SHIFT]);
+        let out = run(
+            &mut sticky,
+            StateFlags::STICKY_KEYS,
+            [
+                KeyEvent::Press(SHIFT),
+                KeyEvent::Depress(SHIFT),
+                KeyEvent::Press(SHIFT),
+                KeyEvent::Depress(SHIFT),
+                KeyEvent::Press(A),
+                KeyEvent::Depress(A),
+            ],
+        );
+
+        assert_eq!(
+            out,
+            [
+                KeyEvent::Press(SHIFT),
+                KeyEvent::Press(A),
+                KeyEvent::Depress(A),
+            ]
+        );
+        assert_eq!(sticky.stickiness(SHIFT), Some(Stickiness::Locked));
+
+        let out = run(
+            &mut sticky,
+            StateFlags::STICKY_KEYS,
+            [KeyEvent::Press(SHIFT), KeyEvent::Depress(SHIFT)],
+        );
+        assert_eq!(out, [KeyEvent::Depress(SHIFT)]);
+        assert_eq!(sticky.stickiness(SHIFT), Some(Stickiness::Off));
+    }
+
+    #[test]
+    fn chords_and_disabling_behave_normally() {
+        let mut sticky = StickyKeys::new([0xe0, SHIFT]);
+        let out = run(
+            &mut sticky,
+            StateFlags::STICKY_KEYS,
+            [
+                KeyEvent::Press(SHIFT),
+                KeyEvent::Press(A),
+                KeyEvent::Depress(A),
+                KeyEvent::Depress(SHIFT),
+            ],
+        );
+        assert_eq!(
+            out,
+            [
+                KeyEvent::Press(SHIFT),
+                KeyEvent::Press(A),
+                KeyEvent::Depress(A),
+                KeyEvent::Depress(SHIFT),
+            ]
+        );
+
+        let out = run(
+            &mut sticky,
+            StateFlags::STICKY_KEYS,
+            [KeyEvent::Press(SHIFT), KeyEvent::Depress(SHIFT)],
+        );
+        assert_eq!(out, [KeyEvent::Press(SHIFT)]);
+
+        let out = run(&mut sticky, StateFlags::empty(), [KeyEvent::Press(A)]);
+        assert_eq!(out, [KeyEvent::Depress(SHIFT), KeyEvent::Press(A)]);
+
+        let out = run(
+            &mut sticky,
+            StateFlags::STICKY_KEYS | StateFlags::GAME_MODE,
+            [KeyEvent::Press(SHIFT), KeyEvent::Depress(SHIFT)],
+        );
+        assert_eq!(out, [KeyEvent::Press(SHIFT), KeyEvent::Depress(SHIFT)]);
+    }
+}
@@ -0,0 +1,213 @@
+//! Persistent storage for settings that should survive a power cycle.
+//!
+//! Records start with a version byte so that the layout of a record can
+//! change between firmware versions, see [`write_record`] and
+//! [`read_record`]. A [`Schema`] gives the version a record is written at
+//! and how to upgrade the ones before it, so that firmware changing a layout
+//! can read what an older firmware left behind, see [`read_migrated`].
+
+/// Keyed blob storage, usually backed by flash or EEPROM.
+pub(crate) trait Storage {
+    type Error;
+
+    /// Read the record stored under `key` into `buf`, returning its length,
+    /// or `None` if nothing is stored under `key`.
+    fn read(&mut self, key: u16, buf: &mut [u8]) -> Result<Option<usize>, Self::Error>;
+
+    /// Replace the record stored under `key`.
+    fn write(&mut self, key: u16, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum StorageError<E> {
+    Storage(E),
+    BufferTooSmall,
+    Malformed,
+    /// The record was written with a layout this firmware doesn't know.
+    UnknownVersion(u8),
+}
+
+/// Write a record of `version`, `encode` serializes it into the buffer it is
+/// given and returns its length.
+pub(crate) fn write_record<S: Storage>(
+    storage: &mut S,
+    key: u16,
+    version: u8,
+    buf: &mut [u8],
+    encode: impl FnOnce(&mut [u8]) -> Option<usize>,
+) -> Result<(), StorageError<S::Error>> {
+    let (first, rest) = buf.split_first_mut().ok_or(StorageError::BufferTooSmall)?;
+    *first = version;
+    let len = encode(rest).ok_or(StorageError::BufferTooSmall)?;
+    let record = buf.get(..len + 1).ok_or(StorageError::BufferTooSmall)?;
+
+    storage.write(key, record).map_err(StorageError::Storage)
+}
+
+pub(crate) struct Record<'a> {
+    pub(crate) version: u8,
+    pub(crate) data: &'a [u8],
+}
+
+/// Read a record into `buf`. A length from `storage` past the end of `buf`
+/// is [`StorageError::BufferTooSmall`].
+pub(crate) fn read_record<'a, S: Storage>(
+    storage: &mut S,
+    key: u16,
+    buf: &'a mut [u8],
+) -> Result<Option<Record<'a>>, StorageError<S::Error>> {
+    let Some(len) = storage.read(key, buf).map_err(StorageError::Storage)? else {
+        return Ok(None);
+    };
+
+    let record = buf.get(..len).ok_or(StorageError::BufferTooSmall)?;
+    match record.split_first() {
+        Some((version, data)) => Ok(Some(Record {
+            version: *version,
+            data,
+        })),
+        None => Err(StorageError::Malformed),
+    }
+}
+
+/// Upgrades a record's data from one version to the next, in place. It's
+/// given the buffer the data is at the start of, with whatever room is left
+/// after it, and the data's length, and returns the new length, or `None`
+/// if the data can't be upgraded.
+pub(crate) type Migration = fn(buf: &mut [u8], len: usize) -> Option<usize>;
+
+/// The version records of a kind are written at, and how to bring the
+/// versions before it up to date.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Schema {
+    pub(crate) version: u8,
+    /// `migrations[n]` upgrades version `n + 1` to `n + 2`, so versions from
+    /// 1 up to `migrations.len() + 1` can be read.
+    pub(crate) migrations: &'static [Migration],
+}
+
+/// Read a record into `buf`, upgrading it to `schema.version` if it was
+/// written at an older one. An upgraded record is written back, so it's only
+/// upgraded once. Like [`read_record`], a length past the end of `buf` is
+/// [`StorageError::BufferTooSmall`], and a migration giving one is
+/// [`StorageError::Malformed`].
+pub(crate) fn read_migrated<'a, S: Storage>(
+    storage: &mut S,
+    key: u16,
+    schema: &Schema,
+    buf: &'a mut [u8],
+) -> Result<Option<&'a [u8]>, StorageError<S::Error>> {
+    let Some(len) = storage.read(key, buf).map_err(StorageError::Storage)? else {
+        return Ok(None);
+    };
+    let record = buf.get(..len).ok_or(StorageError::BufferTooSmall)?;
+    let Some(&version) = record.first() else {
+        return Err(StorageError::Malformed);
+    };
+    if version == 0 || version > schema.version {
+        return Err(StorageError::UnknownVersion(version));
+    }
+
+    let mut data_len = len - 1;
+    for from in version..schema.version {
+        let migrate = schema
+            .migrations
+            .get(from as usize - 1)
+            .ok_or(StorageError::UnknownVersion(version))?;
+        data_len = migrate(&mut buf[1..], data_len).ok_or(StorageError::Malformed)?;
+    }
+    if data_len >= buf.len() {
+        return Err(StorageError::Malformed);
+    }
+    if version != schema.version {
+        buf[0] = schema.version;
+        storage
+            .write(key, &buf[..data_len + 1])
+            .map_err(StorageError::Storage)?;
+    }
+
+    Ok(Some(&buf[1..data_len + 1]))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::HashMap;
+
+    use super::{read_migrated, read_record, write_record, Schema, Storage, StorageError};
+
+    /// In memory storage for tests.
+    #[derive(Default)]
+    pub(crate) struct MemoryStorage(pub(crate) HashMap<u16, Vec<u8>>);
+
+    impl Storage for MemoryStorage {
+        type Error = ();
+
+        fn read(&mut self, key: u16, buf: &mut [u8]) -> Result<Option<usize>, ()> {
+            let Some(data) = self.0.get(&key) else {
+                return Ok(None);
+            };
+
+            buf.get_mut(..data.len()).ok_or(())?.copy_from_slice(data);
+            Ok(Some(data.len()))
+        }
+
+        fn write(&mut self, key: u16, data: &[u8]) -> Result<(), ()> {
+            self.0.insert(key, data.to_vec());
+            Ok(())
+        }
+    }
+
+    /// Storage that claims every record is `len` long.
+    struct Lying(usize);
+
+    impl Storage for Lying {
+        type Error = ();
+
+        fn read(&mut self, _: u16, _: &mut [u8]) -> Result<Option<usize>, ()> {
+            Ok(Some(self.0))
+        }
+
+        fn write(&mut self, _: u16, _: &[u8]) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn lengths_past_buffer() {
+        let mut buf = [0; 4];
+        assert!(matches!(
+            read_record(&mut Lying(5), 0, &mut buf),
+            Err(StorageError::BufferTooSmall)
+        ));
+        assert!(matches!(
+            read_record(&mut Lying(0), 0, &mut buf),
+            Err(StorageError::Malformed)
+        ));
+        assert_eq!(
+            write_record(&mut Lying(0), 0, 1, &mut buf, |_| Some(4)),
+            Err(StorageError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn migrated_lengths_past_buffer() {
+        const SCHEMA: Schema = Schema {
+            version: 2,
+            migrations: &[|buf, _| Some(buf.len() + 1)],
+        };
+
+        let mut buf = [0; 4];
+        assert_eq!(
+            read_migrated(&mut Lying(5), 0, &SCHEMA, &mut buf),
+            Err(StorageError::BufferTooSmall)
+        );
+
+        let mut storage = MemoryStorage::default();
+        storage.0.insert(0, vec![1, 7]);
+        assert_eq!(
+            read_migrated(&mut storage, 0, &SCHEMA, &mut buf),
+            Err(StorageError::Malformed)
+        );
+        assert_eq!(storage.0[&0], [1, 7]);
+    }
+}
@@ -0,0 +1,385 @@
+//! Machines as flat, index based tables.
+//!
+//! A [`Table`] holds every state of a machine in one array and every
+//! transition in another. A state refers to its transitions as a run of that
+//! array, and a transition refers to its target by [`StateIndex`], so running
+//! the machine with [`TableMachine`] is a walk over plain data with no
+//! virtual calls. Because states are indices, the current state of a machine
+//! is a number that can be stored and restored.
+//!
+//! Machines are still written as [`State`](crate::State) and
+//! [`Transition`](crate::Transition) statics; [`Table::compile`] lowers such
+//! a machine into a table, with the initial state at index 0.
+
+use core::ops::Range;
+
+use crate::time::{self, Instant};
+use crate::{
+    DynState, DynTransition, GlobalState, InputEvent, InternalEvent, KeyEvent, StateId,
+    TransitionCondition,
+};
+
+/// A state of a [`Table`], by its position in the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct StateIndex(pub(crate) u16);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum CompileError {
+    /// The machine has more states than the table has room for.
+    TooManyStates,
+    /// The machine has more transitions than the table has room for.
+    TooManyTransitions,
+}
+
+#[derive(Clone, Copy)]
+struct TableState {
+    name: &'static str,
+    id: StateId,
+    /// The state's transitions are `transitions[first..first + len]`.
+    first: u16,
+    len: u16,
+}
+
+#[derive(Clone, Copy)]
+struct TableTransition {
+    conditions: &'static [TransitionCondition],
+    key_event_emissions: &'static [KeyEvent],
+    internal_event_emissions: &'static [InternalEvent],
+    target: StateIndex,
+}
+
+/// A machine of up to `STATES` states and `TRANSITIONS` transitions.
+pub(crate) struct Table<const STATES: usize, const TRANSITIONS: usize> {
+    states: [TableState; STATES],
+    transitions: [TableTransition; TRANSITIONS],
+    state_count: u16,
+    transition_count: u16,
+}
+
+const EMPTY_STATE: TableState = TableState {
+    name: "",
+    id: StateId(0),
+    first: 0,
+    len: 0,
+};
+
+const EMPTY_TRANSITION: TableTransition = TableTransition {
+    conditions: &[],
+    key_event_emissions: &[],
+    internal_event_emissions: &[],
+    target: StateIndex(0),
+};
+
+impl<const STATES: usize, const TRANSITIONS: usize> Table<STATES, TRANSITIONS> {
+    /// Lay out every state reachable from `initial`, in the order they're
+    /// reached.
+    pub(crate) fn compile(initial: &'static dyn DynState) -> Result<Self, CompileError> {
+        let mut sources: [Option<&'static dyn DynState>; STATES] = [None; STATES];
+        let mut table = Self {
+            states: [EMPTY_STATE; STATES],
+            transitions: [EMPTY_TRANSITION; TRANSITIONS],
+            state_count: 0,
+            transition_count: 0,
+        };
+
+        // states are numbered as they're found, so the ones still to be laid
+        // out are those from `next` on
+        table.add_state(&mut sources, initial)?;
+        let mut next = 0;
+        while next < table.state_count() {
+            let state = sources[next].unwrap();
+            table.states[next].first = table.transition_count;
+            for transition in state.transitions() {
+                let index = table.transition_count as usize;
+                if index >= TRANSITIONS || index >= u16::MAX as usize {
+                    return Err(CompileError::TooManyTransitions);
+                }
+                let target = table.add_state(&mut sources, transition.target())?;
+                table.transitions[index] = TableTransition {
+                    conditions: transition.conditions(),
+                    key_event_emissions: transition.key_event_emissions(),
+                    internal_event_emissions: transition.internal_event_emissions(),
+                    target,
+                };
+                table.transition_count += 1;
+            }
+            table.states[next].len = table.transition_count - table.states[next].first;
+            next += 1;
+        }
+
+        Ok(table)
+    }
+
+    /// The index of `state`, numbering it if it's new.
+    fn add_state(
+        &mut self,
+        sources: &mut [Option<&'static dyn DynState>; STATES],
+        state: &'static dyn DynState,
+    ) -> Result<StateIndex, CompileError> {
+        let found = sources[..self.state_count()]
+            .iter()
+            .position(|s| matches!(s, Some(s) if core::ptr::addr_eq(*s, state)));
+        if let Some(index) = found {
+            return Ok(StateIndex(index as u16));
+        }
+
+        let index = self.state_count();
+        if index >= STATES || index >= u16::MAX as usize {
+            return Err(CompileError::TooManyStates);
+        }
+        sources[index] = Some(state);
+        self.states[index].name = state.name();
+        self.states[index].id = state.id();
+        self.state_count += 1;
+        Ok(StateIndex(index as u16))
+    }
+
+    fn state_count(&self) -> usize {
+        self.state_count as usize
+    }
+
+    fn transition_count(&self) -> usize {
+        self.transition_count as usize
+    }
+
+    /// The index of the state with `id`, to restore a machine to a state
+    /// saved by id.
+    fn index_of(&self, id: StateId) -> Option<StateIndex> {
+        self.states[..self.state_count()]
+            .iter()
+            .position(|state| state.id == id)
+            .map(|index| StateIndex(index as u16))
+    }
+}
+
+/// How a machine's states and transitions are laid out, for [`TableMachine`]
+/// to run. Transitions are numbered across the whole machine.
+pub(crate) trait Layout {
+    /// How many states there are, numbered from 0.
+    fn state_count(&self) -> usize;
+    /// The transitions of `state`, in the order they're tried.
+    fn transition_range(&self, state: StateIndex) -> Range<usize>;
+    fn conditions(&self, transition: usize) -> &[TransitionCondition];
+    fn key_event_emissions(&self, transition: usize) -> &[KeyEvent];
+    fn internal_event_emissions(&self, transition: usize) -> &[InternalEvent];
+    fn target(&self, transition: usize) -> StateIndex;
+    fn name(&self, state: StateIndex) -> &'static str;
+
+    /// The transitions of `state` that could be taken for `event`. Layouts
+    /// that know which transitions can't hold for an event skip them.
+    fn candidates(&self, state: StateIndex, event: Option<InputEvent>) -> Range<usize> {
+        self.transition_range(state)
+    }
+}
+
+impl<const STATES: usize, const TRANSITIONS: usize> Layout for Table<STATES, TRANSITIONS> {
+    fn state_count(&self) -> usize {
+        self.state_count()
+    }
+
+    fn transition_range(&self, state: StateIndex) -> Range<usize> {
+        let state = &self.states[state.0 as usize];
+        state.first as usize..(state.first + state.len) as usize
+    }
+
+    fn conditions(&self, transition: usize) -> &[TransitionCondition] {
+        self.transitions[transition].conditions
+    }
+
+    fn key_event_emissions(&self, transition: usize) -> &[KeyEvent] {
+        self.transitions[transition].key_event_emissions
+    }
+
+    fn internal_event_emissions(&self, transition: usize) -> &[InternalEvent] {
+        self.transitions[transition].internal_event_emissions
+    }
+
+    fn target(&self, transition: usize) -> StateIndex {
+        self.transitions[transition].target
+    }
+
+    fn name(&self, state: StateIndex) -> &'static str {
+        self.states[state.0 as usize].name
+    }
+}
+
+/// Runs a machine laid out as a [`Table`] or an [`Arena`](crate::arena::Arena),
+/// with the same behaviour as a [`GlobalState`] running the statics it was
+/// compiled from. It always takes the first matching transition, whatever
+/// [`MatchPolicy`](crate::MatchPolicy) the statics would be run with, so that
+/// what it emits can be borrowed straight from the layout.
+pub(crate) struct TableMachine<'t, Clock: time::Clock, L> {
+    layout: &'t L,
+    state: GlobalState<Clock, StateIndex>,
+}
+
+impl<'t, Clock: time::Clock, L: Layout> TableMachine<'t, Clock, L> {
+    pub(crate) fn new(layout: &'t L, current_time: Clock::Instant) -> Self {
+        Self {
+            layout,
+            state: GlobalState::new(StateIndex(0), current_time),
+        }
+    }
+
+    pub(crate) fn current_state(&self) -> StateIndex {
+        self.state.current_state
+    }
+
+    pub(crate) fn tick(&mut self, current_time: Clock::Instant) -> &'t [KeyEvent] {
+        self.step(current_time, None)
+    }
+
+    pub(crate) fn push(
+        &mut self,
+        current_time: Clock::Instant,
+        event: InputEvent,
+    ) -> &'t [KeyEvent] {
+        self.step(current_time, Some(event))
+    }
+
+    fn step(&mut self, current_time: Clock::Instant, event: Option<InputEvent>) -> &'t [KeyEvent] {
+        step(self.layout, &mut self.state, current_time, event)
+    }
+
+    /// See [`GlobalState::next_deadline`].
+    pub(crate) fn next_deadline(&self, current_time: Clock::Instant) -> Option<Clock::Instant> {
+        next_deadline(self.layout, &self.state, current_time)
+    }
+}
+
+/// Push `event`, or tick if there's none, to the machine laid out by
+/// `layout` whose state is `state`. This is [`TableMachine`] for a layout
+/// that's owned alongside the state rather than borrowed for as long as it.
+pub(crate) fn step<'t, Clock: time::Clock, L: Layout>(
+    layout: &'t L,
+    state: &mut GlobalState<Clock, StateIndex>,
+    current_time: Clock::Instant,
+    event: Option<InputEvent>,
+) -> &'t [KeyEvent] {
+    let Some(context) = state.prepare(current_time, event) else {
+        return &[];
+    };
+
+    let Some(transition) = layout.candidates(state.current_state, event).find(|t| {
+        layout
+            .conditions(*t)
+            .iter()
+            .all(|c| c.evaluate(&context, event))
+    }) else {
+        return &[];
+    };
+
+    let at = match event {
+        Some(_) => current_time,
+        None => state.entered_at(
+            state.taken_at(layout.conditions(transition), current_time),
+            layout
+                .transition_range(layout.target(transition))
+                .flat_map(|t| layout.conditions(t)),
+            current_time,
+        ),
+    };
+    state.do_transition(
+        event,
+        layout.internal_event_emissions(transition),
+        layout.key_event_emissions(transition),
+        layout.target(transition),
+        at,
+    );
+
+    layout.key_event_emissions(transition)
+}
+
+/// See [`GlobalState::next_deadline`] and [`step`].
+pub(crate) fn next_deadline<Clock: time::Clock, L: Layout>(
+    layout: &L,
+    state: &GlobalState<Clock, StateIndex>,
+    current_time: Clock::Instant,
+) -> Option<Clock::Instant> {
+    state.earliest_deadline(
+        layout
+            .transition_range(state.current_state)
+            .flat_map(|t| layout.conditions(t)),
+        current_time,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CompileError, Layout, StateIndex, Table, TableMachine};
+    use crate::behaviors::hold_tap;
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{GlobalState, InputEvent};
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn compile() {
+        let table = Table::<4, 16>::compile(home_a::IDLE.as_dyn()).unwrap();
+        assert_eq!(table.state_count(), 4);
+        assert_eq!(table.transition_count(), 15);
+        assert_eq!(table.name(StateIndex(0)), "home_a::IDLE");
+        assert_eq!(
+            table.transition_range(StateIndex(0)).len(),
+            home_a::IDLE.as_dyn().transitions().len()
+        );
+
+        assert_eq!(
+            Table::<3, 16>::compile(home_a::IDLE.as_dyn()).err(),
+            Some(CompileError::TooManyStates)
+        );
+        assert_eq!(
+            Table::<4, 8>::compile(home_a::IDLE.as_dyn()).err(),
+            Some(CompileError::TooManyTransitions)
+        );
+    }
+
+    #[test]
+    fn runs_like_statics() {
+        let table = Table::<4, 15>::compile(home_a::IDLE.as_dyn()).unwrap();
+        let mut clock = TickerClock(0);
+        let mut machine = TableMachine::<TickerClock, _>::new(&table, clock.now());
+        let mut statics = GlobalState::<TickerClock>::new(home_a::IDLE.as_dyn(), clock.now());
+
+        for held in [3, 12] {
+            for event in [InputEvent::Press(1), InputEvent::Press(2)] {
+                assert_eq!(
+                    machine.push(clock.now(), event),
+                    statics.push(clock.now(), event)
+                );
+            }
+            assert_eq!(
+                machine.next_deadline(clock.now()),
+                statics.next_deadline(clock.now())
+            );
+            for _ in 0..held {
+                clock.tick();
+                assert_eq!(machine.tick(clock.now()), statics.tick(clock.now()));
+            }
+            assert_eq!(
+                table.name(machine.current_state()),
+                statics.current_state.name()
+            );
+            for event in [InputEvent::Depress(2), InputEvent::Depress(1)] {
+                assert_eq!(
+                    machine.push(clock.now(), event),
+                    statics.push(clock.now(), event)
+                );
+            }
+        }
+
+        assert_eq!(machine.current_state(), StateIndex(0));
+        assert_eq!(
+            table.index_of(home_a::HOLD.id).map(|s| table.name(s)),
+            Some("home_a::HOLD")
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! Helpers for testing keymaps.
+//!
+//! [`TickerClock`] is a clock that only moves when told to, and a
+//! [`Scenario`] runs a machine against one, collecting what it emits so that
+//! a behaviour test reads as a script:
+//!
+//! ```ignore
+//! Scenario::new(home_a::IDLE.as_dyn())
+//!     .press(1)
+//!     .advance_ms(200)
+//!     .expect_matching(&[Matcher::Press(0xe1), Matcher::AnyLighting])
+//!     .release(1)
+//!     .expect_emitted(&[KeyEvent::Depress(0xe1)]);
+//! ```
+//!
+//! The `expect` methods panic with what was emitted on a mismatch, at the
+//! caller's location, and don't need any unstable features. It's only
+//! built for tests, the kani proofs and with the `testing` feature, which a
+//! keymap crate enables for its own tests, so firmware doesn't carry it.
+
+use embedded_time::Instant;
+
+use crate::{DynState, GlobalState, InputEvent, KeyEvent};
+
+/// A clock with a resolution of a millisecond that only moves when ticked.
+#[derive(Debug)]
+pub struct TickerClock(pub u32);
+
+impl TickerClock {
+    pub fn tick(&mut self) {
+        self.tick_n(1);
+    }
+
+    pub fn tick_n(&mut self, n: u32) {
+        self.0 = self.0.wrapping_add(n);
+    }
+
+    pub fn now(&self) -> Instant<TickerClock> {
+        embedded_time::Clock::try_now(self).unwrap()
+    }
+}
+
+impl embedded_time::Clock for TickerClock {
+    type T = u32;
+    const SCALING_FACTOR: embedded_time::rate::Fraction =
+        embedded_time::rate::Fraction::new(1, 1_000);
+
+    fn try_now(&self) -> Result<embedded_time::Instant<Self>, embedded_time::clock::Error> {
+        Ok(embedded_time::Instant::new(self.0))
+    }
+}
+
+/// A check on one emitted event, for when the exact event doesn't matter.
+#[derive(Debug, Clone, Copy)]
+pub enum Matcher {
+    /// Exactly this event.
+    Is(KeyEvent),
+    /// A press of this key, directly or as the press of the key pushed.
+    Press(u8),
+    Release(u8),
+    /// Any press.
+    AnyPress,
+    AnyRelease,
+    /// Any lighting event.
+    AnyLighting,
+    Any,
+}
+
+impl Matcher {
+    /// Whether `event`, emitted for `pushed`, matches.
+    fn matches(self, event: KeyEvent, pushed: Option<InputEvent>) -> bool {
+        let event = event.resolve_current(pushed);
+        match self {
+            Matcher::Is(expected) => event == expected,
+            Matcher::Press(key) => event == KeyEvent::Press(key),
+            Matcher::Release(key) => event == KeyEvent::Depress(key),
+            Matcher::AnyPress => matches!(event, KeyEvent::Press(_)),
+            Matcher::AnyRelease => matches!(event, KeyEvent::Depress(_)),
+            Matcher::AnyLighting => matches!(event, KeyEvent::Lighting(..)),
+            Matcher::Any => true,
+        }
+    }
+}
+
+/// A machine being driven by a test.
+pub struct Scenario {
+    clock: TickerClock,
+    machine: GlobalState<TickerClock>,
+    /// Events emitted since the last expectation, with the event pushed when
+    /// they were.
+    emitted: Vec<(KeyEvent, Option<InputEvent>)>,
+}
+
+impl Scenario {
+    /// A scenario running the machine starting at `initial`, at time zero.
+    pub fn new(initial: &'static dyn DynState) -> Self {
+        let clock = TickerClock(0);
+        Self {
+            machine: GlobalState::new(initial, clock.now()),
+            clock,
+            emitted: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, pushed: Option<InputEvent>) {
+        let events = self.machine.emitted();
+        self.emitted
+            .extend(events.iter().map(|&event| (event, pushed)));
+    }
+
+    /// Push `event` at the current time.
+    pub fn push(&mut self, event: InputEvent) -> &mut Self {
+        self.machine.push(self.clock.now(), event);
+        self.record(Some(event));
+        self
+    }
+
+    pub fn press(&mut self, key: u8) -> &mut Self {
+        self.push(InputEvent::Press(key))
+    }
+
+    pub fn release(&mut self, key: u8) -> &mut Self {
+        self.push(InputEvent::Depress(key))
+    }
+
+    /// Move time forward by `ms` milliseconds, taking the timed transitions
+    /// that come due on the way at their deadlines.
+    pub fn advance_ms(&mut self, ms: u32) -> &mut Self {
+        let until = self.clock.0.wrapping_add(ms);
+        while let Some(deadline) = self.machine.next_deadline(self.clock.now()) {
+            let deadline = deadline.duration_since_epoch().integer();
+            if deadline > until || deadline <= self.clock.0 {
+                break;
+            }
+            self.clock.0 = deadline;
+            self.machine.tick(self.clock.now());
+            self.record(None);
+        }
+        self.clock.0 = until;
+        self.machine.tick(self.clock.now());
+        self.record(None);
+        self
+    }
+
+    /// The machine being driven.
+    pub fn machine(&self) -> &GlobalState<TickerClock> {
+        &self.machine
+    }
+
+    pub fn clock(&self) -> &TickerClock {
+        &self.clock
+    }
+
+    /// What's been emitted since the last expectation, with `PressCurrent`
+    /// and `DepressCurrent` resolved.
+    pub fn emitted(&self) -> Vec<KeyEvent> {
+        self.emitted
+            .iter()
+            .map(|&(event, pushed)| event.resolve_current(pushed))
+            .collect()
+    }
+
+    /// Check that exactly `expected` was emitted since the last expectation,
+    /// `PressCurrent` and `DepressCurrent` being compared as what they
+    /// resolve to.
+    #[track_caller]
+    pub fn expect_emitted(&mut self, expected: &[KeyEvent]) -> &mut Self {
+        let emitted = self.emitted();
+        assert_eq!(emitted, expected, "unexpected emissions");
+        self.emitted.clear();
+        self
+    }
+
+    /// Check that what was emitted since the last expectation matches
+    /// `matchers`, one for one.
+    #[track_caller]
+    pub fn expect_matching(&mut self, matchers: &[Matcher]) -> &mut Self {
+        let matched = self.emitted.len() == matchers.len()
+            && self
+                .emitted
+                .iter()
+                .zip(matchers)
+                .all(|(&(event, pushed), matcher)| matcher.matches(event, pushed));
+        assert!(
+            matched,
+            "emitted {:?}, which doesn't match {:?}",
+            self.emitted(),
+            matchers
+        );
+        self.emitted.clear();
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_nothing(&mut self) -> &mut Self {
+        self.expect_emitted(&[])
+    }
+
+    /// Check the name of the state the machine is in.
+    #[track_caller]
+    pub fn expect_state(&mut self, name: &str) -> &mut Self {
+        assert_eq!(
+            self.machine.current_state.name(),
+            name,
+            "in the wrong state"
+        );
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Matcher, Scenario};
+    use crate::behaviors::hold_tap;
+    use crate::time::Duration;
+    use crate::{KeyEvent, Lighting};
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(200),
+        }
+    }
+
+    #[test]
+    fn scenario() {
+        Scenario::new(home_a::IDLE.as_dyn())
+            .press(1)
+            .advance_ms(100)
+            .expect_nothing()
+            .release(1)
+            .expect_emitted(&[
+                KeyEvent::Press(6),
+                KeyEvent::Depress(6),
+                KeyEvent::Lighting(1, Lighting::Tapped),
+            ])
+            .press(1)
+            .advance_ms(500)
+            .expect_state("home_a::HOLD")
+            .expect_matching(&[Matcher::Press(0xe1), Matcher::AnyLighting])
+            .press(3)
+            .expect_matching(&[Matcher::Is(KeyEvent::Press(3))])
+            .release(3)
+            .release(1)
+            .expect_matching(&[Matcher::Release(3), Matcher::AnyRelease]);
+    }
+
+    #[test]
+    fn deadlines_on_the_way() {
+        let mut scenario = Scenario::new(home_a::IDLE.as_dyn());
+        scenario.press(1).advance_ms(500);
+        assert_eq!(scenario.clock().0, 500);
+        // the hold was taken at the term, so releasing after it is no tap
+        scenario.release(1).expect_matching(&[
+            Matcher::Press(0xe1),
+            Matcher::AnyLighting,
+            Matcher::Release(0xe1),
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't match")]
+    fn mismatch() {
+        Scenario::new(home_a::IDLE.as_dyn())
+            .press(3)
+            .expect_matching(&[Matcher::AnyRelease]);
+    }
+}
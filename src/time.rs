@@ -0,0 +1,309 @@
+//! The time types the machines are written against.
+//!
+//! The machines only need to measure the time between two instants and add
+//! or subtract a duration, so they are generic over a [`Clock`] giving an
+//! [`Instant`] that can do that rather than over a particular time library.
+//! Durations are this crate's own [`Duration`], kept in microseconds, rather
+//! than any time library's, so the same type serves millisecond terms and
+//! finer timings.
+//!
+//! Measured durations saturate rather than overflow or panic. Instants
+//! further apart than [`Instant::max_duration`] can't be told apart from
+//! closer ones on clocks that wrap, which for an `embedded_time` clock is
+//! half the range of its counter, and at most `u32::MAX` milliseconds, about
+//! 49 days. [`GlobalState`](crate::GlobalState) keeps its own timers within
+//! that, so a state held for longer reads as held for the maximum.
+//!
+//! Every `embedded_time` clock is a [`Clock`], which covers the
+//! [`MonotonicClock`](crate::clock::MonotonicClock) and fugit adapters too.
+//! [`HostClock`] uses [`core::time::Duration`] since some starting point, for
+//! simulating a keyboard on a host.
+
+use core::fmt;
+
+use embedded_time::duration::Microseconds;
+
+/// A length of time, to the microsecond.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Duration(u64);
+
+impl Duration {
+    pub(crate) const ZERO: Self = Self(0);
+
+    pub(crate) const fn from_millis(millis: u32) -> Self {
+        Self(millis as u64 * 1_000)
+    }
+
+    pub(crate) const fn from_micros(micros: u64) -> Self {
+        Self(micros)
+    }
+
+    /// The whole milliseconds, saturating at `u32::MAX`.
+    pub(crate) const fn as_millis(self) -> u32 {
+        let millis = self.0 / 1_000;
+        if millis > u32::MAX as u64 {
+            u32::MAX
+        } else {
+            millis as u32
+        }
+    }
+
+    pub(crate) const fn as_micros(self) -> u64 {
+        self.0
+    }
+
+    pub(crate) const fn saturating_add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    pub(crate) const fn saturating_mul(self, n: u64) -> Self {
+        Self(self.0.saturating_mul(n))
+    }
+}
+
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_multiple_of(1_000) {
+            write!(f, "{} milliseconds", self.0 / 1_000)
+        } else {
+            write!(f, "{} microseconds", self.0)
+        }
+    }
+}
+
+pub trait Instant: Copy + Ord + fmt::Debug {
+    /// The time from `earlier` to `self`, saturating at
+    /// [`Instant::max_duration`]. If `earlier` seems to be later it is taken
+    /// to be from before the clock last wrapped, so this also saturates.
+    fn duration_since(&self, earlier: &Self) -> Duration;
+
+    /// The longest duration that can be measured between two instants.
+    fn max_duration() -> Duration;
+
+    fn checked_add(self, duration: Duration) -> Option<Self>;
+
+    fn checked_sub(self, duration: Duration) -> Option<Self>;
+
+    /// The time since the clock started, saturating, for timestamps shown
+    /// to the host.
+    fn since_start(&self) -> Duration;
+}
+
+pub trait Clock {
+    type Instant: Instant;
+}
+
+/// `ticks` of a clock counting `scale` seconds, rounded down and saturating.
+fn ticks_to_duration(ticks: u64, scale: embedded_time::rate::Fraction) -> Duration {
+    let micros =
+        ticks as u128 * *scale.numerator() as u128 * 1_000_000 / *scale.denominator() as u128;
+    Duration::from_micros(micros.try_into().unwrap_or(u64::MAX))
+}
+
+impl<C: embedded_time::Clock + fmt::Debug> Instant for embedded_time::Instant<C>
+where
+    u64: From<C::T>,
+    C::T: TryFrom<u64>,
+{
+    fn duration_since(&self, earlier: &Self) -> Duration {
+        self.checked_duration_since(earlier)
+            .map_or(Self::max_duration(), |elapsed| {
+                ticks_to_duration(elapsed.integer().into(), C::SCALING_FACTOR)
+                    .min(Self::max_duration())
+            })
+    }
+
+    fn max_duration() -> Duration {
+        // the counter is a u32 or u64, and instants up to half its range apart
+        // can be ordered
+        let half = u64::MAX >> (64 - core::mem::size_of::<C::T>() * 8 + 1);
+        ticks_to_duration(half, C::SCALING_FACTOR).min(Duration::from_millis(u32::MAX))
+    }
+
+    fn checked_add(self, duration: Duration) -> Option<Self> {
+        embedded_time::Instant::checked_add(self, Microseconds(duration.as_micros()))
+    }
+
+    fn checked_sub(self, duration: Duration) -> Option<Self> {
+        embedded_time::Instant::checked_sub(self, Microseconds(duration.as_micros()))
+    }
+
+    fn since_start(&self) -> Duration {
+        ticks_to_duration(
+            self.duration_since_epoch().integer().into(),
+            C::SCALING_FACTOR,
+        )
+    }
+}
+
+impl<C: embedded_time::Clock + fmt::Debug> Clock for C
+where
+    u64: From<C::T>,
+    C::T: TryFrom<u64>,
+{
+    type Instant = embedded_time::Instant<C>;
+}
+
+/// A host duration as one of ours, saturating.
+fn from_host(duration: core::time::Duration) -> Duration {
+    Duration::from_micros(duration.as_micros().try_into().unwrap_or(u64::MAX))
+}
+
+impl Instant for core::time::Duration {
+    fn duration_since(&self, earlier: &Self) -> Duration {
+        match core::time::Duration::checked_sub(*self, *earlier) {
+            Some(elapsed) => from_host(elapsed).min(Self::max_duration()),
+            None => Self::max_duration(),
+        }
+    }
+
+    fn max_duration() -> Duration {
+        Duration::from_millis(u32::MAX)
+    }
+
+    fn checked_add(self, duration: Duration) -> Option<Self> {
+        core::time::Duration::checked_add(
+            self,
+            core::time::Duration::from_micros(duration.as_micros()),
+        )
+    }
+
+    fn checked_sub(self, duration: Duration) -> Option<Self> {
+        core::time::Duration::checked_sub(
+            self,
+            core::time::Duration::from_micros(duration.as_micros()),
+        )
+    }
+
+    fn since_start(&self) -> Duration {
+        from_host(*self)
+    }
+}
+
+/// Instants are the [`core::time::Duration`] since the simulation started.
+#[derive(Debug)]
+pub(crate) struct HostClock;
+
+impl Clock for HostClock {
+    type Instant = core::time::Duration;
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::{HostClock, Instant};
+    use crate::behaviors::hold_tap;
+    use crate::clock::MonotonicClock;
+    use crate::tests::TickerClock;
+    use crate::{
+        GlobalState, InputEvent, KeyEvent, State, StateId, Transition, TransitionCondition,
+    };
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: crate::time::Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn host_clock() {
+        let mut state = GlobalState::<HostClock>::new(home_a::IDLE.as_dyn(), Duration::ZERO);
+
+        state.push(Duration::from_millis(1), InputEvent::Press(1));
+        assert_eq!(
+            state.next_deadline(Duration::from_millis(1)),
+            Some(Duration::from_millis(11))
+        );
+        assert_eq!(state.tick(Duration::from_micros(10_500)), &[]);
+        assert_eq!(
+            state.tick(Duration::from_millis(12))[0],
+            KeyEvent::Press(0xe1)
+        );
+    }
+
+    #[test]
+    fn wrapping_clock() {
+        let mut clock = TickerClock(u32::MAX - 5);
+        let mut state = GlobalState::<TickerClock>::new(home_a::IDLE.as_dyn(), clock.now());
+
+        state.push(clock.now(), InputEvent::Press(1));
+        clock.tick_n(9);
+        assert_eq!(state.tick(clock.now()), &[]);
+        clock.tick_n(2);
+        assert_eq!(state.tick(clock.now())[0], KeyEvent::Press(0xe1));
+    }
+
+    #[test]
+    fn saturating_durations() {
+        let max = <TickerClock as super::Clock>::Instant::max_duration();
+        assert_eq!(max, crate::time::Duration::from_millis(i32::MAX as u32));
+
+        let mut clock = TickerClock(0);
+        let start = clock.now();
+        clock.tick_n(u32::MAX - 10);
+        // looks like it is before `start`, so is taken to have wrapped
+        assert_eq!(clock.now().duration_since(&start), max);
+
+        type Micros = embedded_time::Instant<MonotonicClock<fn() -> u64, 1_000_000>>;
+        let later = Micros::new(u64::MAX / 4);
+        assert_eq!(
+            later.duration_since(&Micros::new(0)),
+            crate::time::Duration::from_millis(u32::MAX)
+        );
+    }
+
+    static WAITING: State = State {
+        name: "waiting",
+        id: StateId(1),
+        transitions: &[&WAITED],
+    };
+
+    // longer than a u32 of microseconds
+    static WAITED: Transition = Transition {
+        conditions: &[TransitionCondition::ElapsedGreaterMicros(
+            crate::time::Duration::from_micros(5_000_000_001),
+        )],
+        key_event_emissions: &[KeyEvent::Press(4)],
+        internal_event_emissions: &[],
+        target: &WAITING,
+    };
+
+    #[test]
+    fn long_fine_timings() {
+        let mut state = GlobalState::<HostClock>::new(WAITING.as_dyn(), Duration::ZERO);
+        assert_eq!(
+            state.next_deadline(Duration::ZERO),
+            Some(Duration::from_micros(5_000_000_001))
+        );
+        assert_eq!(state.tick(Duration::from_micros(5_000_000_000)), &[]);
+        assert_eq!(
+            state.tick(Duration::from_micros(5_000_000_001)),
+            &[KeyEvent::Press(4)]
+        );
+    }
+
+    static IDLE: State = State {
+        name: "idle",
+        id: StateId(0),
+        transitions: &[],
+    };
+
+    #[test]
+    fn long_dwell() {
+        let mut clock = TickerClock(0);
+        let mut state = GlobalState::<TickerClock>::new(IDLE.as_dyn(), clock.now());
+        let max = <TickerClock as super::Clock>::Instant::max_duration();
+
+        // tick slowly through several wraps of the clock, the state shouldn't
+        // look like it was entered any more recently
+        for _ in 0..10 {
+            clock.tick_n(1_500_000_000);
+            state.tick(clock.now());
+        }
+        assert_eq!(state.context(clock.now()).elapsed, max);
+    }
+}
@@ -0,0 +1,159 @@
+//! A trace of recent events for debugging timing issues.
+//!
+//! [`TraceBuffer`] keeps the last `N` input events given to the machine and
+//! key events it emitted, overwriting the oldest once full. It can be read
+//! out by the host over raw HID, see [`raw_hid`](crate::raw_hid). It's
+//! filled by [`GlobalState::push_traced`] and [`GlobalState::tick_traced`],
+//! which mask what was typed whenever the machine is private, see
+//! [`privacy`](crate::privacy).
+//!
+//! [`GlobalState::push_traced`]: crate::GlobalState::push_traced
+//! [`GlobalState::tick_traced`]: crate::GlobalState::tick_traced
+
+use crate::time::{self, Instant};
+use crate::{InputEvent, KeyEvent};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum TraceEvent {
+    Input(InputEvent),
+    Output(KeyEvent),
+}
+
+#[derive(Debug)]
+pub(crate) struct TraceEntry<Clock: time::Clock> {
+    pub(crate) time: Clock::Instant,
+    pub(crate) event: TraceEvent,
+}
+
+impl<Clock: time::Clock> Clone for TraceEntry<Clock> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<Clock: time::Clock> Copy for TraceEntry<Clock> {}
+
+impl<Clock: time::Clock> PartialEq for TraceEntry<Clock> {
+    fn eq(&self, other: &Self) -> bool {
+        self.time == other.time && self.event == other.event
+    }
+}
+
+pub(crate) struct TraceBuffer<Clock: time::Clock, const N: usize> {
+    entries: [Option<TraceEntry<Clock>>; N],
+    head: usize,
+    len: usize,
+}
+
+impl<Clock: time::Clock, const N: usize> TraceBuffer<Clock, N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            entries: [const { None }; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Record `event`, masked if `private`.
+    pub(crate) fn record(
+        &mut self,
+        current_time: Clock::Instant,
+        event: TraceEvent,
+        private: bool,
+    ) {
+        let event = match (private, event) {
+            (true, TraceEvent::Input(event)) => TraceEvent::Input(event.masked()),
+            (true, TraceEvent::Output(event)) => TraceEvent::Output(event.masked()),
+            (false, event) => event,
+        };
+        let entry = Some(TraceEntry {
+            time: current_time,
+            event,
+        });
+
+        if self.len == N {
+            self.entries[self.head] = entry;
+            self.head = (self.head + 1) % N;
+        } else {
+            self.entries[(self.head + self.len) % N] = entry;
+            self.len += 1;
+        }
+    }
+
+    pub(crate) fn record_input(
+        &mut self,
+        current_time: Clock::Instant,
+        event: InputEvent,
+        private: bool,
+    ) {
+        self.record(current_time, TraceEvent::Input(event), private);
+    }
+
+    pub(crate) fn record_outputs(
+        &mut self,
+        current_time: Clock::Instant,
+        events: &[KeyEvent],
+        private: bool,
+    ) {
+        for event in events {
+            self.record(current_time, TraceEvent::Output(*event), private);
+        }
+    }
+
+    /// Remove and return the oldest entry.
+    pub(crate) fn pop(&mut self) -> Option<TraceEntry<Clock>> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let entry = self.entries[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        entry
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TraceBuffer, TraceEntry, TraceEvent};
+    use crate::tests::TickerClock;
+    use crate::{InputEvent, KeyEvent};
+
+    #[test]
+    fn keeps_latest() {
+        let mut clock = TickerClock(0);
+        let mut trace = TraceBuffer::<TickerClock, 3>::new();
+
+        trace.record_input(clock.now(), InputEvent::Press(1), false);
+        clock.tick();
+        trace.record_outputs(
+            clock.now(),
+            &[KeyEvent::Press(4), KeyEvent::Depress(4)],
+            false,
+        );
+        clock.tick();
+        trace.record_input(clock.now(), InputEvent::Depress(1), false);
+
+        assert_eq!(trace.len(), 3);
+        assert_eq!(
+            trace.pop(),
+            Some(TraceEntry {
+                time: TickerClock(1).now(),
+                event: TraceEvent::Output(KeyEvent::Press(4)),
+            })
+        );
+        assert_eq!(
+            trace.pop().map(|e| e.event),
+            Some(TraceEvent::Output(KeyEvent::Depress(4)))
+        );
+        assert_eq!(
+            trace.pop().map(|e| e.event),
+            Some(TraceEvent::Input(InputEvent::Depress(1)))
+        );
+        assert_eq!(trace.pop(), None);
+    }
+}
@@ -0,0 +1,181 @@
+//! Device side key repeat.
+//!
+//! Hosts only auto repeat keys they consider repeatable, so keys they don't
+//! are repeated here instead. [`Typematic`] watches the key events a machine
+//! emits and, once the most recently pressed key has been held for the
+//! repeat delay, re-sends it every repeat interval.
+//!
+//! Only key presses repeat. Unicode characters are emitted without a release
+//! to stop on, so they aren't repeated.
+
+use crate::time::{self, Duration, Instant};
+use crate::{KeyCode, KeyEvent};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct RepeatRate {
+    delay: Duration,
+    interval: Duration,
+}
+
+struct Held<Clock: time::Clock> {
+    key: KeyCode,
+    rate: RepeatRate,
+    since: Clock::Instant,
+    repeating: bool,
+}
+
+struct Typematic<Clock: time::Clock> {
+    rate: RepeatRate,
+    /// Per key replacements for `rate`, a `None` rate disables repeat for
+    /// that key.
+    overrides: &'static [(KeyCode, Option<RepeatRate>)],
+    held: Option<Held<Clock>>,
+}
+
+impl<Clock: time::Clock> Typematic<Clock> {
+    const fn new(rate: RepeatRate, overrides: &'static [(KeyCode, Option<RepeatRate>)]) -> Self {
+        Self {
+            rate,
+            overrides,
+            held: None,
+        }
+    }
+
+    fn rate_for(&self, key: KeyCode) -> Option<RepeatRate> {
+        self.overrides
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map_or(Some(self.rate), |(_, rate)| *rate)
+    }
+
+    /// Track the events emitted by the machine.
+    ///
+    /// Pressing another key cancels the repeat of the previous one, and
+    /// releasing the repeating key stops it.
+    fn observe(&mut self, current_time: Clock::Instant, events: &[KeyEvent]) {
+        for event in events {
+            match *event {
+                KeyEvent::Press(key) => {
+                    self.held = self.rate_for(key).map(|rate| Held {
+                        key,
+                        rate,
+                        since: current_time,
+                        repeating: false,
+                    });
+                }
+                KeyEvent::Depress(key) if self.held.as_ref().map(|h| h.key) == Some(key) => {
+                    self.held = None;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Re-send the held key if it is due, as a release followed by a press so
+    /// that it is still held afterwards.
+    fn tick(&mut self, current_time: Clock::Instant) -> Option<[KeyEvent; 2]> {
+        let held = self.held.as_mut()?;
+
+        let elapsed = current_time.duration_since(&held.since);
+
+        let wait = if held.repeating {
+            held.rate.interval
+        } else {
+            held.rate.delay
+        };
+
+        if elapsed < wait {
+            return None;
+        }
+
+        // keep to the rate when ticks are late, but don't catch up with a
+        // burst after a long gap
+        let next = held.since.checked_add(wait).unwrap_or(current_time);
+        held.since = if current_time.duration_since(&next) >= held.rate.interval {
+            current_time
+        } else {
+            next
+        };
+        held.repeating = true;
+
+        Some([KeyEvent::Depress(held.key), KeyEvent::Press(held.key)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RepeatRate, Typematic};
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::KeyEvent;
+
+    const RATE: RepeatRate = RepeatRate {
+        delay: Duration::from_millis(20),
+        interval: Duration::from_millis(5),
+    };
+
+    static OVERRIDES: [(u8, Option<RepeatRate>); 1] = [(9, None)];
+
+    #[test]
+    fn repeats_after_delay() {
+        let mut clock = TickerClock(0);
+        let mut typematic = Typematic::<TickerClock>::new(RATE, &OVERRIDES);
+
+        typematic.observe(clock.now(), &[KeyEvent::Press(1)]);
+
+        clock.tick_n(19);
+        assert_eq!(typematic.tick(clock.now()), None);
+
+        clock.tick();
+        assert_eq!(
+            typematic.tick(clock.now()),
+            Some([KeyEvent::Depress(1), KeyEvent::Press(1)])
+        );
+
+        clock.tick_n(4);
+        assert_eq!(typematic.tick(clock.now()), None);
+
+        clock.tick();
+        assert_eq!(
+            typematic.tick(clock.now()),
+            Some([KeyEvent::Depress(1), KeyEvent::Press(1)])
+        );
+
+        // a late tick doesn't push back the next repeat
+        clock.tick_n(7);
+        assert!(typematic.tick(clock.now()).is_some());
+        clock.tick_n(3);
+        assert!(typematic.tick(clock.now()).is_some());
+
+        // nor does a long gap repeat more than once
+        clock.tick_n(50);
+        assert!(typematic.tick(clock.now()).is_some());
+        assert_eq!(typematic.tick(clock.now()), None);
+
+        typematic.observe(clock.now(), &[KeyEvent::Depress(1)]);
+        clock.tick_n(50);
+        assert_eq!(typematic.tick(clock.now()), None);
+    }
+
+    #[test]
+    fn other_press_cancels_repeat() {
+        let mut clock = TickerClock(0);
+        let mut typematic = Typematic::<TickerClock>::new(RATE, &OVERRIDES);
+
+        typematic.observe(clock.now(), &[KeyEvent::Press(1)]);
+        clock.tick_n(10);
+        typematic.observe(clock.now(), &[KeyEvent::Press(9)]);
+
+        clock.tick_n(50);
+        assert_eq!(typematic.tick(clock.now()), None);
+
+        typematic.observe(clock.now(), &[KeyEvent::Press(2)]);
+        clock.tick_n(10);
+        typematic.observe(clock.now(), &[KeyEvent::Depress(1)]);
+        clock.tick_n(10);
+        assert_eq!(
+            typematic.tick(clock.now()),
+            Some([KeyEvent::Depress(2), KeyEvent::Press(2)])
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Unicode entry mode.
+//!
+//! After the trigger key is pressed, following key presses are captured
+//! instead of reaching the machine. They are translated into a single
+//! [`KeyEvent::Unicode`] either once they spell out a compose sequence, or as
+//! a hex code point when the commit key is pressed. The cancel key, a key
+//! that can't lead anywhere, or `timeout` passing between two keys abandons
+//! the entry.
+//!
+//! Key codes are HID keyboard usage ids.
+
+use crate::time::{self, Duration, Instant};
+use crate::{InputEvent, KeyCode, KeyEvent, KeySet};
+
+const MAX_ENTRY: usize = 8;
+
+/// Value of a hex digit key.
+fn hex_digit(key: KeyCode) -> Option<u32> {
+    match key {
+        // a - f
+        0x04..=0x09 => Some(key as u32 - 0x04 + 10),
+        // 1 - 9
+        0x1e..=0x26 => Some(key as u32 - 0x1e + 1),
+        0x27 => Some(0),
+        _ => None,
+    }
+}
+
+struct UnicodeEntry<Clock: time::Clock> {
+    trigger: KeyCode,
+    commit: KeyCode,
+    cancel: KeyCode,
+    timeout: Duration,
+    compose: &'static [(&'static [KeyCode], char)],
+    /// When the last key of the current entry was pressed.
+    last_key: Option<Clock::Instant>,
+    entry: [KeyCode; MAX_ENTRY],
+    len: usize,
+    /// Captured presses, whose releases are captured too.
+    captured: KeySet,
+}
+
+impl<Clock: time::Clock> UnicodeEntry<Clock> {
+    const fn new(
+        trigger: KeyCode,
+        commit: KeyCode,
+        cancel: KeyCode,
+        timeout: Duration,
+        compose: &'static [(&'static [KeyCode], char)],
+    ) -> Self {
+        Self {
+            trigger,
+            commit,
+            cancel,
+            timeout,
+            compose,
+            last_key: None,
+            entry: [0; MAX_ENTRY],
+            len: 0,
+            captured: KeySet::empty(),
+        }
+    }
+
+    fn entry(&self) -> &[KeyCode] {
+        &self.entry[..self.len]
+    }
+
+    fn hex(&self) -> Option<char> {
+        if self.len == 0 || self.len > 6 {
+            return None;
+        }
+
+        let code = self
+            .entry()
+            .iter()
+            .try_fold(0, |code, key| Some(code << 4 | hex_digit(*key)?))?;
+
+        char::from_u32(code)
+    }
+
+    fn finish(&mut self) {
+        self.last_key = None;
+        self.len = 0;
+    }
+
+    /// Returns the event if it should still be passed on to the machine.
+    fn push(
+        &mut self,
+        current_time: Clock::Instant,
+        event: InputEvent,
+        mut emit: impl FnMut(KeyEvent),
+    ) -> Option<InputEvent> {
+        // timed out since the last key, without a tick in between
+        self.tick(current_time);
+
+        let key = match event {
+            InputEvent::Depress(key) if self.captured.remove(key) => return None,
+            InputEvent::Press(key) if key == self.trigger && self.last_key.is_none() => {
+                self.captured.insert(key);
+                self.last_key = Some(current_time);
+                return None;
+            }
+            InputEvent::Press(key) if self.last_key.is_some() => key,
+            event => return Some(event),
+        };
+
+        self.captured.insert(key);
+        self.last_key = Some(current_time);
+
+        if key == self.cancel {
+            self.finish();
+            return None;
+        }
+
+        if key == self.commit {
+            if let Some(c) = self.hex() {
+                emit(KeyEvent::Unicode(c));
+            }
+            self.finish();
+            return None;
+        }
+
+        if self.len == MAX_ENTRY {
+            self.finish();
+            return None;
+        }
+
+        self.entry[self.len] = key;
+        self.len += 1;
+
+        let entry = self.entry();
+        if let Some((_, c)) = self.compose.iter().find(|(seq, _)| *seq == entry) {
+            emit(KeyEvent::Unicode(*c));
+            self.finish();
+            return None;
+        }
+
+        let composing = self.compose.iter().any(|(seq, _)| seq.starts_with(entry));
+        let hex = entry.iter().all(|k| hex_digit(*k).is_some());
+        if !composing && !hex {
+            self.finish();
+        }
+
+        None
+    }
+
+    /// Abandons the entry if no key was pressed for `timeout`.
+    fn tick(&mut self, current_time: Clock::Instant) {
+        let Some(last_key) = self.last_key else {
+            return;
+        };
+
+        let elapsed = current_time.duration_since(&last_key);
+
+        if elapsed >= self.timeout {
+            self.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnicodeEntry;
+    use crate::tests::TickerClock;
+    use crate::time::Duration;
+    use crate::{InputEvent, KeyEvent};
+
+    const TRIGGER: u8 = 0x68;
+    const COMMIT: u8 = 0x2c;
+    const CANCEL: u8 = 0x29;
+
+    // ' then e
+    static COMPOSE: [(&[u8], char); 1] = [(&[0x34, 0x08], 'é')];
+
+    fn tap(
+        entry: &mut UnicodeEntry<TickerClock>,
+        clock: &TickerClock,
+        key: u8,
+        out: &mut Vec<KeyEvent>,
+    ) -> Option<InputEvent> {
+        let pressed = entry.push(clock.now(), InputEvent::Press(key), |e| out.push(e));
+        let released = entry.push(clock.now(), InputEvent::Depress(key), |e| out.push(e));
+        assert_eq!(pressed.is_some(), released.is_some());
+        pressed
+    }
+
+    #[test]
+    fn hex_entry() {
+        let clock = TickerClock(0);
+        let mut entry = UnicodeEntry::new(
+            TRIGGER,
+            COMMIT,
+            CANCEL,
+            Duration::from_millis(100),
+            &COMPOSE,
+        );
+        let mut out = Vec::new();
+
+        assert_eq!(
+            tap(&mut entry, &clock, 0x04, &mut out),
+            Some(InputEvent::Press(0x04))
+        );
+
+        // U+00E9
+        for key in [TRIGGER, 0x27, 0x27, 0x08, 0x26, COMMIT] {
+            assert_eq!(tap(&mut entry, &clock, key, &mut out), None);
+        }
+        assert_eq!(out, [KeyEvent::Unicode('é')]);
+
+        assert_eq!(
+            tap(&mut entry, &clock, 0x04, &mut out),
+            Some(InputEvent::Press(0x04))
+        );
+    }
+
+    #[test]
+    fn compose_entry() {
+        let clock = TickerClock(0);
+        let mut entry = UnicodeEntry::new(
+            TRIGGER,
+            COMMIT,
+            CANCEL,
+            Duration::from_millis(100),
+            &COMPOSE,
+        );
+        let mut out = Vec::new();
+
+        for key in [TRIGGER, 0x34, 0x08] {
+            assert_eq!(tap(&mut entry, &clock, key, &mut out), None);
+        }
+        assert_eq!(out, [KeyEvent::Unicode('é')]);
+    }
+
+    #[test]
+    fn cancel_and_timeout() {
+        let mut clock = TickerClock(0);
+        let mut entry = UnicodeEntry::new(
+            TRIGGER,
+            COMMIT,
+            CANCEL,
+            Duration::from_millis(100),
+            &COMPOSE,
+        );
+        let mut out = Vec::new();
+
+        for key in [TRIGGER, 0x27, CANCEL] {
+            tap(&mut entry, &clock, key, &mut out);
+        }
+        assert_eq!(
+            tap(&mut entry, &clock, COMMIT, &mut out),
+            Some(InputEvent::Press(COMMIT))
+        );
+
+        tap(&mut entry, &clock, TRIGGER, &mut out);
+        tap(&mut entry, &clock, 0x27, &mut out);
+        clock.tick_n(100);
+        entry.tick(clock.now());
+        assert_eq!(
+            tap(&mut entry, &clock, COMMIT, &mut out),
+            Some(InputEvent::Press(COMMIT))
+        );
+
+        // timed out with no tick before the next key
+        tap(&mut entry, &clock, TRIGGER, &mut out);
+        tap(&mut entry, &clock, 0x27, &mut out);
+        clock.tick_n(100);
+        assert_eq!(
+            tap(&mut entry, &clock, COMMIT, &mut out),
+            Some(InputEvent::Press(COMMIT))
+        );
+
+        assert!(out.is_empty());
+    }
+}
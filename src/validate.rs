@@ -0,0 +1,435 @@
+//! Checking machines at compile time.
+//!
+//! [`validate`] walks every state reachable from a machine's initial state
+//! and panics if the machine is broken, so that in const position a broken
+//! keymap fails the build:
+//!
+//! ```ignore
+//! const _: () = validate(&home_a::IDLE);
+//! ```
+//!
+//! A machine is broken if two of its states share an id, if a state has no
+//! transitions, if a transition emits more than [`MAX_EMISSIONS`] events or
+//! uses a layer past [`MAX_LAYERS`], or if a state can't lead back to the
+//! initial state, which would leave the machine stuck there.
+//!
+//! [`MAX_LAYERS`]: crate::MAX_LAYERS
+//!
+//! States are told apart by id. Two states with the same id are only caught
+//! if their names differ.
+//!
+//! Machines run with [`MatchPolicy::All`](crate::MatchPolicy::All) take every
+//! transition that matches at once, so [`validate_merged`] also checks that a
+//! state's transitions together emit no more than [`MAX_EMISSIONS`] events of
+//! each kind, as any of them could match together.
+
+use crate::{State, Transition, MAX_LAYERS};
+
+/// The most states a validated machine can have.
+pub(crate) const MAX_STATES: usize = 256;
+/// The most key or internal events one transition can emit.
+pub(crate) const MAX_EMISSIONS: usize = 16;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum ValidationError {
+    /// The named state has no transitions.
+    NoTransitions(&'static str),
+    /// A transition of the named state emits more than [`MAX_EMISSIONS`]
+    /// events.
+    TooManyEmissions(&'static str),
+    /// The named state can't lead back to the initial state.
+    Stuck(&'static str),
+    /// More than [`MAX_STATES`] states are reachable.
+    TooManyStates,
+    /// The named states have the same id.
+    DuplicateId(&'static str, &'static str),
+    /// A transition of the named state uses a layer past
+    /// [`MAX_LAYERS`](crate::MAX_LAYERS).
+    InvalidLayer(&'static str),
+    /// The transitions of the named state emit more than [`MAX_EMISSIONS`]
+    /// events between them.
+    TooManyMerged(&'static str),
+}
+
+impl ValidationError {
+    pub(crate) const fn message(&self) -> &'static str {
+        match self {
+            ValidationError::NoTransitions(_) => "a state has no transitions",
+            ValidationError::TooManyEmissions(_) => "a transition emits too many events",
+            ValidationError::Stuck(_) => "a state can't lead back to the initial state",
+            ValidationError::TooManyStates => "the machine has too many states",
+            ValidationError::DuplicateId(..) => "two states have the same id",
+            ValidationError::InvalidLayer(_) => "a transition uses a layer past the last",
+            ValidationError::TooManyMerged(_) => {
+                "a state's transitions emit too many events together"
+            }
+        }
+    }
+}
+
+const fn same_name(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Whether every layer `transition` checks or changes is one there can be.
+pub(crate) const fn layers_in_range(transition: &Transition) -> bool {
+    let mut i = 0;
+    while i < transition.conditions.len() {
+        if let Some(layer) = transition.conditions[i].layer() {
+            if layer as usize >= MAX_LAYERS {
+                return false;
+            }
+        }
+        i += 1;
+    }
+    let mut i = 0;
+    while i < transition.internal_event_emissions.len() {
+        if let Some(layer) = transition.internal_event_emissions[i].layer() {
+            if layer as usize >= MAX_LAYERS {
+                return false;
+            }
+        }
+        i += 1;
+    }
+    true
+}
+
+/// The index of the state with the id of `state` in the first `len` of
+/// `states`.
+const fn position(
+    states: &[Option<&'static State>],
+    len: usize,
+    state: &'static State,
+) -> Result<Option<usize>, ValidationError> {
+    let mut i = 0;
+    while i < len {
+        if let Some(s) = states[i] {
+            if s.id.0 == state.id.0 {
+                if !same_name(s.name, state.name) {
+                    return Err(ValidationError::DuplicateId(s.name, state.name));
+                }
+                return Ok(Some(i));
+            }
+        }
+        i += 1;
+    }
+    Ok(None)
+}
+
+/// Every state reachable from `initial`, with `initial` first.
+pub(crate) const fn reachable(
+    initial: &'static State,
+) -> Result<([Option<&'static State>; MAX_STATES], usize), ValidationError> {
+    let mut states: [Option<&'static State>; MAX_STATES] = [None; MAX_STATES];
+    states[0] = Some(initial);
+    let mut len = 1;
+
+    let mut next = 0;
+    while next < len {
+        let Some(state) = states[next] else {
+            unreachable!()
+        };
+        let mut t = 0;
+        while t < state.transitions.len() {
+            let target = state.transitions[t].target;
+            let found = match position(&states, len, target) {
+                Ok(found) => found,
+                Err(error) => return Err(error),
+            };
+            if found.is_none() {
+                if len == MAX_STATES {
+                    return Err(ValidationError::TooManyStates);
+                }
+                states[len] = Some(target);
+                len += 1;
+            }
+            t += 1;
+        }
+        next += 1;
+    }
+
+    Ok((states, len))
+}
+
+/// Check the machine starting at `initial`, returning the first problem
+/// found.
+const fn check(initial: &'static State) -> Result<(), ValidationError> {
+    let (states, len) = match reachable(initial) {
+        Ok(reachable) => reachable,
+        Err(error) => return Err(error),
+    };
+
+    let mut i = 0;
+    while i < len {
+        let Some(state) = states[i] else {
+            unreachable!()
+        };
+        if state.transitions.is_empty() {
+            return Err(ValidationError::NoTransitions(state.name));
+        }
+
+        let mut t = 0;
+        while t < state.transitions.len() {
+            let transition = state.transitions[t];
+            if transition.key_event_emissions.len() > MAX_EMISSIONS
+                || transition.internal_event_emissions.len() > MAX_EMISSIONS
+            {
+                return Err(ValidationError::TooManyEmissions(state.name));
+            }
+            if !layers_in_range(transition) {
+                return Err(ValidationError::InvalidLayer(state.name));
+            }
+            t += 1;
+        }
+        i += 1;
+    }
+
+    // then work backwards from the initial state to find the states that
+    // can get back to it
+    let mut returns = [false; MAX_STATES];
+    returns[0] = true;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        let mut i = 0;
+        while i < len {
+            if let (false, Some(state)) = (returns[i], states[i]) {
+                let mut t = 0;
+                while t < state.transitions.len() {
+                    if let Ok(Some(target)) = position(&states, len, state.transitions[t].target) {
+                        if returns[target] {
+                            returns[i] = true;
+                            changed = true;
+                            break;
+                        }
+                    }
+                    t += 1;
+                }
+            }
+            i += 1;
+        }
+    }
+
+    let mut i = 0;
+    while i < len {
+        if let (false, Some(state)) = (returns[i], states[i]) {
+            return Err(ValidationError::Stuck(state.name));
+        }
+        i += 1;
+    }
+
+    Ok(())
+}
+
+/// [`check`], and then that the transitions of each state fit in
+/// [`MAX_EMISSIONS`] when they're all taken.
+const fn check_merged(initial: &'static State) -> Result<(), ValidationError> {
+    if let Err(error) = check(initial) {
+        return Err(error);
+    }
+    let (states, len) = match reachable(initial) {
+        Ok(reachable) => reachable,
+        Err(error) => return Err(error),
+    };
+
+    let mut i = 0;
+    while i < len {
+        let Some(state) = states[i] else {
+            unreachable!()
+        };
+        let (mut key, mut internal) = (0, 0);
+        let mut t = 0;
+        while t < state.transitions.len() {
+            key += state.transitions[t].key_event_emissions.len();
+            internal += state.transitions[t].internal_event_emissions.len();
+            t += 1;
+        }
+        if key > MAX_EMISSIONS || internal > MAX_EMISSIONS {
+            return Err(ValidationError::TooManyMerged(state.name));
+        }
+        i += 1;
+    }
+
+    Ok(())
+}
+
+/// Panic if the machine starting at `initial` is broken, for use in const
+/// position to fail the build.
+pub(crate) const fn validate(initial: &'static State) {
+    if let Err(error) = check(initial) {
+        panic!("{}", error.message());
+    }
+}
+
+/// [`validate`] for machines run with
+/// [`MatchPolicy::All`](crate::MatchPolicy::All).
+pub(crate) const fn validate_merged(initial: &'static State) {
+    if let Err(error) = check_merged(initial) {
+        panic!("{}", error.message());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, check_merged, validate, validate_merged, ValidationError};
+    use crate::behaviors::hold_tap;
+    use crate::time::Duration;
+    use crate::{InternalEvent, KeyEvent, State, StateId, Transition, TransitionCondition};
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(10),
+        }
+    }
+
+    const _: () = validate(&home_a::IDLE);
+    const _: () = validate_merged(&home_a::IDLE);
+
+    static IDLE: State = State {
+        name: "IDLE",
+        id: StateId(0),
+        transitions: &[&IDLE_PRESS],
+    };
+
+    static IDLE_PRESS: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(0)],
+        key_event_emissions: &[],
+        internal_event_emissions: &[],
+        target: &HELD,
+    };
+
+    static HELD: State = State {
+        name: "HELD",
+        id: StateId(1),
+        transitions: &[&HELD_PRESS],
+    };
+
+    static HELD_PRESS: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(1)],
+        key_event_emissions: &[],
+        internal_event_emissions: &[],
+        target: &DONE,
+    };
+
+    static DONE: State = State {
+        name: "DONE",
+        id: StateId(2),
+        transitions: &[&DONE_LOOP],
+    };
+
+    static DONE_LOOP: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(2)],
+        key_event_emissions: &[],
+        internal_event_emissions: &[],
+        target: &DONE,
+    };
+
+    static LOUD: State = State {
+        name: "LOUD",
+        id: StateId(3),
+        transitions: &[&LOUD_PRESS],
+    };
+
+    static LOUD_PRESS: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(0)],
+        key_event_emissions: &[KeyEvent::Press(0); 17],
+        internal_event_emissions: &[],
+        target: &LOUD,
+    };
+
+    static EMPTY: State = State {
+        name: "EMPTY",
+        id: StateId(4),
+        transitions: &[],
+    };
+
+    static CLASH: State = State {
+        name: "CLASH",
+        id: StateId(5),
+        transitions: &[&CLASH_PRESS],
+    };
+
+    static CLASH_PRESS: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(0)],
+        key_event_emissions: &[],
+        internal_event_emissions: &[],
+        target: &CLASH_TOO,
+    };
+
+    static CLASH_TOO: State = State {
+        name: "CLASH_TOO",
+        id: StateId(5),
+        transitions: &[&CLASH_PRESS],
+    };
+
+    static DEEP: State = State {
+        name: "DEEP",
+        id: StateId(6),
+        transitions: &[&DEEP_LAYER],
+    };
+
+    static DEEP_LAYER: Transition = Transition {
+        conditions: &[TransitionCondition::LayerActive(31)],
+        key_event_emissions: &[],
+        internal_event_emissions: &[InternalEvent::ActivateLayer(32)],
+        target: &DEEP,
+    };
+
+    static BUSY: State = State {
+        name: "BUSY",
+        id: StateId(7),
+        transitions: &[&BUSY_PRESS, &BUSY_PRESS],
+    };
+
+    static BUSY_PRESS: Transition = Transition {
+        conditions: &[TransitionCondition::pressed_single(0)],
+        key_event_emissions: &[KeyEvent::Press(0); 9],
+        internal_event_emissions: &[],
+        target: &BUSY,
+    };
+
+    #[test]
+    fn check_merged_machines() {
+        // fine when only one is taken
+        assert_eq!(check(&BUSY), Ok(()));
+        assert_eq!(
+            check_merged(&BUSY),
+            Err(ValidationError::TooManyMerged("BUSY"))
+        );
+        assert_eq!(check_merged(&DONE), Ok(()));
+        assert_eq!(
+            check_merged(&LOUD),
+            Err(ValidationError::TooManyEmissions("LOUD"))
+        );
+    }
+
+    #[test]
+    fn check_machines() {
+        assert_eq!(check(&home_a::IDLE), Ok(()));
+        assert_eq!(check(&EMPTY), Err(ValidationError::NoTransitions("EMPTY")));
+        assert_eq!(check(&LOUD), Err(ValidationError::TooManyEmissions("LOUD")));
+        assert_eq!(check(&IDLE), Err(ValidationError::Stuck("HELD")));
+        // looping in the last state is fine if it's the initial one
+        assert_eq!(check(&DONE), Ok(()));
+        assert_eq!(check(&home_a::HOLD), Ok(()));
+        assert_eq!(
+            check(&CLASH),
+            Err(ValidationError::DuplicateId("CLASH", "CLASH_TOO"))
+        );
+        assert_eq!(check(&DEEP), Err(ValidationError::InvalidLayer("DEEP")));
+    }
+}
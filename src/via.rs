@@ -0,0 +1,350 @@
+//! Via and Vial compatibility.
+//!
+//! [`Via`] answers the raw HID commands the Via and Vial configurators send,
+//! mapped onto a [`Keymap`], so they can be used to remap keys without
+//! reflashing. It covers protocol version 12 keymap access, layer count and
+//! the macro buffer, plus the parts of Vial needed to fetch the keyboard
+//! definition. Commands it doesn't handle are answered with `0xff` as the
+//! command byte, as Via expects.
+//!
+//! Positions are numbered `row * COLS + col`, matching
+//! [`Matrix`](crate::matrix::Matrix). Getting or setting a keycode at a row
+//! or column past the matrix is answered as a command it doesn't handle.
+//!
+//! Keycodes follow QMK: `0x0000` is [`Action::None`], `0x0001` is
+//! [`Action::Transparent`], basic keycodes are [`Action::Key`], and
+//! `MO(layer)` is [`Action::MomentaryLayer`]. Machines show up as the
+//! keyboard keycodes `QK_KB_0` onwards, and wireless actions as the user
+//! keycodes starting at `QK_USER_0`, in the order select profile 0 to 4,
+//! next, previous, clear bond, clear all bonds, USB, BLE, toggle transport.
+//! The locking lock keys `KC_LCAP`, `KC_LNUM` and `KC_LSCR` are
+//! [`Action::LockingKey`]s.
+//!
+//! The macro buffer is stored for Via to read and write, and
+//! [`Via::macro_events`] plays back the key taps, presses and releases in a
+//! macro. Typed text and delays are left to the firmware.
+
+use crate::keymap::{Action, Keymap};
+use crate::time::{self, Instant};
+use crate::{KeyEvent, Transport, Wireless};
+
+const REPORT_LEN: usize = 32;
+const VIA_PROTOCOL_VERSION: u16 = 12;
+const VIAL_PROTOCOL_VERSION: u32 = 6;
+
+const MOMENTARY: u16 = 0x5220;
+const KEYBOARD: u16 = 0x7e00;
+const USER: u16 = 0x7e40;
+
+const WIRELESS: [Wireless; 12] = [
+    Wireless::SelectProfile(0),
+    Wireless::SelectProfile(1),
+    Wireless::SelectProfile(2),
+    Wireless::SelectProfile(3),
+    Wireless::SelectProfile(4),
+    Wireless::NextProfile,
+    Wireless::PreviousProfile,
+    Wireless::ClearBond,
+    Wireless::ClearAllBonds,
+    Wireless::SetTransport(Transport::Usb),
+    Wireless::SetTransport(Transport::Ble),
+    Wireless::ToggleTransport,
+];
+
+/// The QMK locking keycodes, and the lock key of each.
+const LOCKING: [(u16, u8); 3] = [(0x0082, 0x39), (0x0083, 0x53), (0x0084, 0x47)];
+
+/// The QMK keycode for an action, if it has one.
+fn to_keycode(action: Action) -> Option<u16> {
+    Some(match action {
+        Action::None => 0x0000,
+        Action::Transparent => 0x0001,
+        Action::Key(key) => key as u16,
+        Action::MomentaryLayer(layer) if layer < 32 => MOMENTARY | layer as u16,
+        Action::Machine(machine) if machine < 64 => KEYBOARD + machine as u16,
+        Action::Wireless(wireless) => USER + WIRELESS.iter().position(|w| *w == wireless)? as u16,
+        Action::LockingKey(key) => LOCKING.iter().find(|(_, k)| *k == key)?.0,
+        _ => return None,
+    })
+}
+
+fn from_keycode(keycode: u16) -> Option<Action> {
+    Some(match keycode {
+        0x0000 => Action::None,
+        0x0001 => Action::Transparent,
+        0x0082..=0x0084 => Action::LockingKey(LOCKING[keycode as usize - 0x82].1),
+        0x0004..=0x00ff => Action::Key(keycode as u8),
+        0x5220..=0x523f => Action::MomentaryLayer((keycode - MOMENTARY) as u8),
+        0x7e00..=0x7e3f => Action::Machine((keycode - KEYBOARD) as usize),
+        _ => Action::Wireless(*WIRELESS.get(keycode.checked_sub(USER)? as usize)?),
+    })
+}
+
+struct Via<const ROWS: usize, const COLS: usize, const MACRO_BUFFER: usize> {
+    macro_count: u8,
+    macros: [u8; MACRO_BUFFER],
+    /// The Vial keyboard definition, as the compressed JSON Vial expects.
+    definition: &'static [u8],
+    uid: [u8; 8],
+}
+
+impl<const ROWS: usize, const COLS: usize, const MACRO_BUFFER: usize>
+    Via<ROWS, COLS, MACRO_BUFFER>
+{
+    const fn new(macro_count: u8, definition: &'static [u8], uid: [u8; 8]) -> Self {
+        Self {
+            macro_count,
+            macros: [0; MACRO_BUFFER],
+            definition,
+            uid,
+        }
+    }
+
+    fn keycode<Clock, const LAYERS: usize, const KEYS: usize, const MACHINES: usize>(
+        keymap: &Keymap<Clock, LAYERS, KEYS, MACHINES>,
+        index: usize,
+    ) -> u16
+    where
+        Clock: time::Clock,
+    {
+        keymap
+            .action(index / KEYS, index % KEYS)
+            .and_then(to_keycode)
+            .unwrap_or(0)
+    }
+
+    /// Carry out the command in `report`, replacing it with the response.
+    fn handle<Clock, const LAYERS: usize, const KEYS: usize, const MACHINES: usize>(
+        &mut self,
+        report: &mut [u8; REPORT_LEN],
+        keymap: &mut Keymap<Clock, LAYERS, KEYS, MACHINES>,
+    ) where
+        Clock: time::Clock,
+    {
+        const { assert!(ROWS * COLS == KEYS) };
+
+        let offset = u16::from_be_bytes([report[1], report[2]]) as usize;
+        let size = (report[3] as usize).min(REPORT_LEN - 4);
+
+        match *report {
+            [0x01, ..] => report[1..3].copy_from_slice(&VIA_PROTOCOL_VERSION.to_be_bytes()),
+            // layout options
+            [0x02, 0x02, ..] => report[2..6].fill(0),
+            [0x03, 0x02, ..] => {}
+            [0x04 | 0x05, _, row, col, ..] if row as usize >= ROWS || col as usize >= COLS => {
+                report[0] = 0xff
+            }
+            [0x04, layer, row, col, ..] => {
+                let index = layer as usize * KEYS + row as usize * COLS + col as usize;
+                report[4..6].copy_from_slice(&Self::keycode(keymap, index).to_be_bytes());
+            }
+            [0x05, layer, row, col, hi, lo, ..] => {
+                if let Some(action) = from_keycode(u16::from_be_bytes([hi, lo])) {
+                    let position = row as usize * COLS + col as usize;
+                    keymap.set_action(layer as usize, position, action);
+                }
+            }
+            [0x0c, ..] => report[1] = self.macro_count,
+            [0x0d, ..] => report[1..3].copy_from_slice(&(MACRO_BUFFER as u16).to_be_bytes()),
+            [0x0e, ..] => {
+                for (i, byte) in report[4..4 + size].iter_mut().enumerate() {
+                    *byte = self.macros.get(offset + i).copied().unwrap_or(0);
+                }
+            }
+            [0x0f, ..] => {
+                for (i, byte) in report[4..4 + size].iter().enumerate() {
+                    if let Some(slot) = self.macros.get_mut(offset + i) {
+                        *slot = *byte;
+                    }
+                }
+            }
+            [0x10, ..] => self.macros.fill(0),
+            [0x11, ..] => report[1] = LAYERS as u8,
+            [0x12, ..] => {
+                for (i, byte) in report[4..4 + size].iter_mut().enumerate() {
+                    let keycode = Self::keycode(keymap, (offset + i) / 2).to_be_bytes();
+                    *byte = keycode[(offset + i) % 2];
+                }
+            }
+            [0x13, ..] if offset.is_multiple_of(2) => {
+                let data = report[4..4 + size - size % 2].chunks_exact(2);
+                for (i, keycode) in data.enumerate() {
+                    let index = offset / 2 + i;
+                    if let Some(action) = from_keycode(u16::from_be_bytes([keycode[0], keycode[1]]))
+                    {
+                        keymap.set_action(index / KEYS, index % KEYS, action);
+                    }
+                }
+            }
+            [0xfe, command, ..] => self.handle_vial(command, report),
+            _ => report[0] = 0xff,
+        }
+    }
+
+    fn handle_vial(&self, command: u8, report: &mut [u8; REPORT_LEN]) {
+        match command {
+            // keyboard id
+            0x00 => {
+                report[..4].copy_from_slice(&VIAL_PROTOCOL_VERSION.to_le_bytes());
+                report[4..12].copy_from_slice(&self.uid);
+            }
+            0x01 => report[..4].copy_from_slice(&(self.definition.len() as u32).to_le_bytes()),
+            // a page of the definition
+            0x02 => {
+                let page = u16::from_le_bytes([report[2], report[3]]) as usize;
+                let chunk = self
+                    .definition
+                    .chunks(REPORT_LEN)
+                    .nth(page)
+                    .unwrap_or_default();
+                report.fill(0);
+                report[..chunk.len()].copy_from_slice(chunk);
+            }
+            // unlock status, always unlocked
+            0x05 => {
+                report.fill(0xff);
+                report[0] = 1;
+                report[1] = 0;
+            }
+            _ => report[0] = 0xff,
+        }
+    }
+
+    /// Play back the key taps, presses and releases of macro `index`.
+    fn macro_events(&self, index: usize, mut emit: impl FnMut(KeyEvent)) {
+        let Some(bytes) = self.macros.split(|b| *b == 0).nth(index) else {
+            return;
+        };
+
+        let mut bytes = bytes.iter().copied();
+        while let Some(byte) = bytes.next() {
+            if byte != 0x01 {
+                continue;
+            }
+
+            match (bytes.next(), bytes.next()) {
+                (Some(0x01), Some(key)) => {
+                    emit(KeyEvent::Press(key));
+                    emit(KeyEvent::Depress(key));
+                }
+                (Some(0x02), Some(key)) => emit(KeyEvent::Press(key)),
+                (Some(0x03), Some(key)) => emit(KeyEvent::Depress(key)),
+                (Some(0x04), _) => {
+                    // skip the delay, up to its terminator
+                    for byte in bytes.by_ref() {
+                        if byte == b'|' {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_keycode, to_keycode, Via};
+    use crate::keymap::{Action, Keymap};
+    use crate::tests::TickerClock;
+    use crate::{KeyEvent, Wireless};
+
+    static LAYERS: [[Action; 4]; 2] = [
+        [
+            Action::Key(4),
+            Action::MomentaryLayer(1),
+            Action::Machine(0),
+            Action::Wireless(Wireless::NextProfile),
+        ],
+        [Action::Transparent; 4],
+    ];
+
+    fn command(bytes: &[u8]) -> [u8; 32] {
+        let mut report = [0; 32];
+        report[..bytes.len()].copy_from_slice(bytes);
+        report
+    }
+
+    #[test]
+    fn keycodes() {
+        for action in LAYERS[0] {
+            assert_eq!(from_keycode(to_keycode(action).unwrap()), Some(action));
+        }
+        assert_eq!(to_keycode(Action::MomentaryLayer(1)), Some(0x5221));
+        assert_eq!(
+            from_keycode(0x7e45),
+            Some(Action::Wireless(Wireless::NextProfile))
+        );
+        assert_eq!(from_keycode(0x7f00), None);
+        assert_eq!(from_keycode(0x0082), Some(Action::LockingKey(0x39)));
+        assert_eq!(to_keycode(Action::LockingKey(0x47)), Some(0x0084));
+        assert_eq!(to_keycode(Action::LockingKey(4)), None);
+    }
+
+    #[test]
+    fn keymap_commands() {
+        let clock = TickerClock(0);
+        let mut keymap = Keymap::<TickerClock, 2, 4, 0>::new(&LAYERS, [], clock.now());
+        let mut via = Via::<2, 2, 16>::new(4, &[1, 2, 3], [0xaa; 8]);
+
+        let mut run = |keymap: &mut Keymap<_, 2, 4, 0>, bytes: &[u8]| {
+            let mut report = command(bytes);
+            via.handle(&mut report, keymap);
+            report
+        };
+
+        assert_eq!(run(&mut keymap, &[0x01])[..3], [0x01, 0, 12]);
+        assert_eq!(run(&mut keymap, &[0x11])[1], 2);
+        assert_eq!(run(&mut keymap, &[0x04, 0, 0, 1])[4..6], [0x52, 0x21]);
+        assert_eq!(run(&mut keymap, &[0x42])[0], 0xff);
+
+        // past the matrix, rather than the next row or layer
+        assert_eq!(run(&mut keymap, &[0x04, 0, 0, 2])[0], 0xff);
+        assert_eq!(run(&mut keymap, &[0x05, 0, 2, 0, 0x00, 0x29])[0], 0xff);
+        assert_eq!(keymap.action(1, 0), Some(Action::Transparent));
+
+        run(&mut keymap, &[0x05, 1, 1, 0, 0x00, 0x29]);
+        assert_eq!(keymap.action(1, 2), Some(Action::Key(0x29)));
+
+        // the whole keymap, two bytes per key
+        let report = run(&mut keymap, &[0x12, 0, 8, 6]);
+        assert_eq!(report[4..10], [0, 1, 0, 1, 0, 0x29]);
+
+        run(&mut keymap, &[0x13, 0, 0, 4, 0x52, 0x20, 0x00, 0x05]);
+        assert_eq!(keymap.action(0, 0), Some(Action::MomentaryLayer(0)));
+        assert_eq!(keymap.action(0, 1), Some(Action::Key(5)));
+
+        assert_eq!(run(&mut keymap, &[0xfe, 0x01])[..4], [3, 0, 0, 0]);
+        assert_eq!(run(&mut keymap, &[0xfe, 0x02, 0, 0])[..4], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn macros() {
+        let clock = TickerClock(0);
+        let mut keymap = Keymap::<TickerClock, 2, 4, 0>::new(&LAYERS, [], clock.now());
+        let mut via = Via::<2, 2, 32>::new(2, &[], [0; 8]);
+
+        let mut report = command(&[0x0f, 0, 0, 14]);
+        report[4..18].copy_from_slice(&[
+            0, // empty first macro
+            1, 2, 0xe1, 1, 4, b'5', b'0', b'|', 1, 1, 4, 1, 3,
+        ]);
+        via.handle(&mut report, &mut keymap);
+
+        let mut report = command(&[0x0e, 0, 1, 3]);
+        via.handle(&mut report, &mut keymap);
+        assert_eq!(report[4..7], [1, 2, 0xe1]);
+
+        let mut events = Vec::new();
+        via.macro_events(1, |e| events.push(e));
+        assert_eq!(
+            events,
+            [
+                KeyEvent::Press(0xe1),
+                KeyEvent::Press(4),
+                KeyEvent::Depress(4)
+            ]
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! A simulator for driving machines from a browser.
+//!
+//! [`Simulator`] runs the same [`GlobalState`] as the firmware against a
+//! simulated clock, with an API of plain numbers and byte buffers so that it
+//! can be exported to JavaScript from a `wasm32-unknown-unknown` build.
+//! Events go in and come out in the encodings of [`InputEvent::to_bytes`]
+//! and [`KeyEvent::to_bytes`].
+//!
+//! The `#[wasm_bindgen]` exports themselves aren't here: this crate doesn't
+//! depend on `wasm-bindgen`, and the machines a simulator runs are the
+//! firmware's statics, which only the keymap tester linking them in can
+//! name. The tester wraps a [`Simulator`] in an exported struct whose
+//! methods each forward to the one of the same name here, which is why the
+//! API has nothing a binding would have to convert.
+//!
+//! The machines a simulator can load are the statics it's built with, so a
+//! keymap tester links in the firmware's own machines:
+//!
+//! ```ignore
+//! static MACHINES: [&dyn DynState; 2] = [home_a::IDLE.as_dyn(), home_s::IDLE.as_dyn()];
+//!
+//! let mut simulator = Simulator::new(&MACHINES).unwrap();
+//! simulator.push([0, 4, 0]);
+//! simulator.advance(300);
+//! let emitted = simulator.take_emissions();
+//! ```
+
+use core::time::Duration;
+
+use crate::time::HostClock;
+use crate::{DynState, GlobalState, InputEvent, KeyEvent};
+
+struct Simulator {
+    machines: &'static [&'static dyn DynState],
+    machine: GlobalState<HostClock>,
+    now: Duration,
+    /// Emitted events not yet taken, three bytes each.
+    emitted: Vec<u8>,
+}
+
+impl Simulator {
+    /// A simulator running the first of `machines`, or `None` if there
+    /// aren't any.
+    fn new(machines: &'static [&'static dyn DynState]) -> Option<Self> {
+        let initial = *machines.first()?;
+        Some(Self {
+            machines,
+            machine: GlobalState::new(initial, Duration::ZERO),
+            now: Duration::ZERO,
+            emitted: Vec::new(),
+        })
+    }
+
+    /// Start over running `machines[index]`, at time zero. Returns `false`
+    /// if there's no such machine.
+    fn load(&mut self, index: usize) -> bool {
+        let Some(machine) = self.machines.get(index) else {
+            return false;
+        };
+        self.machine = GlobalState::new(*machine, Duration::ZERO);
+        self.now = Duration::ZERO;
+        self.emitted.clear();
+        true
+    }
+
+    /// Record what the machine last emitted for `event`, or for a tick, with
+    /// `PressCurrent` and `DepressCurrent` resolved. Events without an
+    /// encoding are left out.
+    fn emit(&mut self, event: Option<InputEvent>) {
+        for emitted in self.machine.emitted() {
+            let bytes = emitted.resolve_current(event).to_bytes();
+            if bytes[0] != 0xff {
+                self.emitted.extend_from_slice(&bytes);
+            }
+        }
+    }
+
+    /// Push an encoded event at the current time. Returns `false` if `event`
+    /// isn't a valid encoding.
+    fn push(&mut self, event: [u8; 3]) -> bool {
+        let Some(event) = InputEvent::from_bytes(event) else {
+            return false;
+        };
+        self.machine.push(self.now, event);
+        self.emit(Some(event));
+        true
+    }
+
+    /// Move time forward by `ms` milliseconds. Timed transitions that come
+    /// due on the way are taken at their deadlines, in order.
+    fn advance(&mut self, ms: u32) {
+        let until = self.now + Duration::from_millis(ms as u64);
+        while let Some(deadline) = self.machine.next_deadline(self.now) {
+            if deadline > until {
+                break;
+            }
+            self.now = deadline;
+            self.machine.tick(self.now);
+            self.emit(None);
+        }
+        self.now = until;
+        self.machine.tick(self.now);
+        self.emit(None);
+    }
+
+    /// Milliseconds since the machine was loaded.
+    fn now_ms(&self) -> u64 {
+        self.now.as_millis() as u64
+    }
+
+    /// The events emitted since this was last called.
+    fn take_emissions(&mut self) -> Vec<u8> {
+        core::mem::take(&mut self.emitted)
+    }
+
+    fn state_id(&self) -> u16 {
+        self.machine.current_state.id().0
+    }
+
+    fn state_name(&self) -> String {
+        self.machine.current_state.name().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Simulator;
+    use crate::behaviors::hold_tap;
+    use crate::time::Duration;
+    use crate::{DynState, InputEvent, KeyEvent};
+
+    hold_tap! {
+        mod home_a {
+            key: 1,
+            tap: 6,
+            hold: 0xe1,
+            tapping_term: Duration::from_millis(200),
+        }
+    }
+
+    hold_tap! {
+        mod home_s {
+            key: 2,
+            tap: 7,
+            hold: 0xe0,
+            tapping_term: Duration::from_millis(200),
+        }
+    }
+
+    static MACHINES: [&dyn DynState; 2] = [home_a::IDLE.as_dyn(), home_s::IDLE.as_dyn()];
+
+    #[test]
+    fn simulate() {
+        assert!(Simulator::new(&[]).is_none());
+        let mut simulator = Simulator::new(&MACHINES).unwrap();
+
+        assert!(simulator.push(InputEvent::Press(1).to_bytes()));
+        assert_eq!(simulator.state_name(), "home_a::UNDECIDED");
+        simulator.advance(250);
+        assert_eq!(simulator.now_ms(), 250);
+        assert_eq!(simulator.state_id(), home_a::HOLD.id.0);
+        assert_eq!(
+            simulator.take_emissions()[..3],
+            KeyEvent::Press(0xe1).to_bytes()
+        );
+        assert!(simulator.take_emissions().is_empty());
+        assert!(!simulator.push([0xfe, 0, 0]));
+
+        assert!(simulator.load(1));
+        assert_eq!(simulator.now_ms(), 0);
+        assert!(simulator.push(InputEvent::Press(2).to_bytes()));
+        simulator.advance(50);
+        assert!(simulator.push(InputEvent::Depress(2).to_bytes()));
+        assert_eq!(
+            simulator.take_emissions()[..6],
+            [
+                KeyEvent::Press(7).to_bytes(),
+                KeyEvent::Depress(7).to_bytes()
+            ]
+            .concat()
+        );
+        assert!(!simulator.load(2));
+    }
+
+    #[test]
+    fn passes_through_resolved() {
+        let mut simulator = Simulator::new(&MACHINES).unwrap();
+        simulator.push(InputEvent::Press(5).to_bytes());
+        simulator.push(InputEvent::Depress(5).to_bytes());
+        assert_eq!(
+            simulator.take_emissions(),
+            [
+                KeyEvent::Press(5).to_bytes(),
+                KeyEvent::Depress(5).to_bytes()
+            ]
+            .concat()
+        );
+    }
+}